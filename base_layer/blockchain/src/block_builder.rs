@@ -0,0 +1,234 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+// This file contains the builder with which miners assemble the next block of the chain
+
+use crate::{
+    blockchainstate::BlockchainState,
+    error::BlockBuildError,
+    store::BlockchainBackend,
+};
+use chrono::{DateTime, Utc};
+use tari_core::{
+    block::{calculate_weight, AggregateBody, Block},
+    blockheader::BlockHeader,
+    pow::ProofOfWork,
+    transaction::{KernelFeatures, Transaction, TransactionKernel, TransactionOutput},
+};
+use tari_utilities::Hashable;
+
+/// Builds the block that follows the tip of a chain. Transactions are added one at a time, while the builder keeps
+/// track of the weight and fees of the body, so that a miner can stop adding transactions once the block is full and
+/// then claim the fees with a coinbase, after which the body is complete. The MMR roots are only calculated when
+/// `update_roots` is called, as they depend on the chain, after which the builder emits a template to mine or, given
+/// the nonce that meets the target difficulty, the sealed block.
+pub struct BlockBuilder {
+    header: BlockHeader,
+    body: AggregateBody,
+    max_weight: u64,
+    fees: u64,
+    has_coinbase: bool,
+    roots_outdated: bool,
+}
+
+impl BlockBuilder {
+    /// Start the block that follows the tip of the chain, with the version and weight limit of the consensus constants
+    /// of the chain and the target difficulty the chain requires of its next block. The timestamp is the current time.
+    pub fn new<B: BlockchainBackend>(state: &BlockchainState<B>) -> Result<BlockBuilder, BlockBuildError> {
+        let prev = state.get_tip_header().ok_or(BlockBuildError::EmptyChain)?;
+        let constants = state.get_consensus_constants();
+        let height = prev.height + 1;
+        let mut prev_hash = [0u8; 32];
+        prev_hash.copy_from_slice(&prev.hash());
+        let header = BlockHeader {
+            version: constants.blockchain_version(height),
+            height,
+            prev_hash,
+            timestamp: Utc::now(),
+            output_mmr: [0; 32],
+            kernel_mmr: [0; 32],
+            total_kernel_offset: prev.total_kernel_offset,
+            pow: ProofOfWork::new(state.get_next_difficulty()),
+        };
+        Ok(BlockBuilder {
+            header,
+            body: AggregateBody::empty(),
+            max_weight: constants.max_block_weight,
+            fees: 0,
+            has_coinbase: false,
+            roots_outdated: true,
+        })
+    }
+
+    /// Set the timestamp of the block
+    pub fn with_timestamp(mut self, timestamp: DateTime<Utc>) -> BlockBuilder {
+        self.header.timestamp = timestamp;
+        self
+    }
+
+    /// Add a transaction to the block. The transaction must be valid and may be mined at the height of the block, and
+    /// the block must stay within the maximum block weight. Transactions can not be added after the coinbase, which
+    /// claims their fees.
+    pub fn add_transaction(mut self, tx: Transaction) -> Result<BlockBuilder, BlockBuildError> {
+        if self.has_coinbase {
+            return Err(BlockBuildError::TransactionAfterCoinbase);
+        }
+        tx.validate()?;
+        tx.validate_lock_height(self.header.height)?;
+        if self.weight() + tx.calculate_weight() > self.max_weight {
+            return Err(BlockBuildError::BlockTooHeavy);
+        }
+        self.fees = tx.body.kernels.iter().fold(self.fees, |fees, kernel| fees.saturating_add(kernel.fee));
+        let block = Block { header: self.header, body: self.body }.add_transaction(tx);
+        self.header = block.header;
+        self.body = block.body;
+        self.roots_outdated = true;
+        Ok(self)
+    }
+
+    /// Add the coinbase output and kernel of the block, which claim the block reward plus `fees()`. A block has a
+    /// single coinbase.
+    pub fn add_coinbase(
+        mut self,
+        output: TransactionOutput,
+        kernel: TransactionKernel,
+    ) -> Result<BlockBuilder, BlockBuildError>
+    {
+        if self.has_coinbase {
+            return Err(BlockBuildError::DuplicateCoinbase);
+        }
+        if !output.features.is_coinbase() || !kernel.features.contains(KernelFeatures::COINBASE_KERNEL) {
+            return Err(BlockBuildError::InvalidCoinbase);
+        }
+        if self.weight() + calculate_weight(0, 1, 1) > self.max_weight {
+            return Err(BlockBuildError::BlockTooHeavy);
+        }
+        self.body = self.body.add_output(output).add_kernel(kernel);
+        self.has_coinbase = true;
+        self.roots_outdated = true;
+        Ok(self)
+    }
+
+    /// The weight of the body so far
+    pub fn weight(&self) -> u64 {
+        self.body.calculate_weight()
+    }
+
+    /// The fees of the transactions added so far, which the coinbase claims along with the block reward
+    pub fn fees(&self) -> u64 {
+        self.fees
+    }
+
+    /// Calculate the MMR roots of the block, which must build on the tip of the given chain. Nothing is calculated if
+    /// the body has not changed since the roots were last calculated.
+    pub fn update_roots<B: BlockchainBackend>(&mut self, state: &BlockchainState<B>) -> Result<(), BlockBuildError> {
+        if !self.roots_outdated {
+            return Ok(());
+        }
+        match state.get_tip_header() {
+            Some(tip) if tip.hash()[..] == self.header.prev_hash[..] => {},
+            _ => return Err(BlockBuildError::NotOnTip),
+        }
+        let roots = state.calculate_roots(&self.body);
+        self.header.output_mmr = roots.output_mmr;
+        self.header.kernel_mmr = roots.kernel_mmr;
+        self.roots_outdated = false;
+        Ok(())
+    }
+
+    /// Returns the block to mine, whose header has everything but the nonce that meets the target difficulty
+    pub fn template(&self) -> Result<Block, BlockBuildError> {
+        if self.roots_outdated {
+            return Err(BlockBuildError::OutdatedRoots);
+        }
+        Ok(Block::new(self.header.clone(), self.body.clone()))
+    }
+
+    /// Returns the block sealed with the nonce, which must meet the target difficulty
+    pub fn seal(self, nonce: u64) -> Result<Block, BlockBuildError> {
+        let mut block = self.template()?;
+        block.header.pow.nonce = nonce;
+        if !block.header.validate_pow() {
+            return Err(BlockBuildError::BadPoW);
+        }
+        Ok(block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blockchainstate::test::{
+        balancing_offset,
+        create_block,
+        create_coinbase,
+        create_fee_kernel,
+        create_output,
+        spend,
+    };
+    use rand::OsRng;
+    use tari_core::consensus::{ConsensusConstants, Network};
+
+    #[test]
+    fn build_block() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        assert_eq!(BlockBuilder::new(&state).err(), Some(BlockBuildError::EmptyChain));
+        let genesis_output = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![genesis_output], vec![]));
+        state.add_block(genesis.clone()).unwrap();
+        let constants = Network::LocalNet.consensus_constants();
+
+        let body =
+            AggregateBody::new(vec![spend(&genesis_output)], vec![create_output(&mut rng, 90)], vec![
+                create_fee_kernel(&mut rng, 10),
+            ]);
+        let offset = balancing_offset(&body);
+        let tx = Transaction::new(body.inputs, body.outputs, body.kernels, offset);
+        let light = ConsensusConstants { max_block_weight: tx.calculate_weight() - 1, ..constants.clone() };
+        let mut light_state = BlockchainState::new().with_consensus_constants(light);
+        light_state.add_block(genesis).unwrap();
+        let builder = BlockBuilder::new(&light_state).unwrap();
+        assert_eq!(builder.add_transaction(tx.clone()).err(), Some(BlockBuildError::BlockTooHeavy));
+
+        let mut builder = BlockBuilder::new(&state).unwrap().add_transaction(tx.clone()).unwrap();
+        assert_eq!(builder.fees(), 10);
+        let reward = constants.emission_schedule().block_reward(1);
+        let (output, kernel) = create_coinbase(&mut rng, reward + builder.fees(), 1, constants.coinbase_lock_height);
+        // The body is complete once the coinbase claims the fees
+        let with_coinbase = || BlockBuilder::new(&state).unwrap().add_coinbase(output.clone(), kernel.clone()).unwrap();
+        let error = with_coinbase().add_transaction(tx.clone()).err();
+        assert_eq!(error, Some(BlockBuildError::TransactionAfterCoinbase));
+        let error = with_coinbase().add_coinbase(output.clone(), kernel.clone()).err();
+        assert_eq!(error, Some(BlockBuildError::DuplicateCoinbase));
+        builder = builder.add_coinbase(output, kernel).unwrap();
+        assert_eq!(builder.template().err(), Some(BlockBuildError::OutdatedRoots));
+        builder.update_roots(&state).unwrap();
+        let template = builder.template().unwrap();
+        assert_eq!(template.body.outputs.len(), 2);
+        let block = builder.seal(7).unwrap();
+        assert_eq!(block.header.pow.nonce, 7);
+        assert_eq!(block.header.pow.target_difficulty, state.get_next_difficulty());
+        state.add_block(block).unwrap();
+        assert_eq!(state.get_tip_height(), Some(1));
+    }
+}
@@ -22,11 +22,2254 @@
 
 // This file is used to store the current blockchain state
 
-/// The BlockchainState struct keeps record of the current UTXO, total kernels and headers.
-pub struct BlockchainState {}
+use crate::{
+    error::{AuditError, BlockValidationError, ChainError, MerkleRoot, SnapshotError, StoreError},
+    snapshot::Snapshot,
+    store::{BlockRecord, BlockchainBackend, MemoryBackend, MmrTree, WriteOperation},
+};
+use chrono::{DateTime, Duration, Utc};
+use crypto::commitment::HomomorphicCommitment;
+use merklemountainrange::{merklenode::ObjectHash, mmr::MerkleMountainRange, prunedmmr::PrunedMmr};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    ops::Bound,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+use tari_core::{
+    block::{AggregateBody, Block},
+    blockheader::{BlockHash, BlockHeader},
+    consensus::{ConsensusConstants, Network},
+    difficulty::{Difficulty, DifficultyAdjustment},
+    transaction::{TransactionError, TransactionKernel, TransactionOutput},
+    types::{Commitment, MmrHasher},
+};
+use tari_utilities::Hashable;
 
-impl BlockchainState {
-    pub fn new() -> BlockchainState {
-        BlockchainState {}
+/// The MMR backends buffer their changes in memory until the state is written, so adding nodes can not fail, and
+/// the stored nodes read when rewinding were written by this node
+const IN_MEMORY: &str = "The MMR changes are buffered in memory and the stored nodes are readable";
+
+/// The BlockchainState struct keeps record of the current UTXO, total kernels and headers. Each change to the main
+/// chain is persisted in the backend in a single write: the blocks with a record of each, the outputs and kernels, and
+/// the nodes of the MMRs. The state is loaded from them without applying the blocks again.
+pub struct BlockchainState<B = MemoryBackend>
+where B: BlockchainBackend
+{
+    /// The store holding the blocks of the main chain
+    backend: B,
+    /// The network the chain belongs to
+    network: Network,
+    /// The consensus rules of the network
+    consensus_constants: ConsensusConstants,
+    /// The MMR of the block headers on the main chain
+    headers: MerkleMountainRange<BlockHeader, MmrHasher, B::MmrBackend>,
+    /// The hashes of the block headers on the main chain, indexed by block height
+    header_hashes: Vec<ObjectHash>,
+    /// The accumulated target difficulty of the main chain up to each block, indexed by block height
+    accumulated_difficulty: Vec<Difficulty>,
+    /// The median timestamp of the most recent headers of the main chain up to each block, indexed by block height.
+    /// The timestamp of the next block must be later than the last of them.
+    median_timestamps: Vec<DateTime<Utc>>,
+    /// The headers of the best header chain above the main chain tip, whose blocks have not been added yet. They are
+    /// only kept in memory.
+    pending_headers: VecDeque<BlockHeader>,
+    /// The changes each block on the main chain made to the MMRs and UTXO set, indexed by block height, so that the
+    /// blocks can be reverted
+    journals: Vec<BlockJournal>,
+    /// The MMR of every output created on the main chain, with the bitmap of the outputs that have been spent. Its
+    /// root commits to both and is the output root of the block headers. It has a checkpoint for every block on the
+    /// main chain.
+    outputs: PrunedMmr<TransactionOutput, MmrHasher, B::MmrBackend>,
+    /// The MMR of every kernel on the main chain
+    kernels: MerkleMountainRange<TransactionKernel, MmrHasher, B::MmrBackend>,
+    /// The unspent outputs, mapping the bytes of the output commitment to the output hash in the output MMR. The map
+    /// is ordered so that the UTXO set can be paged through.
+    utxos: BTreeMap<Vec<u8>, ObjectHash>,
+    /// The height of the block that created each output, keyed by output hash, for the outputs that are unspent or
+    /// whose spending block can still be reverted. Relative time locks count from this height.
+    output_heights: HashMap<ObjectHash, u64>,
+    /// The leaf index in the output MMR of every output created on the main chain, keyed by output hash
+    output_leaves: HashMap<ObjectHash, usize>,
+    /// The number of outputs spent on the main chain with each commitment, keyed by the commitment bytes. The outputs
+    /// pruned before the state was loaded are not counted, as only their hashes are stored.
+    spent_commitments: HashMap<Vec<u8>, usize>,
+    /// In pruned mode, the number of blocks kept above the pruned height
+    pruning_horizon: Option<u64>,
+    /// The blocks below this height can not be reverted. In pruned mode the outputs they spent have been discarded.
+    pruned_height: u64,
+    /// The hashes of the outputs discarded by pruning that have not been removed from the backend yet
+    pruned_outputs: Vec<ObjectHash>,
+    /// The largest number of blocks a reorganisation may revert, or None if reorganisations are not limited
+    max_reorg_depth: Option<u64>,
+    /// The channels events are sent to
+    subscribers: Vec<Sender<BlockchainEvent>>,
+}
+
+/// The changes applying a block made to the MMRs and UTXO set, recorded so that they can be undone
+#[derive(Default)]
+struct BlockJournal {
+    /// The number of leaves in the kernel MMR before the block was applied
+    kernel_leaf_count: usize,
+    /// The UTXO set entries removed by the block: the commitment bytes and output hash of each spent output
+    spent: Vec<(Vec<u8>, ObjectHash)>,
+    /// The commitment bytes of the UTXO set entries added by the block
+    created: Vec<Vec<u8>>,
+}
+
+/// The result of a chain reorganisation
+#[derive(Debug)]
+pub struct Reorg {
+    /// The blocks removed from the main chain, in ascending order of height
+    pub reverted: Vec<Block>,
+    /// The blocks added to the main chain, in ascending order of height
+    pub applied: Vec<Block>,
+}
+
+/// The MMR roots a block header commits to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MmrRoots {
+    /// The root of the output MMR once the block is applied
+    pub output_mmr: BlockHash,
+    /// The root of the kernel MMR once the block is applied
+    pub kernel_mmr: BlockHash,
+}
+
+/// The changes to the main chain that subscribers are notified of
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockchainEvent {
+    /// A block was added to the main chain
+    BlockAdded { height: u64, hash: ObjectHash },
+    /// The main chain was reorganised, reverting `depth` blocks. It follows the `BlockAdded` events of the blocks
+    /// that replaced them.
+    Reorged { depth: u64 },
+    /// An unspent output, identified by its hash, was spent by the block at the given height
+    OutputSpent { height: u64, output: ObjectHash },
+    /// The main chain has a new tip
+    NewTip { height: u64, hash: ObjectHash },
+    /// A heavier fork, identified by the hash of its last block, was refused because reorganising onto it would revert
+    /// `depth` blocks, more than the maximum reorganisation depth
+    DeepForkRejected { depth: u64, hash: ObjectHash },
+}
+
+impl BlockchainState<MemoryBackend> {
+    /// Create an empty blockchain state for a local network that is only kept in memory. The first block added
+    /// becomes the genesis block.
+    pub fn new() -> BlockchainState<MemoryBackend> {
+        BlockchainState::empty(MemoryBackend::new(), Network::LocalNet).expect(IN_MEMORY)
+    }
+}
+
+impl<B> BlockchainState<B>
+where B: BlockchainBackend
+{
+    /// Load the chain of the network stored in the backend. The MMRs are opened on their stored nodes and the headers,
+    /// kernels and unpruned outputs are read back into them, so the stored blocks are not applied again. If the backend
+    /// is empty, the genesis block of the network is added to it.
+    pub fn with_backend(backend: B, network: Network) -> Result<BlockchainState<B>, ChainError> {
+        let mut state = BlockchainState::empty(backend, network)?;
+        state.load()?;
+        if let (None, Some(genesis)) = (state.get_tip_height(), network.genesis_block()) {
+            state.apply_block(&genesis);
+            let operations = state.push_operations(&genesis);
+            state.write(operations)?;
+        }
+        Ok(state)
+    }
+
+    /// Create a blockchain state from a snapshot, for instance one exported by another node or taken as a backup.
+    /// The headers must form a valid header chain from the genesis block of the network, with the proof of work and
+    /// target difficulty of every header checked as they are for blocks, and the MMR roots rebuilt from the snapshot
+    /// must match the commitments in the tip header. The snapshot replaces the chain stored in the backend.
+    pub fn from_snapshot(backend: B, network: Network, snapshot: Snapshot) -> Result<BlockchainState<B>, ChainError> {
+        let mut state = BlockchainState::empty(backend, network)?;
+        state.validate_snapshot_headers(&snapshot)?;
+        state.restore_snapshot(&snapshot)?;
+        let mut operations = vec![WriteOperation::WriteSnapshot(Box::new(snapshot))];
+        for height in 0..state.header_hashes.len() {
+            operations.push(WriteOperation::PushBlock { record: Box::new(state.block_record(height)), block: None });
+        }
+        for (leaf_index, leaf) in state.kernels.iter_leaves().enumerate() {
+            if let (_, Some(kernel)) = leaf.expect(IN_MEMORY) {
+                operations.push(WriteOperation::InsertKernel(leaf_index as u64, Box::new(kernel.clone())));
+            }
+        }
+        let utxos = state.utxos.values().filter_map(|hash| state.outputs.get_object(hash));
+        operations.extend(utxos.map(|output| WriteOperation::InsertOutput(Box::new(*output))));
+        state.write(operations)?;
+        Ok(state)
+    }
+
+    /// Create a state without any blocks. The MMRs are opened on the nodes stored in the backend and rewound, so that
+    /// the stored nodes are replaced when the state is written.
+    fn empty(backend: B, network: Network) -> Result<BlockchainState<B>, StoreError> {
+        let mut headers = MerkleMountainRange::with_backend(backend.mmr_backend(MmrTree::Header)?)?;
+        headers.rewind(0)?;
+        let mut kernels = MerkleMountainRange::with_backend(backend.mmr_backend(MmrTree::Kernel)?)?;
+        kernels.rewind(0)?;
+        let mut outputs = MerkleMountainRange::with_backend(backend.mmr_backend(MmrTree::Output)?)?;
+        outputs.rewind(0)?;
+        Ok(BlockchainState {
+            backend,
+            network,
+            consensus_constants: network.consensus_constants(),
+            headers,
+            header_hashes: Vec::new(),
+            accumulated_difficulty: Vec::new(),
+            median_timestamps: Vec::new(),
+            pending_headers: VecDeque::new(),
+            journals: Vec::new(),
+            outputs: PrunedMmr::from_checkpoints(outputs, &[])?,
+            kernels,
+            utxos: BTreeMap::new(),
+            output_heights: HashMap::new(),
+            output_leaves: HashMap::new(),
+            spent_commitments: HashMap::new(),
+            pruning_horizon: None,
+            pruned_height: 0,
+            pruned_outputs: Vec::new(),
+            max_reorg_depth: None,
+            subscribers: Vec::new(),
+        })
+    }
+
+    /// Load the chain stored in the backend into an empty state. The MMRs are opened on their stored nodes and the
+    /// deleted outputs are marked from the block records. The headers, kernels and outputs that have not been pruned
+    /// are read back into the MMRs, and the UTXO set and the journals of the blocks that can still be reverted are
+    /// rebuilt from them. A record that does not match the MMRs fails with `StoreError::CorruptRecord`.
+    fn load(&mut self) -> Result<(), StoreError> {
+        let records = (0..self.backend.block_count())
+            .map(|height| self.backend.fetch_record(height)?.ok_or(StoreError::CorruptRecord))
+            .collect::<Result<Vec<BlockRecord>, StoreError>>()?;
+        self.headers = MerkleMountainRange::with_backend(self.backend.mmr_backend(MmrTree::Header)?)?;
+        self.kernels = MerkleMountainRange::with_backend(self.backend.mmr_backend(MmrTree::Kernel)?)?;
+        let outputs = MerkleMountainRange::with_backend(self.backend.mmr_backend(MmrTree::Output)?)?;
+        let checkpoints: Vec<(usize, Vec<u32>)> =
+            records.iter().map(|record| (record.output_leaf_count as usize, record.spent_leaves.clone())).collect();
+        self.outputs = PrunedMmr::from_checkpoints(outputs, &checkpoints).map_err(|_| StoreError::CorruptRecord)?;
+        self.pruned_height = self.backend.fetch_pruned_height()?;
+        if self.headers.get_leaf_count() != records.len() || self.pruned_height > records.len() as u64 {
+            return Err(StoreError::CorruptRecord);
+        }
+        for (height, record) in records.iter().enumerate() {
+            if !self.headers.restore_object(height, record.header.clone())? {
+                return Err(StoreError::CorruptRecord);
+            }
+            self.index_header(&record.header);
+        }
+        for leaf_index in 0..self.kernels.get_leaf_count() {
+            let kernel = self.backend.fetch_kernel(leaf_index as u64)?.ok_or(StoreError::CorruptRecord)?;
+            if !self.kernels.restore_object(leaf_index, kernel)? {
+                return Err(StoreError::CorruptRecord);
+            }
+        }
+
+        // Each block added the output leaves up to the leaf count of the next block
+        let leaf_hashes = self.outputs.get_mmr().get_leaf_hashes()?;
+        let created = |height: usize| {
+            let end = records.get(height + 1).map_or(leaf_hashes.len(), |next| next.output_leaf_count as usize);
+            records[height].output_leaf_count as usize..end
+        };
+        for height in 0..records.len() {
+            for leaf_index in created(height) {
+                let hash = &leaf_hashes[leaf_index];
+                self.output_leaves.insert(hash.clone(), leaf_index);
+                let spent = self.outputs.is_deleted(leaf_index);
+                let output = match self.backend.fetch_output(hash)? {
+                    Some(output) => output,
+                    // Only the hash of a spent output is kept once it has been pruned
+                    None if spent => continue,
+                    None => return Err(StoreError::CorruptRecord),
+                };
+                let key = output.commitment.as_bytes().to_vec();
+                if !self.outputs.restore_object(leaf_index, output)? {
+                    return Err(StoreError::CorruptRecord);
+                }
+                self.output_heights.insert(hash.clone(), height as u64);
+                if spent {
+                    *self.spent_commitments.entry(key).or_insert(0) += 1;
+                } else {
+                    self.utxos.insert(key, hash.clone());
+                }
+            }
+        }
+
+        // The outputs spent and created by the blocks that can still be reverted have not been pruned
+        let commitment = |leaf_index: usize| {
+            let output = self.outputs.get_object(&leaf_hashes[leaf_index]).ok_or(StoreError::CorruptRecord)?;
+            Ok(output.commitment.as_bytes().to_vec())
+        };
+        let mut journals = Vec::with_capacity(records.len());
+        for (height, record) in records.iter().enumerate() {
+            let mut journal =
+                BlockJournal { kernel_leaf_count: record.kernel_leaf_count as usize, ..BlockJournal::default() };
+            if height as u64 >= self.pruned_height {
+                for &leaf_index in record.spent_leaves.iter() {
+                    let leaf_index = leaf_index as usize;
+                    journal.spent.push((commitment(leaf_index)?, leaf_hashes[leaf_index].clone()));
+                }
+                journal.created = created(height).map(commitment).collect::<Result<_, StoreError>>()?;
+            }
+            journals.push(journal);
+        }
+        self.journals = journals;
+
+        // The MMRs must have the roots the tip header commits to
+        if let Some(tip) = records.last() {
+            if self.outputs.get_merkle_root()[..] != tip.header.output_mmr[..] ||
+                self.kernels.get_merkle_root()[..] != tip.header.kernel_mmr[..]
+            {
+                return Err(StoreError::CorruptRecord);
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch the state to pruned mode. Outputs spent by blocks more than `horizon` blocks below the tip are
+    /// discarded from the output MMR, leaving only their hashes, so that the MMR roots can still be verified. The
+    /// kernel and header MMRs are kept in full. Blocks below the horizon can no longer be reverted, so reorganisations
+    /// are limited to `horizon` blocks.
+    pub fn with_pruning_horizon(mut self, horizon: u64) -> BlockchainState<B> {
+        self.pruning_horizon = Some(horizon);
+        self.prune();
+        self
+    }
+
+    /// Override the consensus constants of the network, for instance to run a test network with a shorter block
+    /// interval. The constants apply to the blocks added from now on: the blocks already loaded are not validated
+    /// again.
+    pub fn with_consensus_constants(mut self, constants: ConsensusConstants) -> BlockchainState<B> {
+        self.consensus_constants = constants;
+        self
+    }
+
+    /// Limit reorganisations to reverting at most `depth` blocks. A heavier fork that would revert more blocks is
+    /// refused with `ChainError::ReorgTooDeep` and a `DeepForkRejected` event is sent, so that the peers that sent the
+    /// fork can be penalised. Such a fork needs operator intervention, for instance with `rewind_to_height`.
+    pub fn with_max_reorg_depth(mut self, depth: u64) -> BlockchainState<B> {
+        self.max_reorg_depth = Some(depth);
+        self
+    }
+
+    /// Subscribe to the changes to the main chain. An event is sent for every change after it has been made, so
+    /// consumers such as the wallet and mempool can react without polling. Dropping the receiver ends the
+    /// subscription.
+    pub fn subscribe(&mut self) -> Receiver<BlockchainEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Returns the network the chain belongs to
+    pub fn get_network(&self) -> Network {
+        self.network
+    }
+
+    /// Returns the consensus rules of the network
+    pub fn get_consensus_constants(&self) -> &ConsensusConstants {
+        &self.consensus_constants
+    }
+
+    /// Returns the pruning horizon, or None if the state is not pruned
+    pub fn get_pruning_horizon(&self) -> Option<u64> {
+        self.pruning_horizon
+    }
+
+    /// Returns the maximum reorganisation depth, or None if reorganisations are not limited
+    pub fn get_max_reorg_depth(&self) -> Option<u64> {
+        self.max_reorg_depth
+    }
+
+    /// Returns the height below which blocks can not be reverted. In pruned mode the outputs spent by these blocks
+    /// have been discarded.
+    pub fn get_pruned_height(&self) -> u64 {
+        self.pruned_height
+    }
+
+    /// Returns the height of the chain tip, or None if no block has been added yet
+    pub fn get_tip_height(&self) -> Option<u64> {
+        (self.header_hashes.len() as u64).checked_sub(1)
+    }
+
+    /// Returns the header of the chain tip, or None if no block has been added yet
+    pub fn get_tip_header(&self) -> Option<&BlockHeader> {
+        self.header_hashes.last().and_then(|hash| self.headers.get_object(hash))
+    }
+
+    /// Returns the accumulated target difficulty of the main chain, which measures the work done to build it
+    pub fn get_accumulated_difficulty(&self) -> Difficulty {
+        self.accumulated_difficulty.last().cloned().unwrap_or_else(Difficulty::zero)
+    }
+
+    /// Returns the accumulated difficulty of the main chain up to and including the block at the given height, or None
+    /// if the main chain is not that long
+    pub fn get_accumulated_difficulty_at(&self, height: u64) -> Option<Difficulty> {
+        self.accumulated_difficulty.get(height as usize).cloned()
+    }
+
+    /// Returns the target difficulty the next block on the main chain must have. Miners use it to set the target of
+    /// the blocks they build. It is calculated with a linearly weighted moving average over the most recent blocks.
+    pub fn get_next_difficulty(&self) -> Difficulty {
+        calculate_difficulty(&self.consensus_constants, self.main_chain_headers())
+    }
+
+    /// Returns the header at the top of the best header chain: the last header added with `add_header` whose block
+    /// has not been added yet, or else the header of the chain tip
+    pub fn get_best_header(&self) -> Option<&BlockHeader> {
+        self.pending_headers.back().or_else(|| self.get_tip_header())
+    }
+
+    /// Returns the height of the best header chain, or None if no header has been added yet
+    pub fn get_best_header_height(&self) -> Option<u64> {
+        self.get_best_header().map(|header| header.height)
+    }
+
+    /// Returns the headers of the best header chain whose blocks have not been added yet, in ascending order of
+    /// height. Their blocks can be downloaded in parallel and must then be added in this order.
+    pub fn get_pending_headers(&self) -> impl Iterator<Item = &BlockHeader> {
+        self.pending_headers.iter()
+    }
+
+    /// Validate a header on top of the best header chain, without its block, and append it to the best header chain.
+    /// Only the header rules are checked: height, previous hash, timestamp, version and target difficulty. The block
+    /// bodies are validated when the blocks are added with `add_block`.
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<(), ChainError> {
+        let headers = || self.main_chain_headers().chain(self.pending_headers.iter());
+        let difficulty = calculate_difficulty(&self.consensus_constants, headers());
+        let median = median_timestamp(headers().rev().take(self.consensus_constants.median_timestamp_count));
+        validate_header_on(&self.consensus_constants, self.get_best_header(), &header, difficulty, median)?;
+        self.pending_headers.push_back(header);
+        Ok(())
+    }
+
+    /// Returns the height of the main chain block with the given header hash, or None if it is not on the main chain
+    pub fn get_block_height(&self, hash: &[u8]) -> Option<u64> {
+        self.header_hashes.iter().rposition(|h| h.as_slice() == hash).map(|height| height as u64)
+    }
+
+    /// Returns the main chain block at the given height
+    pub fn get_block(&self, height: u64) -> Result<Option<Block>, ChainError> {
+        Ok(self.backend.fetch_block(height)?)
+    }
+
+    /// Returns the number of unspent outputs
+    pub fn utxo_count(&self) -> usize {
+        self.utxos.len()
+    }
+
+    /// Returns the unspent output with the given commitment, or None if there is no such unspent output
+    pub fn fetch_utxo(&self, commitment: &Commitment) -> Option<&TransactionOutput> {
+        self.utxos.get(commitment.as_bytes()).and_then(|hash| self.outputs.get_object(hash))
+    }
+
+    /// Returns the unspent output with the given output hash, or None if the output is spent or unknown
+    pub fn fetch_utxo_by_hash(&self, hash: &ObjectHash) -> Option<&TransactionOutput> {
+        self.outputs.get_object(hash).filter(|output| self.utxos.get(output.commitment.as_bytes()) == Some(hash))
+    }
+
+    /// Returns true if an output with the given commitment is in the UTXO set
+    pub fn is_unspent(&self, commitment: &Commitment) -> bool {
+        self.utxos.contains_key(commitment.as_bytes())
+    }
+
+    /// Returns true if the output with the given hash was spent on the main chain
+    pub fn is_spent(&self, hash: &ObjectHash) -> bool {
+        self.output_leaves.get(hash).map_or(false, |&leaf_index| self.outputs.is_deleted(leaf_index))
+    }
+
+    /// Returns a page of at most `count` unspent outputs, ordered by commitment. The page starts after the output
+    /// with the commitment `after`, or at the beginning of the UTXO set when `after` is None. Pass the commitment of
+    /// the last output of a page to fetch the next page.
+    pub fn fetch_utxos(&self, after: Option<&Commitment>, count: usize) -> Vec<&TransactionOutput> {
+        let start = match after {
+            Some(commitment) => Bound::Excluded(commitment.as_bytes().to_vec()),
+            None => Bound::Unbounded,
+        };
+        self.utxos
+            .range((start, Bound::Unbounded))
+            .filter_map(|(_, hash)| self.outputs.get_object(hash))
+            .take(count)
+            .collect()
+    }
+
+    /// Export a snapshot of the state at the chain tip. In pruned mode the snapshot is as complete as one exported by
+    /// an archive node, since it only holds the hashes of spent outputs.
+    pub fn export_snapshot(&self) -> Snapshot {
+        match self.get_tip_height() {
+            Some(tip) => self.export_snapshot_at(tip).expect("The tip can always be exported"),
+            None => Snapshot::default(),
+        }
+    }
+
+    /// Export a snapshot of the state at the given height of the main chain. The state at a height is rebuilt by
+    /// undoing the blocks above it from their journals, so the height can not be below the pruned height, as with
+    /// `rewind_to_height`.
+    pub fn export_snapshot_at(&self, height: u64) -> Result<Snapshot, ChainError> {
+        if self.get_tip_height().map_or(true, |tip| height > tip) {
+            return Err(ChainError::HeightAboveTip);
+        }
+        if height + 1 < self.pruned_height {
+            return Err(ChainError::BeyondPruningHorizon);
+        }
+        let above = &self.journals[height as usize + 1..];
+        let kernel_count = above.first().map_or(self.kernels.get_leaf_count(), |journal| journal.kernel_leaf_count);
+        let output_count = match self.outputs.get_checkpoint(height as usize + 1) {
+            Some(checkpoint) => checkpoint.leaf_count,
+            None => self.outputs.get_mmr().get_leaf_count(),
+        };
+        let spent_leaves = (0..=height as usize)
+            .map(|height| self.outputs.get_checkpoint(height).map(|checkpoint| checkpoint.nodes_deleted.to_vec()))
+            .collect::<Option<Vec<_>>>()
+            .expect("There is a checkpoint for every block");
+        let mut utxos = self.utxos.clone();
+        for journal in above.iter().rev() {
+            for key in journal.created.iter() {
+                utxos.remove(key);
+            }
+            utxos.extend(journal.spent.iter().cloned());
+        }
+        let mut output_hashes = self.outputs.get_mmr().get_leaf_hashes().expect(IN_MEMORY);
+        output_hashes.truncate(output_count);
+        let kernels = self.kernels.iter_leaves().take(kernel_count).filter_map(|leaf| leaf.expect(IN_MEMORY).1);
+        Ok(Snapshot {
+            headers: self.main_chain_headers().take(height as usize + 1).cloned().collect(),
+            kernels: kernels.cloned().collect(),
+            output_hashes,
+            spent_leaves,
+            utxos: utxos.values().filter_map(|hash| self.outputs.get_object(hash)).cloned().collect(),
+        })
+    }
+
+    /// Validate the block against the current chain tip and UTXO set, and apply it if it is valid. The block is
+    /// applied to the MMRs and UTXO set, the resulting MMR roots are checked against the roots in its header and it is
+    /// then written to the backend. If the roots do not match or the write fails the changes are undone from the
+    /// block's journal, so the state is not modified if the block is rejected or cannot be stored. If the
+    /// block is not the next block of the best header chain, the pending headers no longer build on the tip and are
+    /// dropped. Subscribers are notified once the block has been added.
+    pub fn add_block(&mut self, block: Block) -> Result<(), ChainError> {
+        let spent = self.connect_block(&block)?;
+        self.publish_block(&block, spent);
+        self.publish_tip();
+        Ok(())
+    }
+
+    /// Calculate the output and kernel MMR roots that the header of a block with the given body, built on the current
+    /// tip, must commit to. Miners use this to complete the header of a block template.
+    pub fn calculate_roots(&self, body: &AggregateBody) -> MmrRoots {
+        let spent: Vec<usize> = body
+            .inputs
+            .iter()
+            .filter_map(|input| self.utxos.get(input.commitment.as_bytes()))
+            .filter_map(|hash| self.output_leaves.get(hash).cloned())
+            .collect();
+        let mut kernels = self.kernels.view();
+        body.kernels.iter().for_each(|kernel| kernels.add_object(kernel));
+        let mut roots = MmrRoots { output_mmr: BlockHash::default(), kernel_mmr: BlockHash::default() };
+        roots.output_mmr.copy_from_slice(&self.outputs.calculate_root(&body.outputs, &spent));
+        roots.kernel_mmr.copy_from_slice(&kernels.get_merkle_root());
+        roots
+    }
+
+    /// Validate and apply the block, returning the hashes of the outputs it spent
+    fn connect_block(&mut self, block: &Block) -> Result<Vec<ObjectHash>, ChainError> {
+        self.verify_and_apply(block)?;
+        let operations = self.push_operations(block);
+        if let Err(e) = self.write(operations) {
+            self.undo_tip();
+            return Err(e.into());
+        }
+        let journal = self.journals.last().expect("The block was just applied");
+        let spent = journal.spent.iter().map(|(_, hash)| hash.clone()).collect();
+        self.prune();
+        let hash = block.header.hash();
+        if self.pending_headers.front().map(Hashable::hash) == Some(hash) {
+            self.pending_headers.pop_front();
+        } else {
+            self.pending_headers.clear();
+        }
+        Ok(spent)
+    }
+
+    /// Validate the block and apply it to the MMRs and UTXO set, without writing it to the backend. If the MMR roots
+    /// in the header are not the roots with the block applied, the block is undone again.
+    fn verify_and_apply(&mut self, block: &Block) -> Result<(), BlockValidationError> {
+        self.validate_header(&block.header)?;
+        self.validate_body(block)?;
+        self.apply_block(block);
+        if let Err(e) = self.validate_roots(&block.header) {
+            self.undo_tip();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Rebuild the chain from the blocks stored in the backend, starting from its snapshot if it has one, fully
+    /// validating every block as it is replayed, and check that the rebuilt UTXO set and MMR roots match this state.
+    /// Operators can use this to audit their database after disk errors or upgrades. The state is not modified.
+    pub fn validate_chain_from_genesis(&self) -> Result<(), ChainError> {
+        let mut replay = BlockchainState::empty(MemoryBackend::new(), self.network)?
+            .with_consensus_constants(self.consensus_constants.clone());
+        if let Some(snapshot) = self.backend.fetch_snapshot()? {
+            replay.validate_snapshot_headers(&snapshot)?;
+            replay.restore_snapshot(&snapshot)?;
+        }
+        let first_height = replay.header_hashes.len() as u64;
+        for height in first_height..self.backend.block_count() {
+            let block = self.backend.fetch_block(height)?.ok_or(StoreError::MissingBlock)?;
+            replay.verify_and_apply(&block).map_err(|reason| AuditError::InvalidBlock { height, reason })?;
+        }
+        if replay.utxos != self.utxos {
+            return Err(AuditError::UtxoSetMismatch.into());
+        }
+        if replay.headers.get_merkle_root() != self.headers.get_merkle_root() ||
+            replay.outputs.get_merkle_root() != self.outputs.get_merkle_root() ||
+            replay.kernels.get_merkle_root() != self.kernels.get_merkle_root()
+        {
+            return Err(AuditError::MmrRootMismatch.into());
+        }
+        Ok(())
+    }
+
+    /// Send the events for a block added to the main chain
+    fn publish_block(&mut self, block: &Block, spent: Vec<ObjectHash>) {
+        let height = block.header.height;
+        for output in spent {
+            self.publish(BlockchainEvent::OutputSpent { height, output });
+        }
+        self.publish(BlockchainEvent::BlockAdded { height, hash: block.header.hash() });
+    }
+
+    /// Send the event for the current chain tip
+    fn publish_tip(&mut self) {
+        if let (Some(height), Some(hash)) = (self.get_tip_height(), self.header_hashes.last().cloned()) {
+            self.publish(BlockchainEvent::NewTip { height, hash });
+        }
+    }
+
+    /// Send the event to every subscriber, dropping the subscribers that have gone away
+    fn publish(&mut self, event: BlockchainEvent) {
+        self.subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    /// Replace the main chain above a fork point with a competing chain. The blocks must be in ascending order of
+    /// height and the first block must build on a block of the main chain. The competing chain must have strictly more
+    /// accumulated difficulty than the main chain; when both chains have done the same work the main chain, which was
+    /// seen first, is kept.
+    ///
+    /// The headers of the fork are validated, including their target difficulty and proof of work, before its
+    /// accumulated difficulty is compared, and before anything is disconnected. The reorganisation is atomic: the fork
+    /// is applied in memory, where the blocks above the fork point can always be undone from their journals, and is
+    /// only written to the backend, in a single write, once all of its blocks are valid. If any of the new blocks is
+    /// invalid, or the write fails, the original main chain is restored and the error is returned. A fork reverting
+    /// more blocks than the maximum reorganisation depth is refused. An invalid block of the fork is reported with
+    /// `ChainError::InvalidForkBlock`.
+    pub fn reorganize(&mut self, blocks: Vec<Block>) -> Result<Reorg, ChainError> {
+        let fork_height = blocks
+            .first()
+            .and_then(|first| {
+                let parent_height = first.header.height.checked_sub(1)?;
+                let parent_hash = self.header_hashes.get(parent_height as usize)?;
+                if parent_hash.as_slice() == &first.header.prev_hash[..] {
+                    Some(parent_height)
+                } else {
+                    None
+                }
+            })
+            .ok_or(BlockValidationError::ForkPointNotFound)?;
+        if fork_height + 1 < self.pruned_height {
+            return Err(BlockValidationError::ForkBeyondPruningHorizon.into());
+        }
+        self.validate_fork_headers(fork_height, &blocks)?;
+        let fork_difficulty = blocks.iter().fold(self.accumulated_difficulty[fork_height as usize], |acc, block| {
+            acc + block.header.pow.target_difficulty
+        });
+        if fork_difficulty <= self.get_accumulated_difficulty() {
+            return Err(BlockValidationError::ForkNotHeavier.into());
+        }
+        let depth = self.header_hashes.len() as u64 - fork_height - 1;
+        if self.max_reorg_depth.map_or(false, |max| depth > max) {
+            let hash = blocks.last().expect("The fork has a first block").header.hash();
+            self.publish(BlockchainEvent::DeepForkRejected { depth, hash });
+            return Err(ChainError::ReorgTooDeep { depth });
+        }
+
+        let reverted = ((fork_height + 1)..self.header_hashes.len() as u64)
+            .map(|height| self.backend.fetch_block(height)?.ok_or(StoreError::MissingBlock))
+            .collect::<Result<Vec<Block>, StoreError>>()?;
+        // Nothing is pruned until the reorganisation completes, so the journals of the blocks above the fork point
+        // are kept and every change below can be undone
+        let mut operations = Vec::new();
+        for block in reverted.iter().rev() {
+            operations.extend(self.pop_operations(block));
+            self.undo_tip();
+        }
+        let mut spent = Vec::with_capacity(blocks.len());
+        for block in blocks.iter() {
+            if let Err(reason) = self.verify_and_apply(block) {
+                self.restore_main_chain(fork_height, &reverted);
+                return Err(ChainError::InvalidForkBlock { hash: block.header.hash(), reason });
+            }
+            operations.extend(self.push_operations(block));
+            let journal = self.journals.last().expect("The block was just applied");
+            spent.push(journal.spent.iter().map(|(_, hash)| hash.clone()).collect::<Vec<_>>());
+        }
+        if let Err(e) = self.write(operations) {
+            self.restore_main_chain(fork_height, &reverted);
+            return Err(e.into());
+        }
+        self.pending_headers.clear();
+        self.prune();
+        for (block, block_spent) in blocks.iter().zip(spent) {
+            self.publish_block(block, block_spent);
+        }
+        self.publish(BlockchainEvent::Reorged { depth: reverted.len() as u64 });
+        self.publish_tip();
+        Ok(Reorg { reverted, applied: blocks })
+    }
+
+    /// Check that the headers of the fork form a chain on the main chain block at the fork height, as they would be
+    /// checked when their blocks are added, without modifying the state
+    fn validate_fork_headers(&self, fork_height: u64, blocks: &[Block]) -> Result<(), ChainError> {
+        let constants = &self.consensus_constants;
+        // Only the headers within the difficulty window and the median timestamp window are needed
+        let window = (constants.difficulty_block_window + 1).max(constants.median_timestamp_count);
+        let start = (fork_height as usize + 1).saturating_sub(window);
+        let mut headers: Vec<BlockHeader> =
+            self.main_chain_headers().take(fork_height as usize + 1).skip(start).cloned().collect();
+        for block in blocks.iter() {
+            validate_next_header(constants, &headers, &block.header)
+                .map_err(|reason| ChainError::InvalidForkBlock { hash: block.header.hash(), reason })?;
+            headers.push(block.header.clone());
+        }
+        Ok(())
+    }
+
+    /// Undo the blocks above the fork point in memory and apply the reverted blocks of the main chain again. The
+    /// reverted blocks were valid when they were first added and no journal above the fork point has been pruned, so
+    /// this can not fail.
+    fn restore_main_chain(&mut self, fork_height: u64, reverted: &[Block]) {
+        while self.get_tip_height().map_or(false, |tip| tip > fork_height) {
+            self.undo_tip();
+        }
+        for block in reverted.iter() {
+            self.apply_block(block);
+        }
+    }
+
+    /// Remove all blocks above the given height from the main chain, returning them in ascending order of height. Each
+    /// block is removed from the backend and its journal is used to undo its changes to the MMRs and UTXO set. The
+    /// pending headers are dropped, since they build on the removed blocks. Blocks below the pruned height can not be
+    /// removed.
+    pub fn rewind_to_height(&mut self, height: u64) -> Result<Vec<Block>, ChainError> {
+        let reverted = self.disconnect_to_height(height)?;
+        if !reverted.is_empty() {
+            self.publish_tip();
+        }
+        Ok(reverted)
+    }
+
+    /// Remove all blocks above the given height from the main chain without notifying the subscribers
+    fn disconnect_to_height(&mut self, height: u64) -> Result<Vec<Block>, ChainError> {
+        if height + 1 < self.pruned_height {
+            return Err(ChainError::BeyondPruningHorizon);
+        }
+        self.pending_headers.clear();
+        let mut reverted = Vec::new();
+        while self.get_tip_height().map_or(false, |tip| tip > height) {
+            reverted.push(self.revert_tip()?);
+        }
+        reverted.reverse();
+        Ok(reverted)
+    }
+
+    /// The headers of the main chain, in ascending order of height
+    fn main_chain_headers(&self) -> impl DoubleEndedIterator<Item = &BlockHeader> {
+        self.header_hashes.iter().filter_map(move |hash| self.headers.get_object(hash))
+    }
+
+    /// Check that the header builds on the current chain tip. The first block added must be the genesis block.
+    fn validate_header(&self, header: &BlockHeader) -> Result<(), BlockValidationError> {
+        let median = self.median_timestamps.last().cloned();
+        validate_header_on(&self.consensus_constants, self.get_tip_header(), header, self.get_next_difficulty(), median)
+    }
+
+    /// Check that the body is sorted, that every input spends a distinct unspent output that has matured, counting
+    /// its relative maturity from the block that created it, and whose script it satisfies, that no output is already
+    /// in the UTXO set or, spent or not, in the output MMR, and finally the checks of
+    /// `Block::check_internal_consistency`, which do not depend on the UTXO set. The block is the next block on the
+    /// main chain.
+    fn validate_body(&self, block: &Block) -> Result<(), BlockValidationError> {
+        let body = &block.body;
+        // Checked first, so that a body in any other order is rejected before its contents are looked at
+        body.verify_sorting()?;
+        let height = self.header_hashes.len() as u64;
+
+        let mut spent = HashSet::new();
+        for input in body.inputs.iter() {
+            let key = input.commitment.as_bytes().to_vec();
+            let utxo = self.utxos.get(&key).and_then(|hash| Some((hash, self.outputs.get_object(hash)?)));
+            let (hash, output) = match utxo {
+                Some(utxo) => utxo,
+                None if self.spent_commitments.contains_key(&key) => {
+                    return Err(BlockValidationError::DoubleSpend { input: input.hash() });
+                },
+                None => return Err(BlockValidationError::UnknownInput { input: input.hash() }),
+            };
+            // The maturity is read from the output being spent, as the features of the input are set by the spender
+            let created_at = self.output_heights.get(hash).cloned().unwrap_or_default();
+            if !output.features.is_spendable_at(height, created_at) {
+                return Err(BlockValidationError::ImmatureInput { input: input.hash() });
+            }
+            if let Some(script) = &output.script {
+                if script.execute(input, &body.kernels, height).is_err() {
+                    return Err(BlockValidationError::ScriptFailed { input: input.hash() });
+                }
+            }
+            if !spent.insert(key) {
+                return Err(BlockValidationError::DoubleSpend { input: input.hash() });
+            }
+        }
+
+        let mut created = HashSet::new();
+        for output in body.outputs.iter() {
+            let key = output.commitment.as_bytes().to_vec();
+            let known = self.utxos.contains_key(&key) || self.output_leaves.contains_key(&output.hash());
+            if known || !created.insert(key) {
+                return Err(BlockValidationError::DuplicateOutput { output: output.hash() });
+            }
+        }
+
+        let result = block.check_internal_consistency(self.get_tip_header(), &self.consensus_constants);
+        result.map_err(|e| match e {
+            TransactionError::MaxWeightExceeded => BlockValidationError::BlockTooHeavy,
+            TransactionError::InsufficientFee => BlockValidationError::InsufficientFee,
+            TransactionError::InvalidLockHeight => BlockValidationError::InvalidLockHeight,
+            TransactionError::InvalidCoinbase | TransactionError::MissingCoinbase => {
+                BlockValidationError::InvalidCoinbase
+            },
+            TransactionError::ValidationError => BlockValidationError::KernelSumMismatch,
+            TransactionError::InvalidRangeProof => {
+                let output = body.outputs.iter().find(|output| output.verify_range_proof().is_err());
+                BlockValidationError::InvalidRangeProof { output: output.map(Hashable::hash).unwrap_or_default() }
+            },
+            e => e.into(),
+        })
+    }
+
+    /// Check that the roots of the output and kernel MMRs, with the block applied, match the roots in its header
+    fn validate_roots(&self, header: &BlockHeader) -> Result<(), BlockValidationError> {
+        if self.outputs.get_merkle_root()[..] != header.output_mmr[..] {
+            return Err(BlockValidationError::BadMerkleRoot { which: MerkleRoot::Output });
+        }
+        if self.kernels.get_merkle_root()[..] != header.kernel_mmr[..] {
+            return Err(BlockValidationError::BadMerkleRoot { which: MerkleRoot::Kernel });
+        }
+        Ok(())
+    }
+
+    /// Append the block to the MMRs and update the UTXO set, recording the changes in the block's journal. The block
+    /// must already have been validated.
+    fn apply_block(&mut self, block: &Block) {
+        let mut journal = BlockJournal {
+            kernel_leaf_count: self.kernels.get_leaf_count(),
+            spent: Vec::with_capacity(block.body.inputs.len()),
+            created: Vec::with_capacity(block.body.outputs.len()),
+        };
+        for input in block.body.inputs.iter() {
+            let key = input.commitment.as_bytes().to_vec();
+            if let Some(hash) = self.utxos.remove(&key) {
+                if let Some(&leaf_index) = self.output_leaves.get(&hash) {
+                    self.outputs.delete(leaf_index);
+                }
+                *self.spent_commitments.entry(key.clone()).or_insert(0) += 1;
+                journal.spent.push((key, hash));
+            }
+        }
+        let height = self.header_hashes.len() as u64;
+        for output in block.body.outputs.iter() {
+            let key = output.commitment.as_bytes().to_vec();
+            self.utxos.insert(key.clone(), output.hash());
+            self.output_heights.insert(output.hash(), height);
+            journal.created.push(key);
+            let leaf_index = self.outputs.push(*output).expect(IN_MEMORY);
+            self.output_leaves.insert(output.hash(), leaf_index);
+        }
+        self.outputs.commit_checkpoint().expect(IN_MEMORY);
+        self.kernels.add_vec(block.body.kernels.clone()).expect(IN_MEMORY);
+        self.push_header(block.header.clone());
+        self.journals.push(journal);
+    }
+
+    /// Undo the changes the tip block made to the MMRs and UTXO set, using its journal
+    fn undo_tip(&mut self) {
+        let journal = self.journals.pop().expect("Every applied block has a journal");
+        self.header_hashes.pop();
+        self.accumulated_difficulty.pop();
+        self.median_timestamps.pop();
+        self.headers.rewind(self.header_hashes.len()).expect(IN_MEMORY);
+        self.outputs.rewind_checkpoints(self.journals.len()).expect(IN_MEMORY);
+        self.kernels.rewind(journal.kernel_leaf_count).expect(IN_MEMORY);
+        for key in journal.created.iter() {
+            if let Some(hash) = self.utxos.remove(key) {
+                self.output_heights.remove(&hash);
+                self.output_leaves.remove(&hash);
+            }
+        }
+        for (key, _) in journal.spent.iter() {
+            if let Some(count) = self.spent_commitments.get_mut(key) {
+                *count -= 1;
+                if *count == 0 {
+                    self.spent_commitments.remove(key);
+                }
+            }
+        }
+        self.utxos.extend(journal.spent);
+    }
+
+    /// Append the header to the header MMR and the records indexed by height
+    fn push_header(&mut self, header: BlockHeader) {
+        self.headers.add_single(header.clone()).expect(IN_MEMORY);
+        self.index_header(&header);
+    }
+
+    /// Append the header, which must be the last leaf of the header MMR, to the records indexed by height
+    fn index_header(&mut self, header: &BlockHeader) {
+        let accumulated_difficulty = self.get_accumulated_difficulty() + header.pow.target_difficulty;
+        self.accumulated_difficulty.push(accumulated_difficulty);
+        self.header_hashes.push(header.hash());
+        let count = self.consensus_constants.median_timestamp_count;
+        let median = median_timestamp(self.main_chain_headers().rev().take(count));
+        self.median_timestamps.push(median.expect("The header was just added"));
+    }
+
+    /// In pruned mode, discard the outputs spent by the blocks that have dropped below the pruning horizon, along
+    /// with the records needed to revert those blocks. The outputs are removed from the backend by the next write.
+    fn prune(&mut self) {
+        let (horizon, tip) = match (self.pruning_horizon, self.get_tip_height()) {
+            (Some(horizon), Some(tip)) => (horizon, tip),
+            _ => return,
+        };
+        let horizon_height = match (tip + 1).checked_sub(horizon) {
+            Some(height) => height,
+            None => return,
+        };
+        while self.pruned_height < horizon_height {
+            for (_, hash) in self.journals[self.pruned_height as usize].spent.drain(..) {
+                self.outputs.prune_object(&hash);
+                self.output_heights.remove(&hash);
+                self.pruned_outputs.push(hash);
+            }
+            self.pruned_height += 1;
+        }
+    }
+
+    /// Check that the headers of the snapshot form a valid header chain from the genesis block of the network
+    fn validate_snapshot_headers(&self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        let genesis = snapshot.headers.first().ok_or(SnapshotError::EmptySnapshot)?;
+        if self.network.genesis_block().map_or(false, |g| g.header.hash() != genesis.hash()) {
+            return Err(SnapshotError::InvalidHeaderChain);
+        }
+        for (height, header) in snapshot.headers.iter().enumerate() {
+            validate_next_header(&self.consensus_constants, &snapshot.headers[..height], header)
+                .map_err(|reason| SnapshotError::InvalidHeader { height: height as u64, reason })?;
+        }
+        Ok(())
+    }
+
+    /// Rebuild the MMRs and UTXO set of an empty state from the snapshot, verifying it against the headers. The
+    /// headers must have been validated with `validate_snapshot_headers` when the snapshot was imported. The blocks
+    /// in the snapshot can not be reverted.
+    fn restore_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        let tip = snapshot.headers.last().ok_or(SnapshotError::EmptySnapshot)?;
+        if snapshot.spent_leaves.len() != snapshot.headers.len() {
+            return Err(SnapshotError::InvalidSpentOutput);
+        }
+        let mut utxos: HashMap<ObjectHash, &TransactionOutput> =
+            snapshot.utxos.iter().map(|output| (output.hash(), output)).collect();
+        // Each block spends its outputs and then adds the leaves up to the output root in its header, which is checked
+        // for every block and gives the heights that relative time locks count from
+        let mut hashes = snapshot.output_hashes.iter();
+        for (height, (header, spent)) in snapshot.headers.iter().zip(snapshot.spent_leaves.iter()).enumerate() {
+            for &leaf_index in spent.iter() {
+                if !self.outputs.delete(leaf_index as usize) {
+                    return Err(SnapshotError::InvalidSpentOutput);
+                }
+                if self.outputs.get_object(&snapshot.output_hashes[leaf_index as usize]).is_some() {
+                    return Err(SnapshotError::UtxoSetMismatch);
+                }
+            }
+            while self.outputs.get_merkle_root()[..] != header.output_mmr[..] {
+                let hash = hashes.next().ok_or(SnapshotError::OutputRootMismatch)?;
+                if self.output_leaves.contains_key(hash) {
+                    return Err(SnapshotError::DuplicateOutput);
+                }
+                let leaf_index = match utxos.remove(hash) {
+                    Some(output) => {
+                        self.utxos.insert(output.commitment.as_bytes().to_vec(), hash.clone());
+                        self.output_heights.insert(hash.clone(), height as u64);
+                        self.outputs.push(*output)
+                    },
+                    None => self.outputs.push_pruned(hash.clone()),
+                };
+                self.output_leaves.insert(hash.clone(), leaf_index.expect(IN_MEMORY));
+            }
+            self.outputs.commit_checkpoint().expect(IN_MEMORY);
+        }
+        if hashes.next().is_some() {
+            return Err(SnapshotError::OutputRootMismatch);
+        }
+        if !utxos.is_empty() {
+            return Err(SnapshotError::UnknownOutput);
+        }
+        // Every unspent leaf must come with its output, once, so that the UTXO set is complete
+        if self.utxos.len() != self.outputs.get_unspent_count() {
+            return Err(SnapshotError::UtxoSetMismatch);
+        }
+        self.kernels.add_vec_parallel(snapshot.kernels.clone()).expect(IN_MEMORY);
+        if self.kernels.get_merkle_root()[..] != tip.kernel_mmr[..] {
+            return Err(SnapshotError::KernelRootMismatch);
+        }
+
+        for header in snapshot.headers.iter() {
+            self.push_header(header.clone());
+            self.journals.push(BlockJournal::default());
+        }
+        self.pruned_height = snapshot.headers.len() as u64;
+        Ok(())
+    }
+
+    /// Undo the changes the tip block made to the MMRs and UTXO set and remove it from the backend. If the write fails
+    /// the block is applied again.
+    fn revert_tip(&mut self) -> Result<Block, ChainError> {
+        let height = self.get_tip_height().ok_or(StoreError::MissingBlock)?;
+        let block = self.backend.fetch_block(height)?.ok_or(StoreError::MissingBlock)?;
+        let operations = self.pop_operations(&block);
+        self.undo_tip();
+        if let Err(e) = self.write(operations) {
+            self.apply_block(&block);
+            return Err(e.into());
+        }
+        Ok(block)
+    }
+
+    /// Returns the record of the main chain block at the given height
+    fn block_record(&self, height: usize) -> BlockRecord {
+        let checkpoint = self.outputs.get_checkpoint(height).expect("There is a checkpoint for every block");
+        BlockRecord {
+            header: self.headers.get_object(&self.header_hashes[height]).expect("Headers are never pruned").clone(),
+            kernel_leaf_count: self.journals[height].kernel_leaf_count as u64,
+            output_leaf_count: checkpoint.leaf_count as u64,
+            spent_leaves: checkpoint.nodes_deleted.to_vec(),
+        }
+    }
+
+    /// Returns the operations that store the tip block once it has been applied: the block with its record, and the
+    /// outputs and kernels it added
+    fn push_operations(&self, block: &Block) -> Vec<WriteOperation> {
+        let record = Box::new(self.block_record(self.header_hashes.len() - 1));
+        let kernel_leaf_count = record.kernel_leaf_count;
+        let mut operations = vec![WriteOperation::PushBlock { record, block: Some(Box::new(block.clone())) }];
+        operations.extend(block.body.outputs.iter().map(|output| WriteOperation::InsertOutput(Box::new(*output))));
+        for (index, kernel) in block.body.kernels.iter().enumerate() {
+            let leaf_index = kernel_leaf_count + index as u64;
+            operations.push(WriteOperation::InsertKernel(leaf_index, Box::new(kernel.clone())));
+        }
+        operations
+    }
+
+    /// Returns the operations that remove the tip block from the backend, along with the outputs and kernels it added.
+    /// They are built before the block is undone.
+    fn pop_operations(&self, block: &Block) -> Vec<WriteOperation> {
+        let journal = self.journals.last().expect("Every applied block has a journal");
+        let mut operations = vec![WriteOperation::PopBlock];
+        operations.extend(block.body.outputs.iter().map(|output| WriteOperation::DeleteOutput(output.hash())));
+        let kernels = journal.kernel_leaf_count as u64..self.kernels.get_leaf_count() as u64;
+        operations.extend(kernels.map(WriteOperation::DeleteKernel));
+        operations
+    }
+
+    /// Write the operations to the backend, along with the changes to the MMR nodes, the removal of the outputs
+    /// discarded by pruning and the pruned height, in a single write
+    fn write(&mut self, mut operations: Vec<WriteOperation>) -> Result<(), StoreError> {
+        operations.extend(self.pruned_outputs.iter().cloned().map(WriteOperation::DeleteOutput));
+        operations.push(WriteOperation::SetPrunedHeight(self.pruned_height));
+        let mut mmrs = [self.headers.get_backend_mut(), self.kernels.get_backend_mut(), self.outputs.get_backend_mut()];
+        self.backend.write(operations, &mut mmrs)?;
+        self.pruned_outputs.clear();
+        Ok(())
+    }
+}
+
+/// Check that the header is the next header of the chain of headers before it, which starts at the genesis block, with
+/// the checks of a header added to the main chain: its target difficulty must be the difficulty calculated from the
+/// headers before it, and its proof of work must meet it. Headers that arrive without the blocks they commit to, in a
+/// snapshot or from a horizon sync server, are validated with it, so that the accumulated difficulty of the chain they
+/// form can be trusted.
+pub(crate) fn validate_next_header(
+    constants: &ConsensusConstants,
+    headers: &[BlockHeader],
+    header: &BlockHeader,
+) -> Result<(), BlockValidationError>
+{
+    let difficulty = calculate_difficulty(constants, headers.iter());
+    let median = median_timestamp(headers.iter().rev().take(constants.median_timestamp_count));
+    validate_header_on(constants, headers.last(), header, difficulty, median)
+}
+
+/// Returns the target difficulty of the block following the given chain of headers, calculated with a linearly
+/// weighted moving average over the most recent headers
+fn calculate_difficulty<'a, I>(constants: &ConsensusConstants, headers: I) -> Difficulty
+where I: DoubleEndedIterator<Item = &'a BlockHeader> {
+    let mut lwma = constants.difficulty_adjustment();
+    let window = constants.difficulty_block_window;
+    let recent: Vec<&BlockHeader> = headers.rev().take(window + 1).collect();
+    for header in recent.into_iter().rev() {
+        lwma.add(header.timestamp, header.pow.target_difficulty);
+    }
+    lwma.get_difficulty()
+}
+
+/// Check that the header builds on its parent and has the expected target difficulty, which its proof of work must
+/// meet. Its timestamp must be later than the median timestamp of the most recent headers of the chain it builds
+/// on, and no further in the future than the network allows. A header without a parent must be a genesis header.
+fn validate_header_on(
+    constants: &ConsensusConstants,
+    parent: Option<&BlockHeader>,
+    header: &BlockHeader,
+    difficulty: Difficulty,
+    median_timestamp: Option<DateTime<Utc>>,
+) -> Result<(), BlockValidationError>
+{
+    if header.version != constants.blockchain_version(header.height) {
+        return Err(BlockValidationError::InvalidVersion);
+    }
+    let parent = match parent {
+        Some(parent) => parent,
+        None if header.height == 0 => return Ok(()),
+        None => return Err(BlockValidationError::InvalidHeight),
+    };
+    if header.height != parent.height + 1 {
+        return Err(BlockValidationError::InvalidHeight);
+    }
+    if header.prev_hash[..] != parent.hash()[..] {
+        return Err(BlockValidationError::InvalidPreviousHash);
+    }
+    let future_limit = Utc::now() + Duration::seconds(constants.future_time_limit as i64);
+    if median_timestamp.map_or(false, |median| header.timestamp <= median) || header.timestamp > future_limit {
+        return Err(BlockValidationError::TimestampOutOfRange);
+    }
+    if header.pow.target_difficulty != difficulty {
+        return Err(BlockValidationError::InvalidDifficulty);
+    }
+    if !header.validate_pow() {
+        return Err(BlockValidationError::BadPoW);
+    }
+    Ok(())
+}
+
+/// Returns the median timestamp of the given headers, or None if there are none. With an even number of headers the
+/// later of the two middle timestamps is used.
+pub(crate) fn median_timestamp<'a, I>(headers: I) -> Option<DateTime<Utc>>
+where I: Iterator<Item = &'a BlockHeader> {
+    let mut timestamps: Vec<DateTime<Utc>> = headers.map(|header| header.timestamp).collect();
+    timestamps.sort();
+    timestamps.get(timestamps.len() / 2).cloned()
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::store::LMDBBackend;
+    use crypto::{
+        commitment::HomomorphicCommitmentFactory,
+        keys::{PublicKey as PublicKeyTrait, SecretKey},
+        ristretto::RistrettoSecretKey,
+    };
+    use rand::{OsRng, RngCore};
+    use std::{cell::RefCell, fs};
+    use tari_core::{
+        block::WEIGHT_PER_OUTPUT,
+        coinbase::CoinbaseBuilder,
+        consensus::{Network, VersionUpgrade},
+        pow::ProofOfWork,
+        range_proof::RangeProof,
+        script::{sign_script, Script},
+        transaction::{build_kernel_challenge, KernelFeatures, OutputFeatures, TransactionError, TransactionInput},
+        types::{BlindingFactor, CommitmentFactory, PublicKey, Signature},
+    };
+
+    pub(crate) fn create_kernel(rng: &mut OsRng) -> TransactionKernel {
+        let excess_key = BlindingFactor::random(rng);
+        sign_kernel(rng, excess_key, KernelFeatures::empty(), 0)
+    }
+
+    /// Create a kernel paying the given fee, for bodies that spend the value of their inputs on fees
+    pub(crate) fn create_fee_kernel(rng: &mut OsRng, fee: u64) -> TransactionKernel {
+        let excess_key = BlindingFactor::random(rng);
+        sign_kernel(rng, excess_key, KernelFeatures::empty(), fee)
+    }
+
+    fn sign_kernel(
+        rng: &mut OsRng,
+        excess_key: BlindingFactor,
+        features: KernelFeatures,
+        fee: u64,
+    ) -> TransactionKernel
+    {
+        let nonce = BlindingFactor::random(rng);
+        let excess = CommitmentFactory::create(&excess_key, &RistrettoSecretKey::default());
+        let challenge =
+            build_kernel_challenge(&PublicKey::from_secret_key(&nonce), excess.as_public_key(), features, fee, 0);
+        let excess_sig = Signature::sign(excess_key, nonce, challenge).unwrap();
+        remember_blinding_factor(&excess, excess_key);
+        TransactionKernel { features, fee, lock_height: 0, excess: Some(excess), excess_sig: Some(excess_sig) }
+    }
+
+    /// Create a coinbase output worth `value` and the kernel balancing it, for the block at the given height
+    pub(crate) fn create_coinbase(
+        rng: &mut OsRng,
+        value: u64,
+        height: u64,
+        lock_height: u64,
+    ) -> (TransactionOutput, TransactionKernel)
+    {
+        let spend_key = BlindingFactor::random(rng);
+        let (output, kernel) = CoinbaseBuilder::new()
+            .with_block_height(height)
+            .with_coinbase_lock_height(lock_height)
+            .with_reward(value)
+            .with_spend_key(spend_key)
+            .with_nonce(BlindingFactor::random(rng))
+            .build()
+            .unwrap();
+        remember_blinding_factor(&output.commitment, spend_key);
+        remember_blinding_factor(&kernel.excess.unwrap(), spend_key);
+        (output, kernel)
+    }
+
+    /// Add a coinbase claiming the block reward of the local network plus the fees of the body, unless the body
+    /// already has a coinbase output or kernel
+    fn add_coinbase(body: AggregateBody, height: u64) -> AggregateBody {
+        if body.outputs.iter().any(|output| output.features.is_coinbase()) ||
+            body.kernels.iter().any(|kernel| kernel.features.contains(KernelFeatures::COINBASE_KERNEL))
+        {
+            return body;
+        }
+        let fees: u64 = body.kernels.iter().map(|kernel| kernel.fee).sum();
+        let constants = Network::LocalNet.consensus_constants();
+        let reward = constants.emission_schedule().block_reward(height);
+        let lock_height = constants.coinbase_lock_height;
+        let (output, kernel) = create_coinbase(&mut OsRng::new().unwrap(), reward + fees, height, lock_height);
+        body.add_output(output).add_kernel(kernel)
+    }
+
+    pub(crate) fn create_output(rng: &mut OsRng, value: u64) -> TransactionOutput {
+        let key = BlindingFactor::random(rng);
+        let commitment = CommitmentFactory::create(&key, &RistrettoSecretKey::from(value));
+        remember_blinding_factor(&commitment, key);
+        TransactionOutput::new(OutputFeatures::empty(), commitment, RangeProof([0; 1]))
+    }
+
+    pub(crate) fn spend(output: &TransactionOutput) -> TransactionInput {
+        TransactionInput::new(output.features, output.commitment)
+    }
+
+    thread_local! {
+        /// The blinding factors of the outputs and kernel excesses made by the helpers, keyed by commitment, so that
+        /// `create_block` can set the offset that balances the blinding factors of a body
+        static BLINDING_FACTORS: RefCell<HashMap<Vec<u8>, BlindingFactor>> = RefCell::new(HashMap::new());
+    }
+
+    fn remember_blinding_factor(commitment: &Commitment, key: BlindingFactor) {
+        BLINDING_FACTORS.with(|keys| keys.borrow_mut().insert(commitment.as_bytes().to_vec(), key));
+    }
+
+    /// The offset that balances the blinding factors of the outputs against those of the inputs and kernel excesses.
+    /// The values of the body must already balance for it to pass the kernel sum check.
+    pub(crate) fn balancing_offset(body: &AggregateBody) -> BlindingFactor {
+        BLINDING_FACTORS.with(|keys| {
+            let keys = keys.borrow();
+            let sum = |commitments: Vec<&Commitment>| {
+                commitments.into_iter().fold(BlindingFactor::default(), |sum, commitment| {
+                    &sum + &keys.get(commitment.as_bytes()).cloned().unwrap_or_default()
+                })
+            };
+            let outputs = sum(body.outputs.iter().map(|output| &output.commitment).collect());
+            let inputs = sum(body.inputs.iter().map(|input| &input.commitment).collect());
+            let excesses = sum(body.kernels.iter().filter_map(|kernel| kernel.excess.as_ref()).collect());
+            &(&outputs - &inputs) - &excesses
+        })
+    }
+
+    /// The MMR leaves of a chain: the hash and commitment bytes of every output with the leaf indices of the spent
+    /// outputs, and the hash of every kernel
+    #[derive(Clone, Default)]
+    struct Leaves {
+        outputs: Vec<(ObjectHash, Vec<u8>)>,
+        spent: HashSet<usize>,
+        kernels: Vec<ObjectHash>,
+    }
+
+    thread_local! {
+        /// The MMR leaves once each block made by `create_block` is applied, keyed by the MMR roots in its header, so
+        /// that blocks can be built on any block and not only on the tip of a state
+        static LEAVES: RefCell<HashMap<(BlockHash, BlockHash), Leaves>> = RefCell::new(HashMap::new());
+    }
+
+    /// Calculate the root of an output MMR with the given leaves
+    fn output_root(leaves: &Leaves) -> BlockHash {
+        let mut mmr = PrunedMmr::<TransactionOutput, MmrHasher>::new();
+        for (hash, _) in leaves.outputs.iter() {
+            mmr.push_pruned(hash.clone()).expect(IN_MEMORY);
+        }
+        leaves.spent.iter().for_each(|&leaf_index| assert!(mmr.delete(leaf_index)));
+        let mut root = BlockHash::default();
+        root.copy_from_slice(&mmr.get_merkle_root());
+        root
+    }
+
+    /// Calculate the root of an MMR with the given leaf hashes
+    fn mmr_root<T, I>(leaves: I) -> BlockHash
+    where
+        T: Hashable,
+        I: IntoIterator<Item = ObjectHash>,
+    {
+        let mut mmr = MerkleMountainRange::<T, MmrHasher>::new();
+        for hash in leaves {
+            mmr.add_pruned(hash).expect(IN_MEMORY);
+        }
+        let mut root = BlockHash::default();
+        root.copy_from_slice(&mmr.get_merkle_root());
+        root
+    }
+
+    pub(crate) fn create_block(prev: Option<&BlockHeader>, body: AggregateBody) -> Block {
+        let mut prev_hash = [0u8; 32];
+        let (height, timestamp) = match prev {
+            Some(prev) => {
+                prev_hash.copy_from_slice(&prev.hash());
+                (prev.height + 1, prev.timestamp + Duration::minutes(1))
+            },
+            None => (0, Utc::now() - Duration::hours(1)),
+        };
+        let mut body = if height > 0 { add_coinbase(body, height) } else { body };
+        body.sort();
+        let prev_offset = prev.map(|prev| prev.total_kernel_offset).unwrap_or_default();
+        let mut leaves = prev
+            .and_then(|prev| LEAVES.with(|leaves| leaves.borrow().get(&(prev.output_mmr, prev.kernel_mmr)).cloned()))
+            .unwrap_or_default();
+        // An input spends the unspent output with its commitment, if there is one
+        for input in body.inputs.iter() {
+            let commitment = input.commitment.as_bytes();
+            let spent = &leaves.spent;
+            let leaf = leaves.outputs.iter().rposition(|(_, c)| c.as_slice() == commitment);
+            if let Some(leaf_index) = leaf.filter(|leaf_index| !spent.contains(leaf_index)) {
+                leaves.spent.insert(leaf_index);
+            }
+        }
+        let outputs = body.outputs.iter().map(|output| (output.hash(), output.commitment.as_bytes().to_vec()));
+        leaves.outputs.extend(outputs);
+        leaves.kernels.extend(body.kernels.iter().map(Hashable::hash));
+        let output_mmr = output_root(&leaves);
+        let kernel_mmr = mmr_root::<TransactionKernel, _>(leaves.kernels.iter().cloned());
+        LEAVES.with(|all_leaves| all_leaves.borrow_mut().insert((output_mmr, kernel_mmr), leaves));
+        let header = BlockHeader {
+            version: 0,
+            height,
+            prev_hash,
+            timestamp,
+            output_mmr,
+            kernel_mmr,
+            total_kernel_offset: &prev_offset + &balancing_offset(&body),
+            pow: ProofOfWork::new(Difficulty::min()),
+        };
+        Block::new(header, body)
+    }
+
+    #[test]
+    fn add_blocks() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        assert_eq!(state.get_tip_height(), None);
+
+        let coinbase = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+        assert_eq!(state.get_tip_height(), Some(0));
+        assert_eq!(state.utxo_count(), 1);
+
+        let body = AggregateBody::new(
+            vec![spend(&coinbase)],
+            vec![create_output(&mut rng, 60), create_output(&mut rng, 40)],
+            vec![create_kernel(&mut rng)],
+        );
+        let block = create_block(state.get_tip_header(), body);
+        let roots = state.calculate_roots(&block.body);
+        assert_eq!((roots.output_mmr, roots.kernel_mmr), (block.header.output_mmr, block.header.kernel_mmr));
+        state.add_block(block).unwrap();
+        assert_eq!(state.get_tip_height(), Some(1));
+        assert_eq!(state.utxo_count(), 3);
+    }
+
+    #[test]
+    fn network_genesis() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::with_backend(MemoryBackend::new(), Network::MainNet).unwrap();
+        let genesis = Network::MainNet.genesis_block().unwrap();
+        assert_eq!(state.get_tip_height(), Some(0));
+        assert_eq!(state.get_tip_header().unwrap().hash(), genesis.header.hash());
+        assert_ne!(Network::TestNet.genesis_block().unwrap().header.hash(), genesis.header.hash());
+        assert_eq!(state.get_block(0).unwrap().unwrap().header.hash(), genesis.header.hash());
+
+        let reward = state.get_consensus_constants().emission_schedule().block_reward(1);
+        let lock_height = state.get_consensus_constants().coinbase_lock_height;
+        let (coinbase, coinbase_kernel) = create_coinbase(&mut rng, reward, 1, lock_height);
+        let body = AggregateBody::new(vec![], vec![coinbase], vec![coinbase_kernel]);
+        let mut block = create_block(state.get_tip_header(), body);
+        block.header.version = 1;
+        assert_eq!(state.add_block(block.clone()), Err(BlockValidationError::InvalidVersion.into()));
+        block.header.version = state.get_consensus_constants().blockchain_version(1);
+        state.add_block(block).unwrap();
+        assert_eq!(state.get_tip_height(), Some(1));
+
+        let constants = ConsensusConstants { blockchain_version: 1, ..Network::LocalNet.consensus_constants() };
+        let mut state = BlockchainState::new().with_consensus_constants(constants);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![], vec![create_kernel(&mut rng)]));
+        assert_eq!(state.add_block(genesis), Err(BlockValidationError::InvalidVersion.into()));
+
+        // An upgrade requires the new version from its height onwards
+        let upgrade = VersionUpgrade { height: 2, blockchain_version: 1, transaction_version: 1 };
+        let constants =
+            ConsensusConstants { version_upgrades: vec![upgrade], ..Network::LocalNet.consensus_constants() };
+        assert_eq!(constants.transaction_version(1), 0);
+        assert_eq!(constants.transaction_version(2), 1);
+        let mut state = BlockchainState::new().with_consensus_constants(constants);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+        state.add_block(create_block(state.get_tip_header(), AggregateBody::empty())).unwrap();
+        let mut block = create_block(state.get_tip_header(), AggregateBody::empty());
+        assert_eq!(state.add_block(block.clone()), Err(BlockValidationError::InvalidVersion.into()));
+        block.header.version = 1;
+        state.add_block(block).unwrap();
+        // The audit replays the chain under the overridden constants
+        state.validate_chain_from_genesis().unwrap();
+    }
+
+    #[test]
+    fn difficulty_adjustment() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+        // Blocks arriving at half the target interval push the difficulty up
+        for _ in 0..5 {
+            let body = AggregateBody::new(vec![], vec![create_output(&mut rng, 0)], vec![create_kernel(&mut rng)]);
+            let mut block = create_block(state.get_tip_header(), body);
+            block.header.timestamp = state.get_tip_header().unwrap().timestamp + Duration::seconds(30);
+            block.header.pow.target_difficulty = state.get_next_difficulty();
+            mine(&mut block.header);
+            state.add_block(block).unwrap();
+        }
+        assert!(state.get_next_difficulty() > Difficulty::min());
+        assert!(state.get_accumulated_difficulty() > Difficulty::from(6));
+
+        let mut block = create_block(state.get_tip_header(), AggregateBody::empty());
+        assert_eq!(state.add_block(block.clone()), Err(BlockValidationError::InvalidDifficulty.into()));
+        block.header.pow.target_difficulty = state.get_next_difficulty();
+        while block.header.validate_pow() {
+            block.header.pow.nonce += 1;
+        }
+        assert_eq!(state.add_block(block), Err(BlockValidationError::BadPoW.into()));
+    }
+
+    /// Vary the nonce of the header until its proof of work meets its target difficulty
+    fn mine(header: &mut BlockHeader) {
+        while !header.validate_pow() {
+            header.pow.nonce += 1;
+        }
+    }
+
+    #[test]
+    fn coinbase_reward() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let fee_output = create_output(&mut rng, 7);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![fee_output], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+        let reward = state.get_consensus_constants().emission_schedule().block_reward(1);
+        let fee_key = BlindingFactor::random(&mut rng);
+        let fee_kernel = sign_kernel(&mut rng, fee_key, KernelFeatures::empty(), 7);
+        let fee_input = spend(&fee_output);
+
+        let (output, kernel) = create_coinbase(&mut rng, reward, 1, 5);
+        let body = AggregateBody::new(vec![fee_input.clone()], vec![output], vec![kernel, fee_kernel.clone()]);
+        let block = create_block(state.get_tip_header(), body);
+        assert_eq!(state.add_block(block), Err(BlockValidationError::InvalidCoinbase.into()));
+
+        // The coinbase output must be locked for the coinbase lock height of the network
+        let (output, kernel) = create_coinbase(&mut rng, reward + 7, 1, 4);
+        let body = AggregateBody::new(vec![fee_input.clone()], vec![output], vec![kernel, fee_kernel.clone()]);
+        let block = create_block(state.get_tip_header(), body);
+        assert_eq!(state.add_block(block), Err(BlockValidationError::InvalidCoinbase.into()));
+
+        let (coinbase, kernel) = create_coinbase(&mut rng, reward + 7, 1, 5);
+        assert_eq!(coinbase.features.maturity, 6);
+        let body = AggregateBody::new(vec![fee_input], vec![coinbase], vec![kernel, fee_kernel]);
+        state.add_block(create_block(state.get_tip_header(), body)).unwrap();
+        assert_eq!(state.get_tip_height(), Some(1));
+
+        // It can only be spent once it has matured
+        let output = create_output(&mut rng, reward + 7);
+        let body = AggregateBody::new(vec![spend(&coinbase)], vec![output], vec![create_kernel(&mut rng)]);
+        let block = create_block(state.get_tip_header(), body.clone());
+        let input = spend(&coinbase).hash();
+        assert_eq!(state.add_block(block), Err(BlockValidationError::ImmatureInput { input }.into()));
+        for _ in 2..6 {
+            state.add_block(create_block(state.get_tip_header(), AggregateBody::empty())).unwrap();
+        }
+        state.add_block(create_block(state.get_tip_header(), body)).unwrap();
+    }
+
+    #[test]
+    fn time_locked_outputs() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let vesting = OutputFeatures::empty().with_maturity(3);
+        let absolute = TransactionOutput { features: vesting, ..create_output(&mut rng, 10) };
+        let plain = create_output(&mut rng, 20);
+        let genesis =
+            create_block(None, AggregateBody::new(vec![], vec![absolute, plain], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+        // Created at height 1, so it can be spent from height 3
+        let channel = OutputFeatures::empty().with_relative_maturity(2);
+        let relative = TransactionOutput { features: channel, ..create_output(&mut rng, 20) };
+        let body = AggregateBody::new(vec![spend(&plain)], vec![relative], vec![create_kernel(&mut rng)]);
+        state.add_block(create_block(state.get_tip_header(), body)).unwrap();
+
+        for output in [absolute, relative].iter() {
+            let body = AggregateBody::new(vec![spend(output)], vec![], vec![create_fee_kernel(&mut rng, 0)]);
+            let block = create_block(state.get_tip_header(), body);
+            let input = spend(output).hash();
+            assert_eq!(state.add_block(block), Err(BlockValidationError::ImmatureInput { input }.into()));
+        }
+        state.add_block(create_block(state.get_tip_header(), AggregateBody::empty())).unwrap();
+        let output = create_output(&mut rng, 30);
+        let body =
+            AggregateBody::new(vec![spend(&absolute), spend(&relative)], vec![output], vec![create_kernel(&mut rng)]);
+        state.add_block(create_block(state.get_tip_header(), body)).unwrap();
+        assert_eq!(state.get_tip_height(), Some(3));
+    }
+
+    #[test]
+    fn output_scripts() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let (secret, key) = PublicKey::random_keypair(&mut rng);
+        let locked = create_output(&mut rng, 10).with_script(Script::CheckPubKey(key));
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![locked], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+
+        let unsigned = spend(&locked);
+        let body = AggregateBody::new(vec![unsigned.clone()], vec![create_output(&mut rng, 10)], vec![
+            create_kernel(&mut rng),
+        ]);
+        let block = create_block(state.get_tip_header(), body.clone());
+        let input = unsigned.hash();
+        assert_eq!(state.add_block(block), Err(BlockValidationError::ScriptFailed { input }.into()));
+
+        let excess = body.kernels[0].excess.unwrap();
+        let signed = sign_script(unsigned, &excess, secret, BlindingFactor::random(&mut rng)).unwrap();
+        let body = AggregateBody { inputs: vec![signed], ..body };
+        state.add_block(create_block(state.get_tip_header(), body)).unwrap();
+    }
+
+    #[test]
+    fn median_time_past() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![], vec![create_kernel(&mut rng)]));
+        let start = genesis.header.timestamp;
+        state.add_block(genesis).unwrap();
+        for block in create_chain(&mut rng, state.get_tip_header().unwrap(), 2) {
+            state.add_block(block).unwrap();
+        }
+
+        // A timestamp before its parent's is accepted as long as it is later than the median of the recent blocks
+        let mut block = create_block(state.get_tip_header(), AggregateBody::empty());
+        block.header.timestamp = start + Duration::minutes(1);
+        assert_eq!(state.add_block(block.clone()), Err(BlockValidationError::TimestampOutOfRange.into()));
+        block.header.timestamp = start + Duration::seconds(90);
+        state.add_block(block).unwrap();
+
+        let mut block = create_block(state.get_tip_header(), AggregateBody::empty());
+        block.header.timestamp = Utc::now() + Duration::hours(1);
+        assert_eq!(state.add_block(block.clone()), Err(BlockValidationError::TimestampOutOfRange.into()));
+        assert_eq!(state.add_header(block.header), Err(BlockValidationError::TimestampOutOfRange.into()));
+    }
+
+    #[test]
+    fn headers_first_sync() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![], vec![create_kernel(&mut rng)]));
+        let chain = create_chain(&mut rng, &genesis.header, 3);
+        state.add_header(genesis.header.clone()).unwrap();
+        for block in chain.iter() {
+            state.add_header(block.header.clone()).unwrap();
+        }
+        assert_eq!(state.get_tip_height(), None);
+        assert_eq!(state.get_best_header_height(), Some(3));
+        let orphan = create_chain(&mut rng, &chain[0].header, 1).remove(0);
+        assert_eq!(state.add_header(orphan.header), Err(BlockValidationError::InvalidHeight.into()));
+
+        state.add_block(genesis).unwrap();
+        state.add_block(chain[0].clone()).unwrap();
+        assert_eq!(state.get_tip_height(), Some(1));
+        let pending: Vec<ObjectHash> = state.get_pending_headers().map(Hashable::hash).collect();
+        assert_eq!(pending, vec![chain[1].header.hash(), chain[2].header.hash()]);
+
+        // A block off the best header chain drops the pending headers
+        let fork = create_chain(&mut rng, &chain[0].header, 1);
+        state.add_block(fork[0].clone()).unwrap();
+        assert_eq!(state.get_pending_headers().count(), 0);
+        assert_eq!(state.get_best_header_height(), Some(2));
+    }
+
+    #[test]
+    fn reject_invalid_blocks() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let coinbase = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]));
+        let mut orphan = create_block(Some(&genesis.header), AggregateBody::empty());
+        assert_eq!(state.add_block(orphan), Err(BlockValidationError::InvalidHeight.into()));
+        let genesis_header = genesis.header.clone();
+        state.add_block(genesis).unwrap();
+
+        orphan = create_block(Some(&genesis_header), AggregateBody::empty());
+        orphan.header.prev_hash = [1; 32];
+        assert_eq!(state.add_block(orphan), Err(BlockValidationError::InvalidPreviousHash.into()));
+
+        let mut block = create_block(state.get_tip_header(), AggregateBody::empty());
+        block.header.timestamp = genesis_header.timestamp;
+        assert_eq!(state.add_block(block), Err(BlockValidationError::TimestampOutOfRange.into()));
+
+        let mut block = create_block(state.get_tip_header(), AggregateBody::empty());
+        block.header.kernel_mmr = genesis_header.output_mmr;
+        assert_eq!(
+            state.add_block(block),
+            Err(BlockValidationError::BadMerkleRoot { which: MerkleRoot::Kernel }.into())
+        );
+
+        let unknown = create_output(&mut rng, 5);
+        let body = AggregateBody::new(vec![spend(&unknown)], vec![], vec![create_kernel(&mut rng)]);
+        let block = create_block(state.get_tip_header(), body);
+        assert_eq!(
+            state.add_block(block),
+            Err(BlockValidationError::UnknownInput { input: spend(&unknown).hash() }.into())
+        );
+
+        let body = AggregateBody::new(vec![spend(&coinbase), spend(&coinbase)], vec![], vec![create_kernel(&mut rng)]);
+        let block = create_block(state.get_tip_header(), body);
+        assert_eq!(
+            state.add_block(block),
+            Err(BlockValidationError::DoubleSpend { input: spend(&coinbase).hash() }.into())
+        );
+
+        let body = AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]);
+        let block = create_block(state.get_tip_header(), body);
+        assert_eq!(
+            state.add_block(block),
+            Err(BlockValidationError::DuplicateOutput { output: coinbase.hash() }.into())
+        );
+
+        let mut kernel = create_kernel(&mut rng);
+        kernel.fee = 1;
+        let block = create_block(state.get_tip_header(), AggregateBody::new(vec![], vec![], vec![kernel]));
+        assert_eq!(
+            state.add_block(block),
+            Err(BlockValidationError::TransactionError(TransactionError::InvalidSignatureError).into())
+        );
+
+        let mut kernel = create_kernel(&mut rng);
+        kernel.lock_height = 2;
+        let block = create_block(state.get_tip_header(), AggregateBody::new(vec![], vec![], vec![kernel]));
+        assert_eq!(state.add_block(block), Err(BlockValidationError::InvalidLockHeight.into()));
+
+        let body = AggregateBody::new(vec![], vec![create_output(&mut rng, 0)], vec![create_kernel(&mut rng)]);
+        let mut block = create_block(state.get_tip_header(), body);
+        block.body.kernels.reverse();
+        assert_eq!(
+            state.add_block(block),
+            Err(BlockValidationError::TransactionError(TransactionError::InvalidSortOrder).into())
+        );
+
+        // The header holds the kernel offset accumulated over the chain, not just the offset of the block
+        let body = AggregateBody::new(vec![], vec![], vec![create_kernel(&mut rng)]);
+        let mut block = create_block(state.get_tip_header(), body);
+        block.header.total_kernel_offset = block.header.block_kernel_offset(state.get_tip_header());
+        assert_eq!(state.add_block(block), Err(BlockValidationError::KernelSumMismatch.into()));
+
+        let constants =
+            ConsensusConstants { max_block_weight: WEIGHT_PER_OUTPUT, ..Network::LocalNet.consensus_constants() };
+        let mut state = state.with_consensus_constants(constants);
+        let body = AggregateBody::new(vec![], vec![create_output(&mut rng, 0)], vec![create_kernel(&mut rng)]);
+        let block = create_block(state.get_tip_header(), body);
+        assert_eq!(state.add_block(block), Err(BlockValidationError::BlockTooHeavy.into()));
+
+        let constants = ConsensusConstants { min_fee_per_gram: 1, ..Network::LocalNet.consensus_constants() };
+        let mut state = state.with_consensus_constants(constants);
+        let body = AggregateBody::new(vec![], vec![create_output(&mut rng, 0)], vec![create_kernel(&mut rng)]);
+        let block = create_block(state.get_tip_header(), body);
+        assert_eq!(state.add_block(block), Err(BlockValidationError::InsufficientFee.into()));
+
+        assert_eq!(state.get_tip_height(), Some(0));
+        assert_eq!(state.utxo_count(), 1);
+    }
+
+    pub(crate) fn create_chain(rng: &mut OsRng, parent: &BlockHeader, length: usize) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for _ in 0..length {
+            let parent = blocks.last().map(|b| &b.header).unwrap_or(parent);
+            let body = AggregateBody::new(vec![], vec![create_output(rng, 0)], vec![create_kernel(rng)]);
+            let block = create_block(Some(parent), body);
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn reorganize_pruned() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new().with_pruning_horizon(3);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+        for block in create_chain(&mut rng, state.get_tip_header().unwrap(), 3) {
+            state.add_block(block).unwrap();
+        }
+        assert_eq!(state.get_pruned_height(), 1);
+        let tip_hash = state.get_tip_header().unwrap().hash();
+        let utxo_count = state.utxo_count();
+
+        // The valid blocks of the fork would move the pruned height above the fork point before the last one fails
+        let fork_parent = state.get_block(1).unwrap().unwrap().header;
+        let mut bad_fork = create_chain(&mut rng, &fork_parent, 5);
+        bad_fork[4].body.kernels[0].fee = 1;
+        assert!(state.reorganize(bad_fork).is_err());
+        assert_eq!(state.get_tip_header().unwrap().hash(), tip_hash);
+        assert_eq!(state.get_block(3).unwrap().unwrap().header.hash(), tip_hash);
+        assert!(state.get_block(4).unwrap().is_none());
+        assert_eq!(state.get_pruned_height(), 1);
+        assert_eq!(state.utxo_count(), utxo_count);
+        state.validate_chain_from_genesis().unwrap();
+
+        let fork = create_chain(&mut rng, &fork_parent, 5);
+        state.reorganize(fork.clone()).unwrap();
+        assert_eq!(state.get_tip_header().unwrap().hash(), fork[4].header.hash());
+        assert_eq!(state.get_pruned_height(), 4);
+        state.validate_chain_from_genesis().unwrap();
+    }
+
+    #[test]
+    fn reorganize() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let coinbase = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]));
+        let genesis_header = genesis.header.clone();
+        state.add_block(genesis).unwrap();
+        let spend_body = AggregateBody::new(vec![spend(&coinbase)], vec![], vec![create_fee_kernel(&mut rng, 100)]);
+        state.add_block(create_block(state.get_tip_header(), spend_body)).unwrap();
+        for block in create_chain(&mut rng, state.get_tip_header().unwrap(), 1) {
+            state.add_block(block).unwrap();
+        }
+        assert_eq!(state.utxo_count(), 3);
+        let tip_hash = state.get_tip_header().unwrap().hash();
+
+        let short_fork = create_chain(&mut rng, &genesis_header, 2);
+        assert_eq!(state.reorganize(short_fork.clone()).unwrap_err(), BlockValidationError::ForkNotHeavier.into());
+        // A fork can not claim more work than its headers do
+        let mut forged_fork = short_fork;
+        forged_fork[1].header.pow.target_difficulty = Difficulty::from(1_000_000);
+        let forged_hash = forged_fork[1].header.hash();
+        assert_eq!(
+            state.reorganize(forged_fork).unwrap_err(),
+            ChainError::InvalidForkBlock { hash: forged_hash, reason: BlockValidationError::InvalidDifficulty }
+        );
+        assert_eq!(state.get_tip_header().unwrap().hash(), tip_hash);
+
+        let mut bad_fork = create_chain(&mut rng, &genesis_header, 3);
+        bad_fork[2].body.kernels[0].fee = 1;
+        assert!(state.reorganize(bad_fork).is_err());
+        assert_eq!(state.get_tip_header().unwrap().hash(), tip_hash);
+        assert_eq!(state.utxo_count(), 3);
+
+        let fork = create_chain(&mut rng, &genesis_header, 3);
+        let reorg = state.reorganize(fork).unwrap();
+        assert_eq!(reorg.reverted.len(), 2);
+        assert_eq!(reorg.reverted[1].header.hash(), tip_hash);
+        assert_eq!(reorg.applied.len(), 3);
+        assert_eq!(state.get_tip_height(), Some(3));
+        // The genesis output is unspent again and each fork block added an output and a coinbase output
+        assert_eq!(state.utxo_count(), 7);
+        assert_eq!(state.get_block(3).unwrap().unwrap().header.hash(), reorg.applied[2].header.hash());
+
+        let orphan = create_chain(&mut rng, &reorg.reverted[1].header, 2);
+        assert_eq!(state.reorganize(orphan).unwrap_err(), BlockValidationError::ForkPointNotFound.into());
+
+        // A shorter fork wins when its blocks came faster and so had to meet a higher difficulty
+        let mut fork_state = BlockchainState::new();
+        fork_state.add_block(state.get_block(0).unwrap().unwrap()).unwrap();
+        let mut fork = Vec::new();
+        for _ in 0..2 {
+            let body = AggregateBody::new(vec![], vec![create_output(&mut rng, 0)], vec![create_kernel(&mut rng)]);
+            let mut block = create_block(fork_state.get_tip_header(), body);
+            block.header.timestamp = fork_state.get_tip_header().unwrap().timestamp + Duration::seconds(1);
+            block.header.pow.target_difficulty = fork_state.get_next_difficulty();
+            mine(&mut block.header);
+            fork_state.add_block(block.clone()).unwrap();
+            fork.push(block);
+        }
+        assert!(fork_state.get_accumulated_difficulty() > state.get_accumulated_difficulty());
+        state.reorganize(fork).unwrap();
+        assert_eq!(state.get_tip_height(), Some(2));
+        assert_eq!(state.get_accumulated_difficulty(), fork_state.get_accumulated_difficulty());
+    }
+
+    #[test]
+    fn reorg_depth_limit() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new().with_max_reorg_depth(1);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![], vec![create_kernel(&mut rng)]));
+        let genesis_header = genesis.header.clone();
+        state.add_block(genesis).unwrap();
+        for block in create_chain(&mut rng, &genesis_header, 2) {
+            state.add_block(block).unwrap();
+        }
+        let tip_hash = state.get_tip_header().unwrap().hash();
+        let events = state.subscribe();
+
+        let fork = create_chain(&mut rng, &genesis_header, 3);
+        let fork_hash = fork[2].header.hash();
+        assert_eq!(state.reorganize(fork).unwrap_err(), ChainError::ReorgTooDeep { depth: 2 });
+        assert_eq!(state.get_tip_header().unwrap().hash(), tip_hash);
+        let rejected = BlockchainEvent::DeepForkRejected { depth: 2, hash: fork_hash };
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![rejected]);
+
+        let fork = create_chain(&mut rng, &blocks_header(&state, 1), 2);
+        state.reorganize(fork).unwrap();
+        assert_eq!(state.get_tip_height(), Some(3));
+    }
+
+    /// A backend whose writes fail while `fail` is set
+    #[derive(Default)]
+    struct FailingBackend {
+        blocks: MemoryBackend,
+        fail: bool,
+    }
+
+    impl BlockchainBackend for FailingBackend {
+        type MmrBackend = <MemoryBackend as BlockchainBackend>::MmrBackend;
+
+        fn block_count(&self) -> u64 {
+            self.blocks.block_count()
+        }
+
+        fn fetch_block(&self, height: u64) -> Result<Option<Block>, StoreError> {
+            self.blocks.fetch_block(height)
+        }
+
+        fn fetch_record(&self, height: u64) -> Result<Option<BlockRecord>, StoreError> {
+            self.blocks.fetch_record(height)
+        }
+
+        fn fetch_output(&self, hash: &ObjectHash) -> Result<Option<TransactionOutput>, StoreError> {
+            self.blocks.fetch_output(hash)
+        }
+
+        fn fetch_kernel(&self, leaf_index: u64) -> Result<Option<TransactionKernel>, StoreError> {
+            self.blocks.fetch_kernel(leaf_index)
+        }
+
+        fn fetch_pruned_height(&self) -> Result<u64, StoreError> {
+            self.blocks.fetch_pruned_height()
+        }
+
+        fn fetch_snapshot(&self) -> Result<Option<Snapshot>, StoreError> {
+            self.blocks.fetch_snapshot()
+        }
+
+        fn mmr_backend(&self, tree: MmrTree) -> Result<Self::MmrBackend, StoreError> {
+            self.blocks.mmr_backend(tree)
+        }
+
+        fn write(
+            &mut self,
+            operations: Vec<WriteOperation>,
+            mmrs: &mut [&mut Self::MmrBackend],
+        ) -> Result<(), StoreError>
+        {
+            if self.fail {
+                return Err(StoreError::DatastoreError("write failed".to_string()));
+            }
+            self.blocks.write(operations, mmrs)
+        }
+    }
+
+    #[test]
+    fn undo_applied_blocks() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::with_backend(FailingBackend::default(), Network::LocalNet).unwrap();
+        let coinbase = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+        let genesis_roots = (state.outputs.get_merkle_root(), state.kernels.get_merkle_root());
+
+        let outputs = vec![create_output(&mut rng, 60), create_output(&mut rng, 40)];
+        let body = AggregateBody::new(vec![spend(&coinbase)], outputs.clone(), vec![create_kernel(&mut rng)]);
+        let block = create_block(state.get_tip_header(), body);
+        state.backend.fail = true;
+        assert_eq!(state.add_block(block.clone()), Err(StoreError::DatastoreError("write failed".to_string()).into()));
+        assert_eq!(state.get_tip_height(), Some(0));
+        assert!(state.is_unspent(&coinbase.commitment));
+        assert!(!state.is_spent(&coinbase.hash()));
+        assert_eq!(state.utxo_count(), 1);
+        assert_eq!((state.outputs.get_merkle_root(), state.kernels.get_merkle_root()), genesis_roots);
+
+        state.backend.fail = false;
+        state.add_block(block).unwrap();
+        assert!(state.is_spent(&coinbase.hash()));
+        let body = AggregateBody::new(vec![spend(&coinbase)], vec![], vec![create_fee_kernel(&mut rng, 100)]);
+        let double_spend = create_block(state.get_tip_header(), body);
+        let input = double_spend.body.inputs[0].hash();
+        assert_eq!(state.add_block(double_spend), Err(BlockValidationError::DoubleSpend { input }.into()));
+        for block in create_chain(&mut rng, state.get_tip_header().unwrap(), 2) {
+            state.add_block(block).unwrap();
+        }
+        assert_eq!(state.utxo_count(), 7);
+        let reverted = state.rewind_to_height(0).unwrap();
+        assert_eq!(reverted.len(), 3);
+        // The first block has a coinbase output besides its outputs
+        assert_eq!(reverted[0].body.outputs.len(), outputs.len() + 1);
+        assert_eq!(state.get_tip_height(), Some(0));
+        assert_eq!(state.backend.block_count(), 1);
+        assert!(state.is_unspent(&coinbase.commitment));
+        assert!(!state.is_spent(&coinbase.hash()));
+        assert_eq!(state.utxo_count(), 1);
+        assert_eq!((state.outputs.get_merkle_root(), state.kernels.get_merkle_root()), genesis_roots);
+    }
+
+    #[test]
+    fn subscribe_to_events() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let events = state.subscribe();
+        let coinbase = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]));
+        let genesis_hash = genesis.header.hash();
+        state.add_block(genesis).unwrap();
+        let body = AggregateBody::new(vec![spend(&coinbase)], vec![], vec![create_fee_kernel(&mut rng, 100)]);
+        let block = create_block(state.get_tip_header(), body);
+        let block_hash = block.header.hash();
+        state.add_block(block).unwrap();
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![
+            BlockchainEvent::BlockAdded { height: 0, hash: genesis_hash.clone() },
+            BlockchainEvent::NewTip { height: 0, hash: genesis_hash.clone() },
+            BlockchainEvent::OutputSpent { height: 1, output: coinbase.hash() },
+            BlockchainEvent::BlockAdded { height: 1, hash: block_hash.clone() },
+            BlockchainEvent::NewTip { height: 1, hash: block_hash },
+        ]);
+
+        let fork = create_chain(&mut rng, &blocks_header(&state, 0), 2);
+        state.reorganize(fork.clone()).unwrap();
+        let tip_hash = fork[1].header.hash();
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![
+            BlockchainEvent::BlockAdded { height: 1, hash: fork[0].header.hash() },
+            BlockchainEvent::BlockAdded { height: 2, hash: tip_hash.clone() },
+            BlockchainEvent::Reorged { depth: 1 },
+            BlockchainEvent::NewTip { height: 2, hash: tip_hash },
+        ]);
+
+        state.rewind_to_height(0).unwrap();
+        assert_eq!(events.try_recv(), Ok(BlockchainEvent::NewTip { height: 0, hash: genesis_hash }));
+        drop(events);
+        state.add_block(fork[0].clone()).unwrap();
+        assert!(state.subscribers.is_empty());
+    }
+
+    #[test]
+    fn query_utxos() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let coinbase = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+        assert_eq!(state.fetch_utxo(&coinbase.commitment).unwrap().hash(), coinbase.hash());
+        assert!(state.fetch_utxo_by_hash(&coinbase.hash()).is_some());
+
+        // The block adds a coinbase output to the four outputs
+        let outputs: Vec<TransactionOutput> = (0..4).map(|_| create_output(&mut rng, 25)).collect();
+        let body = AggregateBody::new(vec![spend(&coinbase)], outputs.clone(), vec![create_kernel(&mut rng)]);
+        let block = create_block(state.get_tip_header(), body);
+        let mut expected: Vec<Vec<u8>> = block.body.outputs.iter().map(|output| output.hash()).collect();
+        state.add_block(block).unwrap();
+        assert!(!state.is_unspent(&coinbase.commitment));
+        assert!(state.fetch_utxo(&coinbase.commitment).is_none());
+        assert!(state.fetch_utxo_by_hash(&coinbase.hash()).is_none());
+        assert!(outputs.iter().all(|output| state.is_unspent(&output.commitment)));
+
+        let mut pages = Vec::new();
+        let mut after = None;
+        loop {
+            let page: Vec<TransactionOutput> = state.fetch_utxos(after.as_ref(), 2).into_iter().cloned().collect();
+            if page.is_empty() {
+                break;
+            }
+            after = page.last().map(|output| output.commitment);
+            pages.push(page);
+        }
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![2, 2, 1]);
+        let mut fetched: Vec<Vec<u8>> = pages.iter().flatten().map(|output| output.hash()).collect();
+        fetched.sort();
+        expected.sort();
+        assert_eq!(fetched, expected);
+    }
+
+    #[test]
+    fn pruned_mode() {
+        let mut rng = OsRng::new().unwrap();
+        let mut archive = BlockchainState::new();
+        let mut pruned = BlockchainState::new().with_pruning_horizon(1);
+        let coinbase = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]));
+        let spend_body = AggregateBody::new(vec![spend(&coinbase)], vec![], vec![create_fee_kernel(&mut rng, 100)]);
+        let spend_block = create_block(Some(&genesis.header), spend_body);
+        let mut blocks = vec![genesis, spend_block.clone()];
+        blocks.extend(create_chain(&mut rng, &spend_block.header, 1));
+        for block in blocks {
+            archive.add_block(block.clone()).unwrap();
+            pruned.add_block(block).unwrap();
+        }
+
+        assert_eq!(pruned.get_pruned_height(), 2);
+        assert!(pruned.outputs.get_object(&coinbase.hash()).is_none());
+        assert!(archive.outputs.get_object(&coinbase.hash()).is_some());
+        assert_eq!(pruned.outputs.get_merkle_root(), archive.outputs.get_merkle_root());
+        assert_eq!(pruned.kernels.get_merkle_root(), archive.kernels.get_merkle_root());
+
+        let fork = create_chain(&mut rng, &spend_block.header, 2);
+        assert_eq!(
+            pruned.reorganize(create_chain(&mut rng, &blocks_header(&archive, 0), 3)).unwrap_err(),
+            BlockValidationError::ForkBeyondPruningHorizon.into()
+        );
+        pruned.reorganize(fork).unwrap();
+        assert_eq!(pruned.get_tip_height(), Some(3));
+    }
+
+    #[test]
+    fn snapshots() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new().with_pruning_horizon(1);
+        let coinbase = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+        let spend_body = AggregateBody::new(vec![spend(&coinbase)], vec![], vec![create_fee_kernel(&mut rng, 100)]);
+        state.add_block(create_block(state.get_tip_header(), spend_body)).unwrap();
+        let at_one = state.export_snapshot();
+        let body = AggregateBody::new(vec![], vec![create_output(&mut rng, 0)], vec![create_kernel(&mut rng)]);
+        state.add_block(create_block(state.get_tip_header(), body)).unwrap();
+
+        // The state at a lower height is rebuilt from the journals of the blocks above it
+        let hashes = |snapshot: &Snapshot| {
+            let headers: Vec<ObjectHash> = snapshot.headers.iter().map(Hashable::hash).collect();
+            let kernels: Vec<ObjectHash> = snapshot.kernels.iter().map(Hashable::hash).collect();
+            let utxos: Vec<ObjectHash> = snapshot.utxos.iter().map(Hashable::hash).collect();
+            (headers, kernels, snapshot.output_hashes.clone(), utxos)
+        };
+        assert_eq!(hashes(&state.export_snapshot_at(1).unwrap()), hashes(&at_one));
+        assert_eq!(state.export_snapshot_at(0).err(), Some(ChainError::BeyondPruningHorizon));
+        assert_eq!(state.export_snapshot_at(3).err(), Some(ChainError::HeightAboveTip));
+        let restored = BlockchainState::from_snapshot(MemoryBackend::new(), Network::LocalNet, at_one).unwrap();
+        assert_eq!(restored.get_tip_header().unwrap().hash(), state.header_hashes[1]);
+
+        let snapshot = state.export_snapshot();
+        assert_eq!(snapshot.get_height(), Some(2));
+        assert_eq!(snapshot.output_hashes.len(), 4);
+        assert_eq!(snapshot.utxos.len(), 3);
+
+        let mut bad = snapshot.clone();
+        bad.utxos.push(create_output(&mut rng, 5));
+        assert_eq!(
+            BlockchainState::from_snapshot(MemoryBackend::new(), Network::LocalNet, bad).err(),
+            Some(SnapshotError::UnknownOutput.into())
+        );
+        let mut bad = snapshot.clone();
+        bad.kernels.pop();
+        assert_eq!(
+            BlockchainState::from_snapshot(MemoryBackend::new(), Network::LocalNet, bad).err(),
+            Some(SnapshotError::KernelRootMismatch.into())
+        );
+        let mut bad = snapshot.clone();
+        bad.headers.remove(1);
+        assert_eq!(
+            BlockchainState::from_snapshot(MemoryBackend::new(), Network::LocalNet, bad).err(),
+            Some(SnapshotError::InvalidHeader { height: 1, reason: BlockValidationError::InvalidHeight }.into())
+        );
+        // The output roots in the headers commit to the spent outputs of every block
+        let mut bad = snapshot.clone();
+        bad.spent_leaves[1].clear();
+        assert_eq!(
+            BlockchainState::from_snapshot(MemoryBackend::new(), Network::LocalNet, bad).err(),
+            Some(SnapshotError::OutputRootMismatch.into())
+        );
+        let mut bad = snapshot.clone();
+        bad.spent_leaves[2].push(0);
+        assert_eq!(
+            BlockchainState::from_snapshot(MemoryBackend::new(), Network::LocalNet, bad).err(),
+            Some(SnapshotError::InvalidSpentOutput.into())
+        );
+        let mut bad = snapshot.clone();
+        bad.spent_leaves.pop();
+        assert_eq!(
+            BlockchainState::from_snapshot(MemoryBackend::new(), Network::LocalNet, bad).err(),
+            Some(SnapshotError::InvalidSpentOutput.into())
+        );
+        // The accumulated difficulty of the restored chain is only trusted because every target is checked
+        let mut bad = snapshot.clone();
+        bad.headers[2].pow.target_difficulty = bad.headers[2].pow.target_difficulty + Difficulty::from(1);
+        assert_eq!(
+            BlockchainState::from_snapshot(MemoryBackend::new(), Network::LocalNet, bad).err(),
+            Some(SnapshotError::InvalidHeader { height: 2, reason: BlockValidationError::InvalidDifficulty }.into())
+        );
+
+        let path = std::env::temp_dir().join(format!("blockchain_snapshot_{}", rng.next_u64()));
+        fs::create_dir(&path).unwrap();
+        let path = format!("{}/", path.to_str().unwrap());
+        let next = create_chain(&mut rng, state.get_tip_header().unwrap(), 1);
+        {
+            let backend = LMDBBackend::open(&path, 10).unwrap();
+            let mut restored = BlockchainState::from_snapshot(backend, Network::LocalNet, snapshot).unwrap();
+            assert_eq!(restored.get_tip_header().unwrap().hash(), state.get_tip_header().unwrap().hash());
+            assert_eq!(restored.outputs.get_merkle_root(), state.outputs.get_merkle_root());
+            assert!(restored.is_spent(&coinbase.hash()));
+            for hash in restored.utxos.values() {
+                assert_eq!(restored.output_heights.get(hash), state.output_heights.get(hash));
+            }
+            assert!(restored.get_block(2).unwrap().is_none());
+            restored.add_block(next[0].clone()).unwrap();
+        }
+        let restored = BlockchainState::with_backend(LMDBBackend::open(&path, 10).unwrap(), Network::LocalNet).unwrap();
+        assert_eq!(restored.get_tip_height(), Some(3));
+        assert_eq!(restored.utxo_count(), 5);
+        assert_eq!(restored.get_pruned_height(), 3);
+        assert!(fs::remove_dir_all(&path).is_ok());
+    }
+
+    fn blocks_header<B: BlockchainBackend>(state: &BlockchainState<B>, height: u64) -> BlockHeader {
+        state.get_block(height).unwrap().unwrap().header
+    }
+
+    #[test]
+    fn reload_from_lmdb() {
+        let path = std::env::temp_dir().join(format!("blockchain_state_{}", OsRng::new().unwrap().next_u64()));
+        fs::create_dir(&path).unwrap();
+        let path = format!("{}/", path.to_str().unwrap());
+        let mut rng = OsRng::new().unwrap();
+        let coinbase = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]));
+        let genesis_header = genesis.header.clone();
+        let fork = create_chain(&mut rng, &genesis_header, 2);
+        let loaded = |state: &BlockchainState<LMDBBackend>| {
+            let roots =
+                (state.headers.get_merkle_root(), state.outputs.get_merkle_root(), state.kernels.get_merkle_root());
+            let indexes = (state.accumulated_difficulty.clone(), state.median_timestamps.clone());
+            let outputs = (state.output_heights.clone(), state.output_leaves.clone(), state.spent_commitments.clone());
+            (roots, indexes, state.utxos.clone(), outputs)
+        };
+        let expected = {
+            let mut state =
+                BlockchainState::with_backend(LMDBBackend::open(&path, 10).unwrap(), Network::LocalNet).unwrap();
+            state.add_block(genesis).unwrap();
+            let spend_body = AggregateBody::new(vec![spend(&coinbase)], vec![], vec![create_fee_kernel(&mut rng, 100)]);
+            state.add_block(create_block(state.get_tip_header(), spend_body)).unwrap();
+            state.reorganize(fork.clone()).unwrap();
+            loaded(&state)
+        };
+        let mut state =
+            BlockchainState::with_backend(LMDBBackend::open(&path, 10).unwrap(), Network::LocalNet).unwrap();
+        assert_eq!(state.get_tip_height(), Some(2));
+        assert_eq!(state.get_tip_header().unwrap().hash(), fork[1].header.hash());
+        assert_eq!(state.utxo_count(), 5);
+        assert_eq!(state.get_block(1).unwrap().unwrap().header.hash(), fork[0].header.hash());
+        assert!(loaded(&state) == expected);
+        state.validate_chain_from_genesis().unwrap();
+
+        // The journals are rebuilt from the stored records, so the loaded blocks can be reverted
+        state.rewind_to_height(0).unwrap();
+        assert_eq!(state.utxo_count(), 1);
+        assert!(state.is_unspent(&coinbase.commitment));
+        drop(state);
+        let mut backend = LMDBBackend::open(&path, 10).unwrap();
+        assert_eq!(backend.block_count(), 1);
+        assert!(backend.fetch_output(&fork[0].body.outputs[0].hash()).unwrap().is_none());
+        // The stored MMRs no longer match the records once a block is removed without its MMR changes
+        backend.write(vec![WriteOperation::PopBlock], &mut []).unwrap();
+        assert_eq!(
+            BlockchainState::with_backend(backend, Network::LocalNet).err(),
+            Some(StoreError::CorruptRecord.into())
+        );
+        assert!(fs::remove_dir_all(&path).is_ok());
+    }
+
+    #[test]
+    fn audit_stored_chain() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let coinbase = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+        let spend_body = AggregateBody::new(vec![spend(&coinbase)], vec![], vec![create_fee_kernel(&mut rng, 100)]);
+        state.add_block(create_block(state.get_tip_header(), spend_body)).unwrap();
+        for block in create_chain(&mut rng, state.get_tip_header().unwrap(), 2) {
+            state.add_block(block).unwrap();
+        }
+        state.validate_chain_from_genesis().unwrap();
+
+        // Replace the stored tip block, keeping its record
+        let record = Box::new(state.block_record(3));
+        let replace_tip = |state: &mut BlockchainState, block: Block| {
+            let push = WriteOperation::PushBlock { record: record.clone(), block: Some(Box::new(block)) };
+            state.backend.write(vec![WriteOperation::PopBlock, push], &mut []).unwrap();
+        };
+        let mut tip = state.get_block(3).unwrap().unwrap();
+        let mut corrupt = tip.clone();
+        let kernel = corrupt.body.kernels.iter_mut().find(|kernel| kernel.features.is_empty()).unwrap();
+        kernel.lock_height = 1;
+        corrupt.body.sort();
+        replace_tip(&mut state, corrupt);
+        assert_eq!(
+            state.validate_chain_from_genesis(),
+            Err(AuditError::InvalidBlock {
+                height: 3,
+                reason: BlockValidationError::TransactionError(TransactionError::InvalidSignatureError)
+            }
+            .into())
+        );
+
+        tip.body.outputs.retain(|output| output.features.is_coinbase());
+        let prev_offset = state.get_block(2).unwrap().unwrap().header.total_kernel_offset;
+        tip.header.total_kernel_offset = &prev_offset + &balancing_offset(&tip.body);
+        replace_tip(&mut state, tip);
+        assert_eq!(
+            state.validate_chain_from_genesis(),
+            Err(AuditError::InvalidBlock {
+                height: 3,
+                reason: BlockValidationError::BadMerkleRoot { which: MerkleRoot::Output }
+            }
+            .into())
+        );
+
+        state.rewind_to_height(2).unwrap();
+        state.utxos.clear();
+        assert_eq!(state.validate_chain_from_genesis(), Err(AuditError::UtxoSetMismatch.into()));
     }
 }
@@ -20,31 +20,317 @@
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use crate::{blockchainstate::BlockchainState, error::ChainError, store::Store};
-use tari_core::block::Block;
+use crate::{
+    blockchainstate::BlockchainState,
+    error::{BlockValidationError, ChainError},
+    store::{BlockchainBackend, MemoryBackend},
+};
+use tari_core::{
+    block::Block,
+    blockheader::{BlockHash, BlockHeader},
+    difficulty::Difficulty,
+};
+use tari_utilities::Hashable;
 
 use std::collections::HashMap;
 
-type BlockHash = [u8; 32];
+/// The default maximum number of orphans kept by a chain
+pub const DEFAULT_MAX_ORPHANS: usize = 500;
+/// The default maximum distance in height between an orphan and the chain tip
+pub const DEFAULT_MAX_ORPHAN_DEPTH: u64 = 100;
 
 /// The Chain is the actual data structure to represent the blockchain
-pub struct Chain {
-    /// This is the database used to storepersistentt data in
-    pub store: Store,
-    /// This the the current UTXO set, kernels and headers
-    pub blockchainstate: BlockchainState,
-    /// This is all valid blocks which dont have a parent trace to the genesis block
+pub struct Chain<B = MemoryBackend>
+where B: BlockchainBackend
+{
+    /// This the the current UTXO set, kernels and headers, persisted in its backend
+    pub blockchainstate: BlockchainState<B>,
+    /// This is all blocks with valid proof of work which dont have a parent trace to the genesis block
     pub orphans: HashMap<BlockHash, Block>,
-    /// This is our pruning horizon
-    pub pruning_horizon: Option<u64>,
+    max_orphans: usize,
+    max_orphan_depth: u64,
 }
 
-impl Chain {
-    pub fn new(dbstore: Store, pruning_horizon: Option<u64>) -> Chain {
-        Chain { store: dbstore, blockchainstate: BlockchainState::new(), orphans: HashMap::new(), pruning_horizon }
+impl<B> Chain<B>
+where B: BlockchainBackend
+{
+    /// Create a chain on top of the blockchain state. Use `BlockchainState::with_pruning_horizon` to run a pruned
+    /// node.
+    pub fn new(blockchainstate: BlockchainState<B>) -> Chain<B> {
+        Chain {
+            blockchainstate,
+            orphans: HashMap::new(),
+            max_orphans: DEFAULT_MAX_ORPHANS,
+            max_orphan_depth: DEFAULT_MAX_ORPHAN_DEPTH,
+        }
     }
 
-    pub fn process_new_block(&self, _new_block: &Block) -> Result<(), ChainError> {
-        Ok(())
+    /// Keep at most `count` orphans. When there are more, the orphans furthest above the chain tip are dropped first.
+    pub fn with_max_orphans(mut self, count: usize) -> Chain<B> {
+        self.max_orphans = count;
+        self
+    }
+
+    /// Drop the orphans more than `depth` blocks below or above the chain tip
+    pub fn with_max_orphan_depth(mut self, depth: u64) -> Chain<B> {
+        self.max_orphan_depth = depth;
+        self
+    }
+
+    /// Validate the new block and add it to the blockchain state. A block that does not build on the current tip is
+    /// kept as an orphan if its hash meets its target difficulty. If the orphans leading up to it connect to the main
+    /// chain, the fork is extended with the orphans building on the block that have the most accumulated difficulty.
+    /// If the fork has more accumulated difficulty than the main chain, the chain is reorganised onto it and the
+    /// reverted blocks become orphans. If a block of the fork is invalid, it is dropped with the orphans building on
+    /// it, and the fork up to it is tried again.
+    pub fn process_new_block(&mut self, new_block: Block) -> Result<(), ChainError> {
+        let prev_height = self.blockchainstate.get_block_height(&new_block.header.prev_hash);
+        let extends_tip = self.blockchainstate.get_tip_height().map_or(true, |tip| prev_height == Some(tip));
+        if extends_tip {
+            self.blockchainstate.add_block(new_block)?;
+            self.prune_orphans();
+            return Ok(());
+        }
+
+        // The rest of the header can only be checked once the blocks before it are known
+        if !new_block.header.validate_pow() {
+            return Err(BlockValidationError::BadPoW.into());
+        }
+        let hash = block_hash(&new_block.header);
+        self.orphans.insert(hash, new_block);
+        self.prune_orphans();
+        let mut fork = match self.orphan_chain(hash) {
+            Some(fork) => fork,
+            None => return Ok(()),
+        };
+        fork.extend(self.heaviest_descendants(hash).iter().filter_map(|hash| self.orphans.get(hash)).cloned());
+        let mut rejected = None;
+        while !fork.is_empty() {
+            match self.blockchainstate.reorganize(fork.clone()) {
+                Ok(reorg) => {
+                    for block in reorg.applied.iter() {
+                        self.orphans.remove(&block_hash(&block.header));
+                    }
+                    for block in reorg.reverted {
+                        self.orphans.insert(block_hash(&block.header), block);
+                    }
+                    break;
+                },
+                Err(ChainError::BlockValidationError(BlockValidationError::ForkNotHeavier)) => break,
+                Err(ChainError::InvalidForkBlock { hash: invalid, reason }) => {
+                    let position = fork.iter().position(|block| block.header.hash() == invalid).unwrap_or(0);
+                    let removed = self.remove_orphan_tree(block_hash(&fork[position].header));
+                    fork.truncate(position);
+                    if removed.contains(&hash) {
+                        rejected = Some(ChainError::InvalidForkBlock { hash: invalid, reason });
+                    }
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        self.prune_orphans();
+        rejected.map_or(Ok(()), Err)
+    }
+
+    /// Follow the orphans back from the given orphan until reaching a block on the main chain, returning the orphan
+    /// chain in ascending order of height. Returns None if the orphans do not connect to the main chain.
+    fn orphan_chain(&self, hash: BlockHash) -> Option<Vec<Block>> {
+        let mut chain = Vec::new();
+        let mut block = self.orphans.get(&hash)?;
+        loop {
+            chain.push(block.clone());
+            if self.blockchainstate.get_block_height(&block.header.prev_hash).is_some() {
+                chain.reverse();
+                return Some(chain);
+            }
+            block = self.orphans.get(&block.header.prev_hash)?;
+        }
+    }
+
+    /// Find the chain of orphans building on the given block with the most accumulated difficulty, returning the
+    /// hashes of the chain in ascending order of height. When several chains have the same difficulty, the one whose
+    /// first block has the lowest hash is chosen, so that every node picks the same chain.
+    fn heaviest_descendants(&self, hash: BlockHash) -> Vec<BlockHash> {
+        let children = self.orphan_children();
+        // Every block below the given block comes after its parent in this order
+        let mut descendants = Vec::new();
+        let mut stack = vec![hash];
+        while let Some(parent) = stack.pop() {
+            descendants.push(parent);
+            stack.extend(children.get(&parent).into_iter().flatten());
+        }
+        // The heaviest chain building on each block, as its difficulty and its first block, found from the leaves up
+        let mut heaviest: HashMap<BlockHash, (Difficulty, Option<BlockHash>)> = HashMap::new();
+        for parent in descendants.iter().rev() {
+            let mut best = (Difficulty::zero(), None);
+            for child in children.get(parent).into_iter().flatten() {
+                let difficulty = heaviest[child].0 + self.orphans[child].header.pow.target_difficulty;
+                if difficulty > best.0 {
+                    best = (difficulty, Some(*child));
+                }
+            }
+            heaviest.insert(*parent, best);
+        }
+        let mut chain = Vec::new();
+        let mut next = heaviest[&hash].1;
+        while let Some(child) = next {
+            chain.push(child);
+            next = heaviest[&child].1;
+        }
+        chain
+    }
+
+    /// Returns the hashes of the orphans building on each block, in ascending order
+    fn orphan_children(&self) -> HashMap<BlockHash, Vec<BlockHash>> {
+        let mut children: HashMap<BlockHash, Vec<BlockHash>> = HashMap::new();
+        for (hash, block) in self.orphans.iter() {
+            children.entry(block.header.prev_hash).or_default().push(*hash);
+        }
+        children.values_mut().for_each(|hashes| hashes.sort());
+        children
+    }
+
+    /// Remove the orphan with the given hash and every orphan building on it, returning the hashes of the removed
+    /// orphans
+    fn remove_orphan_tree(&mut self, hash: BlockHash) -> Vec<BlockHash> {
+        let children = self.orphan_children();
+        let mut removed = Vec::new();
+        let mut stack = vec![hash];
+        while let Some(parent) = stack.pop() {
+            if self.orphans.remove(&parent).is_some() {
+                removed.push(parent);
+            }
+            stack.extend(children.get(&parent).into_iter().flatten());
+        }
+        removed
+    }
+
+    /// Drop the orphans too far below or above the chain tip, then the highest orphans until at most the maximum
+    /// number of orphans are kept
+    fn prune_orphans(&mut self) {
+        let tip = self.blockchainstate.get_tip_height().unwrap_or(0);
+        let depth = self.max_orphan_depth;
+        self.orphans.retain(|_, block| {
+            let height = block.header.height;
+            height.saturating_add(depth) >= tip && height <= tip.saturating_add(depth)
+        });
+        if self.orphans.len() > self.max_orphans {
+            let mut by_height: Vec<(u64, BlockHash)> =
+                self.orphans.iter().map(|(hash, block)| (block.header.height, *hash)).collect();
+            by_height.sort();
+            for (_, hash) in by_height.drain(self.max_orphans..) {
+                self.orphans.remove(&hash);
+            }
+        }
+    }
+}
+
+fn block_hash(header: &BlockHeader) -> BlockHash {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&header.hash());
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blockchainstate::test::{create_block, create_chain, create_kernel};
+    use rand::OsRng;
+    use tari_core::block::AggregateBody;
+
+    #[test]
+    fn reorganise_onto_heaviest_orphans() {
+        let mut rng = OsRng::new().unwrap();
+        let mut chain = Chain::new(BlockchainState::new());
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![], vec![create_kernel(&mut rng)]));
+        let main = create_chain(&mut rng, &genesis.header, 2);
+        let fork = create_chain(&mut rng, &genesis.header, 3);
+        chain.process_new_block(genesis).unwrap();
+        for block in main.iter() {
+            chain.process_new_block(block.clone()).unwrap();
+        }
+
+        // The fork arrives in reverse order, so it only connects to the main chain with its last block
+        for block in fork.iter().rev() {
+            chain.process_new_block(block.clone()).unwrap();
+        }
+        let state = &chain.blockchainstate;
+        assert_eq!(state.get_tip_header().unwrap().hash(), fork[2].header.hash());
+        assert_eq!(chain.orphans.len(), 2);
+        assert!(chain.orphans.contains_key(&block_hash(&main[1].header)));
+    }
+
+    #[test]
+    fn bounded_orphans() {
+        let mut rng = OsRng::new().unwrap();
+        let mut chain = Chain::new(BlockchainState::new()).with_max_orphans(3).with_max_orphan_depth(2);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![], vec![create_kernel(&mut rng)]));
+        let main = create_chain(&mut rng, &genesis.header, 1);
+        let fork = create_chain(&mut rng, &genesis.header, 4);
+        let genesis_header = genesis.header.clone();
+        chain.process_new_block(genesis).unwrap();
+        chain.process_new_block(main[0].clone()).unwrap();
+
+        let mut unmined = fork[1].clone();
+        unmined.header.pow.target_difficulty = Difficulty::from(1_000_000_000);
+        assert_eq!(chain.process_new_block(unmined), Err(BlockValidationError::BadPoW.into()));
+        assert!(chain.orphans.is_empty());
+        // The orphan at height 4 is too far above the tip at height 1
+        chain.process_new_block(fork[3].clone()).unwrap();
+        assert!(chain.orphans.is_empty());
+        chain.process_new_block(fork[2].clone()).unwrap();
+        chain.process_new_block(fork[1].clone()).unwrap();
+        assert_eq!(chain.orphans.len(), 2);
+        chain.process_new_block(fork[0].clone()).unwrap();
+        assert_eq!(chain.blockchainstate.get_tip_header().unwrap().hash(), fork[2].header.hash());
+        assert_eq!(chain.orphans.len(), 1);
+        // With more orphans than the limit, the highest are dropped
+        let high = create_chain(&mut rng, &fork[0].header, 2);
+        let low = create_chain(&mut rng, &genesis_header, 2);
+        let on_main = create_chain(&mut rng, &main[0].header, 1);
+        chain.process_new_block(high[1].clone()).unwrap();
+        chain.process_new_block(low[1].clone()).unwrap();
+        chain.process_new_block(on_main[0].clone()).unwrap();
+        assert_eq!(chain.orphans.len(), 3);
+        assert!(!chain.orphans.contains_key(&block_hash(&high[1].header)));
+    }
+
+    #[test]
+    fn drop_invalid_fork_blocks() {
+        let mut rng = OsRng::new().unwrap();
+        let mut chain = Chain::new(BlockchainState::new());
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![], vec![create_kernel(&mut rng)]));
+        let main = create_chain(&mut rng, &genesis.header, 2);
+        let mut fork = create_chain(&mut rng, &genesis.header, 5);
+        fork[4].body.kernels[0].fee = 1;
+        let branch = create_chain(&mut rng, &fork[2].header, 1);
+        chain.process_new_block(genesis).unwrap();
+        for block in main.iter() {
+            chain.process_new_block(block.clone()).unwrap();
+        }
+
+        for block in fork[1..].iter().rev() {
+            chain.process_new_block(block.clone()).unwrap();
+        }
+        chain.process_new_block(branch[0].clone()).unwrap();
+        // The heaviest fork ends in the invalid block, so the chain is reorganised onto the valid blocks before it
+        chain.process_new_block(fork[0].clone()).unwrap();
+        let state = &chain.blockchainstate;
+        assert_eq!(state.get_tip_header().unwrap().hash(), fork[3].header.hash());
+        assert!(!chain.orphans.contains_key(&block_hash(&fork[4].header)));
+        assert!(chain.orphans.contains_key(&block_hash(&branch[0].header)));
+        assert_eq!(chain.orphans.len(), 3);
+
+        // A block building on an invalid block is rejected with it
+        let mut bad = create_chain(&mut rng, &fork[1].header, 3);
+        bad[0].body.kernels[0].fee = 1;
+        chain.process_new_block(bad[2].clone()).unwrap();
+        chain.process_new_block(bad[1].clone()).unwrap();
+        match chain.process_new_block(bad[0].clone()) {
+            Err(ChainError::InvalidForkBlock { hash, .. }) => assert_eq!(hash, bad[0].header.hash()),
+            result => panic!("The invalid block was not rejected: {:?}", result),
+        }
+        assert_eq!(chain.orphans.len(), 3);
+        assert_eq!(chain.blockchainstate.get_tip_header().unwrap().hash(), fork[3].header.hash());
     }
 }
@@ -0,0 +1,199 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+// This file contains the binary encoding used to persist blocks, snapshots and the chain state in the blockchain
+// backends
+
+use crate::{error::StoreError, snapshot::Snapshot, store::BlockRecord};
+use tari_core::{
+    block::Block,
+    blockheader::BlockHeader,
+    encoding::{decode_list, encode_list, BinaryEncoding, EncodingError, Reader},
+    transaction::{TransactionKernel, TransactionOutput},
+};
+
+/// Encode a block into bytes that can be decoded again with `decode_block`
+pub fn encode_block(block: &Block) -> Vec<u8> {
+    block.to_binary()
+}
+
+/// Decode a block written by `encode_block`
+pub fn decode_block(bytes: &[u8]) -> Result<Block, StoreError> {
+    Block::from_binary(bytes).map_err(|_| StoreError::CorruptBlock)
+}
+
+/// Encode a snapshot into bytes that can be decoded again with `decode_snapshot`
+pub fn encode_snapshot(snapshot: &Snapshot) -> Vec<u8> {
+    snapshot.to_binary()
+}
+
+/// Decode a snapshot written by `encode_snapshot`
+pub fn decode_snapshot(bytes: &[u8]) -> Result<Snapshot, StoreError> {
+    Snapshot::from_binary(bytes).map_err(|_| StoreError::CorruptSnapshot)
+}
+
+/// Encode a block record into bytes that can be decoded again with `decode_record`
+pub fn encode_record(record: &BlockRecord) -> Vec<u8> {
+    record.to_binary()
+}
+
+/// Decode a block record written by `encode_record`
+pub fn decode_record(bytes: &[u8]) -> Result<BlockRecord, StoreError> {
+    BlockRecord::from_binary(bytes).map_err(|_| StoreError::CorruptRecord)
+}
+
+/// Encode an output into bytes that can be decoded again with `decode_output`
+pub fn encode_output(output: &TransactionOutput) -> Vec<u8> {
+    output.to_binary()
+}
+
+/// Decode an output written by `encode_output`
+pub fn decode_output(bytes: &[u8]) -> Result<TransactionOutput, StoreError> {
+    TransactionOutput::from_binary(bytes).map_err(|_| StoreError::CorruptRecord)
+}
+
+/// Encode a kernel into bytes that can be decoded again with `decode_kernel`
+pub fn encode_kernel(kernel: &TransactionKernel) -> Vec<u8> {
+    kernel.to_binary()
+}
+
+/// Decode a kernel written by `encode_kernel`
+pub fn decode_kernel(bytes: &[u8]) -> Result<TransactionKernel, StoreError> {
+    TransactionKernel::from_binary(bytes).map_err(|_| StoreError::CorruptRecord)
+}
+
+impl BinaryEncoding for BlockRecord {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        self.header.binary_encode(buf);
+        buf.extend_from_slice(&self.kernel_leaf_count.to_le_bytes());
+        buf.extend_from_slice(&self.output_leaf_count.to_le_bytes());
+        encode_leaf_indices(&self.spent_leaves, buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let header = BlockHeader::binary_decode(reader)?;
+        let kernel_leaf_count = reader.read_u64()?;
+        let output_leaf_count = reader.read_u64()?;
+        let spent_leaves = decode_leaf_indices(reader)?;
+        Ok(BlockRecord { header, kernel_leaf_count, output_leaf_count, spent_leaves })
+    }
+}
+
+impl BinaryEncoding for Snapshot {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        encode_list(&self.headers, buf);
+        encode_list(&self.kernels, buf);
+        buf.extend_from_slice(&(self.output_hashes.len() as u64).to_le_bytes());
+        for hash in self.output_hashes.iter() {
+            buf.extend_from_slice(&(hash.len() as u64).to_le_bytes());
+            buf.extend_from_slice(hash);
+        }
+        buf.extend_from_slice(&(self.spent_leaves.len() as u64).to_le_bytes());
+        for spent in self.spent_leaves.iter() {
+            encode_leaf_indices(spent, buf);
+        }
+        encode_list(&self.utxos, buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let headers = decode_list(reader)?;
+        let kernels = decode_list(reader)?;
+        let mut output_hashes = Vec::new();
+        for _ in 0..reader.read_u64()? {
+            let length = reader.read_u64()? as usize;
+            output_hashes.push(reader.read_bytes(length)?.to_vec());
+        }
+        let mut spent_leaves = Vec::new();
+        for _ in 0..reader.read_u64()? {
+            spent_leaves.push(decode_leaf_indices(reader)?);
+        }
+        let utxos = decode_list(reader)?;
+        Ok(Snapshot { headers, kernels, output_hashes, spent_leaves, utxos })
+    }
+}
+
+/// Encode a list of leaf indices, prefixed with its length
+fn encode_leaf_indices(leaf_indices: &[u32], buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(leaf_indices.len() as u64).to_le_bytes());
+    for leaf_index in leaf_indices.iter() {
+        buf.extend_from_slice(&leaf_index.to_le_bytes());
+    }
+}
+
+/// Decode a list of leaf indices written by `encode_leaf_indices`
+fn decode_leaf_indices(reader: &mut Reader) -> Result<Vec<u32>, EncodingError> {
+    let mut leaf_indices = Vec::new();
+    for _ in 0..reader.read_u64()? {
+        leaf_indices.push(reader.read_u32()?);
+    }
+    Ok(leaf_indices)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::blockchainstate::test::{create_block, create_kernel, create_output};
+    use crypto::keys::SecretKey;
+    use rand::OsRng;
+    use tari_core::{
+        block::AggregateBody,
+        transaction::{OutputFeatures, TransactionInput},
+        types::BlindingFactor,
+    };
+    use tari_utilities::Hashable;
+
+    #[test]
+    fn block_round_trip() {
+        let mut rng = OsRng::new().unwrap();
+        let output = create_output(&mut rng, 10);
+        let mut unsigned = create_kernel(&mut rng);
+        unsigned.excess_sig = None;
+        unsigned.excess = None;
+        let body = AggregateBody::new(
+            vec![TransactionInput::new(OutputFeatures::create_coinbase(7), output.commitment)],
+            vec![output, create_output(&mut rng, 20)],
+            vec![create_kernel(&mut rng), unsigned],
+        );
+        let mut block = create_block(None, body);
+        block.header.total_kernel_offset = BlindingFactor::random(&mut rng);
+        let bytes = encode_block(&block);
+        let decoded = decode_block(&bytes).unwrap();
+        assert_eq!(encode_block(&decoded), bytes);
+        assert_eq!(decoded.header.timestamp, block.header.timestamp);
+        assert_eq!(decoded.body.kernels[0].excess, block.body.kernels[0].excess);
+        let signed = decoded.body.kernels.iter().find(|kernel| kernel.excess_sig.is_some()).unwrap();
+        signed.verify_signature().unwrap();
+        assert_eq!(decode_block(&bytes[..bytes.len() - 1]).unwrap_err(), StoreError::CorruptBlock);
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let header = create_block(None, AggregateBody::empty()).header;
+        let record = BlockRecord { header, kernel_leaf_count: 3, output_leaf_count: 5, spent_leaves: vec![1, 4] };
+        let bytes = encode_record(&record);
+        let decoded = decode_record(&bytes).unwrap();
+        assert_eq!(encode_record(&decoded), bytes);
+        assert_eq!(decoded.header.hash(), record.header.hash());
+        assert_eq!(decoded.spent_leaves, record.spent_leaves);
+        assert_eq!(decode_record(&bytes[..bytes.len() - 1]).unwrap_err(), StoreError::CorruptRecord);
+    }
+}
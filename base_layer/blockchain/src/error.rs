@@ -22,9 +22,217 @@
 
 // this file is used for all blockchain error types
 use derive_error::Error;
+use merklemountainrange::{error::MerkleMountainRangeError, merklenode::ObjectHash};
+use storage::keyvalue_store::DatastoreError;
+use tari_core::transaction::TransactionError;
 
 /// The ChainError is used to present all generic chain error of the actual blockchain
-#[derive(Debug, Error)]
+#[derive(Debug, PartialEq, Error)]
 pub enum ChainError {
     Brokenchain, // place holder for real error
+    /// The block was rejected by the blockchain state
+    BlockValidationError(BlockValidationError),
+    /// The blockchain backend failed
+    StoreError(StoreError),
+    /// The snapshot could not be imported
+    SnapshotError(SnapshotError),
+    /// The blocks to revert are below the pruned height
+    BeyondPruningHorizon,
+    /// The height is above the chain tip
+    HeightAboveTip,
+    /// Horizon sync failed
+    HorizonSyncError(HorizonSyncError),
+    /// The chain stored in the backend failed an audit
+    AuditError(AuditError),
+    /// A heavier fork would revert more blocks than the maximum reorganisation depth. It holds the number of blocks
+    /// that would be reverted. The node stays on its main chain until an operator intervenes.
+    #[error(non_std, no_from)]
+    ReorgTooDeep { depth: u64 },
+    /// A block of a competing chain is invalid, so the chain was not reorganised onto it. It holds the hash of the
+    /// block and the reason it was rejected; the blocks of the fork before it may still be valid.
+    #[error(non_std, no_from)]
+    InvalidForkBlock { hash: ObjectHash, reason: BlockValidationError },
+}
+
+/// The reasons a block can be rejected by `BlockchainState::add_block`. These are consensus failures, raised because the
+/// block itself is invalid, unlike a `StoreError`, which means the node failed to read or write its own data.
+#[derive(Debug, PartialEq, Error)]
+pub enum BlockValidationError {
+    /// The block version is not the version required by the network
+    InvalidVersion,
+    /// The block height does not follow on from the current chain tip
+    InvalidHeight,
+    /// The block does not build on the current chain tip
+    InvalidPreviousHash,
+    /// The block timestamp is outside the range allowed by the blocks before it
+    TimestampOutOfRange,
+    /// The target difficulty of the block is not the difficulty calculated from the blocks before it
+    InvalidDifficulty,
+    /// The block hash does not meet the target difficulty of the block
+    BadPoW,
+    /// An MMR root in the block header does not match the root calculated from the block body
+    #[error(non_std, no_from)]
+    BadMerkleRoot { which: MerkleRoot },
+    /// The total weight of the inputs, outputs and kernels of the block exceeds the consensus maximum
+    BlockTooHeavy,
+    /// The kernel fees of the block do not pay for its weight at the minimum fee rate
+    InsufficientFee,
+    /// A kernel in the block has a lock height later than the height of the block
+    InvalidLockHeight,
+    /// The block does not have exactly one coinbase, worth the block reward plus the fees of the block and locked for
+    /// the coinbase lock height
+    InvalidCoinbase,
+    /// The kernel excesses and offset do not balance the inputs and outputs of the block
+    KernelSumMismatch,
+    /// The block fails a check it shares with transactions, such as a kernel signature or the sort order of its body
+    TransactionError(TransactionError),
+    /// An input spends an output that is not in the UTXO set. It holds the hash of the input.
+    #[error(non_std, no_from)]
+    UnknownInput { input: ObjectHash },
+    /// An output is spent more than once, in the block or on the main chain. It holds the hash of the input spending
+    /// it again.
+    #[error(non_std, no_from)]
+    DoubleSpend { input: ObjectHash },
+    /// An input spends an output before its maturity height, or before its relative maturity has passed since the
+    /// block that created it. It holds the hash of the input.
+    #[error(non_std, no_from)]
+    ImmatureInput { input: ObjectHash },
+    /// An input does not satisfy the script of the output it spends. It holds the hash of the input.
+    #[error(non_std, no_from)]
+    ScriptFailed { input: ObjectHash },
+    /// An output already exists in the UTXO set or more than once in the block. It holds the hash of the output.
+    #[error(non_std, no_from)]
+    DuplicateOutput { output: ObjectHash },
+    /// An output has an invalid range proof. It holds the hash of the output.
+    #[error(non_std, no_from)]
+    InvalidRangeProof { output: ObjectHash },
+    /// The competing chain does not build on a block of the main chain
+    ForkPointNotFound,
+    /// The competing chain does not have more accumulated difficulty than the main chain
+    ForkNotHeavier,
+    /// The competing chain forks from the main chain below the pruning horizon
+    ForkBeyondPruningHorizon,
+}
+
+/// The reasons a `BlockBuilder` can not add to a block or complete it
+#[derive(Debug, PartialEq, Error)]
+pub enum BlockBuildError {
+    /// The block would be heavier than the maximum block weight
+    BlockTooHeavy,
+    /// The coinbase output or kernel is not flagged as a coinbase
+    InvalidCoinbase,
+    /// The MMR roots have not been calculated since the body last changed
+    OutdatedRoots,
+    /// The MMR roots can only be calculated on the tip of the chain the block builds on
+    NotOnTip,
+    /// The nonce does not meet the target difficulty of the block
+    BadPoW,
+    /// The chain has no block to build on
+    EmptyChain,
+    /// The block already has a coinbase
+    DuplicateCoinbase,
+    /// Transactions can not be added once the coinbase, which claims their fees, has been added
+    TransactionAfterCoinbase,
+    /// The transaction is not valid in the block
+    TransactionError(TransactionError),
+}
+
+/// The reasons a horizon sync client rejects the state sent by a server
+#[derive(Debug, PartialEq, Error)]
+pub enum HorizonSyncError {
+    /// The chunk does not answer the last request
+    UnexpectedChunk,
+    /// The headers do not form a chain from the genesis block to the horizon header
+    InvalidHeaderChain,
+    /// A header fails the header checks of block validation. It holds the height of the header and the reason it was
+    /// rejected.
+    #[error(non_std, no_from)]
+    InvalidHeader { height: u64, reason: BlockValidationError },
+    /// The horizon is higher than the client accepts, or the metadata counts more items than a chain up to the horizon
+    /// can hold
+    InvalidMetadata,
+    /// A kernel has an invalid signature
+    InvalidKernel,
+    /// An unspent output has an invalid range proof
+    InvalidRangeProof,
+    /// A block spends more outputs than fit in a block, or outputs that are not in the output MMR, or the blocks
+    /// spend more outputs than the metadata leaves spent
+    InvalidSpentOutputs,
+    /// Not all of the state has been downloaded
+    Incomplete,
+}
+
+/// The ways the chain stored in the backend can fail an audit by `BlockchainState::validate_chain_from_genesis`
+#[derive(Debug, PartialEq, Error)]
+pub enum AuditError {
+    /// A stored block is invalid. It holds the height of the block and the reason it was rejected.
+    #[error(non_std, no_from)]
+    InvalidBlock { height: u64, reason: BlockValidationError },
+    /// The UTXO set rebuilt from the stored chain does not match the UTXO set of the state
+    UtxoSetMismatch,
+    /// The MMR roots rebuilt from the stored chain do not match the MMRs of the state
+    MmrRootMismatch,
+}
+
+/// The MMRs whose roots are committed to in the block header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MerkleRoot {
+    Output,
+    Kernel,
+}
+
+/// Failures of the blockchain storage backends
+#[derive(Debug, PartialEq, Error)]
+pub enum StoreError {
+    /// An error occurred in the underlying data store
+    #[error(msg_embedded, non_std, no_from)]
+    DatastoreError(String),
+    /// A stored block could not be decoded
+    CorruptBlock,
+    /// A block that should be in the store is missing
+    MissingBlock,
+    /// A stored snapshot could not be decoded
+    CorruptSnapshot,
+    /// A stored header record, output or kernel could not be decoded, or does not match the stored MMRs
+    CorruptRecord,
+    /// An error occurred reading or writing the stored MMR nodes
+    #[error(msg_embedded, non_std, no_from)]
+    MmrError(String),
+}
+
+/// The reasons a snapshot can be rejected by `BlockchainState::from_snapshot`
+#[derive(Debug, PartialEq, Error)]
+pub enum SnapshotError {
+    /// The snapshot does not contain any headers
+    EmptySnapshot,
+    /// The snapshot headers do not start at the genesis block of the network
+    InvalidHeaderChain,
+    /// A header in the snapshot is invalid. It holds the height of the header and the reason it was rejected.
+    #[error(non_std, no_from)]
+    InvalidHeader { height: u64, reason: BlockValidationError },
+    /// An unspent output in the snapshot is not in the output MMR
+    UnknownOutput,
+    /// An output is in the output MMR more than once
+    DuplicateOutput,
+    /// The snapshot does not list the spent outputs of every block, or a block spends an output that is not in the
+    /// output MMR or has already been spent
+    InvalidSpentOutput,
+    /// The unspent outputs in the snapshot are not the outputs left unspent in the output MMR
+    UtxoSetMismatch,
+    /// The output MMR root does not match the commitment in a header
+    OutputRootMismatch,
+    /// The kernel MMR root does not match the commitment in the tip header
+    KernelRootMismatch,
+}
+
+impl From<DatastoreError> for StoreError {
+    fn from(e: DatastoreError) -> Self {
+        StoreError::DatastoreError(format!("Blockchain datastore error: {}", e))
+    }
+}
+
+impl From<MerkleMountainRangeError> for StoreError {
+    fn from(e: MerkleMountainRangeError) -> Self {
+        StoreError::MmrError(format!("Blockchain MMR error: {}", e))
+    }
 }
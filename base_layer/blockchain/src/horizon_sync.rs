@@ -0,0 +1,429 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+// This file contains both sides of horizon sync, which bootstraps a node from the state of another node at its chain
+// tip instead of downloading every block since the genesis block
+
+use crate::{
+    blockchainstate::{validate_next_header, BlockchainState},
+    error::{ChainError, HorizonSyncError},
+    snapshot::Snapshot,
+    store::BlockchainBackend,
+};
+use merklemountainrange::merklenode::ObjectHash;
+use tari_core::{
+    block::{WEIGHT_PER_INPUT, WEIGHT_PER_KERNEL, WEIGHT_PER_OUTPUT},
+    blockheader::BlockHeader,
+    consensus::{ConsensusConstants, Network},
+    transaction::{TransactionKernel, TransactionOutput},
+};
+use tari_utilities::Hashable;
+
+/// The highest horizon a client accepts by default. It allows for a chain of two minute blocks for over 30 years.
+pub const DEFAULT_MAX_HORIZON_HEIGHT: u64 = 8_000_000;
+
+/// A request from a horizon sync client. Items are requested by their index, starting at `start`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncRequest {
+    Metadata,
+    Headers { start: u64, count: u64 },
+    Kernels { start: u64, count: u64 },
+    OutputHashes { start: u64, count: u64 },
+    SpentLeaves { start: u64, count: u64 },
+    Utxos { start: u64, count: u64 },
+}
+
+/// The response of a horizon sync server to a `SyncRequest`
+#[derive(Clone, Debug)]
+pub enum SyncChunk {
+    Metadata(SyncMetadata),
+    Headers { start: u64, headers: Vec<BlockHeader> },
+    Kernels { start: u64, kernels: Vec<TransactionKernel> },
+    OutputHashes { start: u64, hashes: Vec<ObjectHash> },
+    /// The leaf indices of the outputs spent by each block, starting at the block at height `start`
+    SpentLeaves { start: u64, spent: Vec<Vec<u32>> },
+    Utxos { start: u64, utxos: Vec<TransactionOutput> },
+}
+
+/// The size of the state being synced. The horizon is the chain tip of the server when the sync started.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyncMetadata {
+    /// The height of the horizon block
+    pub height: u64,
+    /// The hash of the horizon block header
+    pub tip_hash: ObjectHash,
+    /// The number of kernels up to the horizon
+    pub kernel_count: u64,
+    /// The number of outputs, spent or not, up to the horizon
+    pub output_count: u64,
+    /// The number of unspent outputs at the horizon
+    pub utxo_count: u64,
+}
+
+/// Serves the state of a blockchain at a fixed horizon. The state is copied when the server is created, so the
+/// chunks stay consistent while the served chain moves on.
+pub struct HorizonSyncServer {
+    snapshot: Snapshot,
+    metadata: SyncMetadata,
+}
+
+impl HorizonSyncServer {
+    /// Create a server for the state at the current chain tip. Returns None if the chain is empty.
+    pub fn new<B: BlockchainBackend>(state: &BlockchainState<B>) -> Option<HorizonSyncServer> {
+        let snapshot = state.export_snapshot();
+        let metadata = SyncMetadata {
+            height: snapshot.get_height()?,
+            tip_hash: snapshot.headers.last()?.hash(),
+            kernel_count: snapshot.kernels.len() as u64,
+            output_count: snapshot.output_hashes.len() as u64,
+            utxo_count: snapshot.utxos.len() as u64,
+        };
+        Some(HorizonSyncServer { snapshot, metadata })
+    }
+
+    /// Answer a client request. Requests past the end of the state are answered with an empty chunk.
+    pub fn handle(&self, request: SyncRequest) -> SyncChunk {
+        match request {
+            SyncRequest::Metadata => SyncChunk::Metadata(self.metadata.clone()),
+            SyncRequest::Headers { start, count } => {
+                SyncChunk::Headers { start, headers: page(&self.snapshot.headers, start, count) }
+            },
+            SyncRequest::Kernels { start, count } => {
+                SyncChunk::Kernels { start, kernels: page(&self.snapshot.kernels, start, count) }
+            },
+            SyncRequest::OutputHashes { start, count } => {
+                SyncChunk::OutputHashes { start, hashes: page(&self.snapshot.output_hashes, start, count) }
+            },
+            SyncRequest::SpentLeaves { start, count } => {
+                SyncChunk::SpentLeaves { start, spent: page(&self.snapshot.spent_leaves, start, count) }
+            },
+            SyncRequest::Utxos { start, count } => {
+                SyncChunk::Utxos { start, utxos: page(&self.snapshot.utxos, start, count) }
+            },
+        }
+    }
+}
+
+fn page<T: Clone>(items: &[T], start: u64, count: u64) -> Vec<T> {
+    items.iter().skip(start as usize).take(count as usize).cloned().collect()
+}
+
+/// Downloads the state of a server in chunks and bootstraps a blockchain state from it. Every chunk is checked as it
+/// arrives: the headers must form a chain from the genesis block that passes the header checks of block validation,
+/// kernel signatures and range proofs must be valid, and the spent outputs of each block must fit in a block and be in
+/// the output MMR. The counts in the metadata are bounded by what a chain of that
+/// height can hold before anything is downloaded. Once all chunks have arrived, the MMR roots rebuilt from them are
+/// verified against the commitments in the horizon header. The blocks above the horizon are then added as usual.
+pub struct HorizonSyncClient {
+    network: Network,
+    consensus_constants: ConsensusConstants,
+    chunk_size: u64,
+    max_height: u64,
+    metadata: Option<SyncMetadata>,
+    snapshot: Snapshot,
+    spent_count: u64,
+}
+
+impl HorizonSyncClient {
+    /// Create a client for the network that requests at most `chunk_size` items at a time
+    pub fn new(network: Network, chunk_size: u64) -> HorizonSyncClient {
+        HorizonSyncClient {
+            network,
+            consensus_constants: network.consensus_constants(),
+            chunk_size: chunk_size.max(1),
+            max_height: DEFAULT_MAX_HORIZON_HEIGHT,
+            metadata: None,
+            snapshot: Snapshot::default(),
+            spent_count: 0,
+        }
+    }
+
+    /// Set the highest horizon the client accepts from a server
+    pub fn with_max_height(mut self, height: u64) -> HorizonSyncClient {
+        self.max_height = height;
+        self
+    }
+
+    /// Returns the next request to send to the server, or None once all the state has been downloaded
+    pub fn next_request(&self) -> Option<SyncRequest> {
+        let metadata = match self.metadata {
+            Some(ref metadata) => metadata,
+            None => return Some(SyncRequest::Metadata),
+        };
+        let snapshot = &self.snapshot;
+        let next = |received: usize, total: u64| {
+            let start = received as u64;
+            if start < total {
+                Some((start, self.chunk_size.min(total - start)))
+            } else {
+                None
+            }
+        };
+        if let Some((start, count)) = next(snapshot.headers.len(), metadata.height + 1) {
+            return Some(SyncRequest::Headers { start, count });
+        }
+        if let Some((start, count)) = next(snapshot.kernels.len(), metadata.kernel_count) {
+            return Some(SyncRequest::Kernels { start, count });
+        }
+        if let Some((start, count)) = next(snapshot.output_hashes.len(), metadata.output_count) {
+            return Some(SyncRequest::OutputHashes { start, count });
+        }
+        if let Some((start, count)) = next(snapshot.spent_leaves.len(), metadata.height + 1) {
+            return Some(SyncRequest::SpentLeaves { start, count });
+        }
+        if let Some((start, count)) = next(snapshot.utxos.len(), metadata.utxo_count) {
+            return Some(SyncRequest::Utxos { start, count });
+        }
+        None
+    }
+
+    /// Check the chunk and add it to the downloaded state. The chunk must answer the request returned by
+    /// `next_request`, but may hold fewer items than were requested. A rejected chunk is not added, so it can be
+    /// requested again from another server.
+    pub fn add_chunk(&mut self, chunk: SyncChunk) -> Result<(), HorizonSyncError> {
+        let request = self.next_request().ok_or(HorizonSyncError::UnexpectedChunk)?;
+        match (request, chunk) {
+            (SyncRequest::Metadata, SyncChunk::Metadata(metadata)) => {
+                self.check_metadata(&metadata)?;
+                self.metadata = Some(metadata);
+            },
+            (SyncRequest::Headers { start, count }, SyncChunk::Headers { start: s, headers })
+                if s == start && is_partial(headers.len(), count) =>
+            {
+                let received = self.snapshot.headers.len();
+                for header in headers {
+                    if let Err(e) = self.add_header(header) {
+                        self.snapshot.headers.truncate(received);
+                        return Err(e);
+                    }
+                }
+            },
+            (SyncRequest::Kernels { start, count }, SyncChunk::Kernels { start: s, kernels })
+                if s == start && is_partial(kernels.len(), count) =>
+            {
+                for kernel in kernels.iter() {
+                    kernel.verify_signature().map_err(|_| HorizonSyncError::InvalidKernel)?;
+                }
+                self.snapshot.kernels.extend(kernels);
+            },
+            (SyncRequest::OutputHashes { start, count }, SyncChunk::OutputHashes { start: s, hashes })
+                if s == start && is_partial(hashes.len(), count) =>
+            {
+                self.snapshot.output_hashes.extend(hashes);
+            },
+            (SyncRequest::SpentLeaves { start, count }, SyncChunk::SpentLeaves { start: s, spent })
+                if s == start && is_partial(spent.len(), count) =>
+            {
+                self.check_spent_leaves(&spent)?;
+                self.spent_count += spent.iter().map(|leaves| leaves.len() as u64).sum::<u64>();
+                self.snapshot.spent_leaves.extend(spent);
+            },
+            (SyncRequest::Utxos { start, count }, SyncChunk::Utxos { start: s, utxos })
+                if s == start && is_partial(utxos.len(), count) =>
+            {
+                for output in utxos.iter() {
+                    output.verify_range_proof().map_err(|_| HorizonSyncError::InvalidRangeProof)?;
+                }
+                self.snapshot.utxos.extend(utxos);
+            },
+            _ => return Err(HorizonSyncError::UnexpectedChunk),
+        }
+        Ok(())
+    }
+
+    /// Returns true once all the state has been downloaded
+    pub fn is_complete(&self) -> bool {
+        self.next_request().is_none()
+    }
+
+    /// Verify the downloaded state against the horizon header and create a blockchain state from it, replacing the
+    /// chain stored in the backend
+    pub fn finish<B: BlockchainBackend>(self, backend: B) -> Result<BlockchainState<B>, ChainError> {
+        if !self.is_complete() {
+            return Err(HorizonSyncError::Incomplete.into());
+        }
+        BlockchainState::from_snapshot(backend, self.network, self.snapshot)
+    }
+
+    /// Check that the state the metadata describes fits in a chain up to the horizon, so that a server can not make
+    /// the client download more than the chain can hold
+    fn check_metadata(&self, metadata: &SyncMetadata) -> Result<(), HorizonSyncError> {
+        if metadata.height > self.max_height {
+            return Err(HorizonSyncError::InvalidMetadata);
+        }
+        let blocks = metadata.height.saturating_add(1);
+        let max_weight = self.consensus_constants.max_block_weight;
+        let fits = |count: u64, weight: u64| blocks.checked_mul(max_weight / weight).map_or(true, |max| count <= max);
+        if metadata.utxo_count > metadata.output_count ||
+            !fits(metadata.output_count, WEIGHT_PER_OUTPUT) ||
+            !fits(metadata.kernel_count, WEIGHT_PER_KERNEL)
+        {
+            return Err(HorizonSyncError::InvalidMetadata);
+        }
+        Ok(())
+    }
+
+    /// Check that each block spends no more outputs than fit in a block, that the spent outputs are in the output MMR,
+    /// and that no more outputs are spent than the metadata leaves spent. Whether an output is spent twice is checked
+    /// when the output MMR is rebuilt.
+    fn check_spent_leaves(&self, spent: &[Vec<u32>]) -> Result<(), HorizonSyncError> {
+        let metadata = self.metadata.as_ref().ok_or(HorizonSyncError::UnexpectedChunk)?;
+        let max_spent = self.consensus_constants.max_block_weight / WEIGHT_PER_INPUT;
+        let mut spent_count = self.spent_count;
+        for leaves in spent.iter() {
+            spent_count += leaves.len() as u64;
+            if leaves.len() as u64 > max_spent ||
+                spent_count > metadata.output_count - metadata.utxo_count ||
+                leaves.iter().any(|&leaf_index| u64::from(leaf_index) >= metadata.output_count)
+            {
+                return Err(HorizonSyncError::InvalidSpentOutputs);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_header(&mut self, header: BlockHeader) -> Result<(), HorizonSyncError> {
+        let metadata = self.metadata.as_ref().ok_or(HorizonSyncError::UnexpectedChunk)?;
+        let height = self.snapshot.headers.len() as u64;
+        if height == 0 && self.network.genesis_block().map_or(false, |genesis| genesis.header.hash() != header.hash()) {
+            return Err(HorizonSyncError::InvalidHeaderChain);
+        }
+        validate_next_header(&self.consensus_constants, &self.snapshot.headers, &header)
+            .map_err(|reason| HorizonSyncError::InvalidHeader { height, reason })?;
+        if header.height == metadata.height && header.hash() != metadata.tip_hash {
+            return Err(HorizonSyncError::InvalidHeaderChain);
+        }
+        self.snapshot.headers.push(header);
+        Ok(())
+    }
+}
+
+/// A chunk may hold fewer items than requested, but must make progress
+fn is_partial(received: usize, requested: u64) -> bool {
+    received > 0 && received as u64 <= requested
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        blockchainstate::test::{create_block, create_chain, create_fee_kernel, create_kernel, create_output, spend},
+        error::BlockValidationError,
+        store::MemoryBackend,
+    };
+    use rand::OsRng;
+    use tari_core::{block::AggregateBody, difficulty::Difficulty};
+
+    #[test]
+    fn sync_to_horizon() {
+        let mut rng = OsRng::new().unwrap();
+        let mut state = BlockchainState::new();
+        let coinbase = create_output(&mut rng, 100);
+        let genesis = create_block(None, AggregateBody::new(vec![], vec![coinbase], vec![create_kernel(&mut rng)]));
+        state.add_block(genesis).unwrap();
+        let body = AggregateBody::new(vec![spend(&coinbase)], vec![], vec![create_fee_kernel(&mut rng, 100)]);
+        state.add_block(create_block(state.get_tip_header(), body)).unwrap();
+        for block in create_chain(&mut rng, state.get_tip_header().unwrap(), 2) {
+            state.add_block(block).unwrap();
+        }
+        let outputs = vec![create_output(&mut rng, 0), create_output(&mut rng, 0)];
+        state.add_block(create_block(state.get_tip_header(), AggregateBody::new(vec![], outputs, vec![]))).unwrap();
+
+        let server = HorizonSyncServer::new(&state).unwrap();
+        // The served state is fixed when the server is created
+        let next = create_chain(&mut rng, state.get_tip_header().unwrap(), 1);
+        state.add_block(next[0].clone()).unwrap();
+
+        let mut client = HorizonSyncClient::new(Network::LocalNet, 2);
+        assert_eq!(
+            client.add_chunk(server.handle(SyncRequest::Kernels { start: 0, count: 2 })).unwrap_err(),
+            HorizonSyncError::UnexpectedChunk
+        );
+        let mut bad_kernel = create_kernel(&mut rng);
+        bad_kernel.fee = 1;
+        let mut requests = 0;
+        while let Some(request) = client.next_request() {
+            if let SyncRequest::Headers { start: 2, count } = request {
+                // A header must have the difficulty calculated from the headers before it
+                let mut headers = page(&state.export_snapshot().headers, 2, count);
+                headers[1].pow.target_difficulty = headers[1].pow.target_difficulty + Difficulty::from(1);
+                let chunk = SyncChunk::Headers { start: 2, headers };
+                assert_eq!(
+                    client.add_chunk(chunk).unwrap_err(),
+                    HorizonSyncError::InvalidHeader { height: 3, reason: BlockValidationError::InvalidDifficulty }
+                );
+            }
+            if let SyncRequest::SpentLeaves { start: 0, .. } = request {
+                // Only outputs in the output MMR can be spent, and no more than the metadata leaves spent
+                let chunk = SyncChunk::SpentLeaves { start: 0, spent: vec![vec![], vec![10]] };
+                assert_eq!(client.add_chunk(chunk).unwrap_err(), HorizonSyncError::InvalidSpentOutputs);
+                let chunk = SyncChunk::SpentLeaves { start: 0, spent: vec![vec![0], vec![1]] };
+                assert_eq!(client.add_chunk(chunk).unwrap_err(), HorizonSyncError::InvalidSpentOutputs);
+            }
+            if let SyncRequest::Kernels { start: 2, .. } = request {
+                let chunk = SyncChunk::Kernels { start: 2, kernels: vec![bad_kernel.clone()] };
+                assert_eq!(client.add_chunk(chunk).unwrap_err(), HorizonSyncError::InvalidKernel);
+            }
+            client.add_chunk(server.handle(request)).unwrap();
+            requests += 1;
+        }
+        // Metadata, 3 chunks of headers, 4 of kernels, 5 of output hashes, 3 of spent leaves and 4 of UTXOs
+        assert_eq!(requests, 20);
+
+        let mut synced = client.finish(MemoryBackend::new()).unwrap();
+        assert_eq!(synced.get_tip_height(), Some(4));
+        assert_eq!(synced.utxo_count(), 8);
+        assert_eq!(synced.get_accumulated_difficulty(), state.get_accumulated_difficulty_at(4).unwrap());
+        synced.add_block(next[0].clone()).unwrap();
+        assert_eq!(synced.get_tip_header().unwrap().hash(), state.get_tip_header().unwrap().hash());
+    }
+
+    #[test]
+    fn bounded_metadata() {
+        let metadata = SyncMetadata {
+            height: 9,
+            tip_hash: vec![0; 32],
+            kernel_count: 10 * 19_500 / WEIGHT_PER_KERNEL,
+            output_count: 10 * 19_500 / WEIGHT_PER_OUTPUT,
+            utxo_count: 10 * 19_500 / WEIGHT_PER_OUTPUT,
+        };
+        let add_metadata = |client: &mut HorizonSyncClient, metadata: SyncMetadata| {
+            client.add_chunk(SyncChunk::Metadata(metadata)).map(|_| client.next_request())
+        };
+        let mut client = HorizonSyncClient::new(Network::LocalNet, 2);
+        assert_eq!(add_metadata(&mut client, metadata.clone()), Ok(Some(SyncRequest::Headers { start: 0, count: 2 })));
+
+        let invalid = vec![
+            SyncMetadata { kernel_count: metadata.kernel_count + 1, ..metadata.clone() },
+            SyncMetadata { output_count: metadata.output_count + 1, ..metadata.clone() },
+            SyncMetadata { utxo_count: metadata.output_count, output_count: 1, ..metadata.clone() },
+            SyncMetadata { height: u64::max_value(), ..metadata.clone() },
+        ];
+        for metadata in invalid {
+            let mut client = HorizonSyncClient::new(Network::LocalNet, 2);
+            assert_eq!(add_metadata(&mut client, metadata), Err(HorizonSyncError::InvalidMetadata));
+            assert_eq!(client.next_request(), Some(SyncRequest::Metadata));
+        }
+        let mut client = HorizonSyncClient::new(Network::LocalNet, 2).with_max_height(8);
+        assert_eq!(add_metadata(&mut client, metadata), Err(HorizonSyncError::InvalidMetadata));
+    }
+}
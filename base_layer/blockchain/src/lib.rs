@@ -20,7 +20,11 @@
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+pub mod block_builder;
 pub mod blockchainstate;
 pub mod chain;
+mod encoding;
 pub mod error;
+pub mod horizon_sync;
+pub mod snapshot;
 pub mod store;
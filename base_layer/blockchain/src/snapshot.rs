@@ -0,0 +1,53 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+// This file contains the snapshot of the blockchain state used to start a node without replaying the whole chain
+
+use merklemountainrange::merklenode::ObjectHash;
+use tari_core::{
+    blockheader::BlockHeader,
+    transaction::{TransactionKernel, TransactionOutput},
+};
+
+/// A consistent copy of the blockchain state at the chain tip. It holds everything needed to rebuild the MMRs and the
+/// UTXO set, but not the block bodies, so blocks up to the snapshot height can not be reverted after it is imported.
+/// When imported, the rebuilt MMR roots are verified against the commitments in the tip header.
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    /// The headers of the main chain, starting at the genesis block
+    pub headers: Vec<BlockHeader>,
+    /// Every kernel on the main chain, in the order they were added to the kernel MMR
+    pub kernels: Vec<TransactionKernel>,
+    /// The hashes of every output on the main chain, spent or not, in the order they were added to the output MMR
+    pub output_hashes: Vec<ObjectHash>,
+    /// The leaf indices in the output MMR of the outputs spent by each block, indexed by block height
+    pub spent_leaves: Vec<Vec<u32>>,
+    /// The unspent outputs
+    pub utxos: Vec<TransactionOutput>,
+}
+
+impl Snapshot {
+    /// Returns the height of the chain tip in the snapshot, or None if the snapshot holds no headers
+    pub fn get_height(&self) -> Option<u64> {
+        (self.headers.len() as u64).checked_sub(1)
+    }
+}
@@ -22,4 +22,408 @@
 
 // This file is where the database lives
 
-pub struct Store {}
+use crate::{
+    encoding::{
+        decode_block,
+        decode_kernel,
+        decode_output,
+        decode_record,
+        decode_snapshot,
+        encode_block,
+        encode_kernel,
+        encode_output,
+        encode_record,
+        encode_snapshot,
+    },
+    error::StoreError,
+    snapshot::Snapshot,
+};
+use merklemountainrange::{
+    backend::{LMDBBackend as MmrLMDBBackend, MemoryBackend as MmrMemoryBackend, MmrBackend},
+    merklenode::ObjectHash,
+};
+use storage::{
+    keyvalue_store::{BatchWrite, DataStore},
+    lmdb::{LMDBBatch, LMDBBuilder, LMDBStore},
+};
+use tari_core::{
+    block::Block,
+    blockheader::BlockHeader,
+    transaction::{TransactionKernel, TransactionOutput},
+};
+use tari_utilities::Hashable;
+
+/// The MMRs of the chain state whose nodes are kept in the backend
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MmrTree {
+    Header,
+    Kernel,
+    Output,
+}
+
+/// What the chain state records for every block of the stored chain, so that the state can be loaded without
+/// applying the blocks again
+#[derive(Clone, Debug)]
+pub struct BlockRecord {
+    /// The header of the block
+    pub header: BlockHeader,
+    /// The number of leaves in the kernel MMR before the block was applied. It is 0 for the blocks of a snapshot.
+    pub kernel_leaf_count: u64,
+    /// The number of leaves in the output MMR before the block was applied
+    pub output_leaf_count: u64,
+    /// The leaf indices of the outputs the block spent
+    pub spent_leaves: Vec<u32>,
+}
+
+/// A change to the stored chain. The changes of a single call to `BlockchainBackend::write` are applied in order.
+#[derive(Clone, Debug)]
+pub enum WriteOperation {
+    /// Append a block to the top of the stored chain. The block itself is None for the blocks of a snapshot, which
+    /// only have their record.
+    PushBlock { record: Box<BlockRecord>, block: Option<Box<Block>> },
+    /// Remove the block at the top of the stored chain
+    PopBlock,
+    /// Replace the stored chain, outputs and kernels with the snapshot. The blocks of the snapshot are then pushed
+    /// without their bodies.
+    WriteSnapshot(Box<Snapshot>),
+    /// Store an output, keyed by its hash
+    InsertOutput(Box<TransactionOutput>),
+    /// Remove the output with the given hash
+    DeleteOutput(ObjectHash),
+    /// Store the kernel with the given leaf index in the kernel MMR
+    InsertKernel(u64, Box<TransactionKernel>),
+    /// Remove the kernel with the given leaf index in the kernel MMR
+    DeleteKernel(u64),
+    /// Record the height below which the blocks can not be reverted
+    SetPrunedHeight(u64),
+}
+
+/// A persistent store for the chain state: the blocks of the main chain with a record of each, the outputs whose
+/// data is still held, the kernels and the nodes of the header, kernel and output MMRs. The stored chain either starts
+/// at the genesis block or on top of a snapshot, in which case the blocks up to the snapshot height only have their
+/// records. Every write leaves the stored chain consistent, even after a crash.
+pub trait BlockchainBackend {
+    /// The backend holding the nodes of an MMR, whose changes are written by `write`
+    type MmrBackend: MmrBackend;
+
+    /// Returns the height of the next block to be stored, i.e. the length of the stored chain including the blocks
+    /// covered by the snapshot
+    fn block_count(&self) -> u64;
+
+    /// Returns the block at the given height, or None if the store does not contain it
+    fn fetch_block(&self, height: u64) -> Result<Option<Block>, StoreError>;
+
+    /// Returns the record of the block at the given height, or None if the store does not contain it
+    fn fetch_record(&self, height: u64) -> Result<Option<BlockRecord>, StoreError>;
+
+    /// Returns the output with the given hash, or None if the store does not contain it
+    fn fetch_output(&self, hash: &ObjectHash) -> Result<Option<TransactionOutput>, StoreError>;
+
+    /// Returns the kernel with the given leaf index in the kernel MMR, or None if the store does not contain it
+    fn fetch_kernel(&self, leaf_index: u64) -> Result<Option<TransactionKernel>, StoreError>;
+
+    /// Returns the height below which the stored blocks can not be reverted
+    fn fetch_pruned_height(&self) -> Result<u64, StoreError>;
+
+    /// Returns the snapshot the stored chain starts from, or None if it starts at the genesis block
+    fn fetch_snapshot(&self) -> Result<Option<Snapshot>, StoreError>;
+
+    /// Open the backend holding the stored nodes of an MMR. Changes made to it are only stored by `write`.
+    fn mmr_backend(&self, tree: MmrTree) -> Result<Self::MmrBackend, StoreError>;
+
+    /// Apply the operations and store the changes made to the MMR backends since they were last written, all at once.
+    /// If the write fails nothing is stored.
+    fn write(&mut self, operations: Vec<WriteOperation>, mmrs: &mut [&mut Self::MmrBackend]) -> Result<(), StoreError>;
+}
+
+/// A backend that keeps the blocks in memory. The records, outputs, kernels and MMR nodes are not kept, as nothing
+/// survives a restart and the state holds them in memory anyway.
+#[derive(Default)]
+pub struct MemoryBackend {
+    snapshot: Option<Snapshot>,
+    blocks: Vec<Option<Block>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend { snapshot: None, blocks: Vec::new() }
+    }
+}
+
+impl BlockchainBackend for MemoryBackend {
+    type MmrBackend = MmrMemoryBackend;
+
+    fn block_count(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    fn fetch_block(&self, height: u64) -> Result<Option<Block>, StoreError> {
+        Ok(self.blocks.get(height as usize).and_then(Clone::clone))
+    }
+
+    fn fetch_record(&self, _height: u64) -> Result<Option<BlockRecord>, StoreError> {
+        Ok(None)
+    }
+
+    fn fetch_output(&self, _hash: &ObjectHash) -> Result<Option<TransactionOutput>, StoreError> {
+        Ok(None)
+    }
+
+    fn fetch_kernel(&self, _leaf_index: u64) -> Result<Option<TransactionKernel>, StoreError> {
+        Ok(None)
+    }
+
+    fn fetch_pruned_height(&self) -> Result<u64, StoreError> {
+        Ok(0)
+    }
+
+    fn fetch_snapshot(&self) -> Result<Option<Snapshot>, StoreError> {
+        Ok(self.snapshot.clone())
+    }
+
+    fn mmr_backend(&self, _tree: MmrTree) -> Result<MmrMemoryBackend, StoreError> {
+        Ok(MmrMemoryBackend::new())
+    }
+
+    fn write(
+        &mut self,
+        operations: Vec<WriteOperation>,
+        _mmrs: &mut [&mut MmrMemoryBackend],
+    ) -> Result<(), StoreError>
+    {
+        // Check that every block to remove is there first, so that a failed write changes nothing
+        let mut block_count = self.blocks.len();
+        for operation in operations.iter() {
+            match operation {
+                WriteOperation::PushBlock { .. } => block_count += 1,
+                WriteOperation::PopBlock => block_count = block_count.checked_sub(1).ok_or(StoreError::MissingBlock)?,
+                WriteOperation::WriteSnapshot(_) => block_count = 0,
+                _ => {},
+            }
+        }
+        for operation in operations {
+            match operation {
+                WriteOperation::PushBlock { block, .. } => self.blocks.push(block.map(|block| *block)),
+                WriteOperation::PopBlock => {
+                    self.blocks.pop();
+                },
+                WriteOperation::WriteSnapshot(snapshot) => {
+                    self.snapshot = Some(*snapshot);
+                    self.blocks.clear();
+                },
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The name of the LMDB database holding the blocks, the snapshot and the heights of the stored chain
+const BLOCKS_DB: &str = "blocks";
+/// The name of the LMDB database holding the block records, keyed by height
+const RECORDS_DB: &str = "records";
+/// The name of the LMDB database holding the outputs, keyed by output hash
+const OUTPUTS_DB: &str = "outputs";
+/// The name of the LMDB database holding the kernels, keyed by leaf index
+const KERNELS_DB: &str = "kernels";
+/// The names of the LMDB databases holding the nodes of the header, kernel and output MMRs
+const HEADER_MMR_DB: &str = "header_mmr";
+const KERNEL_MMR_DB: &str = "kernel_mmr";
+const OUTPUT_MMR_DB: &str = "output_mmr";
+/// The key of the snapshot in the blocks database. It can not clash with the 8 byte height keys.
+const SNAPSHOT_KEY: &[u8] = b"snapshot";
+/// The key of the number of stored blocks in the blocks database
+const BLOCK_COUNT_KEY: &[u8] = b"block_count";
+/// The key of the pruned height in the blocks database
+const PRUNED_HEIGHT_KEY: &[u8] = b"pruned_height";
+
+/// A backend that stores the chain state in LMDB databases. The blocks, block records and kernels are keyed by height
+/// or leaf index, the outputs by hash, and each MMR has a database of its own. All the changes of a write, usually
+/// those of a single block, are made in a single LMDB write transaction, along with the number of stored blocks.
+pub struct LMDBBackend {
+    blocks: LMDBStore,
+    records: LMDBStore,
+    outputs: LMDBStore,
+    kernels: LMDBStore,
+    block_count: u64,
+}
+
+impl LMDBBackend {
+    /// Open, or create, the blockchain database in the given directory. The directory must already exist and the
+    /// path must have a trailing slash. `size_mb` is the maximum size of the database.
+    pub fn open(path: &str, size_mb: usize) -> Result<LMDBBackend, StoreError> {
+        let store = LMDBBuilder::new()
+            .set_path(path)
+            .set_mapsize(size_mb)
+            .add_database(BLOCKS_DB)
+            .add_database(RECORDS_DB)
+            .add_database(OUTPUTS_DB)
+            .add_database(KERNELS_DB)
+            .add_database(HEADER_MMR_DB)
+            .add_database(KERNEL_MMR_DB)
+            .add_database(OUTPUT_MMR_DB)
+            .build()?;
+        let connect = |name: &str| -> Result<LMDBStore, StoreError> {
+            let mut db = store.clone();
+            db.connect(name)?;
+            Ok(db)
+        };
+        let blocks = connect(BLOCKS_DB)?;
+        let block_count = match blocks.get_raw(BLOCK_COUNT_KEY)? {
+            Some(bytes) => decode_height(&bytes)?,
+            None => 0,
+        };
+        Ok(LMDBBackend {
+            blocks,
+            records: connect(RECORDS_DB)?,
+            outputs: connect(OUTPUTS_DB)?,
+            kernels: connect(KERNELS_DB)?,
+            block_count,
+        })
+    }
+}
+
+impl BlockchainBackend for LMDBBackend {
+    type MmrBackend = MmrLMDBBackend;
+
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    fn fetch_block(&self, height: u64) -> Result<Option<Block>, StoreError> {
+        if height >= self.block_count {
+            return Ok(None);
+        }
+        match self.blocks.get_raw(&height_key(height))? {
+            Some(bytes) => Ok(Some(decode_block(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn fetch_record(&self, height: u64) -> Result<Option<BlockRecord>, StoreError> {
+        if height >= self.block_count {
+            return Ok(None);
+        }
+        match self.records.get_raw(&height_key(height))? {
+            Some(bytes) => Ok(Some(decode_record(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn fetch_output(&self, hash: &ObjectHash) -> Result<Option<TransactionOutput>, StoreError> {
+        match self.outputs.get_raw(hash)? {
+            Some(bytes) => Ok(Some(decode_output(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn fetch_kernel(&self, leaf_index: u64) -> Result<Option<TransactionKernel>, StoreError> {
+        match self.kernels.get_raw(&height_key(leaf_index))? {
+            Some(bytes) => Ok(Some(decode_kernel(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn fetch_pruned_height(&self) -> Result<u64, StoreError> {
+        match self.blocks.get_raw(PRUNED_HEIGHT_KEY)? {
+            Some(bytes) => decode_height(&bytes),
+            None => Ok(0),
+        }
+    }
+
+    fn fetch_snapshot(&self) -> Result<Option<Snapshot>, StoreError> {
+        match self.blocks.get_raw(SNAPSHOT_KEY)? {
+            Some(bytes) => Ok(Some(decode_snapshot(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn mmr_backend(&self, tree: MmrTree) -> Result<MmrLMDBBackend, StoreError> {
+        let db_name = match tree {
+            MmrTree::Header => HEADER_MMR_DB,
+            MmrTree::Kernel => KERNEL_MMR_DB,
+            MmrTree::Output => OUTPUT_MMR_DB,
+        };
+        Ok(MmrLMDBBackend::with_store(&self.blocks, db_name)?)
+    }
+
+    fn write(&mut self, operations: Vec<WriteOperation>, mmrs: &mut [&mut MmrLMDBBackend]) -> Result<(), StoreError> {
+        let mut block_count = self.block_count;
+        let mut batch = LMDBBatch::new(&self.blocks)?;
+        for operation in operations {
+            match operation {
+                WriteOperation::PushBlock { record, block } => {
+                    batch.connect(RECORDS_DB)?;
+                    batch.put_raw(&height_key(block_count), encode_record(&record))?;
+                    batch.connect(BLOCKS_DB)?;
+                    match block {
+                        Some(block) => batch.put_raw(&height_key(block_count), encode_block(&block))?,
+                        None => batch.delete(&height_key(block_count))?,
+                    }
+                    block_count += 1;
+                },
+                WriteOperation::PopBlock => {
+                    block_count = block_count.checked_sub(1).ok_or(StoreError::MissingBlock)?;
+                    batch.connect(RECORDS_DB)?;
+                    batch.delete(&height_key(block_count))?;
+                    batch.connect(BLOCKS_DB)?;
+                    batch.delete(&height_key(block_count))?;
+                },
+                WriteOperation::WriteSnapshot(snapshot) => {
+                    for db_name in [RECORDS_DB, OUTPUTS_DB, KERNELS_DB, BLOCKS_DB].iter() {
+                        batch.connect(db_name)?;
+                        batch.clear()?;
+                    }
+                    batch.put_raw(SNAPSHOT_KEY, encode_snapshot(&snapshot))?;
+                    block_count = 0;
+                },
+                WriteOperation::InsertOutput(output) => {
+                    batch.connect(OUTPUTS_DB)?;
+                    batch.put_raw(&output.hash(), encode_output(&output))?;
+                },
+                WriteOperation::DeleteOutput(hash) => {
+                    batch.connect(OUTPUTS_DB)?;
+                    batch.delete(&hash)?;
+                },
+                WriteOperation::InsertKernel(leaf_index, kernel) => {
+                    batch.connect(KERNELS_DB)?;
+                    batch.put_raw(&height_key(leaf_index), encode_kernel(&kernel))?;
+                },
+                WriteOperation::DeleteKernel(leaf_index) => {
+                    batch.connect(KERNELS_DB)?;
+                    batch.delete(&height_key(leaf_index))?;
+                },
+                WriteOperation::SetPrunedHeight(height) => {
+                    batch.connect(BLOCKS_DB)?;
+                    batch.put_raw(PRUNED_HEIGHT_KEY, height_key(height).to_vec())?;
+                },
+            }
+        }
+        batch.connect(BLOCKS_DB)?;
+        batch.put_raw(BLOCK_COUNT_KEY, height_key(block_count).to_vec())?;
+        for mmr in mmrs.iter() {
+            mmr.write_changes(&mut batch)?;
+        }
+        batch.commit()?;
+        for mmr in mmrs.iter_mut() {
+            mmr.mark_written();
+        }
+        self.block_count = block_count;
+        Ok(())
+    }
+}
+
+/// Heights and leaf indices are stored big-endian so that the keys sort in order
+fn height_key(height: u64) -> [u8; 8] {
+    height.to_be_bytes()
+}
+
+/// Decode a height written with `height_key`
+fn decode_height(bytes: &[u8]) -> Result<u64, StoreError> {
+    if bytes.len() != 8 {
+        return Err(StoreError::CorruptRecord);
+    }
+    let mut key = [0; 8];
+    key.copy_from_slice(bytes);
+    Ok(u64::from_be_bytes(key))
+}
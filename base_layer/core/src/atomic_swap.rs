@@ -0,0 +1,352 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Hash time locked outputs, with which two parties swap coins on two chains without trusting each other.
+//!
+//! The initiator picks a secret preimage and locks an output to its hash lock, to be claimed by the other party, with
+//! a refund height after which the initiator can take it back. The other party locks an output on the other chain to
+//! the same hash lock, with an earlier refund height. The initiator claims that output by revealing the preimage,
+//! which lets the other party claim the first output in turn. If either party walks away, both outputs are refunded.
+//!
+//! Both parties know the blinding factor of a swap output, which on its own does not let either of them spend it: a
+//! claim or a refund carries a script signature bound to the kernel of the spending transaction, which pays into an
+//! output whose blinding factor only the spender knows. A claim seen before it is mined can therefore not be copied
+//! into a transaction that pays someone else.
+
+use crate::{
+    fee::calculate_fee,
+    script::{hash_preimage, sign_script, HashLock, Preimage, Script, ScriptError},
+    transaction::{
+        build_kernel_challenge,
+        KernelFeatures,
+        OutputFeatures,
+        Transaction,
+        TransactionBuilder,
+        TransactionError,
+        TransactionInput,
+        TransactionKernel,
+    },
+    transaction_protocol::UnblindedOutput,
+    types::{BlindingFactor, Commitment, PublicKey, Signature},
+};
+use crypto::keys::PublicKey as PublicKeyTrait;
+use derive_error::Error;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum AtomicSwapError {
+    /// The hash lock was not provided
+    MissingHashLock,
+    /// The key that claims the output was not provided
+    MissingClaimKey,
+    /// The key that refunds the output was not provided
+    MissingRefundKey,
+    /// The height from which the output can be refunded was not provided
+    MissingRefundHeight,
+    /// The blinding factor, offset or one of the secrets or nonces of the spending transaction was not provided
+    MissingSecrets,
+    /// The swap output is not worth the fee of spending it
+    InsufficientFunds,
+    /// The kernel could not be signed
+    SigningError,
+    /// The input could not be signed
+    ScriptError(ScriptError),
+    /// The spending transaction is not valid
+    TransactionError(TransactionError),
+}
+
+/// Builds the hash time lock script of a swap output
+pub struct HashTimeLockBuilder {
+    hash_lock: Option<HashLock>,
+    claim_key: Option<PublicKey>,
+    refund_key: Option<PublicKey>,
+    refund_height: Option<u64>,
+}
+
+impl HashTimeLockBuilder {
+    /// Start a new hash time lock
+    pub fn new() -> Self {
+        Self { hash_lock: None, claim_key: None, refund_key: None, refund_height: None }
+    }
+
+    /// Set the hash lock, which is the hash of the preimage chosen by the initiator of the swap
+    pub fn with_hash_lock(mut self, hash_lock: HashLock) -> Self {
+        self.hash_lock = Some(hash_lock);
+        self
+    }
+
+    /// Set the hash lock from the preimage, for the initiator of the swap
+    pub fn with_preimage(self, preimage: &Preimage) -> Self {
+        self.with_hash_lock(hash_preimage(preimage))
+    }
+
+    /// Set the key of the party that claims the output with the preimage
+    pub fn with_claim_key(mut self, key: PublicKey) -> Self {
+        self.claim_key = Some(key);
+        self
+    }
+
+    /// Set the key of the party that is refunded if the output is not claimed
+    pub fn with_refund_key(mut self, key: PublicKey) -> Self {
+        self.refund_key = Some(key);
+        self
+    }
+
+    /// Set the height from which the output can be refunded
+    pub fn with_refund_height(mut self, height: u64) -> Self {
+        self.refund_height = Some(height);
+        self
+    }
+
+    /// Create the script, with which the swap output is locked
+    pub fn build(self) -> Result<Script, AtomicSwapError> {
+        Ok(Script::HashTimeLock {
+            hash_lock: self.hash_lock.ok_or(AtomicSwapError::MissingHashLock)?,
+            claim_key: self.claim_key.ok_or(AtomicSwapError::MissingClaimKey)?,
+            refund_key: self.refund_key.ok_or(AtomicSwapError::MissingRefundKey)?,
+            refund_height: self.refund_height.ok_or(AtomicSwapError::MissingRefundHeight)?,
+        })
+    }
+}
+
+/// Builds the transaction that spends a swap output, either claiming it with the preimage or refunding it, into an
+/// output whose blinding factor only the spender knows. The script signature of the input is bound to the kernel of
+/// this transaction, so neither the signature nor the revealed preimage can be copied into another transaction that
+/// spends the swap output.
+pub struct SwapSpendBuilder {
+    swap_output: UnblindedOutput,
+    preimage: Option<Preimage>,
+    script_secret: Option<BlindingFactor>,
+    script_nonce: Option<BlindingFactor>,
+    fee_per_gram: u64,
+    spend_key: Option<BlindingFactor>,
+    offset: Option<BlindingFactor>,
+    private_nonce: Option<BlindingFactor>,
+}
+
+impl SwapSpendBuilder {
+    /// Start spending the swap output, whose value and blinding factor both parties know
+    pub fn new(swap_output: UnblindedOutput) -> Self {
+        Self {
+            swap_output,
+            preimage: None,
+            script_secret: None,
+            script_nonce: None,
+            fee_per_gram: 0,
+            spend_key: None,
+            offset: None,
+            private_nonce: None,
+        }
+    }
+
+    /// Claim the output by revealing the preimage. Without it, the output is refunded, which can only be mined from
+    /// the refund height.
+    pub fn with_preimage(mut self, preimage: Preimage) -> Self {
+        self.preimage = Some(preimage);
+        self
+    }
+
+    /// Set the secret of the claim key when claiming, or of the refund key when refunding
+    pub fn with_script_secret(mut self, secret: BlindingFactor) -> Self {
+        self.script_secret = Some(secret);
+        self
+    }
+
+    /// Set the nonce of the script signature. It must never be reused.
+    pub fn with_script_nonce(mut self, nonce: BlindingFactor) -> Self {
+        self.script_nonce = Some(nonce);
+        self
+    }
+
+    /// Set the fee rate, in µT per gram of weight
+    pub fn with_fee_per_gram(mut self, fee_per_gram: u64) -> Self {
+        self.fee_per_gram = fee_per_gram;
+        self
+    }
+
+    /// Set the blinding factor of the output the swap output is spent into, which only the spender knows
+    pub fn with_spend_key(mut self, key: BlindingFactor) -> Self {
+        self.spend_key = Some(key);
+        self
+    }
+
+    /// Set the offset of the transaction
+    pub fn with_offset(mut self, offset: BlindingFactor) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Set the nonce of the kernel signature. It must never be reused.
+    pub fn with_nonce(mut self, nonce: BlindingFactor) -> Self {
+        self.private_nonce = Some(nonce);
+        self
+    }
+
+    /// Create the transaction, and the output it pays to, which the spender keeps to spend it later
+    pub fn build(self) -> Result<(Transaction, UnblindedOutput), AtomicSwapError> {
+        let secrets = (self.script_secret, self.script_nonce, self.spend_key, self.offset, self.private_nonce);
+        let (script_secret, script_nonce, spend_key, offset, nonce) = match secrets {
+            (Some(script_secret), Some(script_nonce), Some(spend_key), Some(offset), Some(nonce)) => {
+                (script_secret, script_nonce, spend_key, offset, nonce)
+            },
+            _ => return Err(AtomicSwapError::MissingSecrets),
+        };
+        let fee = calculate_fee(self.fee_per_gram, 1, 1, 1);
+        let value = self.swap_output.value.checked_sub(fee).ok_or(AtomicSwapError::InsufficientFunds)?;
+        let output = UnblindedOutput::new(value, spend_key, OutputFeatures::empty());
+        let excess_key = &(&spend_key - &offset) - &self.swap_output.spending_key;
+        let public_excess = PublicKey::from_secret_key(&excess_key);
+        let public_nonce = PublicKey::from_secret_key(&nonce);
+        let challenge = build_kernel_challenge(&public_nonce, &public_excess, KernelFeatures::empty(), fee, 0);
+        let excess_sig = Signature::sign(excess_key, nonce, challenge).map_err(|_| AtomicSwapError::SigningError)?;
+        let excess = Commitment::from_public_key(&public_excess);
+        let kernel = TransactionKernel {
+            features: KernelFeatures::empty(),
+            fee,
+            lock_height: 0,
+            excess: Some(excess),
+            excess_sig: Some(excess_sig),
+        };
+        let mut input = self.swap_output.as_transaction_input();
+        if let Some(preimage) = self.preimage {
+            input = input.with_script_preimage(preimage);
+        }
+        let transaction = TransactionBuilder::new()
+            .add_input(sign_script(input, &excess, script_secret, script_nonce)?)
+            .add_output(output.as_transaction_output())
+            .add_offset(offset)
+            .with_kernel(kernel)
+            .build()?;
+        Ok((transaction, output))
+    }
+}
+
+/// Returns the preimage revealed by an input that claimed a swap output, which the other party of the swap uses to
+/// claim the output on the other chain
+pub fn revealed_preimage(input: &TransactionInput, hash_lock: &HashLock) -> Option<Preimage> {
+    input.script_preimage.filter(|preimage| hash_preimage(preimage) == *hash_lock)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{encoding::BinaryEncoding, transaction::TransactionOutput};
+    use crypto::keys::SecretKey;
+    use rand::{OsRng, RngCore};
+
+    fn spend(
+        rng: &mut OsRng,
+        swap_output: &UnblindedOutput,
+        preimage: Option<Preimage>,
+        secret: BlindingFactor,
+    ) -> SwapSpendBuilder
+    {
+        let builder = SwapSpendBuilder::new(swap_output.clone())
+            .with_script_secret(secret)
+            .with_script_nonce(BlindingFactor::random(rng))
+            .with_fee_per_gram(1)
+            .with_spend_key(BlindingFactor::random(rng))
+            .with_offset(BlindingFactor::random(rng))
+            .with_nonce(BlindingFactor::random(rng));
+        match preimage {
+            Some(preimage) => builder.with_preimage(preimage),
+            None => builder,
+        }
+    }
+
+    #[test]
+    fn swap_paths() {
+        let mut rng = OsRng::new().unwrap();
+        let mut preimage = Preimage::default();
+        rng.fill_bytes(&mut preimage);
+        let (claim_secret, claim_key) = PublicKey::random_keypair(&mut rng);
+        let (refund_secret, refund_key) = PublicKey::random_keypair(&mut rng);
+        let builder = HashTimeLockBuilder::new().with_preimage(&preimage).with_claim_key(claim_key);
+        assert_eq!(builder.build().err(), Some(AtomicSwapError::MissingRefundKey));
+        let script = HashTimeLockBuilder::new()
+            .with_preimage(&preimage)
+            .with_claim_key(claim_key)
+            .with_refund_key(refund_key)
+            .with_refund_height(100)
+            .build()
+            .unwrap();
+
+        let swap_output = UnblindedOutput::new(1000, BlindingFactor::random(&mut rng), OutputFeatures::empty());
+        let output = swap_output.as_transaction_output().with_script(script);
+        let output = TransactionOutput::from_binary(&output.to_binary()).unwrap();
+        assert_eq!(output.script, Some(script));
+
+        let (claim, claimed) = spend(&mut rng, &swap_output, Some(preimage), claim_secret).build().unwrap();
+        let fee = calculate_fee(1, 1, 1, 1);
+        assert_eq!(claimed.value, 1000 - fee);
+        assert_eq!(claim.body.outputs[0].commitment, claimed.commitment());
+        let input = &claim.body.inputs[0];
+        assert_eq!(script.execute(input, &claim.body.kernels, 1), Ok(()));
+        assert_eq!(revealed_preimage(input, &hash_preimage(&preimage)), Some(preimage));
+        let (wrong, _) = spend(&mut rng, &swap_output, Some([0; 32]), claim_secret).build().unwrap();
+        assert_eq!(script.execute(&wrong.body.inputs[0], &wrong.body.kernels, 1), Err(ScriptError::InvalidPreimage));
+        let (stolen, _) = spend(&mut rng, &swap_output, Some(preimage), refund_secret).build().unwrap();
+        let result = script.execute(&stolen.body.inputs[0], &stolen.body.kernels, 1);
+        assert_eq!(result, Err(ScriptError::InvalidSignature));
+
+        let (refund, _) = spend(&mut rng, &swap_output, None, refund_secret).build().unwrap();
+        let input = &refund.body.inputs[0];
+        assert_eq!(script.execute(input, &refund.body.kernels, 99), Err(ScriptError::RefundTooEarly));
+        assert_eq!(script.execute(input, &refund.body.kernels, 100), Ok(()));
+
+        let builder = SwapSpendBuilder::new(swap_output.clone()).with_preimage(preimage);
+        assert_eq!(builder.build().err(), Some(AtomicSwapError::MissingSecrets));
+        let expensive = spend(&mut rng, &swap_output, None, refund_secret).with_fee_per_gram(1000);
+        assert_eq!(expensive.build().err(), Some(AtomicSwapError::InsufficientFunds));
+    }
+
+    #[test]
+    fn copied_claim_is_rejected() {
+        let mut rng = OsRng::new().unwrap();
+        let mut preimage = Preimage::default();
+        rng.fill_bytes(&mut preimage);
+        let (claim_secret, claim_key) = PublicKey::random_keypair(&mut rng);
+        let (refund_secret, refund_key) = PublicKey::random_keypair(&mut rng);
+        let script = HashTimeLockBuilder::new()
+            .with_preimage(&preimage)
+            .with_claim_key(claim_key)
+            .with_refund_key(refund_key)
+            .with_refund_height(100)
+            .build()
+            .unwrap();
+        let swap_output = UnblindedOutput::new(1000, BlindingFactor::random(&mut rng), OutputFeatures::empty());
+        let (claim, _) = spend(&mut rng, &swap_output, Some(preimage), claim_secret).build().unwrap();
+
+        // The initiator knows the blinding factor of the swap output and sees the claim before it is mined. It copies
+        // the claiming input, with the preimage and the claim signature, into a transaction that pays itself, which
+        // balances, but the script rejects the signature, as it was made for the kernel of the claim.
+        let (theft, _) = spend(&mut rng, &swap_output, Some(preimage), refund_secret).build().unwrap();
+        let theft = TransactionBuilder::new()
+            .add_input(claim.body.inputs[0].clone())
+            .add_outputs(theft.body.outputs.clone())
+            .add_offset(theft.offset)
+            .with_kernel(theft.body.kernels[0].clone())
+            .build()
+            .unwrap();
+        let result = script.execute(&theft.body.inputs[0], &theft.body.kernels, 1);
+        assert_eq!(result, Err(ScriptError::InvalidSignature));
+    }
+}
@@ -25,17 +25,115 @@
 
 use crate::{
     blockheader::BlockHeader,
-    transaction::{TransactionError, TransactionInput, TransactionKernel, TransactionOutput},
+    consensus::ConsensusConstants,
+    fee::calculate_fee,
+    transaction::{
+        KernelFeatures,
+        KernelSum,
+        Transaction,
+        TransactionError,
+        TransactionInput,
+        TransactionKernel,
+        TransactionOutput,
+    },
+    types::{BlindingFactor, Commitment, CommitmentFactory},
 };
+use crypto::{commitment::HomomorphicCommitmentFactory, ristretto::RistrettoSecretKey};
+use rayon::prelude::*;
+use tari_utilities::Hashable;
+
+/// The weight of a transaction input
+pub const WEIGHT_PER_INPUT: u64 = 1;
+/// The weight of a transaction output, which is dominated by its range proof
+pub const WEIGHT_PER_OUTPUT: u64 = 13;
+/// The weight of a transaction kernel
+pub const WEIGHT_PER_KERNEL: u64 = 3;
+
+/// Calculate the weight of a block or transaction with the given numbers of inputs, outputs and kernels. The weight
+/// of a block is limited by consensus, so miners and the mempool use it to decide which transactions fit in a block.
+pub fn calculate_weight(num_inputs: usize, num_outputs: usize, num_kernels: usize) -> u64 {
+    num_inputs as u64 * WEIGHT_PER_INPUT +
+        num_outputs as u64 * WEIGHT_PER_OUTPUT +
+        num_kernels as u64 * WEIGHT_PER_KERNEL
+}
 
 /// A Tari block. Blocks are linked together into a blockchain.
+#[derive(Clone, Debug)]
 pub struct Block {
     pub header: BlockHeader,
     pub body: AggregateBody,
 }
 
+impl Block {
+    /// Assemble a block from its header and body. The body is sorted into canonical order.
+    pub fn new(header: BlockHeader, mut body: AggregateBody) -> Block {
+        body.sort();
+        Block { header, body }
+    }
+
+    /// Add a transaction to the block. Its inputs, outputs and kernels are merged into the body, which is kept in
+    /// canonical order, and its kernel offset is added to the total kernel offset of the header, so that the
+    /// individual transactions can not be told apart. The MMR roots of the header are not updated.
+    pub fn add_transaction(mut self, tx: Transaction) -> Block {
+        self.header.total_kernel_offset = &self.header.total_kernel_offset + &tx.offset;
+        self.body = self.body.add_inputs(tx.body.inputs).add_outputs(tx.body.outputs).add_kernels(tx.body.kernels);
+        self
+    }
+
+    /// Check that the body is valid for the header on its own, without looking at the UTXO set: the weight, the fees,
+    /// the kernel lock heights, the coinbase and its maturity, which depend on the height in the header, and the
+    /// internal consistency of the body with the kernel offset of the block. `prev` is the header the block builds on.
+    /// The genesis block may leave out the coinbase, and its kernel sum is not checked, as it allocates the initial
+    /// outputs. The MMR roots in the header commit to the whole chain, so they are checked when the block is applied
+    /// to a chain.
+    pub fn check_internal_consistency(
+        &self,
+        prev: Option<&BlockHeader>,
+        constants: &ConsensusConstants,
+    ) -> Result<(), TransactionError>
+    {
+        let body = &self.body;
+        let height = self.header.height;
+        if body.calculate_weight() > constants.max_block_weight {
+            return Err(TransactionError::MaxWeightExceeded);
+        }
+        body.verify_minimum_fee(constants.min_fee_per_gram)?;
+        body.verify_lock_height(height)?;
+        let reward = constants.emission_schedule().block_reward(height);
+        match body.verify_coinbase(reward) {
+            Err(TransactionError::MissingCoinbase) if height == 0 => {},
+            result => result?,
+        }
+        let maturity = height + constants.coinbase_lock_height;
+        if body.outputs.iter().any(|output| output.features.is_coinbase() && output.features.maturity < maturity) {
+            return Err(TransactionError::InvalidCoinbase);
+        }
+        body.verify_sorting()?;
+        self.validate_batch()?;
+        if height == 0 {
+            return Ok(());
+        }
+        let coinbase = body.kernels.iter().fold(reward, |value, kernel| value.saturating_add(kernel.fee));
+        body.verify_kernel_sum(&self.header.block_kernel_offset(prev), coinbase)
+    }
+
+    /// Verify all range proofs and kernel signatures of the block. This gives the same result as
+    /// `AggregateBody::verify_range_proofs` and `AggregateBody::verify_kernel_signatures`, but the kernel signatures
+    /// are split into one batch per thread of the global thread pool, and each batch is verified at once with
+    /// `TransactionKernel::verify_batch`, which is several times faster for a full block.
+    /// TODO: Range proofs are verified one by one, in parallel, until `RangeProof` has a proving system that supports
+    /// batch verification.
+    pub fn validate_batch(&self) -> Result<(), TransactionError> {
+        self.body.outputs.par_iter().try_for_each(TransactionOutput::verify_range_proof)?;
+        let kernels = &self.body.kernels;
+        let batch_size = (kernels.len() / rayon::current_num_threads()).max(1);
+        kernels.par_chunks(batch_size).try_for_each(TransactionKernel::verify_batch)
+    }
+}
+
 /// The components of the block or transaction. The same struct can be used for either, since in Mimblewimble,
 /// cut-through means that blocks and transactions have the same structure.
+#[derive(Clone, Debug)]
 pub struct AggregateBody {
     /// List of inputs spent by the transaction.
     pub inputs: Vec<TransactionInput>,
@@ -64,35 +162,42 @@ impl AggregateBody {
     /// Add an input to the existing aggregate body
     pub fn add_input(mut self, input: TransactionInput) -> AggregateBody {
         self.inputs.push(input);
-        self.inputs.sort();
+        self.inputs.sort_by_cached_key(Hashable::hash);
         self
     }
 
     /// Add a series of inputs to the existing aggregate body
     pub fn add_inputs(mut self, mut inputs: Vec<TransactionInput>) -> AggregateBody {
         self.inputs.append(&mut inputs);
-        self.inputs.sort();
+        self.inputs.sort_by_cached_key(Hashable::hash);
         self
     }
 
     /// Add an output to the existing aggregate body
     pub fn add_output(mut self, output: TransactionOutput) -> AggregateBody {
         self.outputs.push(output);
-        self.outputs.sort();
+        self.outputs.sort_by_cached_key(Hashable::hash);
         self
     }
 
     /// Add an output to the existing aggregate body
     pub fn add_outputs(mut self, mut outputs: Vec<TransactionOutput>) -> AggregateBody {
         self.outputs.append(&mut outputs);
-        self.outputs.sort();
+        self.outputs.sort_by_cached_key(Hashable::hash);
         self
     }
 
     /// Add a kernel to the existing aggregate body
     pub fn add_kernel(mut self, kernel: TransactionKernel) -> AggregateBody {
         self.kernels.push(kernel);
-        self.kernels.sort();
+        self.kernels.sort_by_cached_key(Hashable::hash);
+        self
+    }
+
+    /// Add a series of kernels to the existing aggregate body
+    pub fn add_kernels(mut self, mut kernels: Vec<TransactionKernel>) -> AggregateBody {
+        self.kernels.append(&mut kernels);
+        self.kernels.sort_by_cached_key(Hashable::hash);
         self
     }
 
@@ -102,11 +207,39 @@ impl AggregateBody {
         self
     }
 
-    /// Sort the component lists of the aggregate body
+    /// Sort the component lists of the aggregate body. The lists are sorted by hash, which is computed once per
+    /// element rather than on every comparison.
     pub fn sort(&mut self) {
-        self.inputs.sort();
-        self.outputs.sort();
-        self.kernels.sort();
+        self.inputs.sort_by_cached_key(Hashable::hash);
+        self.outputs.sort_by_cached_key(Hashable::hash);
+        self.kernels.sort_by_cached_key(Hashable::hash);
+    }
+
+    /// Calculate the weight of the inputs, outputs and kernels of the aggregate body
+    pub fn calculate_weight(&self) -> u64 {
+        calculate_weight(self.inputs.len(), self.outputs.len(), self.kernels.len())
+    }
+
+    /// Whether the inputs, outputs and kernels are each sorted in the canonical order given by `sort`. Bodies are
+    /// only valid in this order, so that a block or transaction has one encoding, whatever order it was built in.
+    pub fn is_sorted(&self) -> bool {
+        is_sorted(&self.inputs) && is_sorted(&self.outputs) && is_sorted(&self.kernels)
+    }
+
+    /// Verify that the inputs, outputs and kernels are each sorted in the canonical order given by `sort`
+    pub fn verify_sorting(&self) -> Result<(), TransactionError> {
+        if !self.is_sorted() {
+            return Err(TransactionError::InvalidSortOrder);
+        }
+        Ok(())
+    }
+
+    /// Verify the range proofs of all outputs contained in this aggregate body
+    pub fn verify_range_proofs(&self) -> Result<(), TransactionError> {
+        for output in self.outputs.iter() {
+            output.verify_range_proof()?;
+        }
+        Ok(())
     }
 
     /// Verify the signatures in all kernels contained in this aggregate body
@@ -116,4 +249,176 @@ impl AggregateBody {
         }
         Ok(())
     }
+
+    /// Verify that the lock heights of all kernels have been reached at the given block height
+    pub fn verify_lock_height(&self, height: u64) -> Result<(), TransactionError> {
+        for kernel in self.kernels.iter() {
+            kernel.verify_lock_height(height)?;
+        }
+        Ok(())
+    }
+
+    /// Verify that the kernels pay at least the fee for the weight of the body at the given fee rate. Coinbase outputs
+    /// and kernels are paid for by the block reward, so they do not count towards the weight that must pay fees.
+    pub fn verify_minimum_fee(&self, fee_per_gram: u64) -> Result<(), TransactionError> {
+        let num_outputs = self.outputs.iter().filter(|output| !output.features.is_coinbase()).count();
+        let kernels: Vec<&TransactionKernel> =
+            self.kernels.iter().filter(|kernel| !kernel.features.contains(KernelFeatures::COINBASE_KERNEL)).collect();
+        let fees = kernels.iter().fold(0u64, |fees, kernel| fees.saturating_add(kernel.fee));
+        if fees < calculate_fee(fee_per_gram, self.inputs.len(), num_outputs, kernels.len()) {
+            return Err(TransactionError::InsufficientFee);
+        }
+        Ok(())
+    }
+
+    /// Calculate the sum of the kernel excesses and the offset, along with the total fees of the kernels
+    fn sum_kernels(&self, offset: &BlindingFactor) -> Result<KernelSum, TransactionError> {
+        let offset = CommitmentFactory::create(offset, &RistrettoSecretKey::default());
+        self.kernels.iter().try_fold(KernelSum { fees: 0, sum: offset }, |acc, kernel| {
+            Ok(KernelSum {
+                fees: acc.fees.checked_add(kernel.fee).ok_or(TransactionError::ValidationError)?,
+                sum: &acc.sum + &kernel.excess.unwrap_or(CommitmentFactory::zero()),
+            })
+        })
+    }
+
+    /// Verify that the outputs less the inputs balance the kernel excesses and the offset, once the fees paid by the
+    /// kernels and the value of the coinbase are accounted for. The coinbase value is zero for a transaction.
+    pub fn verify_kernel_sum(&self, offset: &BlindingFactor, coinbase: u64) -> Result<(), TransactionError> {
+        let kernel_sum = self.sum_kernels(offset)?;
+        let outputs = self.outputs.iter().fold(CommitmentFactory::zero(), |acc, output| &acc + &output.commitment);
+        let inputs = self.inputs.iter().fold(CommitmentFactory::zero(), |acc, input| &acc + &input.commitment);
+        let sum_io = &(&outputs - &inputs) + &value_commitment(kernel_sum.fees);
+        if sum_io != &kernel_sum.sum + &value_commitment(coinbase) {
+            return Err(TransactionError::ValidationError);
+        }
+        Ok(())
+    }
+
+    /// Run every check that depends only on the body itself, and not on the chain it is added to: the sort order,
+    /// the range proofs, the kernel signatures and the kernel sum. This is the validation shared by transactions in
+    /// the mempool and blocks, where `coinbase` is the value of the coinbase of the block.
+    pub fn validate_internal_consistency(
+        &self,
+        offset: &BlindingFactor,
+        coinbase: u64,
+    ) -> Result<(), TransactionError>
+    {
+        self.verify_sorting()?;
+        self.verify_range_proofs()?;
+        self.verify_kernel_signatures()?;
+        self.verify_kernel_sum(offset, coinbase)
+    }
+
+    /// Verify that the body has exactly one coinbase output and one coinbase kernel, that the coinbase kernel pays no
+    /// fee and that the coinbase output is worth exactly the block reward plus the fees of all the kernels. The value
+    /// is checked by balancing the coinbase output less that value with the excess of the coinbase kernel.
+    pub fn verify_coinbase(&self, reward: u64) -> Result<(), TransactionError> {
+        let outputs: Vec<&TransactionOutput> =
+            self.outputs.iter().filter(|output| output.features.is_coinbase()).collect();
+        let kernels: Vec<&TransactionKernel> =
+            self.kernels.iter().filter(|kernel| kernel.features.contains(KernelFeatures::COINBASE_KERNEL)).collect();
+        if outputs.is_empty() && kernels.is_empty() {
+            return Err(TransactionError::MissingCoinbase);
+        }
+        if outputs.len() != 1 || kernels.len() != 1 || kernels[0].fee != 0 {
+            return Err(TransactionError::InvalidCoinbase);
+        }
+        let value = self
+            .kernels
+            .iter()
+            .try_fold(reward, |value, kernel| value.checked_add(kernel.fee))
+            .ok_or(TransactionError::InvalidCoinbase)?;
+        let excess = kernels[0].excess.unwrap_or(CommitmentFactory::zero());
+        if &outputs[0].commitment - &value_commitment(value) != excess {
+            return Err(TransactionError::InvalidCoinbase);
+        }
+        Ok(())
+    }
+}
+
+/// Whether the items are in ascending order of their hashes, which is the order `AggregateBody::sort` puts them in
+fn is_sorted<T: Hashable>(items: &[T]) -> bool {
+    let hashes: Vec<Vec<u8>> = items.iter().map(Hashable::hash).collect();
+    hashes.windows(2).all(|pair| pair[0] <= pair[1])
+}
+
+/// A commitment to the value with a zero blinding factor
+fn value_commitment(value: u64) -> Commitment {
+    CommitmentFactory::create(&RistrettoSecretKey::default(), &RistrettoSecretKey::from(value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{coinbase::CoinbaseBuilder, consensus::Network, difficulty::Difficulty, pow::ProofOfWork};
+    use chrono::Utc;
+    use crypto::keys::SecretKey;
+    use rand::OsRng;
+
+    fn create_header(height: u64) -> BlockHeader {
+        BlockHeader {
+            version: 0,
+            height,
+            prev_hash: [0; 32],
+            timestamp: Utc::now(),
+            output_mmr: [0; 32],
+            kernel_mmr: [0; 32],
+            total_kernel_offset: BlindingFactor::default(),
+            pow: ProofOfWork::new(Difficulty::min()),
+        }
+    }
+
+    #[test]
+    fn check_internal_consistency() {
+        let mut rng = OsRng::new().unwrap();
+        let constants = Network::LocalNet.consensus_constants();
+        let header = create_header(1);
+        let prev = BlockHeader { height: 0, ..header.clone() };
+        let reward = constants.emission_schedule().block_reward(1);
+        let (output, kernel) = CoinbaseBuilder::new()
+            .with_block_height(1)
+            .with_coinbase_lock_height(constants.coinbase_lock_height)
+            .with_reward(reward)
+            .with_spend_key(BlindingFactor::random(&mut rng))
+            .with_nonce(BlindingFactor::random(&mut rng))
+            .build()
+            .unwrap();
+        let block = Block::new(header.clone(), AggregateBody::new(vec![], vec![output], vec![kernel]));
+        assert_eq!(block.check_internal_consistency(Some(&prev), &constants), Ok(()));
+
+        let light = ConsensusConstants { max_block_weight: WEIGHT_PER_OUTPUT, ..constants.clone() };
+        assert_eq!(block.check_internal_consistency(Some(&prev), &light), Err(TransactionError::MaxWeightExceeded));
+        let empty = Block::new(header.clone(), AggregateBody::empty());
+        assert_eq!(empty.check_internal_consistency(Some(&prev), &constants), Err(TransactionError::MissingCoinbase));
+        let offset = BlindingFactor::random(&mut rng);
+        let shifted = Block { header: BlockHeader { total_kernel_offset: offset, ..header }, ..block };
+        assert_eq!(shifted.check_internal_consistency(Some(&prev), &constants), Err(TransactionError::ValidationError));
+    }
+
+    #[test]
+    fn validate_batch() {
+        let mut rng = OsRng::new().unwrap();
+        let mut body = AggregateBody::empty();
+        for height in 1..10 {
+            let (output, kernel) = CoinbaseBuilder::new()
+                .with_block_height(height)
+                .with_coinbase_lock_height(1)
+                .with_reward(height * 100)
+                .with_spend_key(BlindingFactor::random(&mut rng))
+                .with_nonce(BlindingFactor::random(&mut rng))
+                .build()
+                .unwrap();
+            body = body.add_outputs(vec![output]).add_kernels(vec![kernel]);
+        }
+        let block = Block::new(create_header(10), body);
+        assert_eq!(block.validate_batch(), Ok(()));
+
+        let mut tampered = block.clone();
+        tampered.body.kernels[4].fee += 1;
+        assert_eq!(tampered.validate_batch(), Err(TransactionError::InvalidSignatureError));
+        let mut unsigned = block;
+        unsigned.body.kernels[8].excess_sig = None;
+        assert_eq!(unsigned.validate_batch(), Err(TransactionError::NoSignatureError));
+    }
 }
@@ -23,21 +23,32 @@
 // Portions of this file were originally copyrighted (c) 2018 The Grin Developers, issued under the Apache License,
 // Version 2.0, available at http://www.apache.org/licenses/LICENSE-2.0.
 
-use crate::{pow::ProofOfWork, types::BlindingFactor};
+use crate::{
+    difficulty::Difficulty,
+    pow::{PowAlgorithm, ProofOfWork},
+    types::{BlindingFactor, BlockHeaderHasher},
+};
 use chrono::{DateTime, Utc};
+use derive::MmrLeaf;
+use tari_utilities::Hashable;
 
-type BlockHash = [u8; 32];
+pub type BlockHash = [u8; 32];
 
 /// The BlockHeader contains all the metadata for the block, including proof of work, a link to the previous block
 /// and the transaction kernels.
+#[derive(Clone, Debug, MmrLeaf)]
+#[Digest = "BlockHeaderHasher"]
 pub struct BlockHeader {
     /// Version of the block
+    #[hashable(with = "u16_bytes")]
     pub version: u16,
     /// Height of this block since the genesis block (height 0)
+    #[hashable(with = "u64_bytes")]
     pub height: u64,
     /// Hash of the block previous to this in the chain.
     pub prev_hash: BlockHash,
     /// Timestamp at which the block was built.
+    #[hashable(with = "timestamp_bytes")]
     pub timestamp: DateTime<Utc>,
     /// This is the MMR root of the outputs
     pub output_mmr: BlockHash,
@@ -46,14 +57,95 @@ pub struct BlockHeader {
     /// Total accumulated sum of kernel offsets since genesis block. We can derive the kernel offset sum for *this*
     /// block from the total kernel offset of the previous block header.
     pub total_kernel_offset: BlindingFactor,
-    /// Nonce used
-    /// Proof of work summary
+    /// Proof of work summary, including the nonce
+    #[hashable(with = "pow_bytes")]
     pub pow: ProofOfWork,
 }
 
 impl BlockHeader {
-    /// This function will validate the proof of work in the header
+    /// The difficulty achieved by the proof of work of the header. For `PowAlgorithm::Blake` this is the difficulty
+    /// of the header hash, which commits to the nonce. Fork choice adds up the target difficulties of the blocks, and
+    /// this is what each block is checked against.
+    pub fn achieved_difficulty(&self) -> Difficulty {
+        match self.pow.pow_algo {
+            PowAlgorithm::Blake => Difficulty::from_hash(&self.hash()),
+        }
+    }
+
+    /// Check that the proof of work of the header meets its target difficulty
     pub fn validate_pow(&self) -> bool {
-        unimplemented!();
+        self.achieved_difficulty() >= self.pow.target_difficulty
+    }
+
+    /// The kernel offset of this block alone, which is the sum of the offsets of the transactions in it. It is the
+    /// difference between the total kernel offset of this header and that of the previous header, if there is one.
+    pub fn block_kernel_offset(&self, prev: Option<&BlockHeader>) -> BlindingFactor {
+        match prev {
+            Some(prev) => &self.total_kernel_offset - &prev.total_kernel_offset,
+            None => self.total_kernel_offset,
+        }
+    }
+}
+
+// Field encodings used by the derived header hash
+
+fn u16_bytes(value: &u16) -> [u8; 2] {
+    value.to_le_bytes()
+}
+
+fn u64_bytes(value: &u64) -> [u8; 8] {
+    value.to_le_bytes()
+}
+
+fn pow_bytes(pow: &ProofOfWork) -> Vec<u8> {
+    pow.to_bytes()
+}
+
+// The seconds and nanoseconds of the timestamp, as in the binary encoding of the header, so that two encodings of a
+// header never share a hash
+fn timestamp_bytes(timestamp: &DateTime<Utc>) -> [u8; 12] {
+    let mut bytes = [0; 12];
+    bytes[..8].copy_from_slice(&timestamp.timestamp().to_le_bytes());
+    bytes[8..].copy_from_slice(&timestamp.timestamp_subsec_nanos().to_le_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn create_header() -> BlockHeader {
+        BlockHeader {
+            version: 0,
+            height: 1,
+            prev_hash: [0; 32],
+            timestamp: Utc::now(),
+            output_mmr: [0; 32],
+            kernel_mmr: [0; 32],
+            total_kernel_offset: BlindingFactor::default(),
+            pow: ProofOfWork::new(Difficulty::min()),
+        }
+    }
+
+    #[test]
+    fn pow_is_hashed() {
+        let header = create_header();
+        let hash = header.hash();
+        let changes = vec![
+            ProofOfWork { nonce: 1, ..header.pow.clone() },
+            ProofOfWork { target_difficulty: Difficulty::from(2), ..header.pow.clone() },
+            ProofOfWork { pow_algo: PowAlgorithm::Blake, pow_data: vec![1], ..header.pow.clone() },
+        ];
+        for pow in changes {
+            assert_ne!(BlockHeader { pow, ..header.clone() }.hash(), hash);
+        }
+    }
+
+    #[test]
+    fn timestamp_is_hashed() {
+        let header = create_header();
+        let timestamp = Utc.timestamp(header.timestamp.timestamp(), header.timestamp.timestamp_subsec_nanos() ^ 1);
+        assert_ne!(BlockHeader { timestamp, ..header.clone() }.hash(), header.hash());
     }
 }
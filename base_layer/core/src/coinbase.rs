@@ -0,0 +1,157 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+// This file contains the builder of the coinbase, the output and kernel with which a miner claims the block reward
+// and the fees of a block
+
+use crate::{
+    transaction::{build_kernel_challenge, KernelFeatures, OutputFeatures, TransactionKernel, TransactionOutput},
+    transaction_protocol::UnblindedOutput,
+    types::{BlindingFactor, Commitment, PublicKey, Signature},
+};
+use crypto::keys::PublicKey as PublicKeyTrait;
+use derive_error::Error;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum CoinbaseBuildError {
+    /// The height of the block was not provided
+    MissingBlockHeight,
+    /// The number of blocks the coinbase output is locked for was not provided
+    MissingCoinbaseLockHeight,
+    /// The value of the coinbase was not provided
+    MissingReward,
+    /// The spend key of the coinbase output was not provided
+    MissingSpendKey,
+    /// The nonce of the kernel signature was not provided
+    MissingNonce,
+    /// The kernel signature could not be created
+    SigningError,
+}
+
+/// Builds the coinbase output and kernel of a block. The output is flagged as a coinbase output that matures
+/// `coinbase_lock_height` blocks after the block, and the kernel is flagged as a coinbase kernel that is locked to the
+/// height of the block.
+pub struct CoinbaseBuilder {
+    block_height: Option<u64>,
+    coinbase_lock_height: Option<u64>,
+    reward: Option<u64>,
+    spend_key: Option<BlindingFactor>,
+    private_nonce: Option<BlindingFactor>,
+}
+
+impl CoinbaseBuilder {
+    /// Start a new coinbase
+    pub fn new() -> Self {
+        Self { block_height: None, coinbase_lock_height: None, reward: None, spend_key: None, private_nonce: None }
+    }
+
+    /// Set the height of the block the coinbase is for
+    pub fn with_block_height(mut self, height: u64) -> Self {
+        self.block_height = Some(height);
+        self
+    }
+
+    /// Set the number of blocks the coinbase output can not be spent for, as given by the consensus constants
+    pub fn with_coinbase_lock_height(mut self, lock_height: u64) -> Self {
+        self.coinbase_lock_height = Some(lock_height);
+        self
+    }
+
+    /// Set the value of the coinbase, which is the block reward plus the fees of the block
+    pub fn with_reward(mut self, reward: u64) -> Self {
+        self.reward = Some(reward);
+        self
+    }
+
+    /// Set the blinding factor of the coinbase output, which the miner needs to spend it
+    pub fn with_spend_key(mut self, key: BlindingFactor) -> Self {
+        self.spend_key = Some(key);
+        self
+    }
+
+    /// Set the nonce of the kernel signature. It must never be reused.
+    pub fn with_nonce(mut self, nonce: BlindingFactor) -> Self {
+        self.private_nonce = Some(nonce);
+        self
+    }
+
+    /// Create the coinbase output and the kernel that balances it
+    pub fn build(self) -> Result<(TransactionOutput, TransactionKernel), CoinbaseBuildError> {
+        let height = self.block_height.ok_or(CoinbaseBuildError::MissingBlockHeight)?;
+        let lock_height = self.coinbase_lock_height.ok_or(CoinbaseBuildError::MissingCoinbaseLockHeight)?;
+        let reward = self.reward.ok_or(CoinbaseBuildError::MissingReward)?;
+        let spend_key = self.spend_key.ok_or(CoinbaseBuildError::MissingSpendKey)?;
+        let nonce = self.private_nonce.ok_or(CoinbaseBuildError::MissingNonce)?;
+        let features = OutputFeatures::create_coinbase(height + lock_height);
+        let output = UnblindedOutput::new(reward, spend_key, features).as_transaction_output();
+        let public_excess = PublicKey::from_secret_key(&spend_key);
+        let challenge = build_kernel_challenge(
+            &PublicKey::from_secret_key(&nonce),
+            &public_excess,
+            KernelFeatures::COINBASE_KERNEL,
+            0,
+            height,
+        );
+        let excess_sig = Signature::sign(spend_key, nonce, challenge).map_err(|_| CoinbaseBuildError::SigningError)?;
+        let kernel = TransactionKernel {
+            features: KernelFeatures::COINBASE_KERNEL,
+            fee: 0,
+            lock_height: height,
+            excess: Some(Commitment::from_public_key(&public_excess)),
+            excess_sig: Some(excess_sig),
+        };
+        Ok((output, kernel))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::block::AggregateBody;
+    use crypto::keys::SecretKey;
+    use rand::OsRng;
+
+    #[test]
+    fn build_coinbase() {
+        let mut rng = OsRng::new().unwrap();
+        let builder = CoinbaseBuilder::new().with_block_height(42).with_reward(100);
+        assert_eq!(builder.build().err(), Some(CoinbaseBuildError::MissingCoinbaseLockHeight));
+        let builder = CoinbaseBuilder::new().with_block_height(42).with_coinbase_lock_height(5).with_reward(100);
+        assert_eq!(builder.build().err(), Some(CoinbaseBuildError::MissingSpendKey));
+        let (output, kernel) = CoinbaseBuilder::new()
+            .with_block_height(42)
+            .with_coinbase_lock_height(5)
+            .with_reward(100)
+            .with_spend_key(BlindingFactor::random(&mut rng))
+            .with_nonce(BlindingFactor::random(&mut rng))
+            .build()
+            .unwrap();
+        assert_eq!(output.features, OutputFeatures::create_coinbase(47));
+        assert!(!output.features.is_mature_at(46));
+        assert_eq!(kernel.lock_height, 42);
+        kernel.verify_signature().unwrap();
+        let body = AggregateBody::new(vec![], vec![output], vec![kernel]);
+        body.verify_coinbase(100).unwrap();
+        assert!(body.verify_coinbase(99).is_err());
+        assert!(body.verify_lock_height(41).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Compact blocks, which relay a block to peers that already have most of its transactions in their mempool.
+//!
+//! A compact block carries the header of the block, the coinbase outputs and kernels, which no mempool has, and a
+//! short id for every other kernel. The short id of a kernel is the first `SHORT_ID_LENGTH` bytes of the hash of its
+//! excess, salted with the hash of the block, so that nobody can make transactions whose short ids collide in every
+//! block. The receiver rebuilds the block from the transactions in its mempool whose kernels match the short ids. The
+//! transactions it is missing are requested from the sender with a `BlockTransactionsRequest`, and the block is
+//! rebuilt again with the transactions in the reply added to those of the mempool.
+//!
+//! A block can not be split back into its transactions, so the sender answers from its own mempool, and a receiver
+//! that still can not rebuild the block, or that rebuilds a block that does not validate because two kernels share a
+//! short id, falls back to requesting the full block.
+
+use crate::{
+    block::{AggregateBody, Block},
+    blockheader::{BlockHash, BlockHeader},
+    encoding::{decode_list, encode_list, BinaryEncoding, EncodingError, Reader},
+    transaction::{KernelFeatures, Transaction, TransactionKernel},
+};
+use crypto::{commitment::HomomorphicCommitment, common::Blake256};
+use digest::Digest;
+use std::collections::HashMap;
+use tari_utilities::Hashable;
+
+/// The number of bytes in a short id
+pub const SHORT_ID_LENGTH: usize = 8;
+
+/// The short id of a kernel in a compact block
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ShortId(pub [u8; SHORT_ID_LENGTH]);
+
+impl ShortId {
+    /// The short id of the kernel in the block with the given hash
+    pub fn new(block_hash: &BlockHash, kernel: &TransactionKernel) -> ShortId {
+        let mut hasher = Blake256::new().chain(block_hash);
+        if let Some(excess) = &kernel.excess {
+            hasher = hasher.chain(excess.as_bytes());
+        }
+        let mut id = [0u8; SHORT_ID_LENGTH];
+        id.copy_from_slice(&hasher.result()[..SHORT_ID_LENGTH]);
+        ShortId(id)
+    }
+}
+
+/// A block with the kernels of its transactions replaced by their short ids
+#[derive(Clone, Debug)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    /// The coinbase outputs and kernels of the block
+    pub prefilled: AggregateBody,
+    /// The short ids of the kernels of the transactions in the block, in the order of the kernels in the block
+    pub short_ids: Vec<ShortId>,
+}
+
+/// The outcome of rebuilding a block from a compact block
+#[derive(Debug)]
+pub enum Reconstruction {
+    /// Every kernel was matched, and this is the block
+    Complete(Box<Block>),
+    /// Some kernels were not matched, and the transactions they belong to must be requested from the sender
+    Incomplete(BlockTransactionsRequest),
+}
+
+/// A request for the transactions of a compact block that the receiver is missing
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockTransactionsRequest {
+    pub block_hash: BlockHash,
+    pub short_ids: Vec<ShortId>,
+}
+
+/// The reply to a `BlockTransactionsRequest`, with the requested transactions that the sender has
+#[derive(Clone, Debug)]
+pub struct BlockTransactions {
+    pub block_hash: BlockHash,
+    pub transactions: Vec<Transaction>,
+}
+
+impl CompactBlock {
+    /// Make the compact block of a block
+    pub fn from_block(block: &Block) -> CompactBlock {
+        let block_hash = block_hash(&block.header);
+        let (coinbase_kernels, kernels): (Vec<_>, Vec<_>) =
+            block.body.kernels.iter().partition(|kernel| kernel.features.contains(KernelFeatures::COINBASE_KERNEL));
+        let coinbase_outputs = block.body.outputs.iter().filter(|output| output.features.is_coinbase());
+        CompactBlock {
+            header: block.header.clone(),
+            prefilled: AggregateBody::new(
+                vec![],
+                coinbase_outputs.cloned().collect(),
+                coinbase_kernels.into_iter().cloned().collect(),
+            ),
+            short_ids: kernels.into_iter().map(|kernel| ShortId::new(&block_hash, kernel)).collect(),
+        }
+    }
+
+    /// The hash of the block
+    pub fn block_hash(&self) -> BlockHash {
+        block_hash(&self.header)
+    }
+
+    /// Rebuild the block from the given transactions, which are those of the mempool and, after a
+    /// `BlockTransactionsRequest`, those of the reply. A transaction is part of the block if the short ids of all of
+    /// its kernels are in the compact block.
+    pub fn reconstruct<'a, I>(&self, transactions: I) -> Reconstruction
+    where I: IntoIterator<Item = &'a Transaction> {
+        let block_hash = self.block_hash();
+        let mut matched: HashMap<ShortId, bool> = self.short_ids.iter().map(|id| (*id, false)).collect();
+        let mut body = self.prefilled.clone();
+        for tx in transactions {
+            let ids: Vec<ShortId> = tx.body.kernels.iter().map(|kernel| ShortId::new(&block_hash, kernel)).collect();
+            if ids.is_empty() || !ids.iter().all(|id| matched.get(id) == Some(&false)) {
+                continue;
+            }
+            for id in ids {
+                matched.insert(id, true);
+            }
+            body = body
+                .add_inputs(tx.body.inputs.clone())
+                .add_outputs(tx.body.outputs.clone())
+                .add_kernels(tx.body.kernels.clone());
+        }
+        let missing: Vec<ShortId> = self.short_ids.iter().filter(|id| !matched[id]).cloned().collect();
+        if missing.is_empty() {
+            Reconstruction::Complete(Box::new(Block::new(self.header.clone(), body)))
+        } else {
+            Reconstruction::Incomplete(BlockTransactionsRequest { block_hash, short_ids: missing })
+        }
+    }
+}
+
+impl BlockTransactionsRequest {
+    /// Answer the request with the transactions whose kernels have the requested short ids
+    pub fn respond<'a, I>(&self, transactions: I) -> BlockTransactions
+    where I: IntoIterator<Item = &'a Transaction> {
+        let transactions = transactions
+            .into_iter()
+            .filter(|tx| {
+                tx.body.kernels.iter().any(|kernel| self.short_ids.contains(&ShortId::new(&self.block_hash, kernel)))
+            })
+            .cloned()
+            .collect();
+        BlockTransactions { block_hash: self.block_hash, transactions }
+    }
+}
+
+fn block_hash(header: &BlockHeader) -> BlockHash {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&header.hash());
+    hash
+}
+
+impl BinaryEncoding for ShortId {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.0);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let mut id = [0u8; SHORT_ID_LENGTH];
+        id.copy_from_slice(reader.read_bytes(SHORT_ID_LENGTH)?);
+        Ok(ShortId(id))
+    }
+}
+
+impl BinaryEncoding for CompactBlock {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        self.header.binary_encode(buf);
+        self.prefilled.binary_encode(buf);
+        encode_list(&self.short_ids, buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let header = BlockHeader::binary_decode(reader)?;
+        let prefilled = AggregateBody::binary_decode(reader)?;
+        let short_ids = decode_list(reader)?;
+        Ok(CompactBlock { header, prefilled, short_ids })
+    }
+}
+
+impl BinaryEncoding for BlockTransactionsRequest {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.block_hash);
+        encode_list(&self.short_ids, buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let block_hash = reader.read_hash()?;
+        let short_ids = decode_list(reader)?;
+        Ok(BlockTransactionsRequest { block_hash, short_ids })
+    }
+}
+
+impl BinaryEncoding for BlockTransactions {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.block_hash);
+        encode_list(&self.transactions, buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let block_hash = reader.read_hash()?;
+        let transactions = decode_list(reader)?;
+        Ok(BlockTransactions { block_hash, transactions })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        difficulty::Difficulty,
+        pow::ProofOfWork,
+        range_proof::{RangeProof, RANGE_PROOF_LENGTH},
+        transaction::{OutputFeatures, TransactionInput, TransactionOutput},
+        types::{BlindingFactor, CommitmentFactory},
+    };
+    use chrono::Utc;
+    use crypto::{commitment::HomomorphicCommitmentFactory, keys::SecretKey};
+    use rand::OsRng;
+
+    fn create_transaction(rng: &mut OsRng, features: KernelFeatures, output_features: OutputFeatures) -> Transaction {
+        let commitment = CommitmentFactory::create(&BlindingFactor::random(rng), &BlindingFactor::from(10));
+        let output = TransactionOutput::new(output_features, commitment, RangeProof([0; RANGE_PROOF_LENGTH]));
+        let excess = CommitmentFactory::create(&BlindingFactor::random(rng), &BlindingFactor::from(0));
+        let kernel = TransactionKernel { features, fee: 1, lock_height: 0, excess: Some(excess), excess_sig: None };
+        let input = TransactionInput::new(OutputFeatures::empty(), excess);
+        Transaction::new(vec![input], vec![output], vec![kernel], BlindingFactor::random(rng))
+    }
+
+    #[test]
+    fn reconstruct() {
+        let mut rng = OsRng::new().unwrap();
+        let header = BlockHeader {
+            version: 0,
+            height: 1,
+            prev_hash: [0; 32],
+            timestamp: Utc::now(),
+            output_mmr: [0; 32],
+            kernel_mmr: [0; 32],
+            total_kernel_offset: BlindingFactor::default(),
+            pow: ProofOfWork::new(Difficulty::min()),
+        };
+        let coinbase =
+            create_transaction(&mut rng, KernelFeatures::COINBASE_KERNEL, OutputFeatures::create_coinbase(1));
+        let coinbase_body = AggregateBody::new(vec![], coinbase.body.outputs, coinbase.body.kernels);
+        let txs: Vec<Transaction> =
+            (0..3).map(|_| create_transaction(&mut rng, KernelFeatures::empty(), OutputFeatures::empty())).collect();
+        let block = txs.iter().cloned().fold(Block::new(header, coinbase_body), Block::add_transaction);
+
+        let compact = CompactBlock::from_block(&block);
+        assert_eq!(compact.short_ids.len(), 3);
+        assert_eq!(compact.prefilled.outputs.len(), 1);
+        let compact = CompactBlock::from_binary(&compact.to_binary()).unwrap();
+
+        let unrelated = create_transaction(&mut rng, KernelFeatures::empty(), OutputFeatures::empty());
+        let mempool = vec![txs[0].clone(), unrelated, txs[2].clone()];
+        let request = match compact.reconstruct(&mempool) {
+            Reconstruction::Incomplete(request) => request,
+            Reconstruction::Complete(_) => panic!("The block was rebuilt without one of its transactions"),
+        };
+        assert_eq!(request.block_hash, compact.block_hash());
+        assert_eq!(request.short_ids, vec![ShortId::new(&request.block_hash, &txs[1].body.kernels[0])]);
+
+        let reply = request.respond(&txs);
+        assert_eq!(reply.transactions.len(), 1);
+        let rebuilt = match compact.reconstruct(mempool.iter().chain(&reply.transactions)) {
+            Reconstruction::Complete(block) => *block,
+            Reconstruction::Incomplete(_) => panic!("The block could not be rebuilt"),
+        };
+        assert_eq!(rebuilt.header.hash(), block.header.hash());
+        assert_eq!(rebuilt.body.to_binary(), block.body.to_binary());
+    }
+}
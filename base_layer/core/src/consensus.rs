@@ -0,0 +1,198 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+// This file contains the parameters that differ between the Tari networks
+
+use crate::{
+    block::{AggregateBody, Block},
+    blockheader::BlockHeader,
+    difficulty::{Difficulty, LinearWeightedMovingAverage},
+    emission::EmissionSchedule,
+    pow::ProofOfWork,
+    types::{BlindingFactor, MmrHasher},
+};
+use chrono::{TimeZone, Utc};
+use crypto::common::Blake256;
+use digest::Digest;
+
+/// The consensus rules that can be set per network
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConsensusConstants {
+    /// The block header version required from the genesis block
+    pub blockchain_version: u16,
+    /// The transaction version required from the genesis block
+    pub transaction_version: u16,
+    /// The upgrades that require new block header and transaction versions, in order of height
+    pub version_upgrades: Vec<VersionUpgrade>,
+    /// The target time between blocks, in seconds
+    pub target_block_interval: u64,
+    /// The number of blocks the difficulty adjustment averages over
+    pub difficulty_block_window: usize,
+    /// The number of previous blocks whose median timestamp a block timestamp must be later than
+    pub median_timestamp_count: usize,
+    /// How far into the future a block timestamp may be, in seconds
+    pub future_time_limit: u64,
+    /// The lowest target difficulty, which is also the difficulty of the first blocks
+    pub min_difficulty: Difficulty,
+    /// The largest total weight of the inputs, outputs and kernels of a block
+    pub max_block_weight: u64,
+    /// The lowest fee rate, in µT per gram of weight, that the transactions in a block must pay
+    pub min_fee_per_gram: u64,
+    /// The smallest value, in µT, of the outputs wallets create. Smaller outputs cost more to spend than they are
+    /// worth, so they would stay in the UTXO set for good. Nodes can not see the value of an output, so this is only
+    /// enforced by wallets until the range proof of an output proves it.
+    pub dust_threshold: u64,
+    /// The number of blocks a coinbase output must wait before it can be spent
+    pub coinbase_lock_height: u64,
+    /// The block reward of the genesis block
+    pub emission_initial_reward: u64,
+    /// The block reward shrinks by a factor of 1 - 2^-`emission_decay` every block
+    pub emission_decay: u32,
+    /// The block reward once the decaying reward falls below it
+    pub emission_tail_reward: u64,
+}
+
+/// A consensus upgrade, which requires new block header and transaction versions from a height onwards
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VersionUpgrade {
+    /// The first height at which the new versions are required
+    pub height: u64,
+    pub blockchain_version: u16,
+    pub transaction_version: u16,
+}
+
+impl ConsensusConstants {
+    /// Returns the block header version required at the given height
+    pub fn blockchain_version(&self, height: u64) -> u16 {
+        self.upgrade_at(height).map_or(self.blockchain_version, |upgrade| upgrade.blockchain_version)
+    }
+
+    /// Returns the transaction version required at the given height
+    pub fn transaction_version(&self, height: u64) -> u16 {
+        self.upgrade_at(height).map_or(self.transaction_version, |upgrade| upgrade.transaction_version)
+    }
+
+    /// Returns the latest upgrade in force at the given height, if there is one
+    fn upgrade_at(&self, height: u64) -> Option<&VersionUpgrade> {
+        self.version_upgrades.iter().take_while(|upgrade| upgrade.height <= height).last()
+    }
+
+    /// Returns the difficulty adjustment used to calculate the target difficulty of the next block
+    pub fn difficulty_adjustment(&self) -> LinearWeightedMovingAverage {
+        LinearWeightedMovingAverage::new(self.difficulty_block_window, self.target_block_interval, self.min_difficulty)
+    }
+
+    /// Returns the emission schedule of the block reward
+    pub fn emission_schedule(&self) -> EmissionSchedule {
+        EmissionSchedule::new(self.emission_initial_reward, self.emission_decay, self.emission_tail_reward)
+    }
+}
+
+/// The networks a node can run on. Each network has its own genesis block, hash domain and consensus constants, so
+/// that the chains of different networks can never be mixed up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Network {
+    /// The production network
+    MainNet,
+    /// The public test network
+    TestNet,
+    /// A private network for local development and testing. It has no fixed genesis block: the first block added to
+    /// the chain becomes its genesis block.
+    LocalNet,
+}
+
+impl Network {
+    /// Returns the label that separates the hashes of this network from those of the other networks
+    pub fn hash_domain(self) -> &'static str {
+        match self {
+            Network::MainNet => "com.tari.mainnet",
+            Network::TestNet => "com.tari.testnet",
+            Network::LocalNet => "com.tari.localnet",
+        }
+    }
+
+    /// Returns the consensus constants of the network
+    pub fn consensus_constants(self) -> ConsensusConstants {
+        match self {
+            Network::MainNet | Network::TestNet => ConsensusConstants {
+                blockchain_version: 0,
+                transaction_version: 0,
+                version_upgrades: Vec::new(),
+                target_block_interval: 120,
+                difficulty_block_window: 90,
+                median_timestamp_count: 11,
+                future_time_limit: 720,
+                min_difficulty: Difficulty::min(),
+                max_block_weight: 19_500,
+                min_fee_per_gram: 1,
+                dust_threshold: 100,
+                coinbase_lock_height: 1440,
+                emission_initial_reward: 5_000_000_000,
+                emission_decay: 18,
+                emission_tail_reward: 100_000_000,
+            },
+            Network::LocalNet => ConsensusConstants {
+                blockchain_version: 0,
+                transaction_version: 0,
+                version_upgrades: Vec::new(),
+                target_block_interval: 60,
+                difficulty_block_window: 10,
+                median_timestamp_count: 11,
+                future_time_limit: 360,
+                min_difficulty: Difficulty::min(),
+                max_block_weight: 19_500,
+                min_fee_per_gram: 0,
+                dust_threshold: 0,
+                coinbase_lock_height: 5,
+                emission_initial_reward: 5_000_000_000,
+                emission_decay: 10,
+                emission_tail_reward: 100_000_000,
+            },
+        }
+    }
+
+    /// Returns the genesis block of the network, or None if the network does not have a fixed genesis block
+    pub fn genesis_block(self) -> Option<Block> {
+        let timestamp = match self {
+            Network::MainNet => Utc.ymd(2019, 6, 1).and_hms(0, 0, 0),
+            Network::TestNet => Utc.ymd(2019, 4, 1).and_hms(0, 0, 0),
+            Network::LocalNet => return None,
+        };
+        // The genesis block builds on the hash of the network domain and commits to empty MMRs
+        let mut prev_hash = [0u8; 32];
+        prev_hash.copy_from_slice(&Blake256::digest(self.hash_domain().as_bytes()));
+        let mut empty_root = [0u8; 32];
+        empty_root.copy_from_slice(&MmrHasher::new().result());
+        let constants = self.consensus_constants();
+        let header = BlockHeader {
+            version: constants.blockchain_version(0),
+            height: 0,
+            prev_hash,
+            timestamp,
+            output_mmr: empty_root,
+            kernel_mmr: empty_root,
+            total_kernel_offset: BlindingFactor::default(),
+            pow: ProofOfWork::new(constants.min_difficulty),
+        };
+        Some(Block::new(header, AggregateBody::empty()))
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+// This file contains the difficulty types and the difficulty adjustment algorithm
+
+use chrono::{DateTime, Utc};
+use std::{collections::VecDeque, ops::Add};
+
+/// The difficulty of a block: the expected number of hashes needed to find a block hash that meets the target
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Difficulty(u64);
+
+impl Difficulty {
+    /// The smallest difficulty. Every block hash meets it.
+    pub fn min() -> Difficulty {
+        Difficulty(1)
+    }
+
+    /// No work at all. It is the accumulated difficulty of an empty chain and is never a valid target difficulty.
+    pub fn zero() -> Difficulty {
+        Difficulty(0)
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the difficulty achieved by the hash, i.e. the largest difficulty whose target the hash meets. The
+    /// first 8 bytes of the hash are read as a big-endian number and compared to the maximum target.
+    pub fn from_hash(hash: &[u8]) -> Difficulty {
+        let mut bytes = [0u8; 8];
+        let len = hash.len().min(8);
+        bytes[..len].copy_from_slice(&hash[..len]);
+        let scalar = u64::from_be_bytes(bytes).max(1);
+        Difficulty((u64::max_value() / scalar).max(1))
+    }
+
+    /// Returns the target a hash must be at or below to meet the difficulty, as the big-endian number formed by the
+    /// first 8 bytes of the hash
+    pub fn get_target(self) -> u64 {
+        u64::max_value() / self.0.max(1)
+    }
+}
+
+impl From<u64> for Difficulty {
+    fn from(value: u64) -> Self {
+        Difficulty(value.max(1))
+    }
+}
+
+/// Difficulties are added up to get the accumulated work of a chain. The sum saturates instead of overflowing.
+impl Add for Difficulty {
+    type Output = Difficulty;
+
+    fn add(self, rhs: Difficulty) -> Difficulty {
+        Difficulty(self.0.saturating_add(rhs.0))
+    }
+}
+
+/// An algorithm that calculates the difficulty of the next block from the blocks before it
+pub trait DifficultyAdjustment {
+    /// Add the timestamp and target difficulty of the next block in the chain
+    fn add(&mut self, timestamp: DateTime<Utc>, target_difficulty: Difficulty);
+
+    /// Returns the target difficulty of the block following the blocks added so far
+    fn get_difficulty(&self) -> Difficulty;
+}
+
+/// The linearly weighted moving average difficulty adjustment. The solve times of the blocks in the window are
+/// weighted linearly, with the most recent block weighted the most, so that the difficulty responds quickly to
+/// changes in hash rate without oscillating. Solve times are clamped to [1, 6 * target time] to limit the effect of
+/// manipulated timestamps. Timestamps only have to be later than the median of the blocks before them, so they can be
+/// out of order: each solve time is measured from the latest timestamp before the block rather than from the previous
+/// block. Otherwise a miner could alternate future and past timestamps, so that the late blocks count as slow and the
+/// early ones are clamped to 1 second, and lower the difficulty while the blocks arrive on time.
+pub struct LinearWeightedMovingAverage {
+    /// The number of solve times averaged
+    block_window: usize,
+    /// The target time between blocks, in seconds
+    target_time: u64,
+    /// The difficulty returned until there are enough blocks, and the lowest difficulty returned
+    min_difficulty: Difficulty,
+    timestamps: VecDeque<DateTime<Utc>>,
+    target_difficulties: VecDeque<Difficulty>,
+}
+
+impl LinearWeightedMovingAverage {
+    pub fn new(block_window: usize, target_time: u64, min_difficulty: Difficulty) -> LinearWeightedMovingAverage {
+        LinearWeightedMovingAverage {
+            block_window,
+            target_time,
+            min_difficulty,
+            timestamps: VecDeque::with_capacity(block_window + 1),
+            target_difficulties: VecDeque::with_capacity(block_window + 1),
+        }
+    }
+}
+
+impl DifficultyAdjustment for LinearWeightedMovingAverage {
+    fn add(&mut self, timestamp: DateTime<Utc>, target_difficulty: Difficulty) {
+        self.timestamps.push_back(timestamp);
+        self.target_difficulties.push_back(target_difficulty);
+        while self.timestamps.len() > self.block_window + 1 {
+            self.timestamps.pop_front();
+            self.target_difficulties.pop_front();
+        }
+    }
+
+    fn get_difficulty(&self) -> Difficulty {
+        let n = self.timestamps.len().saturating_sub(1) as u128;
+        if n == 0 {
+            return self.min_difficulty;
+        }
+        let target_time = u128::from(self.target_time.max(1));
+        let mut weighted_solve_times = 0u128;
+        let mut difficulty_sum = 0u128;
+        let mut latest = self.timestamps[0];
+        for i in 1..self.timestamps.len() {
+            let solve_time = (self.timestamps[i] - latest).num_seconds();
+            let solve_time = (solve_time.max(1) as u128).min(6 * target_time);
+            latest = latest.max(self.timestamps[i]);
+            weighted_solve_times += i as u128 * solve_time;
+            difficulty_sum += u128::from(self.target_difficulties[i].as_u64());
+        }
+        // next = average difficulty * target time / weighted average solve time, where the weights sum to n(n+1)/2
+        let next = difficulty_sum * target_time * (n + 1) / (2 * weighted_solve_times);
+        let next = next.min(u128::from(u64::max_value())) as u64;
+        Difficulty(next).max(self.min_difficulty)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn lwma_adjusts_to_solve_times() {
+        let start = Utc::now();
+        let mut lwma = LinearWeightedMovingAverage::new(5, 60, Difficulty::min());
+        assert_eq!(lwma.get_difficulty(), Difficulty::min());
+        for i in 0..10 {
+            lwma.add(start + Duration::seconds(60 * i), Difficulty::from(100));
+        }
+        assert_eq!(lwma.get_difficulty(), Difficulty::from(100));
+
+        // Blocks arriving twice as fast double the difficulty once they fill the window
+        let mut fast = LinearWeightedMovingAverage::new(5, 60, Difficulty::min());
+        for i in 0..6 {
+            fast.add(start + Duration::seconds(30 * i), Difficulty::from(100));
+        }
+        assert_eq!(fast.get_difficulty(), Difficulty::from(200));
+
+        // The solve time of a block a day late is clamped to 6 target times
+        lwma.add(start + Duration::days(1), Difficulty::from(100));
+        assert_eq!(lwma.get_difficulty(), Difficulty::from(37));
+    }
+
+    #[test]
+    fn lwma_resists_alternating_timestamps() {
+        // The blocks arrive every target time, but every other block claims to be 5 target times late and the others
+        // 5 target times early
+        let start = Utc::now();
+        let mut lwma = LinearWeightedMovingAverage::new(10, 60, Difficulty::min());
+        for i in 0..21 {
+            let skew = if i % 2 == 0 { 300 } else { -300 };
+            lwma.add(start + Duration::seconds(60 * i + skew), Difficulty::from(100));
+        }
+        let difficulty = lwma.get_difficulty();
+        assert!(difficulty >= Difficulty::from(90), "{:?}", difficulty);
+    }
+
+    #[test]
+    fn difficulty_from_hash() {
+        assert_eq!(Difficulty::from_hash(&[0xff; 32]), Difficulty::min());
+        assert_eq!(Difficulty::from_hash(&[0; 32]).as_u64(), u64::max_value());
+        let mut hash = [0u8; 32];
+        hash[7] = 0x10;
+        let difficulty = Difficulty::from_hash(&hash);
+        assert_eq!(difficulty.as_u64(), u64::max_value() / 16);
+        assert!(u64::from_be_bytes([0, 0, 0, 0, 0, 0, 0, 0x10]) <= difficulty.get_target());
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+// This file contains the emission schedule, which sets the block reward at every height
+
+/// The number of fractional bits of the fixed point numbers used to calculate the decay of the block reward. Decay
+/// factors are at most 1, so the product of two of them fits in a u128.
+const FRACTION_BITS: u32 = 63;
+
+/// The emission schedule of the block reward. The reward starts at `initial_reward` and shrinks by a factor of
+/// 1 - 2^-`decay` every block, until it falls below `tail_reward`, which is then paid out forever. The reward is
+/// calculated with integer arithmetic only, so that every node arrives at exactly the same value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmissionSchedule {
+    initial_reward: u64,
+    decay: u32,
+    tail_reward: u64,
+}
+
+impl EmissionSchedule {
+    /// Create an emission schedule. A `decay` of 0 pays the initial reward to the genesis block only.
+    pub fn new(initial_reward: u64, decay: u32, tail_reward: u64) -> EmissionSchedule {
+        EmissionSchedule { initial_reward, decay, tail_reward }
+    }
+
+    /// Returns the block reward of the block at the given height
+    pub fn block_reward(&self, height: u64) -> u64 {
+        let one = 1u128 << FRACTION_BITS;
+        let factor = one - (one >> self.decay.min(FRACTION_BITS));
+        let decayed = (u128::from(self.initial_reward) * fixed_pow(factor, height)) >> FRACTION_BITS;
+        (decayed as u64).max(self.tail_reward)
+    }
+}
+
+/// Raise the fixed point number `base`, which must be at most 1, to the power `exp` by repeated squaring
+fn fixed_pow(mut base: u128, mut exp: u64) -> u128 {
+    let mut result = 1u128 << FRACTION_BITS;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = (result * base) >> FRACTION_BITS;
+        }
+        base = (base * base) >> FRACTION_BITS;
+        exp >>= 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn block_reward_decays_to_tail() {
+        let schedule = EmissionSchedule::new(1000, 1, 10);
+        assert_eq!(schedule.block_reward(0), 1000);
+        assert_eq!(schedule.block_reward(1), 500);
+        assert_eq!(schedule.block_reward(3), 125);
+        assert_eq!(schedule.block_reward(7), 10);
+        assert_eq!(schedule.block_reward(u64::max_value()), 10);
+
+        let schedule = EmissionSchedule::new(5_000_000_000, 18, 100_000_000);
+        let rewards: Vec<u64> = (0..5).map(|height| schedule.block_reward(height * 100_000)).collect();
+        assert!(rewards.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert!(rewards[1] < rewards[0] && rewards[1] > rewards[0] / 2);
+        assert_eq!(EmissionSchedule::new(1000, 0, 10).block_reward(1), 10);
+    }
+}
@@ -0,0 +1,487 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! The canonical binary encoding of the consensus types, which is used to store blocks, to send blocks, transactions
+//! and protocol messages over the network, and to hash transaction inputs.
+//!
+//! Fields are written in the order they are declared in. Integers are written little-endian with a fixed width, keys
+//! and commitments as their 32 byte compressed form, optional fields behind a one byte presence flag and lists behind
+//! their length as a `u64`. `to_binary` prefixes the encoding with `ENCODING_VERSION`, so the format can be changed
+//! later without mistaking old data for new. The consensus version carried by transactions and block headers is
+//! separate from it: it selects the consensus rules a transaction or block is validated with, and not its layout.
+//!
+//! This is not the same as `tari_utilities::ConsensusEncoding`, which is the preimage of the hash of an MMR leaf.
+
+use crate::{
+    block::{AggregateBody, Block},
+    blockheader::BlockHeader,
+    difficulty::Difficulty,
+    pow::{PowAlgorithm, ProofOfWork},
+    range_proof::{RangeProof, RANGE_PROOF_LENGTH},
+    script::Script,
+    transaction::{KernelFeatures, OutputFeatures, Transaction, TransactionInput, TransactionKernel, TransactionOutput},
+    types::{Commitment, PublicKey, Signature},
+};
+use chrono::{TimeZone, Utc};
+use crypto::commitment::HomomorphicCommitment;
+use derive_error::Error;
+use tari_utilities::ByteArray;
+
+/// The version of the encoding written by `to_binary`
+pub const ENCODING_VERSION: u8 = 0;
+
+const KEY_LENGTH: usize = 32;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum EncodingError {
+    /// The bytes ended before the value was decoded
+    UnexpectedEnd,
+    /// There are bytes left over after the value was decoded
+    TrailingBytes,
+    /// The bytes were written by an unsupported version of the encoding
+    UnsupportedVersion,
+    /// A field does not hold a valid value for its type
+    InvalidValue,
+}
+
+/// A type with a canonical binary encoding
+pub trait BinaryEncoding: Sized {
+    /// Append the encoding of the value to the buffer
+    fn binary_encode(&self, buf: &mut Vec<u8>);
+
+    /// Decode a value from the front of the reader
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError>;
+
+    /// Encode the value on its own, prefixed with the encoding version
+    fn to_binary(&self) -> Vec<u8> {
+        let mut buf = vec![ENCODING_VERSION];
+        self.binary_encode(&mut buf);
+        buf
+    }
+
+    /// Decode a value written by `to_binary`, which must use all of the bytes
+    fn from_binary(bytes: &[u8]) -> Result<Self, EncodingError> {
+        let mut reader = Reader::new(bytes);
+        if reader.read_u8()? != ENCODING_VERSION {
+            return Err(EncodingError::UnsupportedVersion);
+        }
+        let value = Self::binary_decode(&mut reader)?;
+        reader.finish(value)
+    }
+}
+
+/// Append a list to the buffer, behind its length
+pub fn encode_list<T: BinaryEncoding>(items: &[T], buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&(items.len() as u64).to_le_bytes());
+    for item in items {
+        item.binary_encode(buf);
+    }
+}
+
+/// Decode a list written by `encode_list`
+pub fn decode_list<T: BinaryEncoding>(reader: &mut Reader) -> Result<Vec<T>, EncodingError> {
+    let length = reader.read_u64()?;
+    // Every item takes at least one byte, which bounds the allocation for a corrupt length
+    let mut items = Vec::with_capacity(length.min(reader.remaining() as u64) as usize);
+    for _ in 0..length {
+        items.push(T::binary_decode(reader)?);
+    }
+    Ok(items)
+}
+
+/// Reads values from the front of a byte slice, failing with `EncodingError::UnexpectedEnd` when it runs out of bytes
+pub struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Start reading from the front of the bytes
+    pub fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader(bytes)
+    }
+
+    /// The number of bytes that have not been read yet
+    pub fn remaining(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], EncodingError> {
+        if self.0.len() < n {
+            return Err(EncodingError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, EncodingError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, EncodingError> {
+        let mut buf = [0u8; 2];
+        buf.copy_from_slice(self.read_bytes(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, EncodingError> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, EncodingError> {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    pub fn read_hash(&mut self) -> Result<[u8; 32], EncodingError> {
+        let mut buf = [0u8; 32];
+        buf.copy_from_slice(self.read_bytes(32)?);
+        Ok(buf)
+    }
+
+    pub fn read_key<K: ByteArray>(&mut self) -> Result<K, EncodingError> {
+        K::from_bytes(self.read_bytes(KEY_LENGTH)?).map_err(|_| EncodingError::InvalidValue)
+    }
+
+    pub fn read_commitment(&mut self) -> Result<Commitment, EncodingError> {
+        let key: PublicKey = self.read_key()?;
+        Ok(Commitment::from_public_key(&key))
+    }
+
+    pub fn read_signature(&mut self) -> Result<Signature, EncodingError> {
+        let public_nonce = self.read_key()?;
+        let signature = self.read_key()?;
+        Ok(Signature::new(public_nonce, signature))
+    }
+
+    /// Returns the decoded value if all of the bytes were read
+    pub fn finish<T>(self, value: T) -> Result<T, EncodingError> {
+        if !self.0.is_empty() {
+            return Err(EncodingError::TrailingBytes);
+        }
+        Ok(value)
+    }
+}
+
+/// Append a signature to the buffer, as its public nonce followed by the signature scalar
+pub fn encode_signature(signature: &Signature, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(signature.get_public_nonce().as_bytes());
+    buf.extend_from_slice(signature.get_signature().as_bytes());
+}
+
+impl BinaryEncoding for OutputFeatures {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_bytes());
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        OutputFeatures::from_bytes(reader.read_bytes(OutputFeatures::ENCODED_SIZE)?).ok_or(EncodingError::InvalidValue)
+    }
+}
+
+impl BinaryEncoding for TransactionInput {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        self.features.binary_encode(buf);
+        buf.extend_from_slice(self.commitment.as_bytes());
+        match self.script_signature {
+            Some(sig) => {
+                buf.push(1);
+                encode_signature(&sig, buf);
+            },
+            None => buf.push(0),
+        }
+        match self.script_preimage {
+            Some(preimage) => {
+                buf.push(1);
+                buf.extend_from_slice(&preimage);
+            },
+            None => buf.push(0),
+        }
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let features = OutputFeatures::binary_decode(reader)?;
+        let commitment = reader.read_commitment()?;
+        let script_signature = match reader.read_u8()? {
+            0 => None,
+            1 => Some(reader.read_signature()?),
+            _ => return Err(EncodingError::InvalidValue),
+        };
+        let script_preimage = match reader.read_u8()? {
+            0 => None,
+            1 => Some(reader.read_hash()?),
+            _ => return Err(EncodingError::InvalidValue),
+        };
+        Ok(TransactionInput { features, commitment, script_signature, script_preimage })
+    }
+}
+
+impl BinaryEncoding for TransactionOutput {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        self.features.binary_encode(buf);
+        buf.extend_from_slice(self.commitment.as_bytes());
+        buf.extend_from_slice(self.proof.as_bytes());
+        match self.script {
+            Some(script) => {
+                buf.push(1);
+                script.binary_encode(buf);
+            },
+            None => buf.push(0),
+        }
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let features = OutputFeatures::binary_decode(reader)?;
+        let commitment = reader.read_commitment()?;
+        let proof =
+            RangeProof::from_bytes(reader.read_bytes(RANGE_PROOF_LENGTH)?).map_err(|_| EncodingError::InvalidValue)?;
+        let script = match reader.read_u8()? {
+            0 => None,
+            1 => Some(Script::binary_decode(reader)?),
+            _ => return Err(EncodingError::InvalidValue),
+        };
+        Ok(TransactionOutput { features, commitment, proof, script })
+    }
+}
+
+impl BinaryEncoding for Script {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.opcode());
+        match self {
+            Script::Nop => {},
+            Script::CheckPubKey(key) => buf.extend_from_slice(key.as_bytes()),
+            Script::HashTimeLock { hash_lock, claim_key, refund_key, refund_height } => {
+                buf.extend_from_slice(hash_lock);
+                buf.extend_from_slice(claim_key.as_bytes());
+                buf.extend_from_slice(refund_key.as_bytes());
+                buf.extend_from_slice(&refund_height.to_le_bytes());
+            },
+        }
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        match reader.read_u8()? {
+            Script::OP_NOP => Ok(Script::Nop),
+            Script::OP_CHECK_PUB_KEY => Ok(Script::CheckPubKey(reader.read_key()?)),
+            Script::OP_HASH_TIME_LOCK => Ok(Script::HashTimeLock {
+                hash_lock: reader.read_hash()?,
+                claim_key: reader.read_key()?,
+                refund_key: reader.read_key()?,
+                refund_height: reader.read_u64()?,
+            }),
+            _ => Err(EncodingError::InvalidValue),
+        }
+    }
+}
+
+impl BinaryEncoding for TransactionKernel {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        buf.push(self.features.bits());
+        buf.extend_from_slice(&self.fee.to_le_bytes());
+        buf.extend_from_slice(&self.lock_height.to_le_bytes());
+        match self.excess {
+            Some(excess) => {
+                buf.push(1);
+                buf.extend_from_slice(excess.as_bytes());
+            },
+            None => buf.push(0),
+        }
+        match self.excess_sig {
+            Some(sig) => {
+                buf.push(1);
+                encode_signature(&sig, buf);
+            },
+            None => buf.push(0),
+        }
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let features = KernelFeatures::from_bits(reader.read_u8()?).ok_or(EncodingError::InvalidValue)?;
+        let fee = reader.read_u64()?;
+        let lock_height = reader.read_u64()?;
+        let excess = match reader.read_u8()? {
+            0 => None,
+            1 => Some(reader.read_commitment()?),
+            _ => return Err(EncodingError::InvalidValue),
+        };
+        let excess_sig = match reader.read_u8()? {
+            0 => None,
+            1 => Some(reader.read_signature()?),
+            _ => return Err(EncodingError::InvalidValue),
+        };
+        Ok(TransactionKernel { features, fee, lock_height, excess, excess_sig })
+    }
+}
+
+impl BinaryEncoding for AggregateBody {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        encode_list(&self.inputs, buf);
+        encode_list(&self.outputs, buf);
+        encode_list(&self.kernels, buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let inputs = decode_list(reader)?;
+        let outputs = decode_list(reader)?;
+        let kernels = decode_list(reader)?;
+        Ok(AggregateBody::new(inputs, outputs, kernels))
+    }
+}
+
+impl BinaryEncoding for Transaction {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(self.offset.as_bytes());
+        self.body.binary_encode(buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let version = reader.read_u16()?;
+        let offset = reader.read_key()?;
+        let body = AggregateBody::binary_decode(reader)?;
+        Ok(Transaction { version, offset, body })
+    }
+}
+
+impl BinaryEncoding for BlockHeader {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.version.to_le_bytes());
+        buf.extend_from_slice(&self.height.to_le_bytes());
+        buf.extend_from_slice(&self.prev_hash);
+        buf.extend_from_slice(&self.timestamp.timestamp().to_le_bytes());
+        buf.extend_from_slice(&self.timestamp.timestamp_subsec_nanos().to_le_bytes());
+        buf.extend_from_slice(&self.output_mmr);
+        buf.extend_from_slice(&self.kernel_mmr);
+        buf.extend_from_slice(self.total_kernel_offset.as_bytes());
+        self.pow.binary_encode(buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let version = reader.read_u16()?;
+        let height = reader.read_u64()?;
+        let prev_hash = reader.read_hash()?;
+        let seconds = reader.read_u64()? as i64;
+        let nanos = reader.read_u32()?;
+        let timestamp = Utc.timestamp_opt(seconds, nanos).single().ok_or(EncodingError::InvalidValue)?;
+        let output_mmr = reader.read_hash()?;
+        let kernel_mmr = reader.read_hash()?;
+        let total_kernel_offset = reader.read_key()?;
+        let pow = ProofOfWork::binary_decode(reader)?;
+        Ok(BlockHeader {
+            version,
+            height,
+            prev_hash,
+            timestamp,
+            output_mmr,
+            kernel_mmr,
+            total_kernel_offset,
+            pow,
+        })
+    }
+}
+
+impl BinaryEncoding for ProofOfWork {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.to_bytes());
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let target_difficulty = Difficulty::from(reader.read_u64()?);
+        let pow_algo = PowAlgorithm::from_id(reader.read_u8()?).ok_or(EncodingError::InvalidValue)?;
+        let nonce = reader.read_u64()?;
+        let length = reader.read_u64()? as usize;
+        let pow_data = reader.read_bytes(length)?.to_vec();
+        Ok(ProofOfWork { target_difficulty, pow_algo, nonce, pow_data })
+    }
+}
+
+impl BinaryEncoding for Block {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        self.header.binary_encode(buf);
+        self.body.binary_encode(buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let header = BlockHeader::binary_decode(reader)?;
+        let body = AggregateBody::binary_decode(reader)?;
+        Ok(Block { header, body })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        range_proof::RangeProof,
+        types::{BlindingFactor, CommitmentFactory},
+    };
+    use crypto::{commitment::HomomorphicCommitmentFactory, keys::SecretKey};
+    use rand::OsRng;
+
+    #[test]
+    fn transaction_round_trip() {
+        let mut rng = OsRng::new().unwrap();
+        let commitment = CommitmentFactory::create(&BlindingFactor::random(&mut rng), &BlindingFactor::from(10));
+        let output = TransactionOutput::new(
+            OutputFeatures::create_coinbase(5),
+            commitment,
+            RangeProof([7; RANGE_PROOF_LENGTH]),
+        );
+        let kernel = TransactionKernel {
+            features: KernelFeatures::empty(),
+            fee: 3,
+            lock_height: 2,
+            excess: Some(commitment),
+            excess_sig: None,
+        };
+        let tx = Transaction {
+            version: 1,
+            offset: BlindingFactor::random(&mut rng),
+            body: AggregateBody::new(
+                vec![TransactionInput::new(OutputFeatures::empty(), commitment)],
+                vec![output],
+                vec![kernel],
+            ),
+        };
+        let mut buf = vec![];
+        output.binary_encode(&mut buf);
+        assert_eq!(buf.len(), TransactionOutput::ENCODED_SIZE);
+        let bytes = tx.to_binary();
+        assert_eq!(bytes[0], ENCODING_VERSION);
+        let decoded = Transaction::from_binary(&bytes).unwrap();
+        assert_eq!(decoded.to_binary(), bytes);
+        assert_eq!(decoded.version, 1);
+        assert_eq!(decoded.offset, tx.offset);
+        assert_eq!(decoded.body.outputs[0].features, output.features);
+        assert_eq!(decoded.body.kernels[0].excess, Some(commitment));
+
+        let shorter = &bytes[..bytes.len() - 1];
+        assert_eq!(Transaction::from_binary(shorter).err(), Some(EncodingError::UnexpectedEnd));
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(Transaction::from_binary(&longer).err(), Some(EncodingError::TrailingBytes));
+        let mut newer = bytes;
+        newer[0] = ENCODING_VERSION + 1;
+        assert_eq!(Transaction::from_binary(&newer).err(), Some(EncodingError::UnsupportedVersion));
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+// This file contains the fee policy. Fees are charged per gram of transaction weight, so that a transaction pays for
+// the space it takes up in a block.
+
+use crate::block::calculate_weight;
+
+/// The fee rate wallets use when the user does not choose one, in µT per gram of weight
+pub const DEFAULT_FEE_PER_GRAM: u64 = 25;
+
+/// Estimate the weight of a transaction with the given numbers of inputs, outputs and kernels. The weight only depends
+/// on these numbers, so wallets and the fee estimator can predict the fee of a transaction before its outputs are
+/// built. Each output adds `WEIGHT_PER_OUTPUT` to the weight and `TransactionOutput::ENCODED_SIZE` bytes to the size
+/// of the transaction.
+pub fn estimate_weight(num_inputs: usize, num_outputs: usize, num_kernels: usize) -> u64 {
+    calculate_weight(num_inputs, num_outputs, num_kernels)
+}
+
+/// Calculate the fee of a transaction with the given numbers of inputs, outputs and kernels at the given fee rate
+pub fn calculate_fee(fee_per_gram: u64, num_inputs: usize, num_outputs: usize, num_kernels: usize) -> u64 {
+    estimate_weight(num_inputs, num_outputs, num_kernels).saturating_mul(fee_per_gram)
+}
+
+/// Calculate the fee a wallet must pay for a transaction with a single kernel that spends `num_inputs` outputs and
+/// creates `num_outputs` outputs, including the change output, at the given fee rate
+pub fn calculate_required_fee(fee_per_gram: u64, num_inputs: usize, num_outputs: usize) -> u64 {
+    calculate_fee(fee_per_gram, num_inputs, num_outputs, 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        block::{AggregateBody, WEIGHT_PER_INPUT, WEIGHT_PER_KERNEL, WEIGHT_PER_OUTPUT},
+        transaction::{OutputFeatures, TransactionInput, TransactionKernel},
+        types::{BlindingFactor, CommitmentFactory},
+    };
+    use crypto::commitment::HomomorphicCommitmentFactory;
+
+    #[test]
+    fn fees() {
+        let weight = 2 * WEIGHT_PER_INPUT + 2 * WEIGHT_PER_OUTPUT + WEIGHT_PER_KERNEL;
+        assert_eq!(calculate_required_fee(DEFAULT_FEE_PER_GRAM, 2, 2), weight * DEFAULT_FEE_PER_GRAM);
+        assert_eq!(calculate_fee(0, 2, 2, 1), 0);
+        assert_eq!(calculate_fee(u64::max_value(), 1, 1, 1), u64::max_value());
+    }
+
+    #[test]
+    fn estimate_weight_before_building() {
+        let commitment = CommitmentFactory::create(&BlindingFactor::default(), &BlindingFactor::from(1));
+        let input = TransactionInput::new(OutputFeatures::empty(), commitment);
+        let body = AggregateBody::new(vec![input.clone(), input], vec![], vec![TransactionKernel::empty()]);
+        assert_eq!(estimate_weight(2, 0, 1), body.calculate_weight());
+        assert_eq!(estimate_weight(2, 3, 1), body.calculate_weight() + 3 * WEIGHT_PER_OUTPUT);
+    }
+}
@@ -0,0 +1,286 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Fee estimation for wallets and RPC clients, from the fee rates of recently mined transactions and of the mempool.
+//!
+//! The estimator follows transactions from the moment they enter the mempool until one of their kernels is mined, and
+//! keeps the fee rate of every mined transaction with the number of blocks it waited. The fee rate that gets a
+//! transaction mined within a number of blocks is the lowest rate at which enough of the recent transactions were mined
+//! that quickly, raised if needed to outbid the mempool transactions that would fill those blocks first.
+
+use crate::{block::Block, consensus::ConsensusConstants, fee::calculate_required_fee, transaction::Transaction};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+};
+use tari_utilities::Hashable;
+
+/// The share, in percent, of the transactions paying a fee rate that must have been mined within the target number of
+/// blocks for the fee rate to be recommended
+pub const SUCCESS_PERCENT: usize = 85;
+/// The number of transactions a group of fee rates needs before its success rate is judged
+pub const MIN_SAMPLES: usize = 10;
+
+/// A transaction in the mempool, waiting for one of its kernels to be mined
+struct PendingTransaction {
+    fee_per_gram: u64,
+    weight: u64,
+    seen_at: u64,
+    kernels: Vec<Vec<u8>>,
+}
+
+/// A mined transaction: its fee rate and the number of blocks it waited in the mempool
+struct MinedSample {
+    fee_per_gram: u64,
+    blocks: u64,
+}
+
+/// Estimates the fee rate a transaction must pay to be mined within a number of blocks. The mempool reports the
+/// transactions it accepts and drops, and the chain reports the blocks added to it.
+pub struct FeeEstimator {
+    min_fee_per_gram: u64,
+    max_block_weight: u64,
+    window: u64,
+    height: u64,
+    pending: HashMap<Vec<u8>, PendingTransaction>,
+    kernels: HashMap<Vec<u8>, Vec<u8>>,
+    mined: VecDeque<(u64, Vec<MinedSample>)>,
+}
+
+impl FeeEstimator {
+    /// Create an estimator for a chain with the given consensus constants, which learns from the transactions mined
+    /// in the last `window` blocks
+    pub fn new(constants: &ConsensusConstants, window: u64) -> FeeEstimator {
+        FeeEstimator {
+            min_fee_per_gram: constants.min_fee_per_gram,
+            max_block_weight: constants.max_block_weight,
+            window,
+            height: 0,
+            pending: HashMap::new(),
+            kernels: HashMap::new(),
+            mined: VecDeque::new(),
+        }
+    }
+
+    /// Track a transaction that entered the mempool at the height of the last block added
+    pub fn add_transaction(&mut self, tx: &Transaction) {
+        let weight = tx.calculate_weight();
+        if weight == 0 {
+            return;
+        }
+        let fees = tx.body.kernels.iter().fold(0u64, |fees, kernel| fees.saturating_add(kernel.fee));
+        let hash = tx.hash();
+        let kernels: Vec<Vec<u8>> = tx.body.kernels.iter().map(Hashable::hash).collect();
+        for kernel in kernels.iter() {
+            self.kernels.insert(kernel.clone(), hash.clone());
+        }
+        let pending = PendingTransaction { fee_per_gram: fees / weight, weight, seen_at: self.height, kernels };
+        self.pending.insert(hash, pending);
+    }
+
+    /// Stop tracking a transaction that left the mempool without being mined, because it was evicted, replaced or
+    /// conflicts with a mined transaction
+    pub fn remove_transaction(&mut self, tx: &Transaction) {
+        self.remove_pending(&tx.hash());
+    }
+
+    /// Add a block to the tip of the chain. The tracked transactions mined in the block become samples of how long
+    /// their fee rate waited, and the samples of blocks that fell out of the window are dropped.
+    pub fn add_block(&mut self, block: &Block) {
+        let height = block.header.height;
+        let mut samples = Vec::new();
+        for kernel in block.body.kernels.iter() {
+            let hash = match self.kernels.get(&kernel.hash()) {
+                Some(hash) => hash.clone(),
+                None => continue,
+            };
+            if let Some(pending) = self.remove_pending(&hash) {
+                let blocks = height.saturating_sub(pending.seen_at).max(1);
+                samples.push(MinedSample { fee_per_gram: pending.fee_per_gram, blocks });
+            }
+        }
+        self.height = height;
+        self.mined.push_back((height, samples));
+        let window = self.window;
+        while self.mined.front().map_or(false, |(mined_at, _)| mined_at + window <= height) {
+            self.mined.pop_front();
+        }
+    }
+
+    /// The fee rate, in µT per gram, that gets a transaction mined within `target` blocks. It is the highest of the
+    /// minimum fee rate, the rate at which recent transactions were mined within `target` blocks and the rate that
+    /// outbids the mempool transactions that would fill the next `target` blocks. A target of zero counts as one.
+    pub fn estimate_fee_per_gram(&self, target: u64) -> u64 {
+        let target = target.max(1);
+        let mined = self.mined_estimate(target).unwrap_or(0);
+        self.min_fee_per_gram.max(mined).max(self.mempool_estimate(target))
+    }
+
+    /// The fee that gets a transaction with a single kernel, spending `num_inputs` outputs and creating `num_outputs`
+    /// outputs, mined within `target` blocks
+    pub fn estimate_fee(&self, target: u64, num_inputs: usize, num_outputs: usize) -> u64 {
+        calculate_required_fee(self.estimate_fee_per_gram(target), num_inputs, num_outputs)
+    }
+
+    fn remove_pending(&mut self, hash: &[u8]) -> Option<PendingTransaction> {
+        let pending = self.pending.remove(hash)?;
+        for kernel in pending.kernels.iter() {
+            self.kernels.remove(kernel);
+        }
+        Some(pending)
+    }
+
+    // Goes through the samples from the highest fee rate down, in groups of at least `MIN_SAMPLES`, and returns the
+    // lowest fee rate of the groups in which at least `SUCCESS_PERCENT` of the transactions were mined within `target`
+    // blocks, stopping at the first group that falls short. Transactions that have been waiting for longer than
+    // `target` blocks count against their fee rate.
+    fn mined_estimate(&self, target: u64) -> Option<u64> {
+        let mined = self.mined.iter().flat_map(|(_, samples)| samples.iter());
+        let mined = mined.map(|sample| (sample.fee_per_gram, sample.blocks <= target));
+        let waiting = self.pending.values().filter(|pending| self.height.saturating_sub(pending.seen_at) > target);
+        let mut samples: Vec<(u64, bool)> = mined.chain(waiting.map(|pending| (pending.fee_per_gram, false))).collect();
+        samples.sort_by_key(|sample| Reverse(sample.0));
+
+        let mut estimate = None;
+        let (mut successes, mut total) = (0, 0);
+        for (i, &(fee_per_gram, success)) in samples.iter().enumerate() {
+            total += 1;
+            if success {
+                successes += 1;
+            }
+            // Transactions paying the same fee rate are always judged together
+            let last_at_rate = samples.get(i + 1).map_or(true, |next| next.0 != fee_per_gram);
+            if last_at_rate && total >= MIN_SAMPLES {
+                if successes * 100 < total * SUCCESS_PERCENT {
+                    break;
+                }
+                estimate = Some(fee_per_gram);
+                successes = 0;
+                total = 0;
+            }
+        }
+        estimate
+    }
+
+    // Returns the fee rate that outbids the mempool transactions that fill the next `target` blocks, or zero if the
+    // whole mempool fits in them
+    fn mempool_estimate(&self, target: u64) -> u64 {
+        let mut pending: Vec<&PendingTransaction> = self.pending.values().collect();
+        pending.sort_by_key(|tx| Reverse(tx.fee_per_gram));
+        let capacity = self.max_block_weight.saturating_mul(target);
+        let mut weight = 0u64;
+        for tx in pending {
+            weight = weight.saturating_add(tx.weight);
+            if weight >= capacity {
+                return tx.fee_per_gram.saturating_add(1);
+            }
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        block::{AggregateBody, WEIGHT_PER_KERNEL},
+        blockheader::BlockHeader,
+        consensus::Network,
+        difficulty::Difficulty,
+        pow::ProofOfWork,
+        transaction::TransactionKernel,
+        types::BlindingFactor,
+    };
+    use chrono::Utc;
+
+    fn create_transaction(fee_per_gram: u64, lock_height: u64) -> Transaction {
+        let kernel = TransactionKernel::empty().with_fee(fee_per_gram * WEIGHT_PER_KERNEL);
+        let kernel = kernel.with_lock_height(lock_height);
+        Transaction::new(vec![], vec![], vec![kernel], BlindingFactor::default())
+    }
+
+    fn create_block(height: u64, txs: &[Transaction]) -> Block {
+        let header = BlockHeader {
+            version: 0,
+            height,
+            prev_hash: [0; 32],
+            timestamp: Utc::now(),
+            output_mmr: [0; 32],
+            kernel_mmr: [0; 32],
+            total_kernel_offset: BlindingFactor::default(),
+            pow: ProofOfWork::new(Difficulty::min()),
+        };
+        let kernels = txs.iter().flat_map(|tx| tx.body.kernels.iter().cloned()).collect();
+        Block::new(header, AggregateBody::new(vec![], vec![], kernels))
+    }
+
+    #[test]
+    fn estimate_from_mined_transactions() {
+        let constants = Network::TestNet.consensus_constants();
+        let mut estimator = FeeEstimator::new(&constants, 10);
+        assert_eq!(estimator.estimate_fee_per_gram(1), constants.min_fee_per_gram);
+
+        let fast: Vec<Transaction> = (0..20).map(|i| create_transaction(100, i)).collect();
+        let slow: Vec<Transaction> = (0..20).map(|i| create_transaction(10, i)).collect();
+        for tx in fast.iter().chain(slow.iter()) {
+            estimator.add_transaction(tx);
+        }
+        estimator.add_block(&create_block(1, &fast));
+        for height in 2..5 {
+            estimator.add_block(&create_block(height, &[]));
+        }
+        // The slow transactions have been waiting for longer than one block, but not yet for longer than four
+        assert_eq!(estimator.estimate_fee_per_gram(1), 100);
+        assert_eq!(estimator.estimate_fee_per_gram(4), 100);
+        estimator.add_block(&create_block(5, &slow));
+        assert_eq!(estimator.estimate_fee_per_gram(4), 100);
+        assert_eq!(estimator.estimate_fee_per_gram(5), 10);
+        assert_eq!(estimator.estimate_fee(5, 1, 2), calculate_required_fee(10, 1, 2));
+
+        // The samples of the fast transactions fall out of the window
+        for height in 6..11 {
+            estimator.add_block(&create_block(height, &[]));
+        }
+        assert_eq!(estimator.estimate_fee_per_gram(1), 100);
+        estimator.add_block(&create_block(11, &[]));
+        assert_eq!(estimator.estimate_fee_per_gram(1), constants.min_fee_per_gram);
+    }
+
+    #[test]
+    fn estimate_from_mempool() {
+        let constants =
+            ConsensusConstants { max_block_weight: 10 * WEIGHT_PER_KERNEL, ..Network::TestNet.consensus_constants() };
+        let mut estimator = FeeEstimator::new(&constants, 10);
+        let txs: Vec<Transaction> = (0..25).map(|i| create_transaction(50 + i / 10, i)).collect();
+        for tx in txs.iter() {
+            estimator.add_transaction(tx);
+        }
+        // The ten best paying transactions fill the next block, the twenty best the next two
+        assert_eq!(estimator.estimate_fee_per_gram(1), 52);
+        assert_eq!(estimator.estimate_fee_per_gram(2), 51);
+        assert_eq!(estimator.estimate_fee_per_gram(3), constants.min_fee_per_gram);
+        for tx in txs.iter().take(10) {
+            estimator.remove_transaction(tx);
+        }
+        assert_eq!(estimator.estimate_fee_per_gram(2), constants.min_fee_per_gram);
+    }
+}
@@ -0,0 +1,493 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Human-readable serialization of transactions and blocks.
+//!
+//! In human-readable formats such as JSON, commitments, keys, signatures, proofs, scripts and hashes are written as
+//! hex, and fees, nonces and difficulties as decimal strings so that parsers that read every number as a double do
+//! not round them. Timestamps are RFC 3339 strings and flags are their bit values. Every field is always written, with
+//! absent values as `null`, and unknown fields are rejected, so each value has a single canonical JSON form.
+//!
+//! This form is only meant for explorers, debugging tools and wallet backups. It is not hashed or signed; the
+//! consensus form of a value is its binary encoding, which is what non-human-readable formats get.
+
+use crate::{
+    block::{AggregateBody, Block},
+    blockheader::BlockHeader,
+    difficulty::Difficulty,
+    encoding::{BinaryEncoding, EncodingError, Reader},
+    pow::{PowAlgorithm, ProofOfWork},
+    range_proof::RangeProof,
+    script::Script,
+    transaction::{
+        KernelFeatures,
+        OutputFeatures,
+        OutputFlags,
+        Transaction,
+        TransactionInput,
+        TransactionKernel,
+        TransactionOutput,
+    },
+    types::{BlindingFactor, Commitment, PublicKey, Signature},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use crypto::commitment::HomomorphicCommitment;
+use serde::{
+    de::{self, DeserializeOwned, MapAccess, SeqAccess, Visitor},
+    ser::SerializeStruct,
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+use std::{fmt, marker::PhantomData};
+use tari_utilities::{
+    hex::{from_hex, to_hex},
+    ByteArray,
+};
+
+/// How a field is written to and read from a human-readable format
+trait Codec<T> {
+    fn serialize<S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error>;
+
+    fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error>;
+}
+
+// Serializes a borrowed field with the codec `C`
+struct With<'a, C, T>(&'a T, PhantomData<C>);
+
+impl<'a, C: Codec<T>, T> With<'a, C, T> {
+    fn new(value: &'a T) -> With<'a, C, T> {
+        With(value, PhantomData)
+    }
+}
+
+impl<'a, C: Codec<T>, T> Serialize for With<'a, C, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        C::serialize(self.0, serializer)
+    }
+}
+
+// Deserializes a field with the codec `C`
+struct Owned<C, T>(T, PhantomData<C>);
+
+impl<'de, C: Codec<T>, T> Deserialize<'de> for Owned<C, T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Owned<C, T>, D::Error> {
+        C::deserialize(deserializer).map(|value| Owned(value, PhantomData))
+    }
+}
+
+/// Values that use their own human-readable form
+struct Plain;
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for Plain {
+    fn serialize<S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        value.serialize(serializer)
+    }
+
+    fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+        T::deserialize(deserializer)
+    }
+}
+
+/// Values written as a decimal string
+struct Decimal;
+
+impl Codec<u64> for Decimal {
+    fn serialize<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Codec<Difficulty> for Decimal {
+    fn serialize<S: Serializer>(value: &Difficulty, serializer: S) -> Result<S::Ok, S::Error> {
+        <Decimal as Codec<u64>>::serialize(&value.as_u64(), serializer)
+    }
+
+    fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Difficulty, D::Error> {
+        <Decimal as Codec<u64>>::deserialize(deserializer).map(Difficulty::from)
+    }
+}
+
+/// Values written as the hex of their bytes
+struct Hex;
+
+// The bytes a value is written as by the `Hex` codec
+trait HexBytes: Sized {
+    fn hex_bytes(&self) -> Vec<u8>;
+
+    fn from_hex_bytes(bytes: &[u8]) -> Result<Self, EncodingError>;
+}
+
+impl<T: HexBytes> Codec<T> for Hex {
+    fn serialize<S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&to_hex(&value.hex_bytes()))
+    }
+
+    fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        let bytes = from_hex(&hex).map_err(|_| de::Error::custom("invalid hex"))?;
+        T::from_hex_bytes(&bytes).map_err(de::Error::custom)
+    }
+}
+
+macro_rules! byte_array_hex {
+    ($($ty:ty),*) => {
+        $(impl HexBytes for $ty {
+            fn hex_bytes(&self) -> Vec<u8> {
+                self.as_bytes().to_vec()
+            }
+
+            fn from_hex_bytes(bytes: &[u8]) -> Result<Self, EncodingError> {
+                <$ty>::from_bytes(bytes).map_err(|_| EncodingError::InvalidValue)
+            }
+        })*
+    };
+}
+
+byte_array_hex!(PublicKey, BlindingFactor, RangeProof, Vec<u8>);
+
+impl HexBytes for [u8; 32] {
+    fn hex_bytes(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+
+    fn from_hex_bytes(bytes: &[u8]) -> Result<Self, EncodingError> {
+        let mut reader = Reader::new(bytes);
+        let hash = reader.read_hash()?;
+        reader.finish(hash)
+    }
+}
+
+impl HexBytes for Commitment {
+    fn hex_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn from_hex_bytes(bytes: &[u8]) -> Result<Self, EncodingError> {
+        let mut reader = Reader::new(bytes);
+        let commitment = reader.read_commitment()?;
+        reader.finish(commitment)
+    }
+}
+
+/// Signatures are written as their public nonce followed by the signature scalar, as in the binary encoding
+impl HexBytes for Signature {
+    fn hex_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.get_public_nonce().to_vec();
+        bytes.extend_from_slice(self.get_signature().as_bytes());
+        bytes
+    }
+
+    fn from_hex_bytes(bytes: &[u8]) -> Result<Self, EncodingError> {
+        let mut reader = Reader::new(bytes);
+        let signature = reader.read_signature()?;
+        reader.finish(signature)
+    }
+}
+
+/// Scripts are written as their binary encoding, without the version prefix
+impl HexBytes for Script {
+    fn hex_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.binary_encode(&mut bytes);
+        bytes
+    }
+
+    fn from_hex_bytes(bytes: &[u8]) -> Result<Self, EncodingError> {
+        let mut reader = Reader::new(bytes);
+        let script = Script::binary_decode(&mut reader)?;
+        reader.finish(script)
+    }
+}
+
+/// Optional values written with the codec `C`, or as null when absent
+struct Optional<C>(PhantomData<C>);
+
+impl<C: Codec<T>, T> Codec<Option<T>> for Optional<C> {
+    fn serialize<S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => serializer.serialize_some(&With::<C, T>::new(value)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+        let value = Option::<Owned<C, T>>::deserialize(deserializer)?;
+        Ok(value.map(|value| value.0))
+    }
+}
+
+/// Flags written as their bit values
+struct Bits;
+
+macro_rules! flags_bits {
+    ($($ty:ty),*) => {
+        $(impl Codec<$ty> for Bits {
+            fn serialize<S: Serializer>(value: &$ty, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_u8(value.bits())
+            }
+
+            fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<$ty, D::Error> {
+                let bits = u8::deserialize(deserializer)?;
+                <$ty>::from_bits(bits).ok_or_else(|| de::Error::custom("unknown flags"))
+            }
+        })*
+    };
+}
+
+flags_bits!(KernelFeatures, OutputFlags);
+
+/// Proof of work algorithms written as their id
+struct AlgorithmId;
+
+impl Codec<PowAlgorithm> for AlgorithmId {
+    fn serialize<S: Serializer>(value: &PowAlgorithm, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(value.id())
+    }
+
+    fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PowAlgorithm, D::Error> {
+        let id = u8::deserialize(deserializer)?;
+        PowAlgorithm::from_id(id).ok_or_else(|| de::Error::custom("unknown proof of work algorithm"))
+    }
+}
+
+/// Timestamps written as RFC 3339 strings in UTC, with nanosecond precision
+struct Rfc3339;
+
+impl Codec<DateTime<Utc>> for Rfc3339 {
+    fn serialize<S: Serializer>(value: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_rfc3339_opts(SecondsFormat::Nanos, true))
+    }
+
+    fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let timestamp = DateTime::parse_from_rfc3339(&value).map_err(de::Error::custom)?;
+        Ok(timestamp.with_timezone(&Utc))
+    }
+}
+
+// The most bytes a sequence visitor allocates up front, since the size hint comes from the untrusted input
+const MAX_PREALLOCATED_BYTES: usize = 1024;
+
+// Decodes a value from its binary encoding, given as bytes or as a sequence of bytes
+struct BinaryVisitor<T>(&'static str, PhantomData<T>);
+
+impl<'de, T: BinaryEncoding> Visitor<'de> for BinaryVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "the binary encoding of {}", self.0)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<T, E> {
+        T::from_binary(bytes).map_err(E::custom)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(MAX_PREALLOCATED_BYTES));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        T::from_binary(&bytes).map_err(de::Error::custom)
+    }
+}
+
+// Implements `Serialize` and `Deserialize` for a struct, writing each field with its codec in human-readable formats
+// and the binary encoding of the struct otherwise
+macro_rules! human_readable {
+    ($name:ident, $description:expr, { $($field:ident: $codec:ty),* $(,)* }) => {
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                if !serializer.is_human_readable() {
+                    return serializer.serialize_bytes(&self.to_binary());
+                }
+                let fields: &[&str] = &[$(stringify!($field)),*];
+                let mut state = serializer.serialize_struct(stringify!($name), fields.len())?;
+                $(state.serialize_field(stringify!($field), &With::<$codec, _>::new(&self.$field))?;)*
+                state.end()
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<$name, D::Error> {
+                const FIELDS: &[&str] = &[$(stringify!($field)),*];
+
+                struct FieldVisitor;
+
+                impl<'de> Visitor<'de> for FieldVisitor {
+                    type Value = $name;
+
+                    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                        write!(formatter, "{}", $description)
+                    }
+
+                    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<$name, A::Error> {
+                        $(let mut $field = None;)*
+                        while let Some(key) = map.next_key::<String>()? {
+                            match key.as_str() {
+                                $(stringify!($field) => {
+                                    if $field.is_some() {
+                                        return Err(de::Error::duplicate_field(stringify!($field)));
+                                    }
+                                    $field = Some(map.next_value::<Owned<$codec, _>>()?.0);
+                                },)*
+                                _ => return Err(de::Error::unknown_field(&key, FIELDS)),
+                            }
+                        }
+                        Ok($name {
+                            $($field: $field.ok_or_else(|| de::Error::missing_field(stringify!($field)))?,)*
+                        })
+                    }
+                }
+
+                if deserializer.is_human_readable() {
+                    deserializer.deserialize_struct(stringify!($name), FIELDS, FieldVisitor)
+                } else {
+                    deserializer.deserialize_bytes(BinaryVisitor::<$name>($description, PhantomData))
+                }
+            }
+        }
+    };
+}
+
+human_readable!(OutputFeatures, "output features", {
+    flags: Bits,
+    maturity: Plain,
+    relative_maturity: Plain,
+});
+
+human_readable!(TransactionInput, "a transaction input", {
+    features: Plain,
+    commitment: Hex,
+    script_signature: Optional<Hex>,
+    script_preimage: Optional<Hex>,
+});
+
+human_readable!(TransactionOutput, "a transaction output", {
+    features: Plain,
+    commitment: Hex,
+    proof: Hex,
+    script: Optional<Hex>,
+});
+
+human_readable!(TransactionKernel, "a transaction kernel", {
+    features: Bits,
+    fee: Decimal,
+    lock_height: Plain,
+    excess: Optional<Hex>,
+    excess_sig: Optional<Hex>,
+});
+
+human_readable!(AggregateBody, "an aggregate body", {
+    inputs: Plain,
+    outputs: Plain,
+    kernels: Plain,
+});
+
+human_readable!(Transaction, "a transaction", {
+    version: Plain,
+    offset: Hex,
+    body: Plain,
+});
+
+human_readable!(ProofOfWork, "a proof of work", {
+    target_difficulty: Decimal,
+    pow_algo: AlgorithmId,
+    nonce: Decimal,
+    pow_data: Hex,
+});
+
+human_readable!(BlockHeader, "a block header", {
+    version: Plain,
+    height: Plain,
+    prev_hash: Hex,
+    timestamp: Rfc3339,
+    output_mmr: Hex,
+    kernel_mmr: Hex,
+    total_kernel_offset: Hex,
+    pow: Plain,
+});
+
+human_readable!(Block, "a block", {
+    header: Plain,
+    body: Plain,
+});
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{range_proof::RANGE_PROOF_LENGTH, types::CommitmentFactory};
+    use crypto::{commitment::HomomorphicCommitmentFactory, keys::SecretKey};
+    use rand::OsRng;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn transaction_json() {
+        let mut rng = OsRng::new().unwrap();
+        let commitment = CommitmentFactory::create(&BlindingFactor::random(&mut rng), &BlindingFactor::from(10));
+        let output = TransactionOutput::new(
+            OutputFeatures::create_coinbase(5),
+            commitment,
+            RangeProof([7; RANGE_PROOF_LENGTH]),
+        );
+        let kernel = TransactionKernel {
+            features: KernelFeatures::empty(),
+            fee: u64::max_value(),
+            lock_height: 2,
+            excess: Some(commitment),
+            excess_sig: None,
+        };
+        let tx = Transaction {
+            version: 1,
+            offset: BlindingFactor::random(&mut rng),
+            body: AggregateBody::new(
+                vec![TransactionInput::new(OutputFeatures::empty(), commitment)],
+                vec![output],
+                vec![kernel],
+            ),
+        };
+
+        let json = serde_json::to_value(&tx).unwrap();
+        assert_eq!(json["offset"], Value::String(tx.offset.to_hex()));
+        let kernel = &json["body"]["kernels"][0];
+        assert_eq!(kernel["fee"], json!("18446744073709551615"));
+        assert_eq!(kernel["excess"], Value::String(to_hex(&commitment.as_bytes().to_vec())));
+        assert_eq!(kernel["excess_sig"], Value::Null);
+        assert_eq!(json["body"]["outputs"][0]["features"], json!({"flags": 1, "maturity": 5, "relative_maturity": 0}));
+
+        let decoded: Transaction = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(decoded.to_binary(), tx.to_binary());
+
+        let mut unknown = json.clone();
+        unknown["body"]["kernels"][0]["extra"] = json!(0);
+        assert!(serde_json::from_value::<Transaction>(unknown).is_err());
+        let mut missing = json;
+        missing["body"]["kernels"][0].as_object_mut().unwrap().remove("excess_sig");
+        assert!(serde_json::from_value::<Transaction>(missing).is_err());
+    }
+}
@@ -23,9 +23,22 @@
 #[macro_use]
 extern crate bitflags;
 
+pub mod atomic_swap;
 pub mod block;
 pub mod blockheader;
+pub mod coinbase;
+pub mod compact_block;
+pub mod consensus;
+pub mod difficulty;
+pub mod emission;
+pub mod encoding;
+pub mod fee;
+pub mod fee_estimator;
+pub mod json;
+pub mod multi_party;
 pub mod pow;
 pub mod range_proof;
+pub mod script;
 pub mod transaction;
+pub mod transaction_protocol;
 pub mod types;
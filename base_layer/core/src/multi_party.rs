@@ -0,0 +1,356 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Outputs held in shared custody by several parties, none of whom knows the blinding factor of the output on its own.
+//!
+//! The parties combine their public keys into a MuSig joint key, `X = sum(a_i * P_i)`. The keys are never used as they
+//! are: every output of the parties has an index, and each party tweaks its key for the output with `t = H(X || index)`
+//! into `P_i + t.G`. The output key `Y` is the MuSig joint key of the tweaked keys, and the output commits to its value
+//! with the negation of the secret of `Y` as its blinding factor, so the commitment is `v.H - Y`. An output like this
+//! looks the same as any other output on the chain, and since every output of the parties has its own key, spending
+//! one of them reveals nothing about the others, nor can its kernel be replayed to spend them.
+//!
+//! Spending the output adds `Y` to the kernel excess of the spending transaction, which the parties sign together with
+//! the MuSig signer, as the kernel challenge has the form that MuSig signs. Funding the output adds `-Y` to the kernel
+//! excess of the funding transaction, which the MuSig signer can not sign for, so each party signs a kernel for its
+//! own share `-a_i * (P_i + t.G)` of it instead.
+
+use crate::{
+    range_proof::{RangeProof, RANGE_PROOF_LENGTH},
+    transaction::{
+        build_kernel_challenge,
+        build_kernel_message,
+        KernelFeatures,
+        OutputFeatures,
+        TransactionInput,
+        TransactionKernel,
+        TransactionOutput,
+    },
+    types::{BlindingFactor, Commitment, CommitmentFactory, PublicKey, Signature, SignatureHash},
+};
+use crypto::{
+    challenge::Challenge,
+    commitment::HomomorphicCommitmentFactory,
+    keys::PublicKey as PublicKeyTrait,
+    musig::{JointKey, JointKeyBuilder, MuSigError},
+    ristretto::musig::RistrettoMuSig,
+};
+use derive_error::Error;
+use tari_utilities::ByteArray;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum MultiPartyError {
+    /// The participant keys could not be combined into a joint key, or the key is not one of them
+    MuSigError(MuSigError),
+    /// The MuSig ceremony has not produced the aggregate signature yet
+    IncompleteSignature,
+    /// The aggregate signature does not sign the spending kernel
+    InvalidSignature,
+    /// The funding kernel could not be signed
+    SigningError,
+}
+
+/// The scheme with which the keys of the parties are combined into the key of a multi-party output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregationScheme {
+    /// An n-of-n MuSig joint key, which is not open to rogue key attacks
+    MuSig,
+}
+
+/// An output whose blinding factor is split between several parties, along with what the parties need to know to
+/// fund and spend it together
+#[derive(Debug, Clone)]
+pub struct MultiPartyOutput {
+    pub value: u64,
+    pub features: OutputFeatures,
+    pub scheme: AggregationScheme,
+    /// The public keys of the parties, in the sorted order MuSig gives them
+    pub participants: Vec<PublicKey>,
+    /// The index that gives this output a key of its own among the outputs of the same parties
+    pub index: u64,
+    /// The keys of the parties tweaked for this output, in the sorted order MuSig gives them
+    pub output_keys: Vec<PublicKey>,
+    /// The output key, which is the joint public key of the tweaked keys
+    pub joint_key: PublicKey,
+    tweak: BlindingFactor,
+    musig_scalars: Vec<BlindingFactor>,
+}
+
+impl MultiPartyOutput {
+    /// Create the multi-party output with the given index, held by the parties with the given public keys, in any
+    /// order. The parties must never use an index for two outputs.
+    pub fn new(
+        value: u64,
+        features: OutputFeatures,
+        participants: Vec<PublicKey>,
+        index: u64,
+    ) -> Result<MultiPartyOutput, MultiPartyError>
+    {
+        let joint_key = build_joint_key(participants)?;
+        let tweak_hash = Challenge::<SignatureHash>::new()
+            .concat(joint_key.get_joint_pubkey().as_bytes())
+            .concat(&index.to_le_bytes())
+            .hash();
+        // The hash is as long as a secret key, so any hash is a valid key
+        let tweak = BlindingFactor::from_vec(&tweak_hash).expect("The tweak hash is not a valid secret key");
+        let tweak_key = PublicKey::from_secret_key(&tweak);
+        let participants: Vec<PublicKey> = (0..joint_key.size()).map(|i| joint_key.get_pub_keys(i).clone()).collect();
+        let output_key = build_joint_key(participants.iter().map(|key| key + tweak_key).collect())?;
+        Ok(MultiPartyOutput {
+            value,
+            features,
+            scheme: AggregationScheme::MuSig,
+            participants,
+            index,
+            output_keys: (0..output_key.size()).map(|i| output_key.get_pub_keys(i).clone()).collect(),
+            joint_key: output_key.get_joint_pubkey().clone(),
+            tweak,
+            musig_scalars: (0..output_key.size()).map(|i| output_key.get_musig_scalar(i).clone()).collect(),
+        })
+    }
+
+    /// The secret key of a party tweaked for this output, with which the party signs in the MuSig ceremony that spends
+    /// the output
+    pub fn tweak_secret(&self, secret: &BlindingFactor) -> BlindingFactor {
+        secret + self.tweak
+    }
+
+    /// The commitment to the value of the output, `v.H - Y`
+    pub fn commitment(&self) -> Commitment {
+        let value = CommitmentFactory::create(&BlindingFactor::default(), &BlindingFactor::from(self.value));
+        &value - &Commitment::from_public_key(&self.joint_key)
+    }
+
+    /// Create the transaction output for this output, which the funding transaction creates. Range proofs do not have
+    /// a proving system yet, so the output carries an all-zero placeholder proof.
+    pub fn as_transaction_output(&self) -> TransactionOutput {
+        TransactionOutput::new(self.features, self.commitment(), RangeProof([0; RANGE_PROOF_LENGTH]))
+    }
+
+    /// Create the transaction input that spends this output
+    pub fn as_transaction_input(&self) -> TransactionInput {
+        TransactionInput::new(self.features, self.commitment())
+    }
+
+    /// Sign the kernel for the share of the party with the given secret key of the excess of the funding transaction.
+    /// The kernels of all of the parties go in the funding transaction, and do not pay a fee, which the funder pays
+    /// with a kernel of its own. The nonce must never be reused.
+    pub fn sign_funding_kernel(
+        &self,
+        secret: &BlindingFactor,
+        nonce: BlindingFactor,
+    ) -> Result<TransactionKernel, MultiPartyError>
+    {
+        let secret = self.tweak_secret(secret);
+        let index = self
+            .output_keys
+            .binary_search(&PublicKey::from_secret_key(&secret))
+            .map_err(|_| MuSigError::ParticipantNotFound)?;
+        let share = &BlindingFactor::default() - &(&self.musig_scalars[index] * &secret);
+        let public_share = PublicKey::from_secret_key(&share);
+        let challenge =
+            build_kernel_challenge(&PublicKey::from_secret_key(&nonce), &public_share, KernelFeatures::empty(), 0, 0);
+        let signature = Signature::sign(share, nonce, challenge).map_err(|_| MultiPartyError::SigningError)?;
+        Ok(TransactionKernel {
+            features: KernelFeatures::empty(),
+            fee: 0,
+            lock_height: 0,
+            excess: Some(Commitment::from_public_key(&public_share)),
+            excess_sig: Some(signature),
+        })
+    }
+
+    /// Start the MuSig ceremony in which the parties sign the kernel that spends this output. The kernel is a plain
+    /// kernel with the given fee and lock height, whose excess is the output key. The parties sign with their keys
+    /// tweaked by `tweak_secret`, carry on with the nonce and signature rounds of the ceremony, and any of them can
+    /// then build the kernel with `build_spend_kernel`.
+    pub fn start_spend(&self, fee: u64, lock_height: u64) -> RistrettoMuSig<SignatureHash> {
+        let message = build_kernel_message(KernelFeatures::empty(), fee, lock_height);
+        self.output_keys
+            .iter()
+            .fold(RistrettoMuSig::new(self.output_keys.len()), |musig, key| musig.add_public_key(key))
+            .set_message(&message)
+    }
+
+    /// Build the kernel that spends this output from a finished MuSig ceremony, which was started by `start_spend`
+    /// with the same fee and lock height
+    pub fn build_spend_kernel(
+        &self,
+        musig: &RistrettoMuSig<SignatureHash>,
+        fee: u64,
+        lock_height: u64,
+    ) -> Result<TransactionKernel, MultiPartyError>
+    {
+        let signature = musig.get_aggregated_signature().ok_or(MultiPartyError::IncompleteSignature)?;
+        let kernel = TransactionKernel {
+            features: KernelFeatures::empty(),
+            fee,
+            lock_height,
+            excess: Some(Commitment::from_public_key(&self.joint_key)),
+            excess_sig: Some(signature.clone()),
+        };
+        kernel.verify_signature().map_err(|_| MultiPartyError::InvalidSignature)?;
+        Ok(kernel)
+    }
+}
+
+/// Combine the keys into a MuSig joint key
+fn build_joint_key(keys: Vec<PublicKey>) -> Result<JointKey<PublicKey, BlindingFactor>, MultiPartyError> {
+    let mut builder = JointKeyBuilder::new(keys.len())?;
+    builder.add_keys(keys)?;
+    Ok(builder.build::<SignatureHash>()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction::Transaction;
+    use crypto::keys::SecretKey;
+    use rand::OsRng;
+
+    /// Run the MuSig ceremony of the parties with the given secret keys, who all sign with the same ceremony
+    fn sign(
+        mut musig: RistrettoMuSig<SignatureHash>,
+        rng: &mut OsRng,
+        secrets: &[BlindingFactor],
+    ) -> RistrettoMuSig<SignatureHash>
+    {
+        let keys: Vec<PublicKey> = secrets.iter().map(PublicKey::from_secret_key).collect();
+        let nonces: Vec<BlindingFactor> = secrets.iter().map(|_| BlindingFactor::random(rng)).collect();
+        let public_nonces: Vec<PublicKey> = nonces.iter().map(PublicKey::from_secret_key).collect();
+        for (key, nonce) in keys.iter().zip(public_nonces.iter()) {
+            musig = musig.add_nonce_commitment(key, Challenge::<SignatureHash>::hash_input(nonce.to_vec()));
+        }
+        for (key, nonce) in keys.iter().zip(public_nonces) {
+            musig = musig.add_nonce(key, nonce);
+        }
+        let partial_signatures: Vec<Signature> = keys
+            .iter()
+            .zip(secrets.iter().zip(nonces.iter()))
+            .map(|(key, (secret, nonce))| musig.calculate_partial_signature(key, secret, nonce).unwrap())
+            .collect();
+        partial_signatures.iter().fold(musig, |musig, signature| musig.add_signature(signature, true))
+    }
+
+    /// A plain kernel for the given excess, signed by its owner
+    fn sign_kernel(rng: &mut OsRng, excess: BlindingFactor, fee: u64) -> TransactionKernel {
+        let nonce = BlindingFactor::random(rng);
+        let public_excess = PublicKey::from_secret_key(&excess);
+        let public_nonce = PublicKey::from_secret_key(&nonce);
+        let challenge = build_kernel_challenge(&public_nonce, &public_excess, KernelFeatures::empty(), fee, 0);
+        TransactionKernel {
+            features: KernelFeatures::empty(),
+            fee,
+            lock_height: 0,
+            excess: Some(Commitment::from_public_key(&public_excess)),
+            excess_sig: Some(Signature::sign(excess, nonce, challenge).unwrap()),
+        }
+    }
+
+    /// A transaction that spends the shared output, paying the whole value less the fee to a recipient, who signs for
+    /// the rest of the excess
+    fn spend(rng: &mut OsRng, shared: &MultiPartyOutput, shared_kernel: TransactionKernel) -> Transaction {
+        let offset = BlindingFactor::random(rng);
+        let spend_key = BlindingFactor::random(rng);
+        let output = TransactionOutput::new(
+            OutputFeatures::empty(),
+            CommitmentFactory::create(&spend_key, &BlindingFactor::from(shared.value - shared_kernel.fee)),
+            RangeProof([0; RANGE_PROOF_LENGTH]),
+        );
+        let recipient_kernel = sign_kernel(rng, &spend_key - &offset, 0);
+        let mut tx = Transaction::new(
+            vec![shared.as_transaction_input()],
+            vec![output],
+            vec![shared_kernel, recipient_kernel],
+            offset,
+        );
+        tx.body.sort();
+        tx
+    }
+
+    #[test]
+    fn fund_multi_party_output() {
+        let mut rng = OsRng::new().unwrap();
+        let secrets: Vec<BlindingFactor> = (0..3).map(|_| BlindingFactor::random(&mut rng)).collect();
+        let keys: Vec<PublicKey> = secrets.iter().map(PublicKey::from_secret_key).collect();
+        let shared = MultiPartyOutput::new(100, OutputFeatures::empty(), keys, 0).unwrap();
+
+        // The funder spends an input worth 120 into the shared output and change, and pays the fee
+        let offset = BlindingFactor::random(&mut rng);
+        let input_key = BlindingFactor::random(&mut rng);
+        let change_key = BlindingFactor::random(&mut rng);
+        let input = TransactionInput::new(
+            OutputFeatures::empty(),
+            CommitmentFactory::create(&input_key, &BlindingFactor::from(120)),
+        );
+        let change = TransactionOutput::new(
+            OutputFeatures::empty(),
+            CommitmentFactory::create(&change_key, &BlindingFactor::from(15)),
+            RangeProof([0; RANGE_PROOF_LENGTH]),
+        );
+        let mut kernels = vec![sign_kernel(&mut rng, &(&change_key - &input_key) - &offset, 5)];
+        let outsider = shared.sign_funding_kernel(&BlindingFactor::random(&mut rng), BlindingFactor::random(&mut rng));
+        assert_eq!(outsider.err(), Some(MultiPartyError::MuSigError(MuSigError::ParticipantNotFound)));
+        let outputs = vec![change, shared.as_transaction_output()];
+        let mut tx = Transaction::new(vec![input], outputs, kernels.clone(), offset);
+        tx.body.sort();
+        assert!(tx.validate().is_err());
+
+        for secret in &secrets {
+            kernels.push(shared.sign_funding_kernel(secret, BlindingFactor::random(&mut rng)).unwrap());
+        }
+        tx.body.kernels = kernels;
+        tx.body.sort();
+        tx.validate().unwrap();
+    }
+
+    #[test]
+    fn spend_multi_party_output() {
+        let mut rng = OsRng::new().unwrap();
+        let secrets: Vec<BlindingFactor> = (0..3).map(|_| BlindingFactor::random(&mut rng)).collect();
+        let keys: Vec<PublicKey> = secrets.iter().rev().map(PublicKey::from_secret_key).collect();
+        let shared = MultiPartyOutput::new(100, OutputFeatures::empty(), keys.clone(), 0).unwrap();
+        assert_eq!(shared.scheme, AggregationScheme::MuSig);
+        let mut duplicated = keys.clone();
+        duplicated[0] = keys[1].clone();
+        assert_eq!(
+            MultiPartyOutput::new(100, OutputFeatures::empty(), duplicated, 0).err(),
+            Some(MultiPartyError::MuSigError(MuSigError::DuplicatePubKey))
+        );
+        // Every output of the parties has a key of its own, which is not their joint key
+        let other = MultiPartyOutput::new(100, OutputFeatures::empty(), keys.clone(), 1).unwrap();
+        assert_ne!(other.joint_key, shared.joint_key);
+        assert_ne!(&shared.joint_key, build_joint_key(keys).unwrap().get_joint_pubkey());
+
+        let tweaked: Vec<BlindingFactor> = secrets.iter().map(|secret| shared.tweak_secret(secret)).collect();
+        let musig = shared.start_spend(5, 0);
+        assert_eq!(shared.build_spend_kernel(&musig, 5, 0).err(), Some(MultiPartyError::IncompleteSignature));
+        let musig = sign(musig, &mut rng, &tweaked);
+        assert!(musig.is_finalized());
+        assert_eq!(shared.build_spend_kernel(&musig, 6, 0).err(), Some(MultiPartyError::InvalidSignature));
+        let shared_kernel = shared.build_spend_kernel(&musig, 5, 0).unwrap();
+        spend(&mut rng, &shared, shared_kernel.clone()).validate().unwrap();
+
+        // The kernel can not be replayed to spend another output of the parties
+        assert!(spend(&mut rng, &other, shared_kernel).validate().is_err());
+    }
+}
@@ -20,5 +20,56 @@
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use crate::difficulty::Difficulty;
+
+/// The algorithms a block can be mined with. The id of the algorithm is part of the header hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowAlgorithm {
+    /// The header hash itself must meet the target difficulty
+    Blake = 0,
+}
+
+impl PowAlgorithm {
+    /// Returns the algorithm with the given id, or None if there is no such algorithm
+    pub fn from_id(id: u8) -> Option<PowAlgorithm> {
+        match id {
+            0 => Some(PowAlgorithm::Blake),
+            _ => None,
+        }
+    }
+
+    /// Returns the id of the algorithm, as it is encoded and hashed
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+}
+
+/// The proof of work summary of a block header
 #[derive(Clone, Debug, PartialEq)]
-pub struct ProofOfWork {}
+pub struct ProofOfWork {
+    /// The difficulty the block has to meet, as calculated from the blocks before it
+    pub target_difficulty: Difficulty,
+    /// The algorithm the block was mined with
+    pub pow_algo: PowAlgorithm,
+    /// The nonce the miner varies to find a header hash that meets the target difficulty
+    pub nonce: u64,
+    /// Data the algorithm needs to verify the proof, which is empty for `PowAlgorithm::Blake`
+    pub pow_data: Vec<u8>,
+}
+
+impl ProofOfWork {
+    /// Create a proof of work for the target difficulty, mined with `PowAlgorithm::Blake` from a zero nonce
+    pub fn new(target_difficulty: Difficulty) -> ProofOfWork {
+        ProofOfWork { target_difficulty, pow_algo: PowAlgorithm::Blake, nonce: 0, pow_data: Vec::new() }
+    }
+
+    /// The bytes of the proof of work that the header hash commits to
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.target_difficulty.as_u64().to_le_bytes().to_vec();
+        bytes.push(self.pow_algo.id());
+        bytes.extend_from_slice(&self.nonce.to_le_bytes());
+        bytes.extend_from_slice(&(self.pow_data.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.pow_data);
+        bytes
+    }
+}
@@ -23,9 +23,11 @@
 // Portions of this file were originally copyrighted (c) 2018 The Grin Developers, issued under the Apache License,
 // Version 2.0, available at http://www.apache.org/licenses/LICENSE-2.0.
 
-const RANGE_PROOF_LENGTH: usize = 1; // This will be changed
+use derive::{ByteArray, Hex};
 
-#[derive(Debug, Clone)]
+pub const RANGE_PROOF_LENGTH: usize = 1; // This will be changed
+
+#[derive(Debug, Clone, ByteArray, Hex)]
 pub struct RangeProof(pub [u8; RANGE_PROOF_LENGTH]);
 
 impl Copy for RangeProof {}
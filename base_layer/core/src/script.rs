@@ -0,0 +1,223 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Output scripts, which add conditions to spending an output on top of knowing its blinding factor.
+//!
+//! The script of an output is committed to by the output hash, and it is run by the nodes against the input that
+//! spends the output, which may carry a signature for the script. The signature commits to the kernel excess of the
+//! spending transaction, so it only authorizes the transaction it was made for.
+//!
+//! The instructions are a placeholder for a complete scripting language: each is encoded as an opcode followed by its
+//! operands, so that new instructions, such as covenants, can be added later without changing the format of outputs.
+
+use crate::{
+    transaction::{TransactionInput, TransactionKernel},
+    types::{BlindingFactor, Commitment, PublicKey, Signature, SignatureHash},
+};
+use crypto::{challenge::Challenge, commitment::HomomorphicCommitment, keys::PublicKey as PublicKeyTrait};
+use derive_error::Error;
+use digest::Digest;
+use sha2::Sha256;
+use tari_utilities::ByteArray;
+
+/// The SHA-256 hash of a preimage, which is the hash lock used by other chains as well, so that a swap can lock
+/// outputs on both chains to the same preimage
+pub type HashLock = [u8; 32];
+/// The secret whose hash is a hash lock
+pub type Preimage = [u8; 32];
+
+#[derive(Debug, PartialEq, Error)]
+pub enum ScriptError {
+    /// The script requires a signature that the input does not carry
+    MissingSignature,
+    /// The signature of the input does not satisfy the script
+    InvalidSignature,
+    /// The script signature could not be created
+    SigningError,
+    /// The preimage of the input does not hash to the hash lock
+    InvalidPreimage,
+    /// The input refunds the output before its refund height
+    RefundTooEarly,
+}
+
+/// The script of an output
+// The variants are not boxed, so that outputs, which hold their script, stay `Copy`
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Script {
+    /// Does nothing, so the output is spent like an output without a script
+    Nop,
+    /// The input must carry a signature by the key, made with `sign_script`
+    CheckPubKey(PublicKey),
+    /// A hash time lock, for atomic swaps. The output is claimed by an input that reveals the preimage of the hash
+    /// lock and is signed by the claim key. From the refund height, it can also be refunded by an input without a
+    /// preimage that is signed by the refund key.
+    HashTimeLock { hash_lock: HashLock, claim_key: PublicKey, refund_key: PublicKey, refund_height: u64 },
+}
+
+impl Script {
+    /// The opcode of `Script::Nop`
+    pub const OP_NOP: u8 = 0;
+    /// The opcode of `Script::CheckPubKey`
+    pub const OP_CHECK_PUB_KEY: u8 = 1;
+    /// The opcode of `Script::HashTimeLock`
+    pub const OP_HASH_TIME_LOCK: u8 = 2;
+
+    /// The opcode of the instruction
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Script::Nop => Script::OP_NOP,
+            Script::CheckPubKey(_) => Script::OP_CHECK_PUB_KEY,
+            Script::HashTimeLock { .. } => Script::OP_HASH_TIME_LOCK,
+        }
+    }
+
+    /// Run the script against the input that spends the output in a block at the given height. The kernels are the
+    /// kernels of the transaction or block the input is in, one of which the script signature must commit to.
+    pub fn execute(
+        &self,
+        input: &TransactionInput,
+        kernels: &[TransactionKernel],
+        height: u64,
+    ) -> Result<(), ScriptError>
+    {
+        match self {
+            Script::Nop => Ok(()),
+            Script::CheckPubKey(key) => check_signature(input, kernels, key),
+            Script::HashTimeLock { hash_lock, claim_key, refund_key, refund_height } => match input.script_preimage {
+                Some(preimage) if hash_preimage(&preimage) == *hash_lock => check_signature(input, kernels, claim_key),
+                Some(_) => Err(ScriptError::InvalidPreimage),
+                None if height < *refund_height => Err(ScriptError::RefundTooEarly),
+                None => check_signature(input, kernels, refund_key),
+            },
+        }
+    }
+}
+
+/// Check that the input carries a script signature by the key, made for the excess of one of the kernels
+fn check_signature(
+    input: &TransactionInput,
+    kernels: &[TransactionKernel],
+    key: &PublicKey,
+) -> Result<(), ScriptError>
+{
+    let signature = input.script_signature.as_ref().ok_or(ScriptError::MissingSignature)?;
+    let signed = kernels.iter().filter_map(|kernel| kernel.excess.as_ref()).any(|excess| {
+        let challenge = build_script_challenge(signature.get_public_nonce(), key, &input.commitment, excess);
+        signature.verify_challenge(key, challenge)
+    });
+    if !signed {
+        return Err(ScriptError::InvalidSignature);
+    }
+    Ok(())
+}
+
+/// The hash lock of a preimage
+pub fn hash_preimage(preimage: &Preimage) -> HashLock {
+    let mut hash_lock = HashLock::default();
+    hash_lock.copy_from_slice(&Sha256::digest(preimage));
+    hash_lock
+}
+
+/// Sign the input for the script of the output it spends, with the secret key the script checks. The excess is the
+/// kernel excess of the transaction that spends the output. The nonce must never be reused.
+pub fn sign_script(
+    input: TransactionInput,
+    excess: &Commitment,
+    secret: BlindingFactor,
+    nonce: BlindingFactor,
+) -> Result<TransactionInput, ScriptError>
+{
+    let public_nonce = PublicKey::from_secret_key(&nonce);
+    let public_key = PublicKey::from_secret_key(&secret);
+    let challenge = build_script_challenge(&public_nonce, &public_key, &input.commitment, excess);
+    let signature = Signature::sign(secret, nonce, challenge).map_err(|_| ScriptError::SigningError)?;
+    Ok(input.with_script_signature(signature))
+}
+
+/// Build the challenge signed by the script signature of an input. It commits to the output being spent, so that the
+/// signature can not be used to spend any other output locked to the same key. It also commits to the kernel excess
+/// of the spending transaction, so that the signature, and a preimage revealed with it, can not be copied into
+/// another transaction: a transaction that reuses the kernel only balances with the outputs the signer chose.
+pub fn build_script_challenge(
+    public_nonce: &PublicKey,
+    public_key: &PublicKey,
+    commitment: &Commitment,
+    excess: &Commitment,
+) -> Challenge<SignatureHash>
+{
+    Challenge::<SignatureHash>::new()
+        .concat(public_nonce.as_bytes())
+        .concat(public_key.as_bytes())
+        .concat(commitment.as_bytes())
+        .concat(excess.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        transaction::{KernelFeatures, OutputFeatures},
+        types::CommitmentFactory,
+    };
+    use crypto::{commitment::HomomorphicCommitmentFactory, keys::SecretKey};
+    use rand::OsRng;
+
+    fn create_kernel(rng: &mut OsRng) -> TransactionKernel {
+        TransactionKernel {
+            features: KernelFeatures::empty(),
+            fee: 0,
+            lock_height: 0,
+            excess: Some(CommitmentFactory::create(&BlindingFactor::random(rng), &BlindingFactor::from(0))),
+            excess_sig: None,
+        }
+    }
+
+    #[test]
+    fn check_pub_key() {
+        let mut rng = OsRng::new().unwrap();
+        let commitment = CommitmentFactory::create(&BlindingFactor::random(&mut rng), &BlindingFactor::from(10));
+        let input = TransactionInput::new(OutputFeatures::empty(), commitment);
+        let kernels = vec![create_kernel(&mut rng), create_kernel(&mut rng)];
+        assert_eq!(Script::Nop.execute(&input, &kernels, 0), Ok(()));
+
+        let (secret, key) = PublicKey::random_keypair(&mut rng);
+        let script = Script::CheckPubKey(key);
+        let excess = kernels[1].excess.unwrap();
+        assert_eq!(script.execute(&input, &kernels, 0), Err(ScriptError::MissingSignature));
+        let signed = sign_script(input.clone(), &excess, secret, BlindingFactor::random(&mut rng)).unwrap();
+        assert_eq!(script.execute(&signed, &kernels, 0), Ok(()));
+        let other = BlindingFactor::random(&mut rng);
+        let other = sign_script(input.clone(), &excess, other, BlindingFactor::random(&mut rng));
+        assert_eq!(script.execute(&other.unwrap(), &kernels, 0), Err(ScriptError::InvalidSignature));
+        // A signature for another output locked to the same key can not be replayed
+        let elsewhere = CommitmentFactory::create(&BlindingFactor::random(&mut rng), &BlindingFactor::from(10));
+        let nonce = BlindingFactor::random(&mut rng);
+        let replayed = TransactionInput::new(OutputFeatures::empty(), elsewhere);
+        let replayed = sign_script(replayed, &excess, secret, nonce).unwrap().script_signature.unwrap();
+        let replayed = input.clone().with_script_signature(replayed);
+        assert_eq!(script.execute(&replayed, &kernels, 0), Err(ScriptError::InvalidSignature));
+        // Nor can the signature be copied into a transaction with other kernels
+        let copied = vec![create_kernel(&mut rng)];
+        assert_eq!(script.execute(&signed, &copied, 0), Err(ScriptError::InvalidSignature));
+    }
+}
@@ -25,18 +25,24 @@
 
 use crate::{
     block::AggregateBody,
-    range_proof::RangeProof,
-    types::{BlindingFactor, Commitment, CommitmentFactory, Signature},
+    encoding::BinaryEncoding,
+    range_proof::{RangeProof, RANGE_PROOF_LENGTH},
+    script::{Preimage, Script},
+    types::{
+        BlindingFactor,
+        Commitment,
+        InputHasher,
+        KernelHasher,
+        OutputHasher,
+        PublicKey,
+        Signature,
+        TransactionHasher,
+    },
 };
 
 use crate::types::SignatureHash;
-use crypto::{
-    challenge::Challenge,
-    commitment::{HomomorphicCommitment, HomomorphicCommitmentFactory},
-    common::Blake256,
-    ristretto::RistrettoSecretKey,
-};
-use derive::HashableOrdering;
+use crypto::{challenge::Challenge, commitment::HomomorphicCommitment};
+use derive::{HashableOrdering, MmrLeaf};
 use derive_error::Error;
 use digest::Digest;
 use std::cmp::Ordering;
@@ -48,17 +54,108 @@ bitflags! {
     pub struct KernelFeatures: u8 {
         /// Coinbase transaction
         const COINBASE_KERNEL = 1u8;
+        /// The transaction opts in to being replaced in the mempool, until it is mined, by a transaction that spends
+        /// the same inputs with a higher fee. It has no effect once the kernel is in a block.
+        const REPLACEABLE = 2u8;
     }
 }
 
 bitflags! {
-    pub struct OutputFeatures: u8 {
+    /// Flags marking the kind of an output
+    pub struct OutputFlags: u8 {
         /// Output is a coinbase output, must not be spent until maturity
         const COINBASE_OUTPUT = 0b00000001;
     }
 }
 
-type Hasher = Blake256;
+/// Options for an output's structure or use. The features are committed to in the output hash and in the message of
+/// the range proof, so they can not be changed without invalidating the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputFeatures {
+    /// Flags marking the kind of output
+    pub flags: OutputFlags,
+    /// The output can not be spent in a block lower than this height
+    pub maturity: u64,
+    /// The output can not be spent until this many blocks after the block that created it
+    pub relative_maturity: u64,
+}
+
+impl OutputFeatures {
+    /// The size of the canonical encoding of the output features
+    pub const ENCODED_SIZE: usize = 17;
+
+    /// Features of a plain output, which can be spent right away
+    pub fn empty() -> OutputFeatures {
+        OutputFeatures { flags: OutputFlags::empty(), maturity: 0, relative_maturity: 0 }
+    }
+
+    /// Features of a coinbase output that can not be spent before the given height
+    pub fn create_coinbase(maturity: u64) -> OutputFeatures {
+        OutputFeatures { flags: OutputFlags::COINBASE_OUTPUT, maturity, relative_maturity: 0 }
+    }
+
+    /// Build output features that can not be spent before the given height
+    pub fn with_maturity(mut self, maturity: u64) -> OutputFeatures {
+        self.maturity = maturity;
+        self
+    }
+
+    /// Build output features that can not be spent until the given number of blocks after the output was created
+    pub fn with_relative_maturity(mut self, blocks: u64) -> OutputFeatures {
+        self.relative_maturity = blocks;
+        self
+    }
+
+    /// Whether these are the features of a coinbase output
+    pub fn is_coinbase(&self) -> bool {
+        self.flags.contains(OutputFlags::COINBASE_OUTPUT)
+    }
+
+    /// Whether an output with these features has reached its maturity height at the given height. The relative
+    /// maturity also depends on the height the output was created at, which `is_spendable_at` checks.
+    pub fn is_mature_at(&self, height: u64) -> bool {
+        self.maturity <= height
+    }
+
+    /// Whether an output with these features, created in the block at `created_at`, may be spent in a block at the
+    /// given height
+    pub fn is_spendable_at(&self, height: u64, created_at: u64) -> bool {
+        self.is_mature_at(height) && created_at.saturating_add(self.relative_maturity) <= height
+    }
+
+    /// The canonical encoding of the features: the flags byte followed by the little-endian maturity height and
+    /// relative maturity
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::ENCODED_SIZE);
+        buf.push(self.flags.bits());
+        buf.extend_from_slice(&self.maturity.to_le_bytes());
+        buf.extend_from_slice(&self.relative_maturity.to_le_bytes());
+        buf
+    }
+
+    /// Decode features from their canonical encoding, returning `None` for unknown flags or a bad length
+    pub fn from_bytes(bytes: &[u8]) -> Option<OutputFeatures> {
+        if bytes.len() != Self::ENCODED_SIZE {
+            return None;
+        }
+        let flags = OutputFlags::from_bits(bytes[0])?;
+        let mut maturity = [0u8; 8];
+        maturity.copy_from_slice(&bytes[1..9]);
+        let mut relative_maturity = [0u8; 8];
+        relative_maturity.copy_from_slice(&bytes[9..]);
+        Some(OutputFeatures {
+            flags,
+            maturity: u64::from_le_bytes(maturity),
+            relative_maturity: u64::from_le_bytes(relative_maturity),
+        })
+    }
+}
+
+impl Default for OutputFeatures {
+    fn default() -> Self {
+        OutputFeatures::empty()
+    }
+}
 
 #[derive(Debug, PartialEq, Error)]
 pub enum TransactionError {
@@ -68,6 +165,23 @@ pub enum TransactionError {
     InvalidSignatureError,
     // Transaction kernel does not contain a signature
     NoSignatureError,
+    // A range proof could not be verified
+    InvalidRangeProof,
+    // The coinbase is not a single coinbase output worth the block reward plus the fees, balanced by a single coinbase
+    // kernel without a fee
+    InvalidCoinbase,
+    // The block does not have a coinbase output or kernel
+    MissingCoinbase,
+    // The kernel fees do not pay for the weight of the transaction at the minimum fee rate
+    InsufficientFee,
+    // The lock height of a kernel is later than the height of the block
+    InvalidLockHeight,
+    // The inputs, outputs or kernels are not sorted in their canonical order
+    InvalidSortOrder,
+    // The version of the transaction is not the version required at the height it is validated for
+    InvalidVersion,
+    // The weight of the block is more than the maximum block weight
+    MaxWeightExceeded,
 }
 
 /// A transaction input.
@@ -75,17 +189,33 @@ pub enum TransactionError {
 /// Primarily a reference to an output being spent by the transaction.
 #[derive(Debug, Clone, HashableOrdering)]
 pub struct TransactionInput {
-    /// The features of the output being spent. We will check maturity for coinbase output.
+    /// The features of the output being spent. We will check maturity for coinbase and time-locked outputs.
     pub features: OutputFeatures,
     /// The commitment referencing the output being spent.
     pub commitment: Commitment,
+    /// The signature required by the script of the output being spent, if it has one that checks a key
+    pub script_signature: Option<Signature>,
+    /// The preimage revealed to claim an output locked by a hash time lock script
+    pub script_preimage: Option<Preimage>,
 }
 
 /// An input for a transaction that spends an existing output
 impl TransactionInput {
     /// Create a new Transaction Input
     pub fn new(features: OutputFeatures, commitment: Commitment) -> TransactionInput {
-        TransactionInput { features, commitment }
+        TransactionInput { features, commitment, script_signature: None, script_preimage: None }
+    }
+
+    /// Add the signature required by the script of the output being spent
+    pub fn with_script_signature(mut self, signature: Signature) -> TransactionInput {
+        self.script_signature = Some(signature);
+        self
+    }
+
+    /// Reveal the preimage required by the hash time lock script of the output being spent
+    pub fn with_script_preimage(mut self, preimage: Preimage) -> TransactionInput {
+        self.script_preimage = Some(preimage);
+        self
     }
 
     /// Accessor method for the commitment contained in an input
@@ -97,31 +227,46 @@ impl TransactionInput {
 /// Implement the canonical hashing function for TransactionInput for use in ordering
 impl Hashable for TransactionInput {
     fn hash(&self) -> Vec<u8> {
-        let mut hasher = Hasher::new();
-        hasher.input(vec![self.features.bits]);
-        hasher.input(self.commitment.as_bytes());
-        hasher.result().to_vec()
+        let mut buf = Vec::new();
+        self.binary_encode(&mut buf);
+        InputHasher::digest(&buf).to_vec()
     }
 }
 
 /// Output for a transaction, defining the new ownership of coins that are being transferred. The commitment is a
 /// blinded value for the output while the range proof guarantees the commitment includes a positive value without
 /// overflow and the ownership of the private key.
-#[derive(Debug, Copy, Clone, HashableOrdering)]
+#[derive(Debug, Copy, Clone, HashableOrdering, MmrLeaf)]
+#[Digest = "OutputHasher"]
 pub struct TransactionOutput {
     /// Options for an output's structure or use
+    #[hashable(with = "output_features_bytes")]
     pub features: OutputFeatures,
     /// The homomorphic commitment representing the output amount
+    #[hashable(with = "HomomorphicCommitment::as_bytes")]
     pub commitment: Commitment,
     /// A proof that the commitment is in the right range
     pub proof: RangeProof,
+    /// The script that is run against the input that spends the output
+    #[hashable(with = "optional_script_bytes")]
+    pub script: Option<Script>,
 }
 
 /// An output for a transaction, includes a rangeproof
 impl TransactionOutput {
+    /// The size of the canonical encoding of an output without a script: its features, its 32 byte commitment, its
+    /// range proof and the flag marking the script as absent
+    pub const ENCODED_SIZE: usize = OutputFeatures::ENCODED_SIZE + 32 + RANGE_PROOF_LENGTH + 1;
+
     /// Create new Transaction Output
     pub fn new(features: OutputFeatures, commitment: Commitment, proof: RangeProof) -> TransactionOutput {
-        TransactionOutput { features, commitment, proof }
+        TransactionOutput { features, commitment, proof, script: None }
+    }
+
+    /// Lock the output with a script
+    pub fn with_script(mut self, script: Script) -> TransactionOutput {
+        self.script = Some(script);
+        self
     }
 
     /// Accessor method for the commitment contained in an output
@@ -133,16 +278,18 @@ impl TransactionOutput {
     pub fn proof(&self) -> RangeProof {
         self.proof
     }
-}
 
-/// Implement the canonical hashing function for TransactionOutput for use in ordering
-impl Hashable for TransactionOutput {
-    fn hash(&self) -> Vec<u8> {
-        let mut hasher = Hasher::new();
-        hasher.input(vec![self.features.bits]);
-        hasher.input(self.commitment.as_bytes());
-        hasher.input(self.proof.0);
-        hasher.result().to_vec()
+    /// Verify that the range proof proves the commitment hides a value in the valid range
+    /// TODO: `RangeProof` is still a placeholder without a proving system, so every proof is currently accepted. The
+    /// proof must carry the `range_proof_message` of the output as its message, and prove that the value is at least
+    /// the dust threshold of the consensus constants.
+    pub fn verify_range_proof(&self) -> Result<(), TransactionError> {
+        Ok(())
+    }
+
+    /// The message the range proof of this output commits to, which binds the output features to the commitment
+    pub fn range_proof_message(&self) -> Vec<u8> {
+        self.features.to_bytes()
     }
 }
 
@@ -151,21 +298,27 @@ impl Hashable for TransactionOutput {
 /// [Mimblewimble TLU post](https://tlu.tarilabs.com/protocols/mimblewimble-1/sources/PITCHME.link.html?highlight=mimblewimble#mimblewimble).
 /// The kernel also tracks other transaction metadata, such as the lock height for the transaction (i.e. the earliest
 /// this transaction can be mined) and the transaction fee, in cleartext.
-#[derive(Debug, Clone, HashableOrdering)]
+#[derive(Debug, Clone, HashableOrdering, MmrLeaf)]
+#[Digest = "KernelHasher"]
 pub struct TransactionKernel {
     /// Options for a kernel's structure or use
+    #[hashable(with = "kernel_features_bytes")]
     pub features: KernelFeatures,
     /// Fee originally included in the transaction this proof is for.
+    #[hashable(with = "u64_bytes")]
     pub fee: u64,
     /// This kernel is not valid earlier than lock_height blocks
     /// The max lock_height of all *inputs* to this transaction
+    #[hashable(with = "u64_bytes")]
     pub lock_height: u64,
     /// Remainder of the sum of all transaction commitments. If the transaction
     /// is well formed, amounts components should sum to zero and the excess
     /// is hence a valid public key.
+    #[hashable(with = "optional_commitment_bytes")]
     pub excess: Option<Commitment>,
     /// The signature proving the excess is a valid public key, which signs
     /// the transaction fee.
+    #[hashable(with = "optional_signature_bytes")]
     pub excess_sig: Option<Signature>,
 }
 
@@ -197,11 +350,7 @@ impl TransactionKernel {
         let excess = self.excess.unwrap();
         let excess = excess.as_public_key();
         let r = signature.get_public_nonce();
-        let c = Challenge::<SignatureHash>::new()
-            .concat(r.as_bytes())
-            .concat(excess.clone().as_bytes())
-            .concat(&self.fee.to_le_bytes())
-            .concat(&self.lock_height.to_le_bytes());
+        let c = build_kernel_challenge(r, excess, self.features, self.fee, self.lock_height);
 
         if signature.verify_challenge(excess, c) {
             return Ok(());
@@ -209,27 +358,102 @@ impl TransactionKernel {
             return Err(TransactionError::InvalidSignatureError);
         }
     }
-}
 
-/// Implement the canonical hashing function for TransactionKernel for use in ordering
-impl Hashable for TransactionKernel {
-    fn hash(&self) -> Vec<u8> {
-        let mut hasher = Hasher::new();
-        hasher.input(vec![self.features.bits]);
-        hasher.input(self.fee.to_le_bytes());
-        hasher.input(self.lock_height.to_le_bytes());
-        if self.excess.is_some() {
-            hasher.input(self.excess.unwrap().as_bytes());
+    /// Verify the signatures of a batch of kernels at once, with `Signature::verify_batch`. Returns
+    /// `InvalidSignatureError` if any signature in the batch is invalid, without saying which one.
+    pub fn verify_batch(kernels: &[TransactionKernel]) -> Result<(), TransactionError> {
+        let mut signed = Vec::with_capacity(kernels.len());
+        for kernel in kernels {
+            let (excess, signature) = match (&kernel.excess, &kernel.excess_sig) {
+                (Some(excess), Some(signature)) => (excess.as_public_key(), signature),
+                _ => return Err(TransactionError::NoSignatureError),
+            };
+            let r = signature.get_public_nonce();
+            let c = build_kernel_challenge(r, excess, kernel.features, kernel.fee, kernel.lock_height);
+            let e = BlindingFactor::from_vec(&c.hash()).map_err(|_| TransactionError::InvalidSignatureError)?;
+            signed.push((signature, excess, e));
+        }
+        let batch: Vec<_> = signed.iter().map(|(signature, excess, e)| (*signature, *excess, e)).collect();
+        if !Signature::verify_batch(&batch, &mut rand::thread_rng()) {
+            return Err(TransactionError::InvalidSignatureError);
         }
-        if self.excess_sig.is_some() {
-            hasher.input(self.excess_sig.unwrap().get_signature().as_bytes());
+        Ok(())
+    }
+
+    /// Verify that the kernel may be mined in a block at the given height
+    pub fn verify_lock_height(&self, height: u64) -> Result<(), TransactionError> {
+        if self.lock_height > height {
+            return Err(TransactionError::InvalidLockHeight);
         }
-        hasher.result().to_vec()
+        Ok(())
+    }
+}
+
+/// Build the challenge signed by the excess signature of a kernel. Besides the public nonce and excess, it commits to
+/// the kernel features, fee and lock height, so that none of them can be changed without invalidating the signature.
+/// The challenge has the form `H(R || P || H(m))` used by MuSig, with `m` given by `build_kernel_message`, so that an
+/// excess shared between several parties can be signed with the MuSig signer.
+pub fn build_kernel_challenge(
+    public_nonce: &PublicKey,
+    public_excess: &PublicKey,
+    features: KernelFeatures,
+    fee: u64,
+    lock_height: u64,
+) -> Challenge<SignatureHash>
+{
+    let message = SignatureHash::digest(&build_kernel_message(features, fee, lock_height));
+    Challenge::<SignatureHash>::new().concat(public_nonce.as_bytes()).concat(public_excess.as_bytes()).concat(&message)
+}
+
+/// The message signed by the excess signature of a kernel, made up of its features, fee and lock height
+pub fn build_kernel_message(features: KernelFeatures, fee: u64, lock_height: u64) -> Vec<u8> {
+    let mut message = vec![features.bits];
+    message.extend_from_slice(&fee.to_le_bytes());
+    message.extend_from_slice(&lock_height.to_le_bytes());
+    message
+}
+
+// Field encodings used by the derived consensus hashes of outputs and kernels
+
+fn output_features_bytes(features: &OutputFeatures) -> Vec<u8> {
+    features.to_bytes()
+}
+
+fn kernel_features_bytes(features: &KernelFeatures) -> [u8; 1] {
+    [features.bits]
+}
+
+fn u64_bytes(value: &u64) -> [u8; 8] {
+    value.to_le_bytes()
+}
+
+/// An absent excess contributes nothing to the hash
+fn optional_commitment_bytes(commitment: &Option<Commitment>) -> Vec<u8> {
+    commitment.as_ref().map(|c| c.as_bytes().to_vec()).unwrap_or_default()
+}
+
+/// An absent script contributes nothing to the hash, so outputs without a script hash as they did before scripts
+fn optional_script_bytes(script: &Option<Script>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    if let Some(script) = script {
+        script.binary_encode(&mut buf);
     }
+    buf
 }
 
+/// Only the signature scalar of the excess signature is hashed. An absent signature contributes nothing.
+fn optional_signature_bytes(signature: &Option<Signature>) -> Vec<u8> {
+    signature.as_ref().map(|s| s.get_signature().as_bytes().to_vec()).unwrap_or_default()
+}
+
+/// The transaction version built by default, which is the version required from the genesis block
+pub const TRANSACTION_VERSION: u16 = 0;
+
 /// A transaction which consists of a kernel offset and an aggregate body made up of inputs, outputs and kernels.
+#[derive(Clone, Debug)]
 pub struct Transaction {
+    /// The version of the transaction, which selects the consensus rules it is validated with
+    pub version: u16,
     /// This kernel offset will be accumulated when transactions are aggregated to prevent the "subset" problem where
     /// kernels can be linked to inputs and outputs by testing a series of subsets and see which produce valid
     /// transactions
@@ -247,55 +471,61 @@ impl Transaction {
         offset: BlindingFactor,
     ) -> Transaction
     {
-        Transaction { offset, body: AggregateBody::new(inputs, outputs, kernels) }
+        Transaction { version: TRANSACTION_VERSION, offset, body: AggregateBody::new(inputs, outputs, kernels) }
     }
 
-    /// Calculate the sum of the inputs and outputs including the fees
-    fn sum_commitments(&self, fees: u64) -> Commitment {
-        let fee_commitment = CommitmentFactory::create(&RistrettoSecretKey::default(), &RistrettoSecretKey::from(fees));
-
-        let outputs_minus_inputs =
-            &self.body.outputs.iter().fold(CommitmentFactory::zero(), |acc, val| &acc + &val.commitment) -
-                &self.body.inputs.iter().fold(CommitmentFactory::zero(), |acc, val| &acc + &val.commitment);
+    /// Set the version of the transaction
+    pub fn with_version(mut self, version: u16) -> Transaction {
+        self.version = version;
+        self
+    }
 
-        &outputs_minus_inputs + &fee_commitment
+    /// Calculate the weight of the transaction, which counts towards the maximum weight of the block it is mined in
+    pub fn calculate_weight(&self) -> u64 {
+        self.body.calculate_weight()
     }
 
-    /// Calculate the sum of the kernels, taking into account the offset if it exists, and their constituent fees
-    fn sum_kernels(&self) -> KernelSum {
-        // Sum all kernel excesses and fees
-        let mut kernel_sum =
-            self.body.kernels.iter().fold(KernelSum { fees: 0u64, sum: CommitmentFactory::zero() }, |acc, val| {
-                KernelSum {
-                    fees: &acc.fees + val.fee,
-                    sum: &acc.sum + &val.excess.unwrap_or(CommitmentFactory::zero()),
-                }
-            });
+    /// Validate this transaction: the inputs, outputs and kernels must be sorted, every range proof and kernel
+    /// signature must be valid and the outputs less the inputs must balance the kernel excesses, the offset and the
+    /// fees. Blocks are held to the same checks through `AggregateBody::validate_internal_consistency`.
+    pub fn validate(&self) -> Result<(), TransactionError> {
+        self.body.validate_internal_consistency(&self.offset, 0)
+    }
 
-        // Add the offset commitment
-        kernel_sum.sum =
-            kernel_sum.sum + CommitmentFactory::create(&self.offset.into(), &RistrettoSecretKey::default());
+    /// Check that the fee of this transaction pays for its weight at the given minimum fee rate
+    pub fn validate_fee(&self, min_fee_per_gram: u64) -> Result<(), TransactionError> {
+        self.body.verify_minimum_fee(min_fee_per_gram)
+    }
 
-        kernel_sum
+    /// Check that this transaction may be mined in a block at the given height
+    pub fn validate_lock_height(&self, height: u64) -> Result<(), TransactionError> {
+        self.body.verify_lock_height(height)
     }
 
-    /// Confirm that the (sum of the outputs) - (sum of inputs) = Kernel excess
-    fn validate_kernel_sum(&self) -> Result<(), TransactionError> {
-        let kernel_sum = self.sum_kernels();
-        let sum_io = self.sum_commitments(kernel_sum.fees);
+    /// Whether this transaction signals that it may be replaced in the mempool, which every one of its kernels must
+    /// signal with `KernelFeatures::REPLACEABLE`, so that no party to the transaction is replaced against its will
+    pub fn is_replaceable(&self) -> bool {
+        !self.body.kernels.is_empty() &&
+            self.body.kernels.iter().all(|kernel| kernel.features.contains(KernelFeatures::REPLACEABLE))
+    }
 
-        if kernel_sum.sum != sum_io {
-            return Err(TransactionError::ValidationError);
+    /// Check that this transaction has the version that is required, which consensus sets per height with
+    /// `ConsensusConstants::transaction_version`
+    pub fn validate_version(&self, required: u16) -> Result<(), TransactionError> {
+        if self.version != required {
+            return Err(TransactionError::InvalidVersion);
         }
-
         Ok(())
     }
+}
 
-    /// Validate this transaction
-    pub fn validate(&self) -> Result<(), TransactionError> {
-        self.body.verify_kernel_signatures()?;
-        self.validate_kernel_sum()?;
-        Ok(())
+/// The hash of a transaction is the hash of its binary encoding, which identifies the transaction until it is mined.
+/// Once it is aggregated into a block, the transaction can no longer be told apart from the others in the block.
+impl Hashable for Transaction {
+    fn hash(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.binary_encode(&mut buf);
+        TransactionHasher::digest(&buf).to_vec()
     }
 }
 
@@ -353,14 +583,16 @@ impl TransactionBuilder {
         self
     }
 
+    /// Build the transaction, sorting its body into canonical order, and validate it
     pub fn build(&self) -> Result<Transaction, TransactionError> {
         if let Some(offset) = self.offset {
-            let tx = Transaction::new(
+            let mut tx = Transaction::new(
                 self.body.inputs.clone(),
                 self.body.outputs.clone(),
                 self.body.kernels.clone(),
                 offset,
             );
+            tx.body.sort();
             tx.validate()?;
             Ok(tx)
         } else {
@@ -375,15 +607,14 @@ mod test {
     use crate::{
         range_proof::RangeProof,
         transaction::{KernelFeatures, OutputFeatures, TransactionInput, TransactionKernel, TransactionOutput},
-        types::{BlindingFactor, PublicKey},
+        types::{BlindingFactor, CommitmentFactory, PublicKey},
     };
     use crypto::{
-        challenge::Challenge,
-        common::Blake256,
+        commitment::HomomorphicCommitmentFactory,
         keys::{PublicKey as PublicKeyTrait, SecretKey},
+        ristretto::RistrettoSecretKey,
     };
     use rand;
-    use tari_utilities::ByteArray;
 
     #[test]
     fn build_transaction_test_and_validation() {
@@ -463,11 +694,13 @@ mod test {
         let receiver_public_nonce = PublicKey::from_secret_key(&receiver_private_nonce);
         let receiver_public_key = PublicKey::from_secret_key(&receiver_full_secret_key);
 
-        let challenge = Challenge::<Blake256>::new()
-            .concat((&sender_public_nonce + &receiver_public_nonce).as_bytes())
-            .concat((&sender_public_excess + &receiver_public_key).as_bytes())
-            .concat(&fee.to_le_bytes())
-            .concat(&lock_height.to_le_bytes());
+        let challenge = build_kernel_challenge(
+            &(&sender_public_nonce + &receiver_public_nonce),
+            &(&sender_public_excess + &receiver_public_key),
+            KernelFeatures::empty(),
+            fee,
+            lock_height,
+        );
 
         let receiver_partial_sig =
             Signature::sign(receiver_full_secret_key, receiver_private_nonce, challenge.clone()).unwrap();
@@ -486,5 +719,18 @@ mod test {
 
         let tx = tx_builder.with_kernel(kernel).build().unwrap();
         tx.validate().unwrap();
+
+        // The builder sorts the body, which must stay in canonical order
+        let mut unsorted = tx.clone();
+        unsorted.body.outputs.reverse();
+        assert_eq!(unsorted.validate(), Err(TransactionError::InvalidSortOrder));
+
+        // Dropping an output breaks the kernel sum
+        let mut missing_output = tx.clone();
+        missing_output.body.outputs.retain(|o| o.commitment != output2.commitment);
+        assert_eq!(missing_output.validate(), Err(TransactionError::ValidationError));
+
+        assert_eq!(tx.validate_version(TRANSACTION_VERSION), Ok(()));
+        assert_eq!(tx.with_version(1).validate_version(TRANSACTION_VERSION), Err(TransactionError::InvalidVersion));
     }
 }
@@ -0,0 +1,407 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! The interactive protocol with which a sender and a receiver build a MimbleWimble transaction together.
+//!
+//! Neither party may learn the other's blinding factors, so the kernel signature is built from two partial
+//! signatures:
+//! 1. The sender selects the inputs, creates the change output and sends a `SingleRoundSenderData` message with
+//!    the amount, the fee, its public excess and its public nonce.
+//! 2. The receiver creates an output for the amount and replies with a `RecipientSignedMessage` holding the output,
+//!    the public key of its blinding factor and its partial signature.
+//! 3. The sender checks the reply, adds its own partial signature and builds the final transaction.
+//!
+//! Both parties are state machines. A message that fails validation is rejected with an error and leaves the state
+//! unchanged, so the protocol can carry on when a valid message arrives. Messages have a binary encoding so that they
+//! can be sent between wallets.
+
+pub mod receiver;
+pub mod sender;
+pub mod sweep;
+
+use crate::{
+    encoding::{encode_signature, BinaryEncoding, EncodingError, Reader},
+    range_proof::{RangeProof, RANGE_PROOF_LENGTH},
+    transaction::{KernelFeatures, OutputFeatures, TransactionError, TransactionInput, TransactionOutput},
+    types::{BlindingFactor, Commitment, CommitmentFactory, PublicKey, Signature},
+};
+use crypto::commitment::HomomorphicCommitmentFactory;
+use derive_error::Error;
+use tari_utilities::ByteArray;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum TransactionProtocolError {
+    /// The step is not valid in the current state of the protocol
+    InvalidStateError,
+    /// The message belongs to a different transaction
+    InvalidTransactionId,
+    /// The transaction is missing the amount, offset, nonce or change key
+    IncompleteStateError,
+    /// The inputs are not worth the amount plus the fee
+    InsufficientFunds,
+    /// An output would be worth less than the dust threshold
+    DustOutput,
+    /// The recipient's output does not commit to the amount with the recipient's public spend key
+    InvalidOutput,
+    /// The partial signature could not be verified
+    InvalidPartialSignature,
+    /// A partial signature could not be created
+    SigningError,
+    /// The sender's nonce is bound to another reply, and signing a second reply with it would reveal the sender's
+    /// excess key
+    NonceAlreadyUsed,
+    /// The message could not be decoded
+    InvalidMessage,
+    /// The saved state of the protocol could not be decoded
+    InvalidSavedState,
+    /// The sender asked for kernel features that a payment can not have
+    UnsupportedKernelFeatures,
+    /// The finalized transaction is not valid
+    TransactionError(TransactionError),
+}
+
+/// An output together with the value and blinding factor it commits to, which only its owner knows
+#[derive(Debug, Clone)]
+pub struct UnblindedOutput {
+    pub value: u64,
+    pub spending_key: BlindingFactor,
+    pub features: OutputFeatures,
+}
+
+impl UnblindedOutput {
+    /// Create a new unblinded output
+    pub fn new(value: u64, spending_key: BlindingFactor, features: OutputFeatures) -> UnblindedOutput {
+        UnblindedOutput { value, spending_key, features }
+    }
+
+    /// The commitment to the value of the output
+    pub fn commitment(&self) -> Commitment {
+        CommitmentFactory::create(&self.spending_key, &BlindingFactor::from(self.value))
+    }
+
+    /// Create the transaction input that spends this output
+    pub fn as_transaction_input(&self) -> TransactionInput {
+        TransactionInput::new(self.features, self.commitment())
+    }
+
+    /// Create the transaction output for this output. Range proofs do not have a proving system yet, so the output
+    /// carries an all-zero placeholder proof.
+    pub fn as_transaction_output(&self) -> TransactionOutput {
+        TransactionOutput::new(self.features, self.commitment(), RangeProof([0; RANGE_PROOF_LENGTH]))
+    }
+}
+
+impl BinaryEncoding for UnblindedOutput {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.value.to_le_bytes());
+        buf.extend_from_slice(self.spending_key.as_bytes());
+        self.features.binary_encode(buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(UnblindedOutput {
+            value: reader.read_u64()?,
+            spending_key: reader.read_key()?,
+            features: OutputFeatures::binary_decode(reader)?,
+        })
+    }
+}
+
+/// The message the sender sends to the receiver to start the protocol
+#[derive(Debug, Clone, PartialEq)]
+pub struct SingleRoundSenderData {
+    /// The id that ties the messages of one transaction together
+    pub tx_id: u64,
+    /// The amount the receiver will receive
+    pub amount: u64,
+    /// The fee of the transaction
+    pub fee: u64,
+    /// The lock height of the transaction
+    pub lock_height: u64,
+    /// The features of the kernel, which may only signal that the transaction is replaceable
+    pub features: KernelFeatures,
+    /// The public key of the sender's part of the kernel excess
+    pub public_excess: PublicKey,
+    /// The public key of the sender's signature nonce
+    pub public_nonce: PublicKey,
+}
+
+impl SingleRoundSenderData {
+    /// Encode the message into bytes that can be decoded again with `from_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_binary()
+    }
+
+    /// Decode a message written by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<SingleRoundSenderData, TransactionProtocolError> {
+        SingleRoundSenderData::from_binary(bytes).map_err(|_| TransactionProtocolError::InvalidMessage)
+    }
+}
+
+impl BinaryEncoding for SingleRoundSenderData {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        for value in &[self.tx_id, self.amount, self.fee, self.lock_height] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.push(self.features.bits());
+        buf.extend_from_slice(self.public_excess.as_bytes());
+        buf.extend_from_slice(self.public_nonce.as_bytes());
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(SingleRoundSenderData {
+            tx_id: reader.read_u64()?,
+            amount: reader.read_u64()?,
+            fee: reader.read_u64()?,
+            lock_height: reader.read_u64()?,
+            features: KernelFeatures::from_bits(reader.read_u8()?).ok_or(EncodingError::InvalidValue)?,
+            public_excess: reader.read_key()?,
+            public_nonce: reader.read_key()?,
+        })
+    }
+}
+
+/// The reply of the receiver, which completes its part of the transaction
+#[derive(Debug, Clone)]
+pub struct RecipientSignedMessage {
+    /// The id of the transaction, as given by the sender
+    pub tx_id: u64,
+    /// The output that pays the amount to the receiver
+    pub output: TransactionOutput,
+    /// The public key of the blinding factor of the output, which is the receiver's part of the kernel excess
+    pub public_spend_key: PublicKey,
+    /// The receiver's partial signature of the kernel
+    pub partial_signature: Signature,
+}
+
+impl RecipientSignedMessage {
+    /// Encode the message into bytes that can be decoded again with `from_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_binary()
+    }
+
+    /// Decode a message written by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<RecipientSignedMessage, TransactionProtocolError> {
+        RecipientSignedMessage::from_binary(bytes).map_err(|_| TransactionProtocolError::InvalidMessage)
+    }
+}
+
+impl BinaryEncoding for RecipientSignedMessage {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.tx_id.to_le_bytes());
+        self.output.binary_encode(buf);
+        buf.extend_from_slice(self.public_spend_key.as_bytes());
+        encode_signature(&self.partial_signature, buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        Ok(RecipientSignedMessage {
+            tx_id: reader.read_u64()?,
+            output: TransactionOutput::binary_decode(reader)?,
+            public_spend_key: reader.read_key()?,
+            partial_signature: reader.read_signature()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transaction_protocol::{
+        receiver::ReceiverTransactionProtocol,
+        sender::{SenderTransactionInitializer, SenderTransactionProtocol},
+    };
+    use crypto::keys::SecretKey;
+    use rand::OsRng;
+
+    fn create_sender(rng: &mut OsRng, amount: u64) -> Result<SenderTransactionProtocol, TransactionProtocolError> {
+        SenderTransactionInitializer::new(7)
+            .with_amount(amount)
+            .with_fee(2)
+            .with_offset(BlindingFactor::random(rng))
+            .with_private_nonce(BlindingFactor::random(rng))
+            .with_change_secret(BlindingFactor::random(rng))
+            .add_input(UnblindedOutput::new(10, BlindingFactor::random(rng), OutputFeatures::empty()))
+            .add_input(UnblindedOutput::new(5, BlindingFactor::random(rng), OutputFeatures::empty()))
+            .build()
+    }
+
+    #[test]
+    fn single_receiver() {
+        let mut rng = OsRng::new().unwrap();
+        let mut sender = create_sender(&mut rng, 8).unwrap();
+        assert!(sender.is_single_round_message_ready());
+        let message = sender.build_single_round_message().unwrap();
+        assert!(sender.is_collecting_single_signature());
+        assert_eq!(sender.build_single_round_message().unwrap(), message);
+        let message = SingleRoundSenderData::from_bytes(&message.to_bytes()).unwrap();
+
+        let spending_key = BlindingFactor::random(&mut rng);
+        let nonce = BlindingFactor::random(&mut rng);
+        let receiver = ReceiverTransactionProtocol::new(&message, nonce, spending_key, OutputFeatures::empty());
+        assert!(receiver.is_finalized());
+        let reply = receiver.get_signed_data().unwrap();
+
+        // Replies that are not valid are rejected without losing the state of the sender
+        let mut forged = reply.clone();
+        forged.tx_id += 1;
+        assert_eq!(sender.add_single_recipient_info(&forged), Err(TransactionProtocolError::InvalidTransactionId));
+        let changed = SingleRoundSenderData { amount: 9, ..message.clone() };
+        let forged = ReceiverTransactionProtocol::new(&changed, nonce, spending_key, OutputFeatures::empty());
+        assert_eq!(
+            sender.add_single_recipient_info(forged.get_signed_data().unwrap()),
+            Err(TransactionProtocolError::InvalidOutput)
+        );
+        let mut forged = reply.clone();
+        forged.partial_signature = Signature::new(reply.public_spend_key, BlindingFactor::random(&mut rng));
+        assert_eq!(sender.add_single_recipient_info(&forged), Err(TransactionProtocolError::InvalidPartialSignature));
+        assert!(sender.is_collecting_single_signature());
+
+        let reply = RecipientSignedMessage::from_bytes(&reply.to_bytes()).unwrap();
+        sender.add_single_recipient_info(&reply).unwrap();
+        assert!(sender.is_finalized());
+        let transaction = sender.get_transaction().unwrap();
+        transaction.validate().unwrap();
+        assert_eq!(transaction.body.outputs.len(), 2);
+        assert_eq!(sender.add_single_recipient_info(&reply), Err(TransactionProtocolError::InvalidStateError));
+    }
+
+    #[test]
+    fn invalid_senders() {
+        let mut rng = OsRng::new().unwrap();
+        assert_eq!(create_sender(&mut rng, 14).err(), Some(TransactionProtocolError::InsufficientFunds));
+        let sender = SenderTransactionInitializer::new(1)
+            .with_amount(10)
+            .add_input(UnblindedOutput::new(10, BlindingFactor::random(&mut rng), OutputFeatures::empty()))
+            .build();
+        assert_eq!(sender.err(), Some(TransactionProtocolError::IncompleteStateError));
+        let message = create_sender(&mut rng, 13).unwrap().build_single_round_message().unwrap();
+        let bytes = message.to_bytes();
+        assert_eq!(SingleRoundSenderData::from_bytes(&bytes[1..]), Err(TransactionProtocolError::InvalidMessage));
+    }
+
+    #[test]
+    fn replaceable_transaction() {
+        let mut rng = OsRng::new().unwrap();
+        let mut sender = SenderTransactionInitializer::new(4)
+            .with_amount(8)
+            .with_fee(2)
+            .with_replaceable(true)
+            .with_offset(BlindingFactor::random(&mut rng))
+            .with_private_nonce(BlindingFactor::random(&mut rng))
+            .with_change_secret(BlindingFactor::random(&mut rng))
+            .add_input(UnblindedOutput::new(15, BlindingFactor::random(&mut rng), OutputFeatures::empty()))
+            .build()
+            .unwrap();
+        let message = sender.build_single_round_message().unwrap();
+        let message = SingleRoundSenderData::from_bytes(&message.to_bytes()).unwrap();
+        assert_eq!(message.features, KernelFeatures::REPLACEABLE);
+
+        let spending_key = BlindingFactor::random(&mut rng);
+        let nonce = BlindingFactor::random(&mut rng);
+        let coinbase = SingleRoundSenderData { features: KernelFeatures::COINBASE_KERNEL, ..message.clone() };
+        let receiver = ReceiverTransactionProtocol::new(&coinbase, nonce, spending_key, OutputFeatures::empty());
+        assert_eq!(receiver.failure_reason(), Some(&TransactionProtocolError::UnsupportedKernelFeatures));
+        let receiver = ReceiverTransactionProtocol::new(&message, nonce, spending_key, OutputFeatures::empty());
+        sender.add_single_recipient_info(receiver.get_signed_data().unwrap()).unwrap();
+        let transaction = sender.get_transaction().unwrap();
+        transaction.validate().unwrap();
+        assert!(transaction.is_replaceable());
+    }
+
+    #[test]
+    fn dust_outputs() {
+        let mut rng = OsRng::new().unwrap();
+        let initializer = |amount, rng: &mut OsRng| {
+            SenderTransactionInitializer::new(3)
+                .with_amount(amount)
+                .with_fee(2)
+                .with_dust_threshold(3)
+                .with_offset(BlindingFactor::random(rng))
+                .with_private_nonce(BlindingFactor::random(rng))
+                .add_input(UnblindedOutput::new(15, BlindingFactor::random(rng), OutputFeatures::empty()))
+        };
+        assert_eq!(initializer(2, &mut rng).build().err(), Some(TransactionProtocolError::DustOutput));
+        // The change of 1 is dust, so it goes to the fee and no change key is needed
+        let message = initializer(12, &mut rng).build().unwrap().build_single_round_message().unwrap();
+        assert_eq!(message.fee, 3);
+    }
+
+    #[test]
+    fn resume_saved_protocols() {
+        let mut rng = OsRng::new().unwrap();
+        let sender = create_sender(&mut rng, 8).unwrap();
+        let mut sender = SenderTransactionProtocol::from_bytes(&sender.to_bytes()).unwrap();
+        assert!(sender.is_single_round_message_ready());
+        let message = sender.build_single_round_message().unwrap();
+
+        // The sender is saved while it waits for the reply, and the restored sender sends the same message
+        let saved = sender.to_bytes();
+        let mut sender = SenderTransactionProtocol::from_bytes(&saved).unwrap();
+        assert!(sender.is_collecting_single_signature());
+        assert_eq!(sender.build_single_round_message().unwrap(), message);
+        assert_eq!(
+            SenderTransactionProtocol::from_bytes(&saved[..saved.len() - 1]).err(),
+            Some(TransactionProtocolError::InvalidSavedState)
+        );
+
+        let spending_key = BlindingFactor::random(&mut rng);
+        let nonce = BlindingFactor::random(&mut rng);
+        let receiver = ReceiverTransactionProtocol::new(&message, nonce, spending_key, OutputFeatures::empty());
+        let receiver = ReceiverTransactionProtocol::from_bytes(&receiver.to_bytes()).unwrap();
+        assert!(receiver.is_finalized());
+        sender.add_single_recipient_info(receiver.get_signed_data().unwrap()).unwrap();
+
+        let transaction = sender.get_transaction().unwrap().to_binary();
+        let sender = SenderTransactionProtocol::from_bytes(&sender.to_bytes()).unwrap();
+        assert!(sender.is_finalized());
+        assert_eq!(sender.get_transaction().unwrap().to_binary(), transaction);
+
+        let coinbase = SingleRoundSenderData { features: KernelFeatures::COINBASE_KERNEL, ..message };
+        let receiver = ReceiverTransactionProtocol::new(&coinbase, nonce, spending_key, OutputFeatures::empty());
+        let receiver = ReceiverTransactionProtocol::from_bytes(&receiver.to_bytes()).unwrap();
+        assert_eq!(receiver.failure_reason(), Some(&TransactionProtocolError::UnsupportedKernelFeatures));
+    }
+
+    #[test]
+    fn sign_one_reply_only() {
+        let mut rng = OsRng::new().unwrap();
+        let mut sender = create_sender(&mut rng, 8).unwrap();
+        let message = sender.build_single_round_message().unwrap();
+        let reply = |rng: &mut OsRng| {
+            let (nonce, spending_key) = (BlindingFactor::random(rng), BlindingFactor::random(rng));
+            let receiver = ReceiverTransactionProtocol::new(&message, nonce, spending_key, OutputFeatures::empty());
+            receiver.get_signed_data().unwrap().clone()
+        };
+        let (first, second) = (reply(&mut rng), reply(&mut rng));
+
+        // Once the nonce is bound to a reply, the saved state refuses to sign any other reply with it
+        sender.bind_recipient_info(&first).unwrap();
+        let bound = sender.to_bytes();
+        let mut restored = SenderTransactionProtocol::from_bytes(&bound).unwrap();
+        assert_eq!(restored.add_single_recipient_info(&second), Err(TransactionProtocolError::NonceAlreadyUsed));
+        assert_eq!(restored.bind_recipient_info(&second), Err(TransactionProtocolError::NonceAlreadyUsed));
+        assert!(restored.is_collecting_single_signature());
+        restored.add_single_recipient_info(&first).unwrap();
+        restored.get_transaction().unwrap().validate().unwrap();
+    }
+}
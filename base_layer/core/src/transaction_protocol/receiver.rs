@@ -0,0 +1,219 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    encoding::{BinaryEncoding, EncodingError, Reader},
+    transaction::{build_kernel_challenge, KernelFeatures, OutputFeatures, TransactionError},
+    transaction_protocol::{
+        RecipientSignedMessage,
+        SingleRoundSenderData,
+        TransactionProtocolError,
+        UnblindedOutput,
+    },
+    types::{BlindingFactor, PublicKey, Signature},
+};
+use crypto::keys::PublicKey as PublicKeyTrait;
+
+/// The receiver's side of the protocol.
+///
+/// The receiver completes its part in a single step: it creates the output for the amount and signs the kernel with
+/// its partial signature. The signed reply is kept, so it can be sent again if the sender did not receive it. If the
+/// reply cannot be created the protocol fails and should be abandoned.
+///
+/// The state can be saved with `to_bytes` and restored with `from_bytes`, so the reply survives a restart of the
+/// wallet.
+pub struct ReceiverTransactionProtocol {
+    state: ReceiverState,
+}
+
+enum ReceiverState {
+    Finalized(Box<RecipientSignedMessage>),
+    Failed(TransactionProtocolError),
+}
+
+impl ReceiverTransactionProtocol {
+    /// Respond to the sender's message with an output that pays the amount to the given spending key. The nonce of
+    /// the partial signature must never be reused.
+    pub fn new(
+        info: &SingleRoundSenderData,
+        nonce: BlindingFactor,
+        spending_key: BlindingFactor,
+        features: OutputFeatures,
+    ) -> ReceiverTransactionProtocol
+    {
+        let state = match sign(info, nonce, spending_key, features) {
+            Ok(message) => ReceiverState::Finalized(Box::new(message)),
+            Err(e) => ReceiverState::Failed(e),
+        };
+        ReceiverTransactionProtocol { state }
+    }
+
+    /// Returns true if the reply for the sender is ready
+    pub fn is_finalized(&self) -> bool {
+        match self.state {
+            ReceiverState::Finalized(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the reply could not be created
+    pub fn is_failed(&self) -> bool {
+        match self.state {
+            ReceiverState::Failed(_) => true,
+            _ => false,
+        }
+    }
+
+    /// If `is_failed()` is true, this returns the error that caused the failure
+    pub fn failure_reason(&self) -> Option<&TransactionProtocolError> {
+        match &self.state {
+            ReceiverState::Failed(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// The reply for the sender
+    pub fn get_signed_data(&self) -> Result<&RecipientSignedMessage, TransactionProtocolError> {
+        match &self.state {
+            ReceiverState::Finalized(message) => Ok(message),
+            _ => Err(TransactionProtocolError::InvalidStateError),
+        }
+    }
+
+    /// Save the state of the protocol into bytes that can be restored with `from_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.state.to_binary()
+    }
+
+    /// Restore a protocol saved by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<ReceiverTransactionProtocol, TransactionProtocolError> {
+        let state = ReceiverState::from_binary(bytes).map_err(|_| TransactionProtocolError::InvalidSavedState)?;
+        Ok(ReceiverTransactionProtocol { state })
+    }
+}
+
+impl BinaryEncoding for ReceiverState {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            ReceiverState::Finalized(message) => {
+                buf.push(0);
+                message.binary_encode(buf);
+            },
+            ReceiverState::Failed(e) => {
+                buf.push(1);
+                buf.push(failure_code(e));
+            },
+        }
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        match reader.read_u8()? {
+            0 => Ok(ReceiverState::Finalized(Box::new(RecipientSignedMessage::binary_decode(reader)?))),
+            1 => failure_from_code(reader.read_u8()?).map(ReceiverState::Failed).ok_or(EncodingError::InvalidValue),
+            _ => Err(EncodingError::InvalidValue),
+        }
+    }
+}
+
+/// The code a failure is saved as. Errors of the finalized transaction are offset by 16.
+fn failure_code(e: &TransactionProtocolError) -> u8 {
+    match e {
+        TransactionProtocolError::InvalidStateError => 0,
+        TransactionProtocolError::InvalidTransactionId => 1,
+        TransactionProtocolError::IncompleteStateError => 2,
+        TransactionProtocolError::InsufficientFunds => 3,
+        TransactionProtocolError::DustOutput => 4,
+        TransactionProtocolError::InvalidOutput => 5,
+        TransactionProtocolError::InvalidPartialSignature => 6,
+        TransactionProtocolError::SigningError => 7,
+        TransactionProtocolError::InvalidMessage => 8,
+        TransactionProtocolError::InvalidSavedState => 9,
+        TransactionProtocolError::UnsupportedKernelFeatures => 10,
+        TransactionProtocolError::NonceAlreadyUsed => 11,
+        TransactionProtocolError::TransactionError(e) => {
+            16 + match e {
+                TransactionError::ValidationError => 0,
+                TransactionError::InvalidSignatureError => 1,
+                TransactionError::NoSignatureError => 2,
+                TransactionError::InvalidRangeProof => 3,
+                TransactionError::InvalidCoinbase => 4,
+                TransactionError::MissingCoinbase => 5,
+                TransactionError::InsufficientFee => 6,
+                TransactionError::InvalidLockHeight => 7,
+                TransactionError::InvalidSortOrder => 8,
+                TransactionError::InvalidVersion => 9,
+                TransactionError::MaxWeightExceeded => 10,
+            }
+        },
+    }
+}
+
+/// The failure saved as `code` by `failure_code`
+fn failure_from_code(code: u8) -> Option<TransactionProtocolError> {
+    let transaction_error = match code {
+        0 => return Some(TransactionProtocolError::InvalidStateError),
+        1 => return Some(TransactionProtocolError::InvalidTransactionId),
+        2 => return Some(TransactionProtocolError::IncompleteStateError),
+        3 => return Some(TransactionProtocolError::InsufficientFunds),
+        4 => return Some(TransactionProtocolError::DustOutput),
+        5 => return Some(TransactionProtocolError::InvalidOutput),
+        6 => return Some(TransactionProtocolError::InvalidPartialSignature),
+        7 => return Some(TransactionProtocolError::SigningError),
+        8 => return Some(TransactionProtocolError::InvalidMessage),
+        9 => return Some(TransactionProtocolError::InvalidSavedState),
+        10 => return Some(TransactionProtocolError::UnsupportedKernelFeatures),
+        11 => return Some(TransactionProtocolError::NonceAlreadyUsed),
+        16 => TransactionError::ValidationError,
+        17 => TransactionError::InvalidSignatureError,
+        18 => TransactionError::NoSignatureError,
+        19 => TransactionError::InvalidRangeProof,
+        20 => TransactionError::InvalidCoinbase,
+        21 => TransactionError::MissingCoinbase,
+        22 => TransactionError::InsufficientFee,
+        23 => TransactionError::InvalidLockHeight,
+        24 => TransactionError::InvalidSortOrder,
+        25 => TransactionError::InvalidVersion,
+        26 => TransactionError::MaxWeightExceeded,
+        _ => return None,
+    };
+    Some(TransactionProtocolError::TransactionError(transaction_error))
+}
+
+fn sign(
+    info: &SingleRoundSenderData,
+    nonce: BlindingFactor,
+    spending_key: BlindingFactor,
+    features: OutputFeatures,
+) -> Result<RecipientSignedMessage, TransactionProtocolError>
+{
+    if !KernelFeatures::REPLACEABLE.contains(info.features) {
+        return Err(TransactionProtocolError::UnsupportedKernelFeatures);
+    }
+    let output = UnblindedOutput::new(info.amount, spending_key, features).as_transaction_output();
+    let public_spend_key = PublicKey::from_secret_key(&spending_key);
+    let public_nonce = &info.public_nonce + &PublicKey::from_secret_key(&nonce);
+    let public_excess = &info.public_excess + &public_spend_key;
+    let challenge = build_kernel_challenge(&public_nonce, &public_excess, info.features, info.fee, info.lock_height);
+    let partial_signature =
+        Signature::sign(spending_key, nonce, challenge).map_err(|_| TransactionProtocolError::SigningError)?;
+    Ok(RecipientSignedMessage { tx_id: info.tx_id, output, public_spend_key, partial_signature })
+}
@@ -0,0 +1,450 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    encoding::{decode_list, encode_list, BinaryEncoding, EncodingError, Reader},
+    transaction::{
+        build_kernel_challenge,
+        KernelFeatures,
+        OutputFeatures,
+        Transaction,
+        TransactionBuilder,
+        TransactionKernel,
+    },
+    transaction_protocol::{
+        RecipientSignedMessage,
+        SingleRoundSenderData,
+        TransactionProtocolError,
+        UnblindedOutput,
+    },
+    types::{BlindingFactor, Commitment, CommitmentFactory, PublicKey, Signature, SignatureHash},
+};
+use crypto::{challenge::Challenge, commitment::HomomorphicCommitmentFactory, keys::PublicKey as PublicKeyTrait};
+use tari_utilities::ByteArray;
+
+/// Collects what the sender needs to start the protocol: the inputs it spends, the amount, the fee and the secrets it
+/// uses to build its part of the kernel
+pub struct SenderTransactionInitializer {
+    tx_id: u64,
+    amount: Option<u64>,
+    fee: u64,
+    lock_height: u64,
+    replaceable: bool,
+    offset: Option<BlindingFactor>,
+    private_nonce: Option<BlindingFactor>,
+    change_secret: Option<BlindingFactor>,
+    dust_threshold: u64,
+    inputs: Vec<UnblindedOutput>,
+}
+
+impl SenderTransactionInitializer {
+    /// Start a new transaction with the given id
+    pub fn new(tx_id: u64) -> Self {
+        Self {
+            tx_id,
+            amount: None,
+            fee: 0,
+            lock_height: 0,
+            replaceable: false,
+            offset: None,
+            private_nonce: None,
+            change_secret: None,
+            dust_threshold: 0,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Set the amount the receiver will receive
+    pub fn with_amount(mut self, amount: u64) -> Self {
+        self.amount = Some(amount);
+        self
+    }
+
+    /// Set the fee of the transaction
+    pub fn with_fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
+    /// Set the lock height of the transaction
+    pub fn with_lock_height(mut self, lock_height: u64) -> Self {
+        self.lock_height = lock_height;
+        self
+    }
+
+    /// Signal that the transaction may be replaced in the mempool by a transaction that spends the same inputs with a
+    /// higher fee, so that it can be rescued by paying more if it gets stuck
+    pub fn with_replaceable(mut self, replaceable: bool) -> Self {
+        self.replaceable = replaceable;
+        self
+    }
+
+    /// Set the kernel offset of the transaction
+    pub fn with_offset(mut self, offset: BlindingFactor) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Set the nonce of the sender's partial signature. It must never be reused.
+    pub fn with_private_nonce(mut self, nonce: BlindingFactor) -> Self {
+        self.private_nonce = Some(nonce);
+        self
+    }
+
+    /// Set the blinding factor of the change output. It is only needed when the inputs are worth more than the amount
+    /// plus the fee.
+    pub fn with_change_secret(mut self, secret: BlindingFactor) -> Self {
+        self.change_secret = Some(secret);
+        self
+    }
+
+    /// Set the dust threshold of the consensus constants. The amount must be at least the threshold, and change worth
+    /// less than it is added to the fee instead of being paid to a change output.
+    pub fn with_dust_threshold(mut self, threshold: u64) -> Self {
+        self.dust_threshold = threshold;
+        self
+    }
+
+    /// Add an output of the sender that the transaction spends
+    pub fn add_input(mut self, input: UnblindedOutput) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    /// Check that the transaction is complete and balances, create the change output and start the protocol
+    pub fn build(self) -> Result<SenderTransactionProtocol, TransactionProtocolError> {
+        let (amount, offset, private_nonce) = match (self.amount, self.offset, self.private_nonce) {
+            (Some(amount), Some(offset), Some(nonce)) => (amount, offset, nonce),
+            _ => return Err(TransactionProtocolError::IncompleteStateError),
+        };
+        if amount < self.dust_threshold {
+            return Err(TransactionProtocolError::DustOutput);
+        }
+        let total = self
+            .inputs
+            .iter()
+            .try_fold(0u64, |total, input| total.checked_add(input.value))
+            .ok_or(TransactionProtocolError::InsufficientFunds)?;
+        let mut fee = self.fee;
+        let mut change = amount
+            .checked_add(fee)
+            .and_then(|spent| total.checked_sub(spent))
+            .ok_or(TransactionProtocolError::InsufficientFunds)?;
+        if change < self.dust_threshold {
+            fee += change;
+            change = 0;
+        }
+        let mut excess_key = BlindingFactor::default();
+        let mut outputs = Vec::new();
+        if change > 0 {
+            let secret = self.change_secret.ok_or(TransactionProtocolError::IncompleteStateError)?;
+            outputs.push(UnblindedOutput::new(change, secret, OutputFeatures::empty()));
+            excess_key = secret;
+        }
+        let excess_key = self.inputs.iter().fold(&excess_key - &offset, |key, input| &key - &input.spending_key);
+        let mut features = KernelFeatures::empty();
+        features.set(KernelFeatures::REPLACEABLE, self.replaceable);
+        let info = RawTransactionInfo {
+            tx_id: self.tx_id,
+            amount,
+            fee,
+            lock_height: self.lock_height,
+            features,
+            offset,
+            public_excess: PublicKey::from_secret_key(&excess_key),
+            public_nonce: PublicKey::from_secret_key(&private_nonce),
+            excess_key,
+            private_nonce,
+            bound_challenge: None,
+            inputs: self.inputs,
+            outputs,
+        };
+        Ok(SenderTransactionProtocol { state: SenderState::SingleRoundMessageReady(Box::new(info)) })
+    }
+}
+
+/// The sender's side of the protocol.
+///
+/// After it is built, the sender is ready to send its `SingleRoundSenderData` message. It then collects the
+/// receiver's `RecipientSignedMessage` and, once that is valid, holds the finalized transaction. The message can be
+/// built again while the sender waits for the reply, so a lost message can be resent.
+///
+/// The state can be saved with `to_bytes` and restored with `from_bytes`, so the sender can resume after a restart
+/// while it waits for the reply. The saved state holds the sender's secret keys and must be stored as securely as
+/// they are.
+///
+/// The sender's nonce may only ever sign one reply: partial signatures of two different replies made with the same
+/// nonce reveal the sender's excess key. The first reply the sender accepts binds the nonce to the challenge of that
+/// reply, and a bound sender refuses to sign any other reply, also once it is saved and restored. A wallet should
+/// therefore bind the reply with `bind_recipient_info` and save the bound state, replacing the state it saved while
+/// waiting, before it calls `add_single_recipient_info`. A state saved before a reply was bound may be finalized at
+/// most once.
+pub struct SenderTransactionProtocol {
+    state: SenderState,
+}
+
+enum SenderState {
+    SingleRoundMessageReady(Box<RawTransactionInfo>),
+    CollectingSingleSignature(Box<RawTransactionInfo>),
+    Finalized(Box<Transaction>),
+}
+
+/// The sender's view of the transaction before the receiver's part is added
+#[derive(Clone)]
+struct RawTransactionInfo {
+    tx_id: u64,
+    amount: u64,
+    fee: u64,
+    lock_height: u64,
+    features: KernelFeatures,
+    offset: BlindingFactor,
+    excess_key: BlindingFactor,
+    private_nonce: BlindingFactor,
+    /// The hash of the kernel challenge of the reply the nonce is bound to, once a reply has been accepted
+    bound_challenge: Option<Vec<u8>>,
+    public_excess: PublicKey,
+    public_nonce: PublicKey,
+    inputs: Vec<UnblindedOutput>,
+    outputs: Vec<UnblindedOutput>,
+}
+
+impl SenderTransactionProtocol {
+    /// Returns true if the message for the receiver has not been built yet
+    pub fn is_single_round_message_ready(&self) -> bool {
+        match self.state {
+            SenderState::SingleRoundMessageReady(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the sender is waiting for the receiver's reply
+    pub fn is_collecting_single_signature(&self) -> bool {
+        match self.state {
+            SenderState::CollectingSingleSignature(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the transaction is complete
+    pub fn is_finalized(&self) -> bool {
+        match self.state {
+            SenderState::Finalized(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Build the message for the receiver and start waiting for the reply
+    pub fn build_single_round_message(&mut self) -> Result<SingleRoundSenderData, TransactionProtocolError> {
+        let info = match &self.state {
+            SenderState::SingleRoundMessageReady(info) | SenderState::CollectingSingleSignature(info) => info.clone(),
+            _ => return Err(TransactionProtocolError::InvalidStateError),
+        };
+        let message = SingleRoundSenderData {
+            tx_id: info.tx_id,
+            amount: info.amount,
+            fee: info.fee,
+            lock_height: info.lock_height,
+            features: info.features,
+            public_excess: info.public_excess,
+            public_nonce: info.public_nonce,
+        };
+        self.state = SenderState::CollectingSingleSignature(info);
+        Ok(message)
+    }
+
+    /// Check the receiver's reply and bind the sender's nonce to it, without signing. The sender then refuses to sign
+    /// any other reply, so the state should be saved once the reply is bound and before the transaction is finalized
+    /// with `add_single_recipient_info`. A reply that is not valid is rejected and the sender keeps waiting for a valid
+    /// one.
+    pub fn bind_recipient_info(&mut self, message: &RecipientSignedMessage) -> Result<(), TransactionProtocolError> {
+        self.bind_challenge(message).map(|_| ())
+    }
+
+    /// Check the receiver's reply, add the sender's partial signature and finalize the transaction. A reply that is
+    /// not valid is rejected and the sender keeps waiting for a valid one. Once a reply has been accepted, any other
+    /// reply is refused with `NonceAlreadyUsed`, see `bind_recipient_info`.
+    pub fn add_single_recipient_info(
+        &mut self,
+        message: &RecipientSignedMessage,
+    ) -> Result<(), TransactionProtocolError>
+    {
+        let challenge = self.bind_challenge(message)?;
+        let info = match &self.state {
+            SenderState::CollectingSingleSignature(info) => info,
+            _ => return Err(TransactionProtocolError::InvalidStateError),
+        };
+        let public_excess = &info.public_excess + &message.public_spend_key;
+        let partial_signature = Signature::sign(info.excess_key, info.private_nonce, challenge)
+            .map_err(|_| TransactionProtocolError::SigningError)?;
+        let kernel = TransactionKernel {
+            features: info.features,
+            fee: info.fee,
+            lock_height: info.lock_height,
+            excess: Some(Commitment::from_public_key(&public_excess)),
+            excess_sig: Some(&partial_signature + &message.partial_signature),
+        };
+        let transaction = TransactionBuilder::new()
+            .add_inputs(info.inputs.iter().map(UnblindedOutput::as_transaction_input).collect())
+            .add_outputs(info.outputs.iter().map(UnblindedOutput::as_transaction_output).collect())
+            .add_output(message.output)
+            .add_offset(info.offset)
+            .with_kernel(kernel)
+            .build()?;
+        self.state = SenderState::Finalized(Box::new(transaction));
+        Ok(())
+    }
+
+    /// Check the receiver's reply and bind the nonce to its kernel challenge, which is returned. A nonce that is
+    /// already bound to another challenge is refused.
+    fn bind_challenge(
+        &mut self,
+        message: &RecipientSignedMessage,
+    ) -> Result<Challenge<SignatureHash>, TransactionProtocolError>
+    {
+        let info = match &mut self.state {
+            SenderState::CollectingSingleSignature(info) => info,
+            _ => return Err(TransactionProtocolError::InvalidStateError),
+        };
+        if message.tx_id != info.tx_id {
+            return Err(TransactionProtocolError::InvalidTransactionId);
+        }
+        message.output.verify_range_proof()?;
+        let value = CommitmentFactory::create(&BlindingFactor::default(), &BlindingFactor::from(info.amount));
+        if (&message.output.commitment - &value).as_public_key() != &message.public_spend_key {
+            return Err(TransactionProtocolError::InvalidOutput);
+        }
+        let public_nonce = &info.public_nonce + message.partial_signature.get_public_nonce();
+        let public_excess = &info.public_excess + &message.public_spend_key;
+        let challenge =
+            build_kernel_challenge(&public_nonce, &public_excess, info.features, info.fee, info.lock_height);
+        if !message.partial_signature.verify_challenge(&message.public_spend_key, challenge.clone()) {
+            return Err(TransactionProtocolError::InvalidPartialSignature);
+        }
+        let hash = challenge.clone().hash();
+        match &info.bound_challenge {
+            Some(bound) if *bound != hash => return Err(TransactionProtocolError::NonceAlreadyUsed),
+            Some(_) => (),
+            None => info.bound_challenge = Some(hash),
+        }
+        Ok(challenge)
+    }
+
+    /// The finalized transaction
+    pub fn get_transaction(&self) -> Result<&Transaction, TransactionProtocolError> {
+        match &self.state {
+            SenderState::Finalized(transaction) => Ok(transaction),
+            _ => Err(TransactionProtocolError::InvalidStateError),
+        }
+    }
+
+    /// Save the state of the protocol into bytes that can be restored with `from_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.state.to_binary()
+    }
+
+    /// Restore a protocol saved by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<SenderTransactionProtocol, TransactionProtocolError> {
+        let state = SenderState::from_binary(bytes).map_err(|_| TransactionProtocolError::InvalidSavedState)?;
+        Ok(SenderTransactionProtocol { state })
+    }
+}
+
+impl BinaryEncoding for SenderState {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        match self {
+            SenderState::SingleRoundMessageReady(info) => {
+                buf.push(0);
+                info.binary_encode(buf);
+            },
+            SenderState::CollectingSingleSignature(info) => {
+                buf.push(1);
+                info.binary_encode(buf);
+            },
+            SenderState::Finalized(transaction) => {
+                buf.push(2);
+                transaction.binary_encode(buf);
+            },
+        }
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        match reader.read_u8()? {
+            0 => Ok(SenderState::SingleRoundMessageReady(Box::new(RawTransactionInfo::binary_decode(reader)?))),
+            1 => Ok(SenderState::CollectingSingleSignature(Box::new(RawTransactionInfo::binary_decode(reader)?))),
+            2 => Ok(SenderState::Finalized(Box::new(Transaction::binary_decode(reader)?))),
+            _ => Err(EncodingError::InvalidValue),
+        }
+    }
+}
+
+impl BinaryEncoding for RawTransactionInfo {
+    fn binary_encode(&self, buf: &mut Vec<u8>) {
+        for value in &[self.tx_id, self.amount, self.fee, self.lock_height] {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        buf.push(self.features.bits());
+        buf.extend_from_slice(self.offset.as_bytes());
+        buf.extend_from_slice(self.excess_key.as_bytes());
+        buf.extend_from_slice(self.private_nonce.as_bytes());
+        match &self.bound_challenge {
+            Some(hash) => {
+                buf.push(1);
+                buf.extend_from_slice(hash);
+            },
+            None => buf.push(0),
+        }
+        encode_list(&self.inputs, buf);
+        encode_list(&self.outputs, buf);
+    }
+
+    fn binary_decode(reader: &mut Reader) -> Result<Self, EncodingError> {
+        let tx_id = reader.read_u64()?;
+        let amount = reader.read_u64()?;
+        let fee = reader.read_u64()?;
+        let lock_height = reader.read_u64()?;
+        let features = KernelFeatures::from_bits(reader.read_u8()?).ok_or(EncodingError::InvalidValue)?;
+        let offset = reader.read_key()?;
+        let excess_key = reader.read_key()?;
+        let private_nonce = reader.read_key()?;
+        let bound_challenge = match reader.read_u8()? {
+            0 => None,
+            1 => Some(reader.read_hash()?.to_vec()),
+            _ => return Err(EncodingError::InvalidValue),
+        };
+        // The public keys are not saved, but derived again from the secrets
+        Ok(RawTransactionInfo {
+            tx_id,
+            amount,
+            fee,
+            lock_height,
+            features,
+            offset,
+            public_excess: PublicKey::from_secret_key(&excess_key),
+            public_nonce: PublicKey::from_secret_key(&private_nonce),
+            excess_key,
+            private_nonce,
+            bound_challenge,
+            inputs: decode_list(reader)?,
+            outputs: decode_list(reader)?,
+        })
+    }
+}
@@ -0,0 +1,183 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+// This file contains the wallet helpers that sweep dust, the outputs worth less than the dust threshold, into a single
+// output, so that they leave the UTXO set while they are still worth spending
+
+use crate::{
+    block::WEIGHT_PER_INPUT,
+    fee::calculate_fee,
+    transaction::{
+        build_kernel_challenge,
+        KernelFeatures,
+        OutputFeatures,
+        Transaction,
+        TransactionBuilder,
+        TransactionKernel,
+    },
+    transaction_protocol::{TransactionProtocolError, UnblindedOutput},
+    types::{BlindingFactor, Commitment, PublicKey, Signature},
+};
+use crypto::keys::PublicKey as PublicKeyTrait;
+
+/// Select the outputs worth sweeping: those worth less than the dust threshold, but more than the fee of spending them
+/// at the given fee rate
+pub fn select_dust(outputs: &[UnblindedOutput], dust_threshold: u64, fee_per_gram: u64) -> Vec<UnblindedOutput> {
+    let input_fee = WEIGHT_PER_INPUT.saturating_mul(fee_per_gram);
+    outputs.iter().filter(|output| output.value < dust_threshold && output.value > input_fee).cloned().collect()
+}
+
+/// Builds a transaction that spends dust outputs of the wallet into a single output of the wallet, which needs no
+/// receiver. The swept output is worth the value of the dust less the fee, which must leave at least the dust
+/// threshold.
+pub struct SweepBuilder {
+    inputs: Vec<UnblindedOutput>,
+    fee_per_gram: u64,
+    dust_threshold: u64,
+    spend_key: Option<BlindingFactor>,
+    offset: Option<BlindingFactor>,
+    private_nonce: Option<BlindingFactor>,
+}
+
+impl SweepBuilder {
+    /// Start a new sweep
+    pub fn new() -> Self {
+        Self {
+            inputs: Vec::new(),
+            fee_per_gram: 0,
+            dust_threshold: 0,
+            spend_key: None,
+            offset: None,
+            private_nonce: None,
+        }
+    }
+
+    /// Add the outputs to sweep, which are usually chosen with `select_dust`
+    pub fn add_inputs(mut self, mut inputs: Vec<UnblindedOutput>) -> Self {
+        self.inputs.append(&mut inputs);
+        self
+    }
+
+    /// Set the fee rate, in µT per gram of weight
+    pub fn with_fee_per_gram(mut self, fee_per_gram: u64) -> Self {
+        self.fee_per_gram = fee_per_gram;
+        self
+    }
+
+    /// Set the dust threshold of the consensus constants
+    pub fn with_dust_threshold(mut self, threshold: u64) -> Self {
+        self.dust_threshold = threshold;
+        self
+    }
+
+    /// Set the blinding factor of the swept output
+    pub fn with_spend_key(mut self, key: BlindingFactor) -> Self {
+        self.spend_key = Some(key);
+        self
+    }
+
+    /// Set the offset of the transaction
+    pub fn with_offset(mut self, offset: BlindingFactor) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Set the nonce of the kernel signature. It must never be reused.
+    pub fn with_nonce(mut self, nonce: BlindingFactor) -> Self {
+        self.private_nonce = Some(nonce);
+        self
+    }
+
+    /// Create the transaction, and the swept output, which the wallet keeps to spend it later
+    pub fn build(self) -> Result<(Transaction, UnblindedOutput), TransactionProtocolError> {
+        let (spend_key, offset, nonce) = match (self.spend_key, self.offset, self.private_nonce) {
+            (Some(spend_key), Some(offset), Some(nonce)) => (spend_key, offset, nonce),
+            _ => return Err(TransactionProtocolError::IncompleteStateError),
+        };
+        let fee = calculate_fee(self.fee_per_gram, self.inputs.len(), 1, 1);
+        let value = self
+            .inputs
+            .iter()
+            .try_fold(0u64, |total, input| total.checked_add(input.value))
+            .and_then(|total| total.checked_sub(fee))
+            .ok_or(TransactionProtocolError::InsufficientFunds)?;
+        if value < self.dust_threshold {
+            return Err(TransactionProtocolError::DustOutput);
+        }
+        let output = UnblindedOutput::new(value, spend_key, OutputFeatures::empty());
+        let excess_key = self.inputs.iter().fold(&spend_key - &offset, |key, input| &key - &input.spending_key);
+        let public_excess = PublicKey::from_secret_key(&excess_key);
+        let public_nonce = PublicKey::from_secret_key(&nonce);
+        let challenge = build_kernel_challenge(&public_nonce, &public_excess, KernelFeatures::empty(), fee, 0);
+        let excess_sig =
+            Signature::sign(excess_key, nonce, challenge).map_err(|_| TransactionProtocolError::SigningError)?;
+        let kernel = TransactionKernel {
+            features: KernelFeatures::empty(),
+            fee,
+            lock_height: 0,
+            excess: Some(Commitment::from_public_key(&public_excess)),
+            excess_sig: Some(excess_sig),
+        };
+        let transaction = TransactionBuilder::new()
+            .add_inputs(self.inputs.iter().map(UnblindedOutput::as_transaction_input).collect())
+            .add_output(output.as_transaction_output())
+            .add_offset(offset)
+            .with_kernel(kernel)
+            .build()?;
+        Ok((transaction, output))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crypto::keys::SecretKey;
+    use rand::OsRng;
+
+    #[test]
+    fn sweep_dust() {
+        let mut rng = OsRng::new().unwrap();
+        let outputs: Vec<UnblindedOutput> = [1, 30, 60, 90, 500]
+            .iter()
+            .map(|value| UnblindedOutput::new(*value, BlindingFactor::random(&mut rng), OutputFeatures::empty()))
+            .collect();
+        let dust = select_dust(&outputs, 100, 2);
+        assert_eq!(dust.iter().map(|output| output.value).collect::<Vec<_>>(), vec![30, 60, 90]);
+
+        let sweep = |inputs, threshold, rng: &mut OsRng| {
+            SweepBuilder::new()
+                .add_inputs(inputs)
+                .with_fee_per_gram(2)
+                .with_dust_threshold(threshold)
+                .with_spend_key(BlindingFactor::random(rng))
+                .with_offset(BlindingFactor::random(rng))
+                .with_nonce(BlindingFactor::random(rng))
+                .build()
+        };
+        let (transaction, output) = sweep(dust.clone(), 100, &mut rng).unwrap();
+        transaction.validate().unwrap();
+        assert_eq!(output.value, 180 - calculate_fee(2, 3, 1, 1));
+        assert_eq!(transaction.body.outputs[0].commitment, output.commitment());
+        assert_eq!(sweep(dust, 160, &mut rng).err(), Some(TransactionProtocolError::DustOutput));
+        assert_eq!(sweep(vec![], 0, &mut rng).err(), Some(TransactionProtocolError::InsufficientFunds));
+    }
+}
@@ -32,6 +32,8 @@ use crypto::{
         RistrettoSecretKey,
     },
 };
+use digest::{generic_array::GenericArray, FixedOutput, Input, Reset};
+use std::marker::PhantomData;
 
 /// Define the explicit Signature implementation for the Tari base layer. A different signature scheme can be
 /// employed by redefining this type.
@@ -49,3 +51,110 @@ pub type PublicKey = RistrettoPublicKey;
 
 /// Define the hash function that will be used to produce a signature challenge
 pub type SignatureHash = Blake256;
+
+/// The domain of a consensus hash, which sets the label that the hash input starts with. Objects of different kinds
+/// are hashed in different domains, so that the hash of one kind of object can never be passed off as the hash of
+/// another kind of object with the same bytes.
+pub trait HashDomain: Clone + Default {
+    /// The label of the domain, which must be unique among all domains. Changing it changes every hash in the domain.
+    const LABEL: &'static str;
+}
+
+/// A Blake256 hasher whose input starts with the label of the domain `D`. The label is prefixed with its length, like
+/// `#[hash_label]` of `derive(Hashable)` does, so that no label plus data can be mistaken for another label. It is a
+/// `Digest` and can be given to `#[Digest = "..."]` and to `MerkleMountainRange`.
+#[derive(Clone)]
+pub struct DomainHasher<D: HashDomain> {
+    hasher: Blake256,
+    domain: PhantomData<D>,
+}
+
+impl<D: HashDomain> Default for DomainHasher<D> {
+    fn default() -> Self {
+        let mut hasher = Blake256::default();
+        hasher.input((D::LABEL.len() as u64).to_le_bytes());
+        hasher.input(D::LABEL.as_bytes());
+        DomainHasher { hasher, domain: PhantomData }
+    }
+}
+
+impl<D: HashDomain> Input for DomainHasher<D> {
+    fn input<B: AsRef<[u8]>>(&mut self, data: B) {
+        self.hasher.input(data);
+    }
+}
+
+impl<D: HashDomain> FixedOutput for DomainHasher<D> {
+    type OutputSize = <Blake256 as FixedOutput>::OutputSize;
+
+    fn fixed_result(self) -> GenericArray<u8, Self::OutputSize> {
+        self.hasher.fixed_result()
+    }
+}
+
+impl<D: HashDomain> Reset for DomainHasher<D> {
+    fn reset(&mut self) {
+        *self = DomainHasher::default();
+    }
+}
+
+macro_rules! hash_domains {
+    ($($(#[$doc:meta])* $domain:ident, $hasher:ident => $label:expr;)*) => {
+        $(
+            $(#[$doc])*
+            #[derive(Clone, Debug, Default)]
+            pub struct $domain;
+
+            impl HashDomain for $domain {
+                const LABEL: &'static str = $label;
+            }
+
+            $(#[$doc])*
+            pub type $hasher = DomainHasher<$domain>;
+        )*
+
+        /// The labels of all hash domains, which are all distinct
+        pub const HASH_DOMAIN_LABELS: &[&str] = &[$($label),*];
+    };
+}
+
+// The registry of consensus hash domains. All consensus hashes must be made with one of these hashers.
+hash_domains! {
+    /// The domain of block header hashes, which are the block hashes and the leaves of the header MMR
+    BlockHeaderDomain, BlockHeaderHasher => "com.tari.base_layer.block_header";
+    /// The domain of transaction kernel hashes, which are the leaves of the kernel MMR
+    KernelDomain, KernelHasher => "com.tari.base_layer.kernel";
+    /// The domain of transaction output hashes, which are the leaves of the output MMR
+    OutputDomain, OutputHasher => "com.tari.base_layer.output";
+    /// The domain of transaction input hashes, which set the canonical order of inputs
+    InputDomain, InputHasher => "com.tari.base_layer.input";
+    /// The domain of transaction hashes, which identify transactions before they are mined
+    TransactionDomain, TransactionHasher => "com.tari.base_layer.transaction";
+    /// The domain of the leaf and node hashes of the header, output and kernel MMRs
+    MmrNodeDomain, MmrHasher => "com.tari.base_layer.mmr_node";
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use digest::Digest;
+    use std::collections::HashSet;
+
+    #[test]
+    fn hash_domains() {
+        let labels: HashSet<_> = HASH_DOMAIN_LABELS.iter().collect();
+        assert_eq!(labels.len(), HASH_DOMAIN_LABELS.len());
+        assert_ne!(KernelHasher::digest(b"data"), OutputHasher::digest(b"data"));
+        assert_ne!(KernelHasher::digest(b"data"), Blake256::digest(b"data"));
+
+        let mut label = (KernelDomain::LABEL.len() as u64).to_le_bytes().to_vec();
+        label.extend_from_slice(KernelDomain::LABEL.as_bytes());
+        label.extend_from_slice(b"data");
+        assert_eq!(KernelHasher::digest(b"data"), Blake256::digest(&label));
+        let mut hasher = KernelHasher::new();
+        Digest::input(&mut hasher, b"more data");
+        Digest::reset(&mut hasher);
+        Digest::input(&mut hasher, b"data");
+        assert_eq!(hasher.result(), Blake256::digest(&label));
+    }
+}
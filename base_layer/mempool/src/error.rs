@@ -0,0 +1,53 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+// this file is used for all mempool error types
+use derive_error::Error;
+use tari_core::transaction::TransactionError;
+
+/// The reasons a transaction can be rejected by `Mempool::insert`
+#[derive(Debug, PartialEq, Error)]
+pub enum MempoolError {
+    /// The transaction is already in the mempool
+    DuplicateTransaction,
+    /// The transaction creates an output that a transaction in the mempool already creates
+    DuplicateOutput,
+    /// The transaction is not valid
+    TransactionError(TransactionError),
+    /// The transaction spends an input of a transaction in the mempool that it may not replace
+    ReplacementError(ReplacementError),
+}
+
+/// The replacement rules a transaction can break when it spends an input of a transaction in the mempool
+#[derive(Debug, PartialEq, Error)]
+pub enum ReplacementError {
+    /// A transaction the replacement conflicts with does not signal that it is replaceable
+    NotReplaceable,
+    /// The replacement would evict more than `MAX_REPLACED_TRANSACTIONS` transactions from the mempool
+    TooManyReplacements,
+    /// The replacement spends an output of a transaction it would replace
+    SpendsReplacedOutput,
+    /// The replacement does not pay a higher fee rate than every transaction it conflicts with
+    FeeRateTooLow,
+    /// The replacement does not pay the fees of the transactions it replaces plus the minimum fee for its own weight
+    InsufficientFee,
+}
@@ -0,0 +1,276 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! The pool of transactions that are waiting to be mined.
+//!
+//! Every input may only be spent by one transaction in the mempool. A transaction that spends an input of a pending
+//! transaction is rejected, unless it may replace the pending transaction under the rules of the
+//! [replacement](../replacement/index.html) module, in which case the pending transaction and the transactions that
+//! spend its outputs are evicted.
+
+use crate::{error::MempoolError, replacement::check_replacement};
+use crypto::commitment::HomomorphicCommitment;
+use std::collections::HashMap;
+use tari_core::{
+    block::Block,
+    consensus::ConsensusConstants,
+    transaction::{Transaction, TransactionInput},
+};
+use tari_utilities::Hashable;
+
+/// The transactions that are waiting to be mined, indexed by their hash. The mempool checks that each transaction is
+/// valid and that no two transactions spend the same input, but not that the inputs are unspent outputs of the chain,
+/// which the node checks before it inserts a transaction.
+pub struct Mempool {
+    min_fee_per_gram: u64,
+    transactions: HashMap<Vec<u8>, Transaction>,
+    spent: HashMap<Vec<u8>, Vec<u8>>,
+    created: HashMap<Vec<u8>, Vec<u8>>,
+    kernels: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl Mempool {
+    /// Create an empty mempool for a chain with the given consensus constants
+    pub fn new(constants: &ConsensusConstants) -> Mempool {
+        Mempool {
+            min_fee_per_gram: constants.min_fee_per_gram,
+            transactions: HashMap::new(),
+            spent: HashMap::new(),
+            created: HashMap::new(),
+            kernels: HashMap::new(),
+        }
+    }
+
+    /// The number of transactions in the mempool
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    /// Returns true if there are no transactions in the mempool
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Get the transaction with the given hash
+    pub fn get(&self, hash: &[u8]) -> Option<&Transaction> {
+        self.transactions.get(hash)
+    }
+
+    /// The transactions in the mempool, in no particular order
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.transactions.values()
+    }
+
+    /// Add a valid transaction that pays at least the minimum fee rate. If it spends an input of a transaction in the
+    /// mempool, it must satisfy the replacement rules and the transactions it replaces are evicted. Returns the
+    /// evicted transactions, which the node stops tracking, for instance in its `FeeEstimator`.
+    pub fn insert(&mut self, tx: Transaction) -> Result<Vec<Transaction>, MempoolError> {
+        let hash = tx.hash();
+        if self.transactions.contains_key(&hash) {
+            return Err(MempoolError::DuplicateTransaction);
+        }
+        tx.validate()?;
+        tx.validate_fee(self.min_fee_per_gram)?;
+        let conflicts = self.conflicts(&tx.body.inputs);
+        let replaced = self.with_descendants(&conflicts);
+        let duplicate_output = tx.body.outputs.iter().any(|output| {
+            self.created.get(output.commitment.as_bytes()).map_or(false, |creator| !replaced.contains(creator))
+        });
+        if duplicate_output {
+            return Err(MempoolError::DuplicateOutput);
+        }
+        if !conflicts.is_empty() {
+            let lookup = |hashes: &[Vec<u8>]| hashes.iter().map(|hash| &self.transactions[hash]).collect::<Vec<_>>();
+            check_replacement(&tx, &lookup(&conflicts), &lookup(&replaced), self.min_fee_per_gram)?;
+        }
+        let replaced = replaced.iter().filter_map(|hash| self.remove(hash)).collect();
+        self.add(hash, tx);
+        Ok(replaced)
+    }
+
+    /// Remove the transactions that were mined in a block, which are found by their kernels. The transactions that
+    /// spend an input the block spent can no longer be mined, so they are evicted along with the transactions that
+    /// spend their outputs. Returns the evicted transactions.
+    pub fn remove_block(&mut self, block: &Block) -> Vec<Transaction> {
+        let mined: Vec<Vec<u8>> =
+            block.body.kernels.iter().filter_map(|kernel| self.kernels.get(&kernel.hash()).cloned()).collect();
+        for hash in mined.iter() {
+            self.remove(hash);
+        }
+        let conflicts = self.conflicts(&block.body.inputs);
+        self.with_descendants(&conflicts).iter().filter_map(|hash| self.remove(hash)).collect()
+    }
+
+    /// The hashes of the transactions in the mempool that spend one of the inputs
+    fn conflicts(&self, inputs: &[TransactionInput]) -> Vec<Vec<u8>> {
+        let mut conflicts = Vec::new();
+        for input in inputs {
+            if let Some(hash) = self.spent.get(input.commitment.as_bytes()) {
+                if !conflicts.contains(hash) {
+                    conflicts.push(hash.clone());
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// The given transaction hashes followed by the hashes of the transactions in the mempool that spend their
+    /// outputs, directly or through other transactions in the mempool
+    fn with_descendants(&self, hashes: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        let mut result = hashes.to_vec();
+        let mut i = 0;
+        while i < result.len() {
+            let children = self.conflicts_with_outputs(&result[i]);
+            for child in children {
+                if !result.contains(&child) {
+                    result.push(child);
+                }
+            }
+            i += 1;
+        }
+        result
+    }
+
+    /// The hashes of the transactions in the mempool that spend an output of the given transaction
+    fn conflicts_with_outputs(&self, hash: &[u8]) -> Vec<Vec<u8>> {
+        self.transactions[hash]
+            .body
+            .outputs
+            .iter()
+            .filter_map(|output| self.spent.get(output.commitment.as_bytes()).cloned())
+            .collect()
+    }
+
+    fn add(&mut self, hash: Vec<u8>, tx: Transaction) {
+        for input in tx.body.inputs.iter() {
+            self.spent.insert(input.commitment.as_bytes().to_vec(), hash.clone());
+        }
+        for output in tx.body.outputs.iter() {
+            self.created.insert(output.commitment.as_bytes().to_vec(), hash.clone());
+        }
+        for kernel in tx.body.kernels.iter() {
+            self.kernels.insert(kernel.hash(), hash.clone());
+        }
+        self.transactions.insert(hash, tx);
+    }
+
+    fn remove(&mut self, hash: &[u8]) -> Option<Transaction> {
+        let tx = self.transactions.remove(hash)?;
+        for input in tx.body.inputs.iter() {
+            self.spent.remove(input.commitment.as_bytes());
+        }
+        for output in tx.body.outputs.iter() {
+            self.created.remove(output.commitment.as_bytes());
+        }
+        for kernel in tx.body.kernels.iter() {
+            self.kernels.remove(&kernel.hash());
+        }
+        Some(tx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::ReplacementError;
+    use crypto::keys::SecretKey;
+    use rand::{OsRng, RngCore};
+    use tari_core::{
+        block::AggregateBody,
+        consensus::Network,
+        transaction::OutputFeatures,
+        transaction_protocol::{
+            receiver::ReceiverTransactionProtocol, sender::SenderTransactionInitializer, UnblindedOutput,
+        },
+        types::BlindingFactor,
+    };
+
+    /// Pay `amount` from `input` to a new output, which is returned with the transaction
+    fn create_transaction(
+        input: &UnblindedOutput,
+        amount: u64,
+        fee: u64,
+        replaceable: bool,
+    ) -> (Transaction, UnblindedOutput)
+    {
+        let mut rng = OsRng::new().unwrap();
+        let mut sender = SenderTransactionInitializer::new(rng.next_u64())
+            .with_amount(amount)
+            .with_fee(fee)
+            .with_replaceable(replaceable)
+            .with_offset(BlindingFactor::random(&mut rng))
+            .with_private_nonce(BlindingFactor::random(&mut rng))
+            .with_change_secret(BlindingFactor::random(&mut rng))
+            .add_input(input.clone())
+            .build()
+            .unwrap();
+        let message = sender.build_single_round_message().unwrap();
+        let spending_key = BlindingFactor::random(&mut rng);
+        let nonce = BlindingFactor::random(&mut rng);
+        let receiver = ReceiverTransactionProtocol::new(&message, nonce, spending_key, OutputFeatures::empty());
+        sender.add_single_recipient_info(receiver.get_signed_data().unwrap()).unwrap();
+        let output = UnblindedOutput::new(amount, spending_key, OutputFeatures::empty());
+        (sender.get_transaction().unwrap().clone(), output)
+    }
+
+    #[test]
+    fn replace_transaction() {
+        let mut rng = OsRng::new().unwrap();
+        let mut mempool = Mempool::new(&Network::TestNet.consensus_constants());
+        let utxo = UnblindedOutput::new(10_000, BlindingFactor::random(&mut rng), OutputFeatures::empty());
+        let (stuck, output) = create_transaction(&utxo, 1_000, 100, true);
+        let (child, _) = create_transaction(&output, 500, 50, false);
+        assert_eq!(mempool.insert(stuck.clone()).unwrap().len(), 0);
+        assert_eq!(mempool.insert(child.clone()).unwrap().len(), 0);
+        assert_eq!(mempool.insert(stuck.clone()).err(), Some(MempoolError::DuplicateTransaction));
+
+        // The replacement must pay for the transactions it evicts and for its own weight
+        let (cheap, _) = create_transaction(&utxo, 1_000, 150, false);
+        let error = MempoolError::ReplacementError(ReplacementError::InsufficientFee);
+        assert_eq!(mempool.insert(cheap).err(), Some(error));
+        let (replacement, _) = create_transaction(&utxo, 1_000, 300, false);
+        let replaced = mempool.insert(replacement.clone()).unwrap();
+        let mut replaced: Vec<Vec<u8>> = replaced.iter().map(Hashable::hash).collect();
+        let mut expected = vec![stuck.hash(), child.hash()];
+        replaced.sort();
+        expected.sort();
+        assert_eq!(replaced, expected);
+        assert_eq!(mempool.len(), 1);
+
+        // The replacement did not opt in to being replaced itself
+        let (another, _) = create_transaction(&utxo, 1_000, 1_000, true);
+        let error = MempoolError::ReplacementError(ReplacementError::NotReplaceable);
+        assert_eq!(mempool.insert(another.clone()).err(), Some(error));
+
+        // Mining the replacement clears it from the mempool, and a transaction spending the same input is evicted
+        let mut block = Network::TestNet.genesis_block().unwrap();
+        block.body = AggregateBody::new(vec![utxo.as_transaction_input()], vec![], replacement.body.kernels.clone());
+        assert_eq!(mempool.remove_block(&block).len(), 0);
+        assert!(mempool.is_empty());
+        mempool.insert(another.clone()).unwrap();
+        block.body.kernels.clear();
+        let evicted = mempool.remove_block(&block);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].hash(), another.hash());
+        assert!(mempool.is_empty());
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! The rules under which a transaction may replace transactions in the mempool that spend the same inputs.
+//!
+//! A wallet rescues a transaction that is stuck in the mempool by spending its inputs again with a higher fee. Nodes
+//! only accept the replacement if the stuck transaction opted in, by setting `KernelFeatures::REPLACEABLE` on every
+//! kernel, and if the replacement:
+//!
+//! 1. evicts at most `MAX_REPLACED_TRANSACTIONS` transactions, counting the conflicting transactions and the
+//!    transactions in the mempool that spend their outputs, which are evicted with them;
+//! 2. does not spend an output of a transaction it evicts;
+//! 3. pays a higher fee rate than every transaction it conflicts with, so that it is mined sooner;
+//! 4. pays the fees of every transaction it evicts plus the minimum fee for its own weight, so that relaying the
+//!    replacement is paid for and a transaction cannot be replaced over and over for free.
+
+use crate::error::ReplacementError;
+use tari_core::transaction::Transaction;
+
+/// The most transactions a replacement may evict from the mempool
+pub const MAX_REPLACED_TRANSACTIONS: usize = 100;
+
+/// The total fee of the kernels of a transaction
+pub fn transaction_fee(tx: &Transaction) -> u64 {
+    tx.body.kernels.iter().fold(0u64, |fees, kernel| fees.saturating_add(kernel.fee))
+}
+
+/// Check that `replacement` may replace the transactions in the mempool. `conflicts` are the transactions that spend
+/// an input of the replacement and `replaced` are all the transactions that would be evicted, which are the conflicts
+/// and the transactions that spend their outputs.
+pub fn check_replacement(
+    replacement: &Transaction,
+    conflicts: &[&Transaction],
+    replaced: &[&Transaction],
+    min_fee_per_gram: u64,
+) -> Result<(), ReplacementError>
+{
+    if !conflicts.iter().all(|tx| tx.is_replaceable()) {
+        return Err(ReplacementError::NotReplaceable);
+    }
+    if replaced.len() > MAX_REPLACED_TRANSACTIONS {
+        return Err(ReplacementError::TooManyReplacements);
+    }
+    let spends_replaced_output = replacement.body.inputs.iter().any(|input| {
+        replaced.iter().any(|tx| tx.body.outputs.iter().any(|output| output.commitment == input.commitment))
+    });
+    if spends_replaced_output {
+        return Err(ReplacementError::SpendsReplacedOutput);
+    }
+    let fee = transaction_fee(replacement);
+    let weight = replacement.calculate_weight();
+    if !conflicts.iter().all(|tx| pays_higher_rate(fee, weight, transaction_fee(tx), tx.calculate_weight())) {
+        return Err(ReplacementError::FeeRateTooLow);
+    }
+    let required = replaced
+        .iter()
+        .fold(min_fee_per_gram.saturating_mul(weight), |required, tx| required.saturating_add(transaction_fee(tx)));
+    if fee < required {
+        return Err(ReplacementError::InsufficientFee);
+    }
+    Ok(())
+}
+
+/// Whether `fee` for `weight` grams is a strictly higher fee rate than `other_fee` for `other_weight` grams, compared
+/// without rounding the rates down to whole µT per gram
+fn pays_higher_rate(fee: u64, weight: u64, other_fee: u64, other_weight: u64) -> bool {
+    u128::from(fee) * u128::from(other_weight) > u128::from(other_fee) * u128::from(weight)
+}
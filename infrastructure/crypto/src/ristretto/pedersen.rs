@@ -63,6 +63,12 @@ impl PedersenOnRistretto255 {
     pub fn as_public_key(&self) -> &RistrettoPublicKey {
         &self.commitment
     }
+
+    /// Recreate a commitment on the default base from its public key representation, e.g. after reading a commitment
+    /// back from storage
+    pub fn from_public_key(commitment: &RistrettoPublicKey) -> PedersenOnRistretto255 {
+        PedersenOnRistretto255 { base: &DEFAULT_RISTRETTO_PEDERSON_BASE, commitment: *commitment }
+    }
 }
 
 impl HomomorphicCommitmentFactory for PedersenBaseOnRistretto255 {
@@ -214,6 +220,17 @@ mod test {
         }
     }
 
+    #[test]
+    fn from_public_key() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let k = RistrettoSecretKey::random(&mut rng);
+        let v = RistrettoSecretKey::random(&mut rng);
+        let c = PedersenBaseOnRistretto255::create(&k, &v);
+        let c2 = PedersenOnRistretto255::from_public_key(c.as_public_key());
+        assert_eq!(c, c2);
+        assert!(c2.open(&k, &v));
+    }
+
     #[test]
     #[should_panic]
     fn summing_different_bases_panics() {
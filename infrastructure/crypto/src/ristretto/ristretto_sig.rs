@@ -21,9 +21,17 @@
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use crate::{
+    keys::SecretKey,
     ristretto::{RistrettoPublicKey, RistrettoSecretKey},
     signatures::SchnorrSignature,
 };
+use curve25519_dalek::{
+    constants::RISTRETTO_BASEPOINT_POINT,
+    ristretto::RistrettoPoint,
+    scalar::Scalar,
+    traits::{IsIdentity, VartimeMultiscalarMul},
+};
+use rand::{CryptoRng, Rng};
 
 /// # A Schnorr signature implementation on Ristretto
 ///
@@ -102,6 +110,36 @@ use crate::{
 /// ```
 pub type RistrettoSchnorr = SchnorrSignature<RistrettoPublicKey, RistrettoSecretKey>;
 
+impl RistrettoSchnorr {
+    /// Verify a batch of signatures at once. Each signature is given with the public key it is verified against and
+    /// its challenge, as they are passed to `verify`. Returns true only if every signature in the batch is valid.
+    ///
+    /// The whole batch is checked with a single multiscalar multiplication, that
+    /// _sum(z_i.s_i).G - sum(z_i.R_i) - sum(z_i.e_i.P_i) = 0_, which is several times faster than verifying the
+    /// signatures one by one. The weights _z_i_ are random, so that invalid signatures can not be chosen to cancel each
+    /// other out. The check runs in variable time, which is fine as signatures and public keys are public.
+    pub fn verify_batch<R: Rng + CryptoRng>(
+        batch: &[(&RistrettoSchnorr, &RistrettoPublicKey, &RistrettoSecretKey)],
+        rng: &mut R,
+    ) -> bool
+    {
+        let mut scalars = Vec::with_capacity(2 * batch.len() + 1);
+        let mut points = Vec::with_capacity(2 * batch.len() + 1);
+        let mut s_sum = Scalar::zero();
+        for (signature, public_key, challenge) in batch {
+            let z = RistrettoSecretKey::random(rng).0;
+            s_sum += z * signature.get_signature().0;
+            scalars.push(-z);
+            points.push(signature.get_public_nonce().point);
+            scalars.push(-(z * challenge.0));
+            points.push(public_key.point);
+        }
+        scalars.push(s_sum);
+        points.push(RISTRETTO_BASEPOINT_POINT);
+        RistrettoPoint::vartime_multiscalar_mul(scalars, points).is_identity()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::{
@@ -168,4 +206,29 @@ mod test {
         // Check that the multi-sig verifies
         assert!(s_agg.verify_challenge(&(P1 + P2), e3));
     }
+
+    /// A batch verifies only if every signature in it is valid
+    #[test]
+    #[allow(non_snake_case)]
+    fn verify_batch() {
+        let mut rng = rand::OsRng::new().unwrap();
+        let mut signed = Vec::new();
+        for i in 0..5u8 {
+            let (k, P) = get_keypair();
+            let (r, _) = get_keypair();
+            let e = Challenge::<Blake256>::new().concat(P.as_bytes()).concat(&[i]);
+            let sig = RistrettoSchnorr::sign(k, r, e.clone()).unwrap();
+            signed.push((sig, P, RistrettoSecretKey::from_vec(&e.hash()).unwrap()));
+        }
+        let batch: Vec<_> = signed.iter().map(|(sig, P, e)| (sig, P, e)).collect();
+        assert!(RistrettoSchnorr::verify_batch(&batch, &mut rng));
+        assert!(RistrettoSchnorr::verify_batch(&[], &mut rng));
+
+        let mut invalid = batch.clone();
+        invalid[3].2 = batch[2].2;
+        assert!(!RistrettoSchnorr::verify_batch(&invalid, &mut rng));
+        let mut invalid = batch;
+        invalid[0].1 = &signed[1].1;
+        assert!(!RistrettoSchnorr::verify_batch(&invalid, &mut rng));
+    }
 }
@@ -0,0 +1,70 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use proc_macro2::TokenStream;
+use syn::{Data, DeriveInput, Error, Fields, Type};
+
+pub fn derive(ast: &DeriveInput) -> Result<TokenStream, Error> {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let inner = match &ast.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0].ty,
+            _ => return Err(Error::new_spanned(name, "ByteArray can only be derived for single-field tuple structs")),
+        },
+        _ => return Err(Error::new_spanned(name, "ByteArray can only be derived for single-field tuple structs")),
+    };
+    let from_bytes = match inner {
+        // Fixed-size arrays are checked and copied directly
+        Type::Array(array) => {
+            let len = &array.len;
+            quote! {
+                if bytes.len() != #len {
+                    return Err(tari_utilities::ByteArrayError::IncorrectLength);
+                }
+                let mut a = [0u8; #len];
+                a.copy_from_slice(bytes);
+                Ok(#name(a))
+            }
+        },
+        // Anything else must be a ByteArray itself, which does its own validation
+        _ => quote! {
+            <#inner as tari_utilities::ByteArray>::from_bytes(bytes).map(#name)
+        },
+    };
+    let as_bytes = match inner {
+        Type::Array(_) => quote!(&self.0),
+        _ => quote!(tari_utilities::ByteArray::as_bytes(&self.0)),
+    };
+    Ok(quote! {
+        impl #impl_generics tari_utilities::ByteArray for #name #ty_generics #where_clause {
+            fn from_bytes(bytes: &[u8]) -> Result<Self, tari_utilities::ByteArrayError>
+            where Self: Sized {
+                #from_bytes
+            }
+
+            fn as_bytes(&self) -> &[u8] {
+                #as_bytes
+            }
+        }
+    })
+}
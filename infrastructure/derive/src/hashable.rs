@@ -0,0 +1,282 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use proc_macro2::TokenStream;
+use syn::{
+    spanned::Spanned,
+    Attribute,
+    Data,
+    DeriveInput,
+    Error,
+    Field,
+    Ident,
+    Index,
+    Lit,
+    LitByteStr,
+    LitInt,
+    LitStr,
+    Meta,
+    NestedMeta,
+    Path,
+};
+
+/// How a single field takes part in the derived hash
+enum FieldHashing {
+    /// Hash the field's `ByteArray` representation
+    Bytes,
+    /// Leave the field out of the hash entirely
+    Skip,
+    /// Hash the result of calling the given function on a reference to the field
+    With(Path),
+}
+
+pub fn derive(ast: &DeriveInput) -> Result<TokenStream, Error> {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let digest: Path = find_str_attr(&ast.attrs, "Digest")?
+        .ok_or_else(|| Error::new_spanned(name, "Hashable requires a digest attribute, e.g. #[Digest = \"Blake256\"]"))?
+        .parse()?;
+    let inputs = input_statements(ast)?;
+    Ok(quote! {
+        impl #impl_generics tari_utilities::Hashable for #name #ty_generics #where_clause {
+            fn hash(&self) -> Vec<u8> {
+                let mut hasher = <#digest as digest::Digest>::new();
+                {
+                    let mut write = |bytes: &[u8]| digest::Digest::input(&mut hasher, bytes);
+                    #(#inputs)*
+                }
+                digest::Digest::result(hasher).to_vec()
+            }
+        }
+    })
+}
+
+/// Build the statements that produce the hash input of a struct, in order. Each statement passes a byte slice to a
+/// `write: FnMut(&[u8])` closure that the caller must have in scope, so the same input can be fed to a digest or
+/// collected into a buffer.
+pub fn input_statements(ast: &DeriveInput) -> Result<Vec<TokenStream>, Error> {
+    let name = &ast.ident;
+    let fields = match &ast.data {
+        Data::Struct(data) => &data.fields,
+        _ => return Err(Error::new_spanned(name, "Hashable can only be derived for structs")),
+    };
+    let version = find_version(&ast.attrs)?;
+    let mut inputs = Vec::new();
+    if let Some(label) = find_str_attr(&ast.attrs, "hash_label")? {
+        // The label is length-prefixed so that no label can be a prefix of another label plus field data
+        let value = label.value();
+        let len = value.len() as u64;
+        let bytes = LitByteStr::new(value.as_bytes(), label.span());
+        inputs.push(quote! {
+            write(&#len.to_le_bytes());
+            write(#bytes);
+        });
+    }
+    let mut hashed_fields = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let member = match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let index = Index::from(i);
+                quote!(#index)
+            },
+        };
+        let bytes = match field_hashing(field)? {
+            FieldHashing::Skip => continue,
+            FieldHashing::Bytes => quote!(tari_utilities::ByteArray::as_bytes(&self.#member)),
+            FieldHashing::With(func) => quote!(#func(&self.#member)),
+        };
+        hashed_fields.push((field_order(field)?, field, bytes));
+    }
+    check_field_order(name, &hashed_fields)?;
+    hashed_fields.sort_by_key(|(order, _, _)| order.clone().map(|o| o.value()));
+    for (_, _, bytes) in hashed_fields {
+        inputs.push(match version {
+            1 => quote! {
+                {
+                    let bytes = #bytes;
+                    write(bytes.as_ref());
+                }
+            },
+            _ => quote! {
+                {
+                    let bytes = #bytes;
+                    let bytes: &[u8] = bytes.as_ref();
+                    write(&(bytes.len() as u64).to_le_bytes());
+                    write(bytes);
+                }
+            },
+        });
+    }
+    Ok(inputs)
+}
+
+/// Find the string value of a `#[name = "..."]` struct attribute
+fn find_str_attr(attrs: &[Attribute], name: &str) -> Result<Option<LitStr>, Error> {
+    let attr = match attrs.iter().find(|attr| attr.path.is_ident(name)) {
+        Some(attr) => attr,
+        None => return Ok(None),
+    };
+    match attr.parse_meta()? {
+        Meta::NameValue(nv) => match nv.lit {
+            Lit::Str(s) => Ok(Some(s)),
+            lit => Err(Error::new_spanned(lit, format!("expected a string, e.g. #[{} = \"...\"]", name))),
+        },
+        meta => Err(Error::new_spanned(meta, format!("expected #[{} = \"...\"]", name))),
+    }
+}
+
+/// Read the `#[hash_version = n]` struct attribute. Version 1 (the default) concatenates the field bytes, version 2
+/// writes each field's length as a little-endian u64 before its bytes, so that moving bytes between adjacent
+/// variable-length fields always changes the hash.
+fn find_version(attrs: &[Attribute]) -> Result<u64, Error> {
+    let attr = match attrs.iter().find(|attr| attr.path.is_ident("hash_version")) {
+        Some(attr) => attr,
+        None => return Ok(1),
+    };
+    match attr.parse_meta()? {
+        Meta::NameValue(ref nv) => match &nv.lit {
+            Lit::Int(v) if v.value() == 1 || v.value() == 2 => Ok(v.value()),
+            lit => Err(Error::new_spanned(lit, "unsupported hash version, expected 1 or 2")),
+        },
+        meta => Err(Error::new_spanned(meta, "expected #[hash_version = 2]")),
+    }
+}
+
+/// Read the `#[hash_order(n)]` attribute of a field, if any
+fn field_order(field: &Field) -> Result<Option<LitInt>, Error> {
+    let attr = match field.attrs.iter().find(|attr| attr.path.is_ident("hash_order")) {
+        Some(attr) => attr,
+        None => return Ok(None),
+    };
+    match attr.parse_meta()? {
+        Meta::List(ref list) if list.nested.len() == 1 => match &list.nested[0] {
+            NestedMeta::Literal(Lit::Int(order)) => Ok(Some(order.clone())),
+            other => Err(Error::new(other.span(), "expected an integer, e.g. #[hash_order(0)]")),
+        },
+        meta => Err(Error::new_spanned(meta, "expected #[hash_order(n)]")),
+    }
+}
+
+/// If any hashed field pins its position with `#[hash_order(n)]`, every hashed field must do so and the positions
+/// must be exactly 0..n, so that a missing or duplicated position is caught at compile time.
+fn check_field_order(name: &Ident, fields: &[(Option<LitInt>, &Field, TokenStream)]) -> Result<(), Error> {
+    if fields.iter().all(|(order, _, _)| order.is_none()) {
+        return Ok(());
+    }
+    let mut seen = vec![false; fields.len()];
+    for (order, field, _) in fields {
+        let order = order.as_ref().ok_or_else(|| {
+            Error::new_spanned(field, "hash_order is used on this struct, so every hashed field needs #[hash_order(n)]")
+        })?;
+        let position = order.value() as usize;
+        if position >= fields.len() {
+            return Err(Error::new_spanned(
+                order,
+                format!("hash_order must be between 0 and {} (the number of hashed fields - 1)", fields.len() - 1),
+            ));
+        }
+        if seen[position] {
+            return Err(Error::new_spanned(order, format!("duplicate hash_order({})", position)));
+        }
+        seen[position] = true;
+    }
+    if let Some(missing) = seen.iter().position(|s| !s) {
+        return Err(Error::new_spanned(name, format!("missing hash_order({})", missing)));
+    }
+    Ok(())
+}
+
+/// Read the `#[hashable(...)]` attribute of a field, if any
+fn field_hashing(field: &Field) -> Result<FieldHashing, Error> {
+    let mut hashing = FieldHashing::Bytes;
+    for attr in field.attrs.iter().filter(|attr| attr.path.is_ident("hashable")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new_spanned(meta, "expected #[hashable(skip)] or #[hashable(with = \"...\")]")),
+        };
+        for nested in list.nested.iter() {
+            hashing = match nested {
+                NestedMeta::Meta(Meta::Word(word)) if word == "skip" => FieldHashing::Skip,
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.ident == "with" => match &nv.lit {
+                    Lit::Str(s) => FieldHashing::With(s.parse()?),
+                    lit => return Err(Error::new_spanned(lit, "expected a function path string")),
+                },
+                other => return Err(Error::new(other.span(), "unknown hashable attribute, expected `skip` or `with`")),
+            };
+        }
+    }
+    Ok(hashing)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The message of the compile error that deriving `Hashable` for the given struct or enum reports
+    fn diagnostic(input: &str) -> String {
+        let ast: DeriveInput = syn::parse_str(input).unwrap();
+        derive(&ast).expect_err("the derive should fail").to_string()
+    }
+
+    #[test]
+    fn diagnostics() {
+        let cases = [
+            ("struct A { a: u8 }", "Hashable requires a digest attribute, e.g. #[Digest = \"Blake256\"]"),
+            ("#[Digest = 256] struct A { a: u8 }", "expected a string, e.g. #[Digest = \"...\"]"),
+            ("#[Digest(Blake256)] struct A { a: u8 }", "expected #[Digest = \"...\"]"),
+            ("#[Digest = \"D\"] enum A { B }", "Hashable can only be derived for structs"),
+            (
+                "#[Digest = \"D\"] #[hash_label = 1] struct A { a: u8 }",
+                "expected a string, e.g. #[hash_label = \"...\"]",
+            ),
+            ("#[Digest = \"D\"] #[hash_version = 3] struct A { a: u8 }", "unsupported hash version, expected 1 or 2"),
+            ("#[Digest = \"D\"] #[hash_version(2)] struct A { a: u8 }", "expected #[hash_version = 2]"),
+            ("#[Digest = \"D\"] struct A { #[hash_order(a)] a: u8 }", "expected an integer, e.g. #[hash_order(0)]"),
+            ("#[Digest = \"D\"] struct A { #[hash_order] a: u8 }", "expected #[hash_order(n)]"),
+            (
+                "#[Digest = \"D\"] struct A { #[hash_order(0)] a: u8, b: u8 }",
+                "hash_order is used on this struct, so every hashed field needs #[hash_order(n)]",
+            ),
+            (
+                "#[Digest = \"D\"] struct A { #[hash_order(2)] a: u8, #[hash_order(0)] b: u8 }",
+                "hash_order must be between 0 and 1 (the number of hashed fields - 1)",
+            ),
+            (
+                "#[Digest = \"D\"] struct A { #[hash_order(0)] a: u8, #[hash_order(0)] b: u8 }",
+                "duplicate hash_order(0)",
+            ),
+            (
+                "#[Digest = \"D\"] struct A { #[hashable] a: u8 }",
+                "expected #[hashable(skip)] or #[hashable(with = \"...\")]",
+            ),
+            ("#[Digest = \"D\"] struct A { #[hashable(with = 1)] a: u8 }", "expected a function path string"),
+            (
+                "#[Digest = \"D\"] struct A { #[hashable(hide)] a: u8 }",
+                "unknown hashable attribute, expected `skip` or `with`",
+            ),
+        ];
+        for (input, message) in cases.iter() {
+            assert_eq!(diagnostic(input), *message, "{}", input);
+        }
+    }
+}
@@ -1,17 +1,24 @@
-#![recursion_limit = "128"]
+#![recursion_limit = "256"]
 
 extern crate proc_macro;
+#[macro_use]
 extern crate syn;
 #[macro_use]
 extern crate quote;
 
+mod byte_array;
+mod hashable;
+mod hex;
+mod mmr_leaf;
+
 use proc_macro::TokenStream;
+use syn::DeriveInput;
 
 /// This macro will produce the 4 trait implementations required for an hashable struct to be sorted
 #[proc_macro_derive(HashableOrdering)]
 pub fn derive_hashable_ordering(tokens: TokenStream) -> TokenStream {
     // Parse TokenStream into AST
-    let ast: syn::DeriveInput = syn::parse(tokens).unwrap();
+    let ast = parse_macro_input!(tokens as DeriveInput);
     let name = &ast.ident;
     let gen = quote! {
          impl Ord for #name {
@@ -33,3 +40,45 @@ pub fn derive_hashable_ordering(tokens: TokenStream) -> TokenStream {
     };
     gen.into()
 }
+
+/// This macro will produce a `tari_utilities::Hashable` implementation that feeds every field, in declaration order,
+/// into the digest named by the `#[Digest = "..."]` struct attribute. Fields are hashed through
+/// `ByteArray::as_bytes` by default. Use `#[hashable(skip)]` to leave a field out of the hash, or
+/// `#[hashable(with = "path::to::fn")]` to hash the output of `fn(&FieldType) -> impl AsRef<[u8]>` instead.
+/// An optional `#[hash_label = "..."]` struct attribute prefixes the hash input with a length-prefixed domain label,
+/// so that structurally identical types with different labels never produce the same hash. Setting
+/// `#[hash_version = 2]` length-prefixes every field; version 1 (plain concatenation) remains the default so that
+/// existing hashes do not change. Consensus types can pin the hash input order independently of declaration order
+/// with `#[hash_order(n)]` on every hashed field; a missing or duplicated position is a compile error.
+#[proc_macro_derive(Hashable, attributes(Digest, hash_label, hash_order, hash_version, hashable))]
+pub fn derive_hashable(tokens: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(tokens as DeriveInput);
+    hashable::derive(&ast).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// This macro will produce a `tari_utilities::ByteArray` implementation for a newtype wrapper such as
+/// `struct BlockHash([u8; 32])`. Fixed-size array wrappers reject input of the wrong length with
+/// `ByteArrayError::IncorrectLength`; wrappers around any other `ByteArray` type delegate to it.
+#[proc_macro_derive(ByteArray)]
+pub fn derive_byte_array(tokens: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(tokens as DeriveInput);
+    byte_array::derive(&ast).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// This macro will produce `Display`, `FromStr` and serde `Serialize`/`Deserialize` implementations for a type that
+/// implements `tari_utilities::ByteArray`. Display, FromStr and human-readable serde formats (e.g. JSON) use the hex
+/// representation; binary serde formats use the raw bytes. The deriving crate must depend on `serde`.
+#[proc_macro_derive(Hex)]
+pub fn derive_hex(tokens: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(tokens as DeriveInput);
+    hex::derive(&ast).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+/// This macro will produce both a `tari_utilities::Hashable` and a `tari_utilities::ConsensusEncoding`
+/// implementation from the same field description, so that the hash of an MMR leaf is always the digest of its
+/// canonical encoding. It accepts exactly the same attributes as `#[derive(Hashable)]` and replaces it.
+#[proc_macro_derive(MmrLeaf, attributes(Digest, hash_label, hash_order, hash_version, hashable))]
+pub fn derive_mmr_leaf(tokens: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(tokens as DeriveInput);
+    mmr_leaf::derive(&ast).unwrap_or_else(|e| e.to_compile_error()).into()
+}
@@ -0,0 +1,53 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use derive::ByteArray;
+use tari_utilities::{ByteArray, ByteArrayError};
+
+const LEN: usize = 4;
+
+#[derive(ByteArray, Debug, PartialEq)]
+struct Short([u8; LEN]);
+
+#[derive(ByteArray, Debug, PartialEq)]
+struct Wrapped(Vec<u8>);
+
+#[test]
+fn fixed_size_wrapper() {
+    let s = Short::from_bytes(&[1, 2, 3, 4]).unwrap();
+    assert_eq!(s, Short([1, 2, 3, 4]));
+    assert_eq!(s.as_bytes(), &[1, 2, 3, 4]);
+    assert_eq!(s.to_hex(), "01020304");
+    assert_eq!(Short::from_hex("0a0b0c0d").unwrap(), Short([10, 11, 12, 13]));
+    match Short::from_bytes(&[1, 2, 3]) {
+        Err(ByteArrayError::IncorrectLength) => (),
+        _ => panic!("Short input should be rejected"),
+    }
+    assert!(Short::from_bytes(&[1, 2, 3, 4, 5]).is_err());
+}
+
+#[test]
+fn delegating_wrapper() {
+    let w = Wrapped::from_bytes(&[9, 8, 7]).unwrap();
+    assert_eq!(w, Wrapped(vec![9, 8, 7]));
+    assert_eq!(w.to_vec(), vec![9, 8, 7]);
+}
@@ -0,0 +1,152 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use blake2::Blake2b;
+use derive::Hashable;
+use digest::Digest;
+use tari_utilities::Hashable;
+
+fn u64_bytes(v: &u64) -> [u8; 8] {
+    v.to_le_bytes()
+}
+
+#[derive(Hashable)]
+#[Digest = "Blake2b"]
+struct Plain {
+    a: Vec<u8>,
+    b: Vec<u8>,
+}
+
+#[derive(Hashable)]
+#[Digest = "Blake2b"]
+struct WithAttributes {
+    a: Vec<u8>,
+    #[hashable(with = "u64_bytes")]
+    value: u64,
+    #[hashable(skip)]
+    cached_hash: Option<Vec<u8>>,
+}
+
+#[derive(Hashable)]
+#[Digest = "Blake2b"]
+struct Tuple(Vec<u8>, #[hashable(skip)] u32);
+
+#[derive(Hashable)]
+#[Digest = "Blake2b"]
+#[hash_label = "Kernel"]
+struct Kernel {
+    a: Vec<u8>,
+}
+
+#[derive(Hashable)]
+#[Digest = "Blake2b"]
+#[hash_label = "Output"]
+struct Output {
+    a: Vec<u8>,
+}
+
+#[derive(Hashable)]
+#[Digest = "Blake2b"]
+#[hash_version = 2]
+struct PlainV2 {
+    a: Vec<u8>,
+    #[hashable(with = "u64_bytes")]
+    value: u64,
+    b: Vec<u8>,
+}
+
+#[derive(Hashable)]
+#[Digest = "Blake2b"]
+struct Reordered {
+    #[hash_order(1)]
+    b: Vec<u8>,
+    #[hashable(skip)]
+    cached: Vec<u8>,
+    #[hash_order(0)]
+    a: Vec<u8>,
+}
+
+fn blake2b(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Blake2b::new();
+    for part in parts {
+        hasher.input(part);
+    }
+    hasher.result().to_vec()
+}
+
+#[test]
+fn fields_are_hashed_in_order() {
+    let p = Plain { a: vec![1, 2], b: vec![3] };
+    assert_eq!(p.hash(), blake2b(&[&[1, 2], &[3]]));
+}
+
+#[test]
+fn with_and_skip() {
+    let mut w = WithAttributes { a: vec![9], value: 258, cached_hash: None };
+    let h = w.hash();
+    assert_eq!(h, blake2b(&[&[9], &258u64.to_le_bytes()]));
+    // Transient fields do not affect the hash
+    w.cached_hash = Some(h.clone());
+    assert_eq!(w.hash(), h);
+}
+
+#[test]
+fn tuple_struct() {
+    let (t1, t2) = (Tuple(vec![5], 1), Tuple(vec![5], 2));
+    assert_ne!(t1.1, t2.1);
+    assert_eq!(t1.hash(), t2.hash());
+    assert_eq!(t1.hash(), blake2b(&[&[5]]));
+}
+
+#[test]
+fn hash_label_separates_domains() {
+    let k = Kernel { a: vec![1, 2, 3] };
+    let o = Output { a: vec![1, 2, 3] };
+    assert_ne!(k.hash(), o.hash());
+    assert_ne!(k.hash(), Plain { a: vec![1, 2, 3], b: vec![] }.hash());
+    assert_eq!(k.hash(), blake2b(&[&6u64.to_le_bytes(), b"Kernel", &[1, 2, 3]]));
+}
+
+#[test]
+fn v1_field_boundaries_are_ambiguous() {
+    let p1 = Plain { a: vec![1, 2], b: vec![3] };
+    let p2 = Plain { a: vec![1], b: vec![2, 3] };
+    assert_eq!(p1.hash(), p2.hash());
+}
+
+#[test]
+fn v2_fields_are_length_prefixed() {
+    let p1 = PlainV2 { a: vec![1, 2], value: 7, b: vec![3] };
+    let p2 = PlainV2 { a: vec![1], value: 7, b: vec![2, 3] };
+    assert_ne!(p1.hash(), p2.hash());
+    assert_eq!(
+        p1.hash(),
+        blake2b(&[&2u64.to_le_bytes(), &[1, 2], &8u64.to_le_bytes(), &7u64.to_le_bytes(), &1u64.to_le_bytes(), &[3]])
+    );
+}
+
+#[test]
+fn hash_order_overrides_declaration_order() {
+    let r = Reordered { b: vec![3], cached: vec![7], a: vec![1, 2] };
+    assert_eq!(r.hash(), Plain { a: vec![1, 2], b: vec![3] }.hash());
+    assert_eq!(r.cached, vec![7]);
+}
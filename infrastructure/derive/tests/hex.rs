@@ -0,0 +1,53 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use derive::{ByteArray, Hex};
+use std::str::FromStr;
+
+#[derive(ByteArray, Hex, Debug, PartialEq)]
+struct Hash([u8; 4]);
+
+#[test]
+fn display_and_from_str() {
+    let h = Hash([0xde, 0xad, 0xbe, 0xef]);
+    assert_eq!(h.to_string(), "deadbeef");
+    assert_eq!(Hash::from_str("deadbeef").unwrap(), h);
+    assert!(Hash::from_str("deadbe").is_err());
+    assert!(Hash::from_str("deadbeeg").is_err());
+}
+
+#[test]
+fn json_uses_hex() {
+    let h = Hash([1, 2, 3, 4]);
+    let json = serde_json::to_string(&h).unwrap();
+    assert_eq!(json, "\"01020304\"");
+    assert_eq!(serde_json::from_str::<Hash>(&json).unwrap(), h);
+    assert!(serde_json::from_str::<Hash>("\"0102\"").is_err());
+}
+
+#[test]
+fn binary_uses_bytes() {
+    let h = Hash([1, 2, 3, 4]);
+    let bin = bincode::serialize(&h).unwrap();
+    assert_eq!(&bin[bin.len() - 4..], &[1, 2, 3, 4]);
+    assert_eq!(bincode::deserialize::<Hash>(&bin).unwrap(), h);
+}
@@ -0,0 +1,59 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use blake2::Blake2b;
+use derive::MmrLeaf;
+use digest::Digest;
+use tari_utilities::{ConsensusEncoding, Hashable};
+
+fn u64_bytes(v: &u64) -> [u8; 8] {
+    v.to_le_bytes()
+}
+
+#[derive(MmrLeaf)]
+#[Digest = "Blake2b"]
+#[hash_label = "Leaf"]
+#[hash_version = 2]
+struct Leaf {
+    #[hash_order(1)]
+    data: Vec<u8>,
+    #[hash_order(0)]
+    #[hashable(with = "u64_bytes")]
+    height: u64,
+    #[hashable(skip)]
+    _cache: Vec<u8>,
+}
+
+#[test]
+fn hash_is_digest_of_encoding() {
+    let leaf = Leaf { data: vec![1, 2, 3], height: 10, _cache: vec![4] };
+    let encoding = leaf.consensus_encode();
+    let mut expected = Vec::new();
+    expected.extend_from_slice(&4u64.to_le_bytes());
+    expected.extend_from_slice(b"Leaf");
+    expected.extend_from_slice(&8u64.to_le_bytes());
+    expected.extend_from_slice(&10u64.to_le_bytes());
+    expected.extend_from_slice(&3u64.to_le_bytes());
+    expected.extend_from_slice(&[1, 2, 3]);
+    assert_eq!(encoding, expected);
+    assert_eq!(leaf.hash(), Blake2b::digest(&encoding).to_vec());
+}
@@ -0,0 +1,365 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{error::MerkleMountainRangeError, merklenode::ObjectHash};
+use memmap::MmapMut;
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    path::Path,
+};
+use storage::{
+    keyvalue_store::{BatchWrite, DataStore},
+    lmdb::{LMDBBatch, LMDBBuilder, LMDBStore},
+};
+
+/// The storage of the node hashes of a Merkle Mountain Range. Nodes are only ever appended to, or truncated from, the
+/// end of the mmr, so the backend can store them by index. Backends may also support removing single nodes that can be
+/// recomputed from their children, to reclaim space.
+pub trait MmrBackend {
+    /// Returns the number of nodes in the backend
+    fn len(&self) -> usize;
+
+    /// Returns true if the backend holds no nodes
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the hash of the node at the given index, or None if there is no such node
+    fn get(&self, index: usize) -> Result<Option<ObjectHash>, MerkleMountainRangeError>;
+
+    /// Append the hash of a node to the end of the mmr
+    fn append(&mut self, hash: ObjectHash) -> Result<(), MerkleMountainRangeError>;
+
+    /// Remove all the nodes from the given index onwards. Truncating to more nodes than the backend holds does nothing.
+    fn truncate(&mut self, len: usize) -> Result<(), MerkleMountainRangeError>;
+
+    /// Remove the hash of the node at the given index, keeping the nodes after it, so that `get` returns None for it.
+    /// It returns false if the backend can not remove single nodes, in which case the node is kept.
+    fn remove(&mut self, _index: usize) -> Result<bool, MerkleMountainRangeError> {
+        Ok(false)
+    }
+}
+
+/// A backend that keeps the node hashes in memory. Nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryBackend {
+    nodes: Vec<Option<ObjectHash>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> MemoryBackend {
+        MemoryBackend { nodes: Vec::new() }
+    }
+}
+
+impl MmrBackend for MemoryBackend {
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn get(&self, index: usize) -> Result<Option<ObjectHash>, MerkleMountainRangeError> {
+        Ok(self.nodes.get(index).cloned().unwrap_or(None))
+    }
+
+    fn append(&mut self, hash: ObjectHash) -> Result<(), MerkleMountainRangeError> {
+        self.nodes.push(Some(hash));
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), MerkleMountainRangeError> {
+        self.nodes.truncate(len);
+        Ok(())
+    }
+
+    fn remove(&mut self, index: usize) -> Result<bool, MerkleMountainRangeError> {
+        if let Some(node) = self.nodes.get_mut(index) {
+            *node = None;
+        }
+        Ok(true)
+    }
+}
+
+/// The key of the node count. It can not clash with the 8 byte index keys.
+const LEN_KEY: &[u8] = b"len";
+
+/// A backend that stores the node hashes in an LMDB database, keyed by index. Changes are kept in memory until they
+/// are written to the database, along with the node count, in a single transaction, so an interrupted write leaves a
+/// consistent mmr. A backend opened with `open` writes every change straight away, while a backend opened on a shared
+/// store with `with_store` leaves it to the owner of the store to write the changes with `write_changes`, so that
+/// they can be committed in the same transaction as other data.
+pub struct LMDBBackend {
+    store: LMDBStore,
+    db_name: String,
+    len: usize,
+    // The node count in the database
+    stored_len: usize,
+    // The nodes changed since the changes were last written, or None for the nodes that were removed
+    pending: BTreeMap<usize, Option<ObjectHash>>,
+    write_through: bool,
+}
+
+impl LMDBBackend {
+    /// Open, or create, the mmr stored in the named database in the given directory. The directory must already exist
+    /// and the path must have a trailing slash. `size_mb` is the maximum size of the database.
+    pub fn open(path: &str, db_name: &str, size_mb: usize) -> Result<LMDBBackend, MerkleMountainRangeError> {
+        let store = LMDBBuilder::new().set_path(path).set_mapsize(size_mb).add_database(db_name).build()?;
+        let mut backend = LMDBBackend::with_store(&store, db_name)?;
+        backend.write_through = true;
+        Ok(backend)
+    }
+
+    /// Open the mmr stored in the named database of a store that holds other data as well. The database must have
+    /// been added to the store when it was built. Changes are only written by `write_changes`.
+    pub fn with_store(store: &LMDBStore, db_name: &str) -> Result<LMDBBackend, MerkleMountainRangeError> {
+        let mut store = store.clone();
+        store.connect(db_name)?;
+        let len = match store.get_raw(LEN_KEY)? {
+            Some(bytes) => decode_index(&bytes)?,
+            None => 0,
+        };
+        Ok(LMDBBackend {
+            store,
+            db_name: db_name.to_string(),
+            len,
+            stored_len: len,
+            pending: BTreeMap::new(),
+            write_through: false,
+        })
+    }
+
+    /// Add the changes made since the changes were last written to a batch write on the store. Once the batch has
+    /// been committed, `mark_written` must be called so that the changes are not written again.
+    pub fn write_changes<W: BatchWrite>(&self, batch: &mut W) -> Result<(), MerkleMountainRangeError> {
+        batch.connect(&self.db_name)?;
+        for index in self.len..self.stored_len {
+            batch.delete(&index_key(index))?;
+        }
+        for (index, hash) in self.pending.iter() {
+            match hash {
+                Some(hash) => batch.put_raw(&index_key(*index), hash.clone())?,
+                None => batch.delete(&index_key(*index))?,
+            }
+        }
+        batch.put_raw(LEN_KEY, index_key(self.len).to_vec())?;
+        Ok(())
+    }
+
+    /// Record that the changes added to a batch by `write_changes` have been committed
+    pub fn mark_written(&mut self) {
+        self.pending.clear();
+        self.stored_len = self.len;
+    }
+
+    // Write the changes in a transaction of their own, if the backend writes every change straight away
+    fn commit_if_write_through(&mut self) -> Result<(), MerkleMountainRangeError> {
+        if self.write_through {
+            let mut batch = LMDBBatch::new(&self.store)?;
+            self.write_changes(&mut batch)?;
+            batch.commit()?;
+            self.mark_written();
+        }
+        Ok(())
+    }
+}
+
+impl MmrBackend for LMDBBackend {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, index: usize) -> Result<Option<ObjectHash>, MerkleMountainRangeError> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        match self.pending.get(&index) {
+            Some(hash) => Ok(hash.clone()),
+            None => Ok(self.store.get_raw(&index_key(index))?),
+        }
+    }
+
+    fn append(&mut self, hash: ObjectHash) -> Result<(), MerkleMountainRangeError> {
+        self.pending.insert(self.len, Some(hash));
+        self.len += 1;
+        self.commit_if_write_through()
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), MerkleMountainRangeError> {
+        if len >= self.len {
+            return Ok(());
+        }
+        self.pending.split_off(&len);
+        self.len = len;
+        self.commit_if_write_through()
+    }
+
+    fn remove(&mut self, index: usize) -> Result<bool, MerkleMountainRangeError> {
+        if index < self.len {
+            self.pending.insert(index, None);
+            self.commit_if_write_through()?;
+        }
+        Ok(true)
+    }
+}
+
+/// The magic bytes at the start of a file of an mmap backend
+const MMAP_MAGIC: &[u8] = b"TARIMMR1";
+/// The size of the file header of an mmap backend: the magic bytes, the hash size and the node count
+const MMAP_HEADER_SIZE: usize = 24;
+/// The number of nodes the file of an mmap backend grows by when it is full
+const MMAP_GROWTH: usize = 65_536;
+
+/// A backend that stores the node hashes in a memory-mapped, append only file, so that the operating system rather
+/// than the heap holds the nodes. All hashes must have the same size. The file starts with a header holding the hash
+/// size and the node count, followed by the hashes in index order. Appended nodes are only persisted by `flush`, which
+/// writes the nodes before the node count in the header, so a crash loses the nodes appended since the last flush but
+/// never leaves a header that counts unwritten nodes. The backend is flushed when it is dropped. Every node has a fixed
+/// slot in the file, so single nodes can not be removed.
+pub struct MmapBackend {
+    file: File,
+    map: MmapMut,
+    hash_size: usize,
+    len: usize,
+    flushed_len: usize,
+}
+
+impl MmapBackend {
+    /// Open, or create, the mmr stored in the file at the given path, for hashes of `hash_size` bytes
+    pub fn open<P: AsRef<Path>>(path: P, hash_size: usize) -> Result<MmapBackend, MerkleMountainRangeError> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let is_new = file.metadata()?.len() == 0;
+        if is_new {
+            file.set_len((MMAP_HEADER_SIZE + MMAP_GROWTH * hash_size) as u64)?;
+        }
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        if map.len() < MMAP_HEADER_SIZE {
+            return Err(MerkleMountainRangeError::CorruptData);
+        }
+        if is_new {
+            map[..8].copy_from_slice(MMAP_MAGIC);
+            map[8..16].copy_from_slice(&index_key(hash_size));
+            map[16..24].copy_from_slice(&index_key(0));
+            map.flush_range(0, MMAP_HEADER_SIZE)?;
+        }
+        let len = decode_index(&map[16..24])?;
+        let end = len.checked_mul(hash_size).and_then(|size| size.checked_add(MMAP_HEADER_SIZE));
+        if &map[..8] != MMAP_MAGIC ||
+            decode_index(&map[8..16])? != hash_size ||
+            end.map_or(true, |end| end > map.len())
+        {
+            return Err(MerkleMountainRangeError::CorruptData);
+        }
+        Ok(MmapBackend { file, map, hash_size, len, flushed_len: len })
+    }
+
+    /// Write the appended nodes to the file and then update the node count in the header
+    pub fn flush(&mut self) -> Result<(), MerkleMountainRangeError> {
+        if self.len == self.flushed_len {
+            return Ok(());
+        }
+        let start = self.node_offset(self.flushed_len);
+        self.map.flush_range(start, self.node_offset(self.len) - start)?;
+        self.write_count(self.len)?;
+        self.flushed_len = self.len;
+        Ok(())
+    }
+
+    // The offset in the file of the node with the given index
+    fn node_offset(&self, index: usize) -> usize {
+        MMAP_HEADER_SIZE + index * self.hash_size
+    }
+
+    // Write the node count to the header of the file
+    fn write_count(&mut self, count: usize) -> Result<(), MerkleMountainRangeError> {
+        self.map[16..24].copy_from_slice(&index_key(count));
+        Ok(self.map.flush_range(0, MMAP_HEADER_SIZE)?)
+    }
+
+    // Grow the file so that it can hold another node, and map it again
+    fn grow(&mut self) -> Result<(), MerkleMountainRangeError> {
+        self.map.flush()?;
+        let len = self.map.len() + MMAP_GROWTH * self.hash_size;
+        self.file.set_len(len as u64)?;
+        self.map = unsafe { MmapMut::map_mut(&self.file)? };
+        Ok(())
+    }
+}
+
+impl MmrBackend for MmapBackend {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, index: usize) -> Result<Option<ObjectHash>, MerkleMountainRangeError> {
+        if index >= self.len {
+            return Ok(None);
+        }
+        let offset = self.node_offset(index);
+        Ok(Some(self.map[offset..offset + self.hash_size].to_vec()))
+    }
+
+    fn append(&mut self, hash: ObjectHash) -> Result<(), MerkleMountainRangeError> {
+        if hash.len() != self.hash_size {
+            return Err(MerkleMountainRangeError::InvalidHashSize);
+        }
+        let offset = self.node_offset(self.len);
+        if offset + self.hash_size > self.map.len() {
+            self.grow()?;
+        }
+        self.map[offset..offset + self.hash_size].copy_from_slice(&hash);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) -> Result<(), MerkleMountainRangeError> {
+        if len >= self.len {
+            return Ok(());
+        }
+        if len < self.flushed_len {
+            self.write_count(len)?;
+            self.flushed_len = len;
+        }
+        self.len = len;
+        Ok(())
+    }
+}
+
+impl Drop for MmapBackend {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Indices are stored big-endian so that the keys sort in index order
+fn index_key(index: usize) -> [u8; 8] {
+    (index as u64).to_be_bytes()
+}
+
+// Decode an index that was stored with `index_key`
+fn decode_index(bytes: &[u8]) -> Result<usize, MerkleMountainRangeError> {
+    if bytes.len() != 8 {
+        return Err(MerkleMountainRangeError::CorruptData);
+    }
+    let mut buf = [0; 8];
+    buf.copy_from_slice(bytes);
+    Ok(u64::from_be_bytes(buf) as usize)
+}
@@ -21,8 +21,41 @@
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use derive_error::Error;
+use std::io;
+use storage::keyvalue_store::DatastoreError;
 
-#[derive(Debug, Error)]
+#[derive(Debug, PartialEq, Error)]
 pub enum MerkleMountainRangeError {
-    Error, // place holder for real error
+    /// An error occurred in the underlying data store
+    #[error(msg_embedded, non_std, no_from)]
+    DatastoreError(String),
+    /// An error occurred reading or writing a file
+    #[error(msg_embedded, non_std, no_from)]
+    IoError(String),
+    /// A node that should be in the backend is missing
+    MissingNode,
+    /// Data read from the backend could not be decoded
+    CorruptData,
+    /// The leaf index would not fit in the bitmap of deleted leaves
+    TooManyLeaves,
+    /// The checkpoint does not follow on from the last checkpoint of the mmr
+    InvalidCheckpoint,
+    /// The diff does not follow on from the state of the mmr
+    InvalidDiff,
+    /// The hash does not have the hash size of the backend
+    InvalidHashSize,
+    /// The merkle proof is not in its canonical form
+    InvalidProof,
+}
+
+impl From<DatastoreError> for MerkleMountainRangeError {
+    fn from(e: DatastoreError) -> Self {
+        MerkleMountainRangeError::DatastoreError(format!("MMR datastore error: {}", e))
+    }
+}
+
+impl From<io::Error> for MerkleMountainRangeError {
+    fn from(e: io::Error) -> Self {
+        MerkleMountainRangeError::IoError(format!("MMR file error: {}", e))
+    }
 }
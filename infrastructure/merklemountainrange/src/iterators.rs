@@ -0,0 +1,119 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    backend::MmrBackend,
+    error::MerkleMountainRangeError,
+    merklemountainrange::{leaf_to_node_index, MerkleMountainRange},
+    merklenode::ObjectHash,
+};
+use digest::Digest;
+use std::ops::Range;
+use tari_utilities::Hashable;
+
+/// An iterator over the hashes of a range of nodes of a Merkle Mountain Range, in index order
+pub struct NodeIter<'a, T, D, B>
+where
+    T: Hashable,
+    D: Digest,
+    B: MmrBackend,
+{
+    mmr: &'a MerkleMountainRange<T, D, B>,
+    range: Range<usize>,
+}
+
+impl<'a, T, D, B> NodeIter<'a, T, D, B>
+where
+    T: Hashable,
+    D: Digest,
+    B: MmrBackend,
+{
+    pub(crate) fn new(mmr: &'a MerkleMountainRange<T, D, B>, range: Range<usize>) -> NodeIter<'a, T, D, B> {
+        NodeIter { mmr, range }
+    }
+}
+
+impl<'a, T, D, B> Iterator for NodeIter<'a, T, D, B>
+where
+    T: Hashable,
+    D: Digest,
+    B: MmrBackend,
+{
+    type Item = Result<ObjectHash, MerkleMountainRangeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.range.next()?;
+        Some(self.mmr.get_hash(index).and_then(|hash| hash.ok_or(MerkleMountainRangeError::MissingNode)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+/// An iterator over a range of leaves of a Merkle Mountain Range, in the order they were added. Every leaf is returned
+/// as its hash and its data, which is None if the leaf was pruned.
+pub struct LeafIter<'a, T, D, B>
+where
+    T: Hashable,
+    D: Digest,
+    B: MmrBackend,
+{
+    mmr: &'a MerkleMountainRange<T, D, B>,
+    range: Range<usize>,
+}
+
+impl<'a, T, D, B> LeafIter<'a, T, D, B>
+where
+    T: Hashable,
+    D: Digest,
+    B: MmrBackend,
+{
+    pub(crate) fn new(mmr: &'a MerkleMountainRange<T, D, B>, range: Range<usize>) -> LeafIter<'a, T, D, B> {
+        LeafIter { mmr, range }
+    }
+}
+
+impl<'a, T, D, B> Iterator for LeafIter<'a, T, D, B>
+where
+    T: Hashable,
+    D: Digest,
+    B: MmrBackend,
+{
+    type Item = Result<(ObjectHash, Option<&'a T>), MerkleMountainRangeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let leaf_index = self.range.next()?;
+        let mmr = self.mmr;
+        let hash = match mmr.get_hash(leaf_to_node_index(leaf_index)) {
+            Ok(Some(hash)) => hash,
+            Ok(None) => return Some(Err(MerkleMountainRangeError::MissingNode)),
+            Err(e) => return Some(Err(e)),
+        };
+        let object = mmr.get_object(&hash);
+        Some(Ok((hash, object)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
@@ -175,9 +175,18 @@
 //! '''
 //! Now we removed 3 from the MMR
 
+#[macro_use]
+extern crate serde_derive;
+
+pub mod backend;
 pub mod error;
+pub mod iterators;
 pub mod merklemountainrange;
 pub mod merklenode;
+pub mod merkleproof;
+pub mod prunedmmr;
+pub mod segment;
+pub mod view;
 pub mod mmr {
     pub use crate::merklemountainrange::*;
 }
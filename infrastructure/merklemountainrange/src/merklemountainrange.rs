@@ -20,31 +20,83 @@
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-use crate::merklenode::{MerkleNode, ObjectHash};
+use crate::{
+    backend::{MemoryBackend, MmrBackend},
+    error::MerkleMountainRangeError,
+    iterators::{LeafIter, NodeIter},
+    merklenode::{hash_leaf, hash_node, ObjectHash},
+    merkleproof::{bag_peaks, MerkleProof},
+    segment::{boundary_siblings, MmrSegment},
+    view::MmrView,
+};
 use digest::Digest;
-use std::{collections::HashMap, marker::PhantomData};
+use rayon::prelude::*;
+use std::{cmp::min, collections::HashMap, marker::PhantomData, ops::Range};
 use tari_utilities::Hashable;
 
-pub struct MerkleMountainRange<T, D>
+pub struct MerkleMountainRange<T, D, B = MemoryBackend>
 where
     T: Hashable,
     D: Digest,
+    B: MmrBackend,
 {
-    // todo convert these to a bitmap
-    mmr: Vec<MerkleNode>,
+    mmr: B,
     data: HashMap<ObjectHash, T>,
     hasher: PhantomData<D>,
     current_peak_height: (usize, usize), // we store a tuple of peak height,index
+    // the peak hashes and the bagged root are kept up to date as nodes are added and removed
+    peaks: Vec<ObjectHash>,
+    root: ObjectHash,
 }
 
-impl<T, D> MerkleMountainRange<T, D>
+/// The space reclaimed by compacting a Merkle Mountain Range
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompactionStats {
+    /// The number of parent hashes that were removed from the backend
+    pub nodes_removed: usize,
+    /// The number of bytes of the removed parent hashes
+    pub bytes_removed: usize,
+    /// The number of leaves of which the data was discarded
+    pub objects_removed: usize,
+}
+
+impl<T, D> MerkleMountainRange<T, D, MemoryBackend>
 where
     T: Hashable,
     D: Digest,
 {
-    /// This function creates a new empty Merkle Mountain Range
-    pub fn new() -> MerkleMountainRange<T, D> {
-        MerkleMountainRange { mmr: Vec::new(), data: HashMap::new(), hasher: PhantomData, current_peak_height: (0, 0) }
+    /// This function creates a new empty Merkle Mountain Range that is kept in memory
+    pub fn new() -> MerkleMountainRange<T, D, MemoryBackend> {
+        MerkleMountainRange {
+            mmr: MemoryBackend::new(),
+            data: HashMap::new(),
+            hasher: PhantomData,
+            current_peak_height: (0, 0),
+            peaks: Vec::new(),
+            root: D::new().result().to_vec(),
+        }
+    }
+}
+
+impl<T, D, B> MerkleMountainRange<T, D, B>
+where
+    T: Hashable,
+    D: Digest,
+    B: MmrBackend,
+{
+    /// This function creates a Merkle Mountain Range on top of the nodes already in the backend. Only the node hashes
+    /// are stored in the backend, so the leaves it holds have no data, as if they were pruned.
+    pub fn with_backend(backend: B) -> Result<MerkleMountainRange<T, D, B>, MerkleMountainRangeError> {
+        let mut mmr = MerkleMountainRange {
+            mmr: backend,
+            data: HashMap::new(),
+            hasher: PhantomData,
+            current_peak_height: (0, 0),
+            peaks: Vec::new(),
+            root: Vec::new(),
+        };
+        mmr.load_peaks()?;
+        Ok(mmr)
     }
 
     /// This function returns a reference to the data stored in the mmr
@@ -59,37 +111,73 @@ where
         self.data.get_mut(hash)
     }
 
-    pub fn get_hash(&self, index: usize) -> Option<ObjectHash> {
-        if index > self.get_last_added_index() {
-            return None;
-        };
-        Some(self.mmr[index].hash.clone())
+    /// This function restores the data of a leaf node of which only the hash is known, such as a leaf of an mmr opened
+    /// with `with_backend`. It returns false, and does not restore the data, if the object does not hash to the leaf
+    /// with the given leaf index.
+    pub fn restore_object(&mut self, leaf_index: usize, object: T) -> Result<bool, MerkleMountainRangeError> {
+        if leaf_index >= self.get_leaf_count() {
+            return Ok(false);
+        }
+        let hash = object.hash();
+        if self.get_hash(leaf_to_node_index(leaf_index))?.as_ref() != Some(&hash) {
+            return Ok(false);
+        }
+        self.data.insert(hash, object);
+        Ok(true)
+    }
+
+    /// This function returns the backend holding the node hashes, for instance to write out the changes a backend
+    /// buffers. The nodes must not be changed through it.
+    pub fn get_backend_mut(&mut self) -> &mut B {
+        &mut self.mmr
+    }
+
+    /// This function discards the data of the leaf node with the given hash, while keeping its hash so that the merkle
+    /// root and proofs of the mmr can still be calculated. It returns false if there is no such data in the mmr.
+    pub fn prune_object(&mut self, hash: &ObjectHash) -> bool {
+        self.data.remove(hash).is_some()
+    }
+
+    /// This function returns the hash of the node at the given index, or None if there is no such node. The hash of a
+    /// parent that was removed by `compact` is recomputed from its children.
+    pub fn get_hash(&self, index: usize) -> Result<Option<ObjectHash>, MerkleMountainRangeError> {
+        match self.mmr.get(index)? {
+            None if index < self.mmr.len() && get_node_height(index) > 0 => {
+                let height = get_node_height(index);
+                let (left, right) = (index - (1 << height), index - 1);
+                Ok(Some(hash_node::<D>(&self.get_tree_hash(left)?, &self.get_tree_hash(right)?)))
+            },
+            hash => Ok(hash),
+        }
     }
 
     /// This function returns the hash proof tree of a given hash.
     /// If the given hash is not in the tree, the vec will be empty.
     /// The Vec will be created in form of the Lchild-Rchild-parent(Lchild)-Rchild-parent-..
     /// This pattern will be repeated until the parent is the root of the MMR
-    pub fn get_hash_proof(&self, hash: &ObjectHash) -> Vec<ObjectHash> {
+    pub fn get_hash_proof(&self, hash: &ObjectHash) -> Result<Vec<ObjectHash>, MerkleMountainRangeError> {
         let mut result = Vec::new();
         let mut i = self.mmr.len();
         for counter in 0..self.mmr.len() {
-            if self.mmr[counter].hash == *hash {
+            if self.get_node(counter)? == *hash {
                 i = counter;
                 break;
             }
         }
         if i == self.mmr.len() {
-            return result;
+            return Ok(result);
         };
-        self.get_ordered_hash_proof(i, &mut result);
+        self.get_ordered_hash_proof(i, &mut result)?;
 
         if self.current_peak_height.1 == self.get_last_added_index() {
             // we know there is no bagging as the mmr is a balanced binary tree
-            return result;
+            return Ok(result);
         }
 
-        let mut peaks = self.bag_mmr();
+        // the proof holds the node hashes of the peaks, while the bagging uses the hashes they contribute to the root
+        let mut peaks = self.peaks[1..].to_vec();
+        let mut proof_peaks =
+            find_peaks(self.mmr.len())[1..].iter().map(|index| self.get_node(*index)).collect::<Result<Vec<_>, _>>()?;
         let mut i = peaks.len();
         let mut was_on_correct_height = false;
         while i > 1 {
@@ -97,76 +185,105 @@ where
             // siblings. This loop tracks from bottom of the peaks, so we keep going up until we hit a known
             // point, we then add the missing sibling from that point
             if was_on_correct_height {
-                result.push(peaks[i - 2].clone());
-                result.push(peaks[i - 1].clone());
-            } else if peaks[i - 1] == result[result.len() - 1] {
-                result.insert(result.len() - 1, peaks[i - 2].clone());
+                result.push(proof_peaks[i - 2].clone());
+                result.push(proof_peaks[i - 1].clone());
+            } else if proof_peaks[i - 1] == result[result.len() - 1] {
+                result.insert(result.len() - 1, proof_peaks[i - 2].clone());
                 was_on_correct_height = true;
-            } else if peaks[i - 2] == result[result.len() - 1] {
-                result.push(peaks[i - 1].clone());
+            } else if proof_peaks[i - 2] == result[result.len() - 1] {
+                result.push(proof_peaks[i - 1].clone());
                 was_on_correct_height = true;
             }
 
-            let mut hasher = D::new();
-            hasher.input(&peaks[i - 2]);
-            hasher.input(&peaks[i - 1]);
-            peaks[i - 2] = hasher.result().to_vec();
+            peaks[i - 2] = hash_node::<D>(&peaks[i - 2], &peaks[i - 1]);
+            proof_peaks[i - 2] = peaks[i - 2].clone();
             i -= 1;
         }
         // lets calculate the final new peak
-        let mut hasher = D::new();
-        hasher.input(&self.mmr[self.current_peak_height.1].hash);
-        hasher.input(&peaks[0]);
+        let highest_peak = self.get_node(self.current_peak_height.1)?;
+        let root = hash_node::<D>(&highest_peak, &peaks[0]);
         if was_on_correct_height {
             // edge case, our node is in the largest peak, we have already added it
-            result.push(self.mmr[self.current_peak_height.1].hash.clone());
+            result.push(highest_peak);
         }
-        result.push(peaks[0].clone());
-        result.push(hasher.result().to_vec());
+        result.push(proof_peaks[0].clone());
+        result.push(root);
+
+        Ok(result)
+    }
 
-        result
+    /// This function returns an inclusion proof of the leaf with the given leaf index, which is the number of leaves
+    /// added to the mmr before it. It returns None if there is no such leaf.
+    pub fn get_proof(&self, leaf_index: usize) -> Result<Option<MerkleProof>, MerkleMountainRangeError> {
+        let mut index = leaf_to_node_index(leaf_index);
+        if index >= self.mmr.len() {
+            return Ok(None);
+        }
+        let mut path = Vec::new();
+        loop {
+            let sibling = sibling_index(index);
+            if is_node_right(index) {
+                path.push(self.get_tree_hash(sibling)?);
+                index += 1;
+            } else if sibling < self.mmr.len() {
+                path.push(self.get_tree_hash(sibling)?);
+                index = sibling + 1;
+            } else {
+                // we are at a peak
+                break;
+            }
+        }
+        Ok(Some(MerkleProof { mmr_size: self.mmr.len(), leaf_index, path, peaks: self.peaks.clone() }))
     }
 
     // This function is an iterative function. It will add the left node first then the right node to the provided array
     // on the index. It will return when it reaches a single highest point.
     // this function will return the index of the local peak, negating the need to search for it again.
-    fn get_ordered_hash_proof(&self, index: usize, results: &mut Vec<ObjectHash>) {
+    fn get_ordered_hash_proof(
+        &self,
+        index: usize,
+        results: &mut Vec<ObjectHash>,
+    ) -> Result<(), MerkleMountainRangeError>
+    {
         let sibling = sibling_index(index);
         let mut next_index = index + 1;
         if sibling >= self.mmr.len() {
             // we are at a peak
-            results.push(self.mmr[index].hash.clone());
-            return;
+            results.push(self.get_node(index)?);
+            return Ok(());
         }
         if sibling < index {
-            results.push(self.mmr[sibling].hash.clone());
-            results.push(self.mmr[index].hash.clone());
+            results.push(self.get_node(sibling)?);
+            results.push(self.get_node(index)?);
         } else {
-            results.push(self.mmr[index].hash.clone());
-            results.push(self.mmr[sibling].hash.clone());
+            results.push(self.get_node(index)?);
+            results.push(self.get_node(sibling)?);
             next_index = sibling + 1;
         }
-        self.get_ordered_hash_proof(next_index, results);
+        self.get_ordered_hash_proof(next_index, results)
     }
 
     /// This function will verify the provided proof. Internally it uses the get_hash_proof function to construct a
     /// similar proof. This function will return true if the proof is valid
     /// If the order does not match Lchild-Rchild-parent(Lchild)-Rchild-parent-.. the validation will fail
     /// This function will only succeed if the given hash is of height 0
-    pub fn verify_proof(&self, hashes: &Vec<ObjectHash>) -> bool {
+    pub fn verify_proof(&self, hashes: &Vec<ObjectHash>) -> Result<bool, MerkleMountainRangeError> {
         if hashes.len() == 0 {
-            return false;
+            return Ok(false);
         }
         if self.get_object(&hashes[0]).is_none() && self.get_object(&hashes[1]).is_none() {
             // we only want to search for valid object's proofs, either 0 or 1 must be a valid object
-            return false;
+            return Ok(false);
         }
-        let proof = self.get_hash_proof(&hashes[0]);
-        hashes.eq(&proof)
+        let proof = self.get_hash_proof(&hashes[0])?;
+        Ok(hashes.eq(&proof))
     }
 
     // This function calculates the peak height of the mmr
     fn calc_peak_height(&self) -> (usize, usize) {
+        if self.mmr.is_empty() {
+            return (0, 0);
+        }
         let mut height_counter = 0;
         let mmr_len = self.get_last_added_index();
         let mut index: usize = (1 << height_counter + 2) - 2;
@@ -185,61 +302,221 @@ where
         self.current_peak_height.0
     }
 
-    /// This function will return the single merkle root of the MMR.
+    /// This function will return the single merkle root of the MMR. The root of an empty MMR is the hash of no data.
+    /// The root is updated as nodes are added and removed, so this does not hash anything.
     pub fn get_merkle_root(&self) -> ObjectHash {
-        let mut peaks = self.bag_mmr();
-        let mut i = peaks.len();
-        while i > 1 {
-            // lets bag all the other peaks
-            let mut hasher = D::new();
-            hasher.input(&peaks[i - 2]);
-            hasher.input(&peaks[i - 1]);
-            peaks[i - 2] = hasher.result().to_vec();
-            i -= 1;
+        self.root.clone()
+    }
+
+    /// This function returns the index and hash of every peak of the mmr, ordered from left to right. A peak that is a
+    /// leaf is returned as its leaf-prefixed hash. Bagging the hashes with `bag_peaks` gives the merkle root.
+    pub fn peaks(&self) -> Vec<(usize, ObjectHash)> {
+        find_peaks(self.mmr.len()).into_iter().zip(self.peaks.iter().cloned()).collect()
+    }
+
+    /// This function returns a copy-on-write view of the mmr, to which leaves can be added without changing the mmr
+    pub fn view(&self) -> MmrView<'_, T, D, B> {
+        MmrView::new(self, self.mmr.len(), self.peaks.clone(), self.root.clone())
+    }
+
+    /// This function returns the number of leaf nodes in the mmr
+    pub fn get_leaf_count(&self) -> usize {
+        leaf_count(self.mmr.len())
+    }
+
+    /// This function rewinds the mmr to the state it was in when it only contained the first `leaf_count` leaf nodes.
+    /// The data of the removed leaf nodes is removed as well. Rewinding to more leaves than the mmr has does nothing.
+    pub fn rewind(&mut self, leaf_count: usize) -> Result<(), MerkleMountainRangeError> {
+        let new_len = get_node_count(leaf_count);
+        if new_len >= self.mmr.len() {
+            return Ok(());
         }
-        if peaks.len() > 0 {
-            // if there was other peaks, lets bag them with the highest peak
-            let mut hasher = D::new();
-            hasher.input(&self.mmr[self.current_peak_height.1].hash);
-            hasher.input(&peaks[0]);
-            return hasher.result().to_vec();
+        for index in new_len..self.mmr.len() {
+            if get_node_height(index) == 0 {
+                let hash = self.get_node(index)?;
+                self.data.remove(&hash);
+            }
         }
-        // there was no other peaks, return the highest peak
-        return self.mmr[self.current_peak_height.1].hash.clone();
+        self.mmr.truncate(new_len)?;
+        self.load_peaks()
+    }
+
+    /// This function checks the integrity of the mmr by re-hashing every parent node from its children, checking the
+    /// hash of every leaf that still has its data, and checking that the peaks and root match the stored nodes. It
+    /// returns the position of the first corrupt node, or None if the mmr is consistent. If the mmr ends with a node
+    /// whose parent is missing, the position of the missing parent is returned.
+    pub fn validate(&self) -> Result<Option<usize>, MerkleMountainRangeError> {
+        let len = self.mmr.len();
+        for index in 0..len {
+            let hash = match self.get_hash(index)? {
+                Some(hash) => hash,
+                None => return Ok(Some(index)),
+            };
+            let height = get_node_height(index);
+            let expected = if height == 0 {
+                self.data.get(&hash).map(Hashable::hash)
+            } else {
+                let (left, right) = (index - (1 << height), index - 1);
+                match (self.get_hash(left)?, self.get_hash(right)?) {
+                    (Some(left_hash), Some(right_hash)) => {
+                        Some(hash_node::<D>(&tree_hash::<D>(left, &left_hash), &tree_hash::<D>(right, &right_hash)))
+                    },
+                    _ => return Ok(Some(index)),
+                }
+            };
+            if expected.map_or(false, |expected| expected != hash) {
+                return Ok(Some(index));
+            }
+        }
+        if len > 0 && is_node_right(len - 1) {
+            return Ok(Some(len));
+        }
+        for (index, peak) in self.peaks() {
+            if self.get_tree_hash(index)? != peak {
+                return Ok(Some(index));
+            }
+        }
+        if self.peaks.len() != find_peaks(len).len() || bag_peaks::<D>(&self.peaks) != self.root {
+            return Ok(Some(len.saturating_sub(1)));
+        }
+        Ok(None)
+    }
+
+    /// This function compacts the part of the mmr holding the first `horizon` leaves. It discards the data of those
+    /// leaves, keeping their hashes, and removes the hashes of the parents in that part whose children are both still
+    /// stored, so that every removed hash can be recomputed with a single hash. The peaks are never removed, so the
+    /// root, and proofs for every leaf, can still be calculated. Parents are only removed if the backend supports it.
+    pub fn compact(&mut self, horizon: usize) -> Result<CompactionStats, MerkleMountainRangeError> {
+        let end = min(leaf_to_node_index(horizon), self.mmr.len());
+        let peaks = find_peaks(self.mmr.len());
+        let mut stats = CompactionStats::default();
+        let mut can_remove = true;
+        for index in 0..end {
+            let height = get_node_height(index);
+            let hash = match self.mmr.get(index)? {
+                Some(hash) => hash,
+                None => continue,
+            };
+            if height == 0 {
+                if self.data.remove(&hash).is_some() {
+                    stats.objects_removed += 1;
+                }
+                continue;
+            }
+            if !can_remove || peaks.binary_search(&index).is_ok() {
+                continue;
+            }
+            let (left, right) = (index - (1 << height), index - 1);
+            if self.mmr.get(left)?.is_none() || self.mmr.get(right)?.is_none() {
+                continue;
+            }
+            can_remove = self.mmr.remove(index)?;
+            if can_remove {
+                stats.nodes_removed += 1;
+                stats.bytes_removed += hash.len();
+            }
+        }
+        Ok(stats)
     }
 
     /// This function adds a vec of leaf nodes to the mmr.
-    pub fn add_vec(&mut self, objects: Vec<T>) {
+    pub fn add_vec(&mut self, objects: Vec<T>) -> Result<(), MerkleMountainRangeError> {
         for object in objects {
-            self.add_single(object);
+            self.push_object(object)?;
         }
+        self.root = bag_peaks::<D>(&self.peaks);
+        Ok(())
+    }
+
+    /// This function returns the hashes of all the leaf nodes in the mmr, including pruned ones, in the order they
+    /// were added
+    pub fn get_leaf_hashes(&self) -> Result<Vec<ObjectHash>, MerkleMountainRangeError> {
+        self.iter_leaves().map(|leaf| leaf.map(|(hash, _)| hash)).collect()
+    }
+
+    /// This function returns an iterator over the hashes of all the nodes in the mmr, in index order
+    pub fn iter_nodes(&self) -> NodeIter<'_, T, D, B> {
+        self.iter_nodes_range(0..self.mmr.len())
+    }
+
+    /// This function returns an iterator over the hashes of the nodes with an index in the given range. The part of
+    /// the range beyond the end of the mmr is ignored.
+    pub fn iter_nodes_range(&self, range: Range<usize>) -> NodeIter<'_, T, D, B> {
+        let end = min(range.end, self.mmr.len());
+        NodeIter::new(self, range.start..end)
+    }
+
+    /// This function returns the nodes with an index in the given range, along with the peaks of the mmr and the
+    /// sibling hashes that hash the nodes up to the peaks, as a segment that can be sent to a peer. The part of the
+    /// range beyond the end of the mmr is ignored.
+    pub fn get_segment(&self, range: Range<usize>) -> Result<MmrSegment, MerkleMountainRangeError> {
+        let start = range.start;
+        let nodes: Vec<ObjectHash> = self.iter_nodes_range(range).collect::<Result<_, _>>()?;
+        let mut proof = Vec::new();
+        for index in boundary_siblings(self.mmr.len(), start, start + nodes.len()) {
+            proof.push(self.get_hash(index)?.ok_or(MerkleMountainRangeError::MissingNode)?);
+        }
+        Ok(MmrSegment { mmr_size: self.mmr.len(), start, nodes, peaks: self.peaks.clone(), proof })
+    }
+
+    /// This function returns an iterator over all the leaves in the mmr, in the order they were added. Every leaf is
+    /// returned as its hash and its data, which is None if the leaf was pruned.
+    pub fn iter_leaves(&self) -> LeafIter<'_, T, D, B> {
+        self.iter_leaves_range(0..self.get_leaf_count())
+    }
+
+    /// This function returns an iterator over the leaves with a leaf index in the given range. The part of the range
+    /// beyond the last leaf is ignored.
+    pub fn iter_leaves_range(&self, range: Range<usize>) -> LeafIter<'_, T, D, B> {
+        let end = min(range.end, self.get_leaf_count());
+        LeafIter::new(self, range.start..end)
+    }
+
+    /// This function adds a new leaf node of which only the hash is known to the mmr. The leaf is treated as pruned.
+    pub fn add_pruned(&mut self, hash: ObjectHash) -> Result<(), MerkleMountainRangeError> {
+        self.push_leaf(hash)?;
+        self.root = bag_peaks::<D>(&self.peaks);
+        Ok(())
     }
 
     /// This function adds a new leaf node to the mmr.
-    pub fn add_single(&mut self, object: T) {
+    pub fn add_single(&mut self, object: T) -> Result<(), MerkleMountainRangeError> {
+        self.push_object(object)?;
+        self.root = bag_peaks::<D>(&self.peaks);
+        Ok(())
+    }
+
+    // This function pushes the leaf node of an object, without updating the root
+    fn push_object(&mut self, object: T) -> Result<(), MerkleMountainRangeError> {
         let node_hash = object.hash();
-        let node = MerkleNode::new(node_hash.clone());
+        self.push_leaf(node_hash.clone())?;
         self.data.insert(node_hash, object);
-        self.mmr.push(node);
-        if is_node_right(self.get_last_added_index()) {
-            self.add_single_no_leaf(self.get_last_added_index())
-        }
+        Ok(())
     }
 
-    // This function adds non leaf nodes, eg nodes that are not directly a hash of data
-    // This is iterative and will continue to up and till it hits the top, will be a future left child
-    fn add_single_no_leaf(&mut self, index: usize) {
-        let mut hasher = D::new();
-        hasher.input(&self.mmr[sibling_index(index)].hash);
-        hasher.input(&self.mmr[index].hash);
-        let new_hash = hasher.result().to_vec();
-        let new_node = MerkleNode::new(new_hash);
-        self.mmr.push(new_node);
-        if is_node_right(self.get_last_added_index()) {
-            self.add_single_no_leaf(self.get_last_added_index())
-        } else {
-            self.current_peak_height = self.calc_peak_height(); // because we have now stopped adding right nodes, we need to update the height of the mmr
+    // This function pushes a leaf node and adds the parent nodes it completes, without updating the root. A new node
+    // is a peak, and a right node merges the last two peaks into their parent, which is the next node.
+    fn push_leaf(&mut self, hash: ObjectHash) -> Result<(), MerkleMountainRangeError> {
+        self.peaks.push(hash_leaf::<D>(&hash));
+        self.mmr.append(hash)?;
+        while is_node_right(self.get_last_added_index()) {
+            let right = self.peaks.pop().expect("A right node has a left sibling peak");
+            let left = self.peaks.pop().expect("A right node has a left sibling peak");
+            let parent = hash_node::<D>(&left, &right);
+            self.mmr.append(parent.clone())?;
+            self.peaks.push(parent);
         }
+        self.current_peak_height = self.calc_peak_height();
+        Ok(())
+    }
+
+    // This function reads the peaks from the backend and calculates the root, after the mmr was opened or truncated
+    fn load_peaks(&mut self) -> Result<(), MerkleMountainRangeError> {
+        let peaks = find_peaks(self.mmr.len()).into_iter().map(|index| self.get_tree_hash(index));
+        self.peaks = peaks.collect::<Result<_, _>>()?;
+        self.root = bag_peaks::<D>(&self.peaks);
+        self.current_peak_height = self.calc_peak_height();
+        Ok(())
     }
 
     // This function is just a private function to return the index of the last added node
@@ -247,27 +524,103 @@ where
         self.mmr.len() - 1
     }
 
-    fn bag_mmr(&self) -> Vec<ObjectHash> {
-        // lets find all peaks of the mmr
-        let mut peaks = Vec::new();
-        self.find_bagging_indexes(self.current_peak_height.0 as i64, self.current_peak_height.1, &mut peaks);
-        peaks
+    // This function returns the hash of a node that must exist in the backend, or be recomputable from its children
+    fn get_node(&self, index: usize) -> Result<ObjectHash, MerkleMountainRangeError> {
+        self.get_hash(index)?.ok_or(MerkleMountainRangeError::MissingNode)
     }
 
-    fn find_bagging_indexes(&self, mut height: i64, index: usize, peaks: &mut Vec<ObjectHash>) {
-        let mut new_index = index + (1 << height + 1) - 1; // go the potential right sibling
-        while (new_index > self.get_last_added_index()) && (height > 0) {
-            // lets go down left child till we hit a valid node or we reach height 0
-            new_index = new_index - (1 << height);
-            height -= 1;
+    // This function returns the hash that a node that must exist in the backend contributes to its parent
+    fn get_tree_hash(&self, index: usize) -> Result<ObjectHash, MerkleMountainRangeError> {
+        Ok(tree_hash::<D>(index, &self.get_node(index)?))
+    }
+}
+
+impl<T, D, B> MerkleMountainRange<T, D, B>
+where
+    T: Hashable + Sync,
+    D: Digest,
+    B: MmrBackend,
+{
+    /// This function adds a vec of leaf nodes to the mmr like `add_vec`, but hashes the objects in parallel. The
+    /// leaves are still added in the order of the vec, so the resulting mmr is the same. Use it to add large batches,
+    /// such as during initial sync.
+    pub fn add_vec_parallel(&mut self, objects: Vec<T>) -> Result<(), MerkleMountainRangeError> {
+        let hashes: Vec<ObjectHash> = objects.par_iter().map(Hashable::hash).collect();
+        for (hash, object) in hashes.into_iter().zip(objects) {
+            self.push_leaf(hash.clone())?;
+            self.data.insert(hash, object);
         }
-        if (new_index <= self.get_last_added_index()) && (height >= 0) {
-            // is this a valid peak which needs to be bagged
-            peaks.push(self.mmr[new_index].hash.clone());
-            self.find_bagging_indexes(height, new_index, peaks); // lets go look for more peaks
+        self.root = bag_peaks::<D>(&self.peaks);
+        Ok(())
+    }
+}
+/// This function takes in the total number of nodes in an mmr and calculates the number of leaf nodes. The mmr is
+/// split into its perfect binary trees, from largest to smallest, and the leaves of each are counted.
+pub fn leaf_count(mut size: usize) -> usize {
+    let mut peak_size = 1;
+    while peak_size * 2 < size {
+        peak_size = peak_size * 2 + 1;
+    }
+    let mut leaves = 0;
+    while peak_size > 0 {
+        if peak_size <= size {
+            size -= peak_size;
+            leaves += (peak_size + 1) / 2;
         }
+        peak_size /= 2;
     }
+    leaves
 }
+
+/// This function takes in the total number of nodes in an mmr and returns the indices of its peaks, from left to right
+pub fn find_peaks(mut size: usize) -> Vec<usize> {
+    let mut peak_size = 1;
+    while peak_size * 2 < size {
+        peak_size = peak_size * 2 + 1;
+    }
+    let mut peaks = Vec::new();
+    let mut offset = 0;
+    while peak_size > 0 {
+        if peak_size <= size {
+            peaks.push(offset + peak_size - 1);
+            offset += peak_size;
+            size -= peak_size;
+        }
+        peak_size /= 2;
+    }
+    peaks
+}
+
+/// This function returns the hash that the node with the given index and hash contributes to its parent, or to the
+/// merkle root if it is a peak. The hash of a leaf is hashed with the leaf prefix, the hash of a parent is used as is.
+pub fn tree_hash<D: Digest>(index: usize, hash: &[u8]) -> ObjectHash {
+    if get_node_height(index) == 0 {
+        hash_leaf::<D>(hash)
+    } else {
+        hash.to_vec()
+    }
+}
+
+/// This function takes in the number of leaf nodes in an mmr and calculates the total number of nodes
+pub fn get_node_count(leaf_count: usize) -> usize {
+    2 * leaf_count - leaf_count.count_ones() as usize
+}
+
+/// This function takes in the leaf index of a leaf, which is the number of leaves added before it, and returns the
+/// index of its node in the mmr
+pub fn leaf_to_node_index(leaf_index: usize) -> usize {
+    get_node_count(leaf_index)
+}
+
+/// This function takes in the index of a node in the mmr and returns its leaf index, or None if the node is not a leaf
+pub fn node_to_leaf_index(index: usize) -> Option<usize> {
+    if get_node_height(index) == 0 {
+        Some(leaf_count(index))
+    } else {
+        None
+    }
+}
+
 /// This function takes in the index and calculates the index of the sibling.
 pub fn sibling_index(index: usize) -> usize {
     let height = get_node_height(index);
@@ -20,8 +20,37 @@
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use digest::Digest;
+
 pub type ObjectHash = Vec<u8>;
 
+/// The byte that is hashed in front of the hash of a leaf when it is hashed into its parent or bagged as a peak
+pub const LEAF_PREFIX: u8 = 0;
+/// The byte that is hashed in front of the two children of a parent node, and of the two hashes that are bagged
+/// together
+pub const NODE_PREFIX: u8 = 1;
+/// The byte that is hashed in front of a chunk of the deletion bitmap of a PrunedMmr
+pub const CHUNK_PREFIX: u8 = 2;
+
+/// This function hashes the hash of a leaf with the leaf prefix. The mmr stores the hashes of leaves as they are, and
+/// uses this hash wherever a leaf is hashed into its parent or bagged as a peak, so that a leaf can never be mistaken
+/// for a parent node.
+pub fn hash_leaf<D: Digest>(hash: &[u8]) -> ObjectHash {
+    let mut hasher = D::new();
+    hasher.input([LEAF_PREFIX]);
+    hasher.input(hash);
+    hasher.result().to_vec()
+}
+
+/// This function hashes a left and a right child into their parent, with the node prefix
+pub fn hash_node<D: Digest>(left: &[u8], right: &[u8]) -> ObjectHash {
+    let mut hasher = D::new();
+    hasher.input([NODE_PREFIX]);
+    hasher.input(left);
+    hasher.input(right);
+    hasher.result().to_vec()
+}
+
 /// This is the MerkleNode struct. This struct represents a merkle node,
 #[derive(Debug)]
 pub struct MerkleNode {
@@ -0,0 +1,245 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    error::MerkleMountainRangeError,
+    merklemountainrange::{find_peaks, get_node_count, is_node_right, leaf_count, leaf_to_node_index, sibling_index},
+    merklenode::{hash_leaf, hash_node, ObjectHash},
+};
+use digest::Digest;
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize,
+    Deserializer,
+    Serialize,
+    Serializer,
+};
+use std::fmt;
+use tari_utilities::hex::{from_hex, to_hex};
+
+/// The largest hash size a merkle proof can hold
+pub const MAX_PROOF_HASH_SIZE: usize = 64;
+/// The largest mmr a merkle proof can be made for, which bounds the number of hashes in a proof
+pub const MAX_PROOF_MMR_SIZE: usize = 1 << 48;
+/// The size of the header of an encoded proof: the mmr size, the leaf index and the hash size
+const PROOF_HEADER_SIZE: usize = 24;
+
+/// An inclusion proof of a leaf in a Merkle Mountain Range. It holds the sibling hashes on the path from the leaf up to
+/// the peak of its mountain, and the hashes of all the peaks, from which the merkle root is bagged. The proof is
+/// independent of the mmr it was made from, so that it can be verified by nodes that do not have the mmr.
+///
+/// A proof is canonical when the number of path hashes and peaks follows from the mmr size and leaf index and all its
+/// hashes have the same size. Only canonical proofs verify, and the binary encoding can only hold canonical proofs, so
+/// a proof has a single encoding. Proofs serialize as their binary encoding, or as its hex for human readable formats.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MerkleProof {
+    /// The number of nodes in the mmr the proof was made for
+    pub mmr_size: usize,
+    /// The index of the leaf, which is the number of leaves added to the mmr before it
+    pub leaf_index: usize,
+    /// The sibling hashes on the path from the leaf to its peak, starting at the sibling of the leaf. A sibling that is
+    /// a leaf is given by its leaf-prefixed hash from `hash_leaf`.
+    pub path: Vec<ObjectHash>,
+    /// The hashes of the peaks of the mmr, from left to right, as returned by `MerkleMountainRange::peaks`
+    pub peaks: Vec<ObjectHash>,
+}
+
+impl MerkleProof {
+    /// Returns true if the proof is in its canonical form
+    pub fn is_canonical(&self) -> bool {
+        let hash_size = match self.peaks.first() {
+            Some(peak) => peak.len(),
+            None => return false,
+        };
+        self.mmr_size <= MAX_PROOF_MMR_SIZE &&
+            get_node_count(leaf_count(self.mmr_size)) == self.mmr_size &&
+            self.leaf_index < leaf_count(self.mmr_size) &&
+            self.path.len() == path_length(self.mmr_size, self.leaf_index) &&
+            self.peaks.len() == find_peaks(self.mmr_size).len() &&
+            hash_size > 0 &&
+            hash_size <= MAX_PROOF_HASH_SIZE &&
+            self.path.iter().chain(self.peaks.iter()).all(|hash| hash.len() == hash_size)
+    }
+
+    /// Encode the proof into bytes that can be decoded again with `from_bytes`. The proof must be canonical.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let hash_size = self.peaks.first().map_or(0, Vec::len);
+        let mut buf = Vec::with_capacity(PROOF_HEADER_SIZE + (self.path.len() + self.peaks.len()) * hash_size);
+        for value in &[self.mmr_size, self.leaf_index, hash_size] {
+            buf.extend_from_slice(&(*value as u64).to_le_bytes());
+        }
+        for hash in self.path.iter().chain(self.peaks.iter()) {
+            buf.extend_from_slice(hash);
+        }
+        buf
+    }
+
+    /// Decode a proof written by `to_bytes`. Only canonical proofs can be decoded.
+    pub fn from_bytes(bytes: &[u8]) -> Result<MerkleProof, MerkleMountainRangeError> {
+        if bytes.len() < PROOF_HEADER_SIZE {
+            return Err(MerkleMountainRangeError::InvalidProof);
+        }
+        let mut values = [0u64; 3];
+        for (i, value) in values.iter_mut().enumerate() {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(&bytes[i * 8..i * 8 + 8]);
+            *value = u64::from_le_bytes(buf);
+        }
+        let [mmr_size, leaf_index, hash_size] = values;
+        if mmr_size > MAX_PROOF_MMR_SIZE as u64 ||
+            leaf_index >= mmr_size ||
+            hash_size == 0 ||
+            hash_size > MAX_PROOF_HASH_SIZE as u64
+        {
+            return Err(MerkleMountainRangeError::InvalidProof);
+        }
+        let (mmr_size, leaf_index, hash_size) = (mmr_size as usize, leaf_index as usize, hash_size as usize);
+        if get_node_count(leaf_count(mmr_size)) != mmr_size || leaf_index >= leaf_count(mmr_size) {
+            return Err(MerkleMountainRangeError::InvalidProof);
+        }
+        let path_length = path_length(mmr_size, leaf_index);
+        let hashes = &bytes[PROOF_HEADER_SIZE..];
+        if hashes.len() != (path_length + find_peaks(mmr_size).len()) * hash_size {
+            return Err(MerkleMountainRangeError::InvalidProof);
+        }
+        let mut hashes = hashes.chunks(hash_size).map(<[u8]>::to_vec);
+        let path = hashes.by_ref().take(path_length).collect();
+        let peaks = hashes.collect();
+        Ok(MerkleProof { mmr_size, leaf_index, path, peaks })
+    }
+
+    /// Encode the proof as the hex of its binary encoding
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.to_bytes())
+    }
+
+    /// Decode a proof from the hex of its binary encoding
+    pub fn from_hex(hex: &str) -> Result<MerkleProof, MerkleMountainRangeError> {
+        let bytes = from_hex(hex).map_err(|_| MerkleMountainRangeError::InvalidProof)?;
+        MerkleProof::from_bytes(&bytes)
+    }
+}
+
+impl Serialize for MerkleProof {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_hex())
+        } else {
+            serializer.serialize_bytes(&self.to_bytes())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for MerkleProof {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<MerkleProof, D::Error> {
+        if deserializer.is_human_readable() {
+            let hex = String::deserialize(deserializer)?;
+            MerkleProof::from_hex(&hex).map_err(de::Error::custom)
+        } else {
+            deserializer.deserialize_bytes(ProofVisitor)
+        }
+    }
+}
+
+// Decodes a proof from its binary encoding, given as bytes or as a sequence of bytes
+struct ProofVisitor;
+
+impl<'de> Visitor<'de> for ProofVisitor {
+    type Value = MerkleProof;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("the binary encoding of a merkle proof")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<MerkleProof, E> {
+        MerkleProof::from_bytes(bytes).map_err(E::custom)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<MerkleProof, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(PROOF_HEADER_SIZE));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        MerkleProof::from_bytes(&bytes).map_err(de::Error::custom)
+    }
+}
+
+/// This function verifies that the proof shows that a leaf with the given hash is in an mmr with the given merkle root.
+/// The leaf hash is the hash of the leaf data, as stored in the mmr, without the leaf prefix. Proofs that are not
+/// canonical do not verify.
+pub fn verify_proof<D: Digest>(root: &[u8], leaf_hash: &[u8], proof: &MerkleProof) -> bool {
+    if !proof.is_canonical() {
+        return false;
+    }
+    let mut index = leaf_to_node_index(proof.leaf_index);
+    let mut hash = hash_leaf::<D>(leaf_hash);
+    for sibling in proof.path.iter() {
+        if index >= proof.mmr_size {
+            return false;
+        }
+        if is_node_right(index) {
+            hash = hash_node::<D>(sibling, &hash);
+            index += 1;
+        } else {
+            hash = hash_node::<D>(&hash, sibling);
+            index = sibling_index(index) + 1;
+        }
+    }
+    let peaks = find_peaks(proof.mmr_size);
+    match peaks.iter().position(|peak| *peak == index) {
+        Some(i) if peaks.len() == proof.peaks.len() && proof.peaks[i] == hash => bag_peaks::<D>(&proof.peaks) == root,
+        _ => false,
+    }
+}
+
+/// This function bags the peak hashes, ordered from left to right, into the merkle root. Every peak is hashed with the
+/// bag of the peaks to its right, so for peaks P0, P1 and P2 the root is H(1 || P0 || H(1 || P1 || P2)), where || is
+/// plain byte concatenation, 1 is the single `NODE_PREFIX` byte and H is a single digest over the concatenated bytes.
+/// A peak that is a leaf is bagged as its leaf-prefixed hash from `hash_leaf`. The root of an mmr with a single peak is
+/// that peak, and the root of an mmr without peaks is the hash of no data. External verifiers can reproduce the root of
+/// an mmr from the hashes returned by `MerkleMountainRange::peaks` with this function.
+pub fn bag_peaks<D: Digest>(peaks: &[ObjectHash]) -> ObjectHash {
+    let mut peaks = peaks.iter().rev();
+    let last = match peaks.next() {
+        Some(last) => last.clone(),
+        None => return D::new().result().to_vec(),
+    };
+    peaks.fold(last, |bag, peak| hash_node::<D>(peak, &bag))
+}
+
+// This function returns the number of sibling hashes on the path from the leaf with the given leaf index to the peak of
+// its mountain, in an mmr with the given number of nodes that holds the leaf
+fn path_length(mmr_size: usize, leaf_index: usize) -> usize {
+    let mut index = leaf_to_node_index(leaf_index);
+    let mut length = 0;
+    loop {
+        let sibling = sibling_index(index);
+        if is_node_right(index) {
+            index += 1;
+        } else if sibling < mmr_size {
+            index = sibling + 1;
+        } else {
+            return length;
+        }
+        length += 1;
+    }
+}
@@ -0,0 +1,683 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    backend::{MemoryBackend, MmrBackend},
+    error::MerkleMountainRangeError,
+    merklemountainrange::{leaf_count, leaf_to_node_index, MerkleMountainRange},
+    merklenode::{hash_node, ObjectHash, CHUNK_PREFIX},
+    merkleproof::{bag_peaks, verify_proof, MerkleProof},
+};
+use croaring::Bitmap;
+use digest::Digest;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    marker::PhantomData,
+};
+use tari_utilities::Hashable;
+
+/// The number of leaves covered by each chunk of the deletion bitmap. The bit of a leaf is bit `i % 8` of byte `i / 8`
+/// of its chunk, where `i` is the position of the leaf in the chunk.
+pub const BITMAP_CHUNK_BITS: usize = 256;
+
+/// A Merkle Mountain Range of which leaves can be marked as deleted, as needed for the UTXO set, where spent outputs
+/// are deleted. Deleted leaves stay in the mmr, so that it remains append only, and are tracked by leaf index in a
+/// compressed bitmap instead. The bitmap is part of the merkle root of a PrunedMmr, so that the root commits to the
+/// leaves that are still unspent. The root commits to the bitmap through a binary merkle tree over its chunks of
+/// `BITMAP_CHUNK_BITS` bits, so a deletion only rehashes one chunk and its path, and a leaf is proven unspent with its
+/// chunk and that path. The data of deleted leaves can be discarded with `compact`.
+///
+/// The changes made to a PrunedMmr can be grouped into checkpoints, one per block, with `commit_checkpoint`. The
+/// checkpoints let the mmr roll backward and forward a block at a time, and be queried for the changes since a height.
+pub struct PrunedMmr<T, D, B = MemoryBackend>
+where
+    T: Hashable,
+    D: Digest,
+    B: MmrBackend,
+{
+    mmr: MerkleMountainRange<T, D, B>,
+    deleted: Bitmap,
+    bitmap_tree: BitmapTree<D>,
+    checkpoints: Vec<MmrCheckpoint>,
+    // The leaf count and the deletions since the last checkpoint
+    committed_leaf_count: usize,
+    pending_deletions: Bitmap,
+}
+
+/// The changes made to a PrunedMmr between two checkpoints, usually by a single block
+#[derive(Clone, Debug, PartialEq)]
+pub struct MmrCheckpoint {
+    /// The number of leaves in the mmr before the changes were made
+    pub leaf_count: usize,
+    /// The hashes of the leaves that were added
+    pub nodes_added: Vec<ObjectHash>,
+    /// The leaf indices of the leaves that were deleted
+    pub nodes_deleted: Bitmap,
+}
+
+/// The changes between two states of a PrunedMmr, which a node that has the older state applies to catch up with the
+/// newer state. Unlike a checkpoint, a diff may span any number of checkpoints.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MmrDiff {
+    /// The number of leaves in the mmr in the older state
+    pub leaf_count: usize,
+    /// The hashes of the leaves that were added since the older state
+    pub nodes_added: Vec<ObjectHash>,
+    /// The leaf indices of the leaves that were deleted since the older state
+    pub nodes_deleted: Bitmap,
+}
+
+/// A proof that a leaf is in a PrunedMmr and has not been deleted. It holds the inclusion proof of the leaf in the
+/// underlying mmr, and the chunk of the deletion bitmap holding the bit of the leaf with its path in the bitmap tree,
+/// which the merkle root of a PrunedMmr commits to. Its size grows with the logarithm of the number of leaves.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnspentProof {
+    /// The inclusion proof of the leaf in the underlying mmr
+    pub proof: MerkleProof,
+    /// The chunk of the deletion bitmap holding the bit of the leaf, `BITMAP_CHUNK_BITS / 8` bytes long
+    pub chunk: Vec<u8>,
+    /// The sibling hashes on the path from the chunk up to the root of the bitmap tree
+    pub chunk_path: Vec<ObjectHash>,
+}
+
+// A binary merkle tree over the hashes of the chunks of a deletion bitmap, stored level by level from the chunks up.
+// A parent is the node hash of its two children, and a node without a right sibling is carried up to the next level
+// unchanged. The shape of the tree is given by the number of chunks, which the merkle root of a PrunedMmr commits to
+// through its leaf count.
+struct BitmapTree<D> {
+    levels: Vec<Vec<ObjectHash>>,
+    hasher: PhantomData<D>,
+}
+
+impl<D> Clone for BitmapTree<D> {
+    fn clone(&self) -> BitmapTree<D> {
+        BitmapTree { levels: self.levels.clone(), hasher: PhantomData }
+    }
+}
+
+impl<D: Digest> BitmapTree<D> {
+    fn new() -> BitmapTree<D> {
+        BitmapTree { levels: vec![Vec::new()], hasher: PhantomData }
+    }
+
+    // This function returns the root of the tree, or the hash of no data if the tree has no chunks
+    fn get_root(&self) -> ObjectHash {
+        match self.levels.last().and_then(|level| level.first()) {
+            Some(root) => root.clone(),
+            None => D::new().result().to_vec(),
+        }
+    }
+
+    // This function sets the number of chunks, adding chunks with the given hash or removing chunks at the end
+    fn resize(&mut self, count: usize, hash: &[u8]) {
+        let old_count = self.levels[0].len();
+        self.levels[0].resize(count, hash.to_vec());
+        self.update(old_count.min(count.saturating_sub(1)), count);
+    }
+
+    // This function sets the hash of an existing chunk and rehashes its path to the root
+    fn set_chunk(&mut self, index: usize, hash: ObjectHash) {
+        self.levels[0][index] = hash;
+        self.update(index, index + 1);
+    }
+
+    // This function returns the sibling hashes on the path from the chunk with the given index to the root
+    fn get_path(&self, mut index: usize) -> Vec<ObjectHash> {
+        let mut path = Vec::new();
+        for level in self.levels.iter().take(self.levels.len() - 1) {
+            if let Some(sibling) = level.get(index ^ 1) {
+                path.push(sibling.clone());
+            }
+            index /= 2;
+        }
+        path
+    }
+
+    // This function rehashes the parents of the nodes in the range, level by level, after those nodes changed. The
+    // levels are resized to the number of chunks on the way up.
+    fn update(&mut self, mut start: usize, mut end: usize) {
+        let mut level = 0;
+        while self.levels[level].len() > 1 {
+            let parent_count = (self.levels[level].len() + 1) / 2;
+            if self.levels.len() == level + 1 {
+                self.levels.push(Vec::new());
+            }
+            let (children, parents) = self.levels.split_at_mut(level + 1);
+            let (children, parents) = (&children[level], &mut parents[0]);
+            parents.resize(parent_count, ObjectHash::new());
+            let (parent_start, parent_end) = (start / 2, ((end + 1) / 2).min(parent_count));
+            for (parent, hash) in parents.iter_mut().enumerate().take(parent_end).skip(parent_start) {
+                *hash = match children.get(2 * parent + 1) {
+                    Some(right) => hash_node::<D>(&children[2 * parent], right),
+                    None => children[2 * parent].clone(),
+                };
+            }
+            start = parent_start;
+            end = parent_end;
+            level += 1;
+        }
+        self.levels.truncate(level + 1);
+    }
+}
+
+impl<T, D> PrunedMmr<T, D, MemoryBackend>
+where
+    T: Hashable,
+    D: Digest,
+{
+    /// This function creates a new empty PrunedMmr that is kept in memory
+    pub fn new() -> PrunedMmr<T, D, MemoryBackend> {
+        PrunedMmr::from_mmr(MerkleMountainRange::new(), Bitmap::create())
+    }
+}
+
+impl<T, D, B> PrunedMmr<T, D, B>
+where
+    T: Hashable,
+    D: Digest,
+    B: MmrBackend,
+{
+    /// This function creates a PrunedMmr from an mmr and the bitmap of its deleted leaves. Leaves in the bitmap that
+    /// are not in the mmr are ignored. The PrunedMmr starts without checkpoints.
+    pub fn from_mmr(mmr: MerkleMountainRange<T, D, B>, mut deleted: Bitmap) -> PrunedMmr<T, D, B> {
+        let committed_leaf_count = mmr.get_leaf_count();
+        remove_deletions_from(&mut deleted, committed_leaf_count);
+        let mut pruned_mmr = PrunedMmr {
+            mmr,
+            deleted,
+            bitmap_tree: BitmapTree::new(),
+            checkpoints: Vec::new(),
+            committed_leaf_count,
+            pending_deletions: Bitmap::create(),
+        };
+        pruned_mmr.resize_bitmap_tree();
+        let chunks: BTreeSet<usize> =
+            pruned_mmr.deleted.iter().map(|leaf_index| leaf_index as usize / BITMAP_CHUNK_BITS).collect();
+        for chunk in chunks {
+            pruned_mmr.update_chunk(chunk);
+        }
+        pruned_mmr
+    }
+
+    /// This function creates a PrunedMmr from an mmr and the leaf count and deleted leaves of each of its checkpoints,
+    /// oldest first, such as an mmr opened on a backend and the checkpoints that were stored along with it. The leaves
+    /// each checkpoint added are read from the mmr, and the last checkpoint added the leaves up to the end of the mmr.
+    /// The first checkpoint must start from an empty mmr. It returns `InvalidCheckpoint` if it does not, if the leaf
+    /// counts are out of order or beyond the end of the mmr, or if a checkpoint deletes a leaf that was added after it
+    /// or was already deleted.
+    pub fn from_checkpoints(
+        mmr: MerkleMountainRange<T, D, B>,
+        checkpoints: &[(usize, Vec<u32>)],
+    ) -> Result<PrunedMmr<T, D, B>, MerkleMountainRangeError>
+    {
+        let leaf_count = mmr.get_leaf_count();
+        if checkpoints.first().map_or(leaf_count, |(start, _)| *start) != 0 {
+            return Err(MerkleMountainRangeError::InvalidCheckpoint);
+        }
+        let mut deleted = Bitmap::create();
+        let mut restored = Vec::with_capacity(checkpoints.len());
+        for (index, (start, leaves)) in checkpoints.iter().enumerate() {
+            let end = checkpoints.get(index + 1).map_or(leaf_count, |(next, _)| *next);
+            if *start > end || end > leaf_count {
+                return Err(MerkleMountainRangeError::InvalidCheckpoint);
+            }
+            if leaves.iter().any(|&leaf_index| leaf_index as usize >= end || deleted.contains(leaf_index)) {
+                return Err(MerkleMountainRangeError::InvalidCheckpoint);
+            }
+            let nodes_deleted = Bitmap::of(leaves);
+            deleted.or_inplace(&nodes_deleted);
+            let nodes_added = mmr
+                .iter_leaves_range(*start..end)
+                .map(|leaf| leaf.map(|(hash, _)| hash))
+                .collect::<Result<Vec<_>, _>>()?;
+            restored.push(MmrCheckpoint { leaf_count: *start, nodes_added, nodes_deleted });
+        }
+        let mut pruned_mmr = PrunedMmr::from_mmr(mmr, deleted);
+        pruned_mmr.checkpoints = restored;
+        Ok(pruned_mmr)
+    }
+
+    /// This function returns the underlying mmr, which holds both the deleted and the undeleted leaves
+    pub fn get_mmr(&self) -> &MerkleMountainRange<T, D, B> {
+        &self.mmr
+    }
+
+    /// This function returns the bitmap of the indices of the deleted leaves
+    pub fn get_deleted(&self) -> &Bitmap {
+        &self.deleted
+    }
+
+    /// This function adds a new leaf node to the mmr and returns its leaf index
+    pub fn push(&mut self, object: T) -> Result<usize, MerkleMountainRangeError> {
+        let leaf_index = self.mmr.get_leaf_count();
+        if leaf_index > u32::max_value() as usize {
+            return Err(MerkleMountainRangeError::TooManyLeaves);
+        }
+        self.mmr.add_single(object)?;
+        if leaf_index % BITMAP_CHUNK_BITS == 0 {
+            self.resize_bitmap_tree();
+        }
+        Ok(leaf_index)
+    }
+
+    /// This function adds a new leaf node by its hash only, as if its data had been pruned, and returns its leaf index
+    pub fn push_pruned(&mut self, hash: ObjectHash) -> Result<usize, MerkleMountainRangeError> {
+        let leaf_index = self.mmr.get_leaf_count();
+        if leaf_index > u32::max_value() as usize {
+            return Err(MerkleMountainRangeError::TooManyLeaves);
+        }
+        self.mmr.add_pruned(hash)?;
+        if leaf_index % BITMAP_CHUNK_BITS == 0 {
+            self.resize_bitmap_tree();
+        }
+        Ok(leaf_index)
+    }
+
+    /// This function returns a reference to the data of the leaf with the given hash, or None if there is no such
+    /// data in the mmr
+    pub fn get_object(&self, hash: &ObjectHash) -> Option<&T> {
+        self.mmr.get_object(hash)
+    }
+
+    /// This function restores the data of a leaf of which only the hash is known, as
+    /// `MerkleMountainRange::restore_object` does. It returns false if the object does not hash to the leaf with the
+    /// given leaf index.
+    pub fn restore_object(&mut self, leaf_index: usize, object: T) -> Result<bool, MerkleMountainRangeError> {
+        self.mmr.restore_object(leaf_index, object)
+    }
+
+    /// This function returns the backend holding the node hashes of the underlying mmr, for instance to write out the
+    /// changes a backend buffers. The nodes must not be changed through it.
+    pub fn get_backend_mut(&mut self) -> &mut B {
+        self.mmr.get_backend_mut()
+    }
+
+    /// This function discards the data of the leaf with the given hash, keeping its hash, as `compact` does for all
+    /// deleted leaves. It returns false if there is no such data in the mmr.
+    pub fn prune_object(&mut self, hash: &ObjectHash) -> bool {
+        self.mmr.prune_object(hash)
+    }
+
+    /// This function marks the leaf with the given leaf index as deleted. It returns false if there is no such leaf, or
+    /// if it was already deleted.
+    pub fn delete(&mut self, leaf_index: usize) -> bool {
+        if leaf_index >= self.mmr.get_leaf_count() || self.is_deleted(leaf_index) {
+            return false;
+        }
+        self.deleted.add(leaf_index as u32);
+        self.pending_deletions.add(leaf_index as u32);
+        self.update_chunk(leaf_index / BITMAP_CHUNK_BITS);
+        true
+    }
+
+    /// This function marks a deleted leaf as undeleted again, which is needed when the block that deleted it is
+    /// reverted. The data of a leaf that has been compacted is not restored. It returns false if the leaf was not
+    /// deleted.
+    pub fn undelete(&mut self, leaf_index: usize) -> bool {
+        if !self.is_deleted(leaf_index) {
+            return false;
+        }
+        self.deleted.remove(leaf_index as u32);
+        self.pending_deletions.remove(leaf_index as u32);
+        self.update_chunk(leaf_index / BITMAP_CHUNK_BITS);
+        true
+    }
+
+    /// This function returns true if the leaf with the given leaf index has been deleted
+    pub fn is_deleted(&self, leaf_index: usize) -> bool {
+        leaf_index <= u32::max_value() as usize && self.deleted.contains(leaf_index as u32)
+    }
+
+    /// This function returns the number of leaves that have not been deleted
+    pub fn get_unspent_count(&self) -> usize {
+        self.mmr.get_leaf_count() - self.deleted.cardinality() as usize
+    }
+
+    /// This function returns the merkle root of the PrunedMmr, which commits to the underlying mmr and to the deletion
+    /// bitmap, as calculated by `pruned_root`. The bitmap tree is kept up to date as leaves are added and deleted, so
+    /// this only hashes the roots together.
+    pub fn get_merkle_root(&self) -> ObjectHash {
+        pruned_root::<D>(&self.mmr.get_merkle_root(), self.mmr.get_leaf_count(), &self.bitmap_tree.get_root())
+    }
+
+    /// This function calculates the merkle root the PrunedMmr would have once the objects are pushed and the leaves
+    /// with the given leaf indices are deleted, without changing it. Leaf indices that are not in the PrunedMmr with
+    /// the objects pushed are ignored.
+    pub fn calculate_root(&self, objects: &[T], deletions: &[usize]) -> ObjectHash {
+        let mut view = self.mmr.view();
+        objects.iter().for_each(|object| view.add_object(object));
+        let leaf_count = view.get_leaf_count();
+        let mut chunks: BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+        for &leaf_index in deletions.iter().filter(|&&leaf_index| leaf_index < leaf_count) {
+            let chunk = leaf_index / BITMAP_CHUNK_BITS;
+            let bytes = chunks.entry(chunk).or_insert_with(|| self.get_chunk(chunk));
+            let bit = leaf_index % BITMAP_CHUNK_BITS;
+            bytes[bit / 8] |= 1 << (bit % 8);
+        }
+        let mut bitmap_tree = self.bitmap_tree.clone();
+        bitmap_tree.resize(chunk_count(leaf_count), &hash_chunk::<D>(&[0; BITMAP_CHUNK_BITS / 8]));
+        for (chunk, bytes) in chunks {
+            bitmap_tree.set_chunk(chunk, hash_chunk::<D>(&bytes));
+        }
+        pruned_root::<D>(&view.get_merkle_root(), leaf_count, &bitmap_tree.get_root())
+    }
+
+    /// This function returns a proof that the leaf with the given leaf index is in the PrunedMmr and has not been
+    /// deleted, or None if there is no such leaf or it has been deleted
+    pub fn get_unspent_proof(&self, leaf_index: usize) -> Result<Option<UnspentProof>, MerkleMountainRangeError> {
+        if self.is_deleted(leaf_index) {
+            return Ok(None);
+        }
+        let proof = match self.mmr.get_proof(leaf_index)? {
+            Some(proof) => proof,
+            None => return Ok(None),
+        };
+        let chunk = leaf_index / BITMAP_CHUNK_BITS;
+        Ok(Some(UnspentProof { proof, chunk: self.get_chunk(chunk), chunk_path: self.bitmap_tree.get_path(chunk) }))
+    }
+
+    /// This function rewinds the PrunedMmr to the state it was in when it only contained the first `leaf_count` leaf
+    /// nodes. Deletions of the removed leaves are discarded, deletions of the remaining leaves are kept. Use
+    /// `rewind_checkpoints` instead to undo whole checkpoints, including their deletions.
+    pub fn rewind(&mut self, leaf_count: usize) -> Result<(), MerkleMountainRangeError> {
+        self.mmr.rewind(leaf_count)?;
+        let leaf_count = self.mmr.get_leaf_count();
+        // checkpoints that are no longer complete are discarded, and the changes they still hold become uncommitted
+        while self.committed_leaf_count > leaf_count {
+            match self.checkpoints.pop() {
+                Some(checkpoint) => {
+                    self.pending_deletions.or_inplace(&checkpoint.nodes_deleted);
+                    self.committed_leaf_count = checkpoint.leaf_count;
+                },
+                None => self.committed_leaf_count = leaf_count,
+            }
+        }
+        remove_deletions_from(&mut self.deleted, leaf_count);
+        remove_deletions_from(&mut self.pending_deletions, leaf_count);
+        self.resize_bitmap_tree();
+        Ok(())
+    }
+
+    /// This function records the leaves added and deleted since the last checkpoint as a new checkpoint
+    pub fn commit_checkpoint(&mut self) -> Result<(), MerkleMountainRangeError> {
+        let leaf_count = self.mmr.get_leaf_count();
+        let nodes_added = (self.committed_leaf_count..leaf_count)
+            .map(|leaf_index| self.get_leaf_hash(leaf_index))
+            .collect::<Result<Vec<_>, _>>()?;
+        let nodes_deleted = std::mem::replace(&mut self.pending_deletions, Bitmap::create());
+        self.checkpoints.push(MmrCheckpoint { leaf_count: self.committed_leaf_count, nodes_added, nodes_deleted });
+        self.committed_leaf_count = leaf_count;
+        Ok(())
+    }
+
+    /// This function returns the number of checkpoints
+    pub fn get_checkpoint_count(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// This function returns the checkpoint at the given height, or None if there is no such checkpoint
+    pub fn get_checkpoint(&self, height: usize) -> Option<&MmrCheckpoint> {
+        self.checkpoints.get(height)
+    }
+
+    /// This function returns the checkpoints from the given height onwards, which hold the changes a node that has the
+    /// mmr up to that height needs to catch up
+    pub fn get_checkpoints_since(&self, height: usize) -> &[MmrCheckpoint] {
+        &self.checkpoints[height.min(self.checkpoints.len())..]
+    }
+
+    /// This function rolls the PrunedMmr back to the given number of checkpoints. The changes since the last
+    /// checkpoint are discarded, the leaves added by the removed checkpoints are removed and the leaves they deleted
+    /// are undeleted. It returns the removed checkpoints, oldest first.
+    pub fn rewind_checkpoints(&mut self, count: usize) -> Result<Vec<MmrCheckpoint>, MerkleMountainRangeError> {
+        let pending = std::mem::replace(&mut self.pending_deletions, Bitmap::create());
+        let mut undeleted = pending.clone();
+        for leaf_index in pending.iter() {
+            self.deleted.remove(leaf_index);
+        }
+        self.mmr.rewind(self.committed_leaf_count)?;
+        let mut removed = Vec::new();
+        while self.checkpoints.len() > count {
+            let checkpoint = self.checkpoints.pop().expect("There are more checkpoints than the count");
+            self.mmr.rewind(checkpoint.leaf_count)?;
+            for leaf_index in checkpoint.nodes_deleted.iter() {
+                self.deleted.remove(leaf_index);
+            }
+            undeleted.or_inplace(&checkpoint.nodes_deleted);
+            self.committed_leaf_count = checkpoint.leaf_count;
+            removed.push(checkpoint);
+        }
+        remove_deletions_from(&mut self.deleted, self.committed_leaf_count);
+        self.resize_bitmap_tree();
+        let chunks: BTreeSet<usize> = undeleted
+            .iter()
+            .map(|leaf_index| leaf_index as usize)
+            .filter(|leaf_index| *leaf_index < self.committed_leaf_count)
+            .map(|leaf_index| leaf_index / BITMAP_CHUNK_BITS)
+            .collect();
+        for chunk in chunks {
+            self.update_chunk(chunk);
+        }
+        removed.reverse();
+        Ok(removed)
+    }
+
+    /// This function rolls the PrunedMmr forward by applying a checkpoint, received from a peer or removed with
+    /// `rewind_checkpoints`, and commits it. The added leaves only have their hashes. The checkpoint must follow on
+    /// from the last checkpoint, with no changes made since then.
+    pub fn apply_checkpoint(&mut self, checkpoint: &MmrCheckpoint) -> Result<(), MerkleMountainRangeError> {
+        if checkpoint.leaf_count != self.mmr.get_leaf_count() ||
+            self.committed_leaf_count != checkpoint.leaf_count ||
+            !self.pending_deletions.is_empty()
+        {
+            return Err(MerkleMountainRangeError::InvalidCheckpoint);
+        }
+        if !self.is_valid_change(checkpoint.leaf_count, &checkpoint.nodes_added, &checkpoint.nodes_deleted)? {
+            return Err(MerkleMountainRangeError::InvalidCheckpoint);
+        }
+        self.apply_change(&checkpoint.nodes_added, &checkpoint.nodes_deleted)?;
+        self.commit_checkpoint()
+    }
+
+    /// This function returns the changes from the state of the PrunedMmr at the given checkpoint height up to its last
+    /// checkpoint, merged into a single diff. Changes since the last checkpoint are not included. It returns None if
+    /// there is no checkpoint at that height.
+    pub fn get_diff(&self, height: usize) -> Result<Option<MmrDiff>, MerkleMountainRangeError> {
+        let leaf_count = match self.checkpoints.get(height) {
+            Some(checkpoint) => checkpoint.leaf_count,
+            None if height == self.checkpoints.len() => self.committed_leaf_count,
+            None => return Ok(None),
+        };
+        let nodes_added = (leaf_count..self.committed_leaf_count)
+            .map(|leaf_index| self.get_leaf_hash(leaf_index))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut nodes_deleted = Bitmap::create();
+        for checkpoint in self.get_checkpoints_since(height) {
+            nodes_deleted.or_inplace(&checkpoint.nodes_deleted);
+        }
+        Ok(Some(MmrDiff { leaf_count, nodes_added, nodes_deleted }))
+    }
+
+    /// This function applies a diff, received from a peer, to the PrunedMmr. The added leaves only have their hashes.
+    /// The diff must follow on from the current state of the mmr. The changes are not committed, so that they can be
+    /// committed as a checkpoint, or rolled back, along with the other changes of the catch-up.
+    pub fn apply_diff(&mut self, diff: &MmrDiff) -> Result<(), MerkleMountainRangeError> {
+        if !self.is_valid_change(diff.leaf_count, &diff.nodes_added, &diff.nodes_deleted)? {
+            return Err(MerkleMountainRangeError::InvalidDiff);
+        }
+        self.apply_change(&diff.nodes_added, &diff.nodes_deleted)
+    }
+
+    /// This function discards the data of all the deleted leaves, keeping only their hashes. It returns the number of
+    /// leaves of which data was discarded.
+    pub fn compact(&mut self) -> Result<usize, MerkleMountainRangeError> {
+        let mut count = 0;
+        for leaf_index in self.deleted.iter() {
+            let hash = self.get_leaf_hash(leaf_index as usize)?;
+            if self.mmr.prune_object(&hash) {
+                count += 1;
+            }
+        }
+        self.deleted.run_optimize();
+        Ok(count)
+    }
+
+    // This function checks that leaves added to an mmr with the given leaf count can be added to this mmr, and that the
+    // deleted leaves exist once they are added and have not been deleted yet
+    fn is_valid_change(
+        &self,
+        leaf_count: usize,
+        nodes_added: &[ObjectHash],
+        nodes_deleted: &Bitmap,
+    ) -> Result<bool, MerkleMountainRangeError>
+    {
+        if leaf_count != self.mmr.get_leaf_count() {
+            return Ok(false);
+        }
+        let leaf_count = leaf_count + nodes_added.len();
+        if leaf_count > u32::max_value() as usize + 1 {
+            return Err(MerkleMountainRangeError::TooManyLeaves);
+        }
+        let valid_deletion =
+            |leaf_index: u32| (leaf_index as usize) < leaf_count && !self.is_deleted(leaf_index as usize);
+        Ok(nodes_deleted.iter().all(valid_deletion))
+    }
+
+    // This function adds the leaves by their hashes and deletes the leaves, which must have been checked to be valid
+    fn apply_change(
+        &mut self,
+        nodes_added: &[ObjectHash],
+        nodes_deleted: &Bitmap,
+    ) -> Result<(), MerkleMountainRangeError>
+    {
+        for hash in nodes_added.iter() {
+            self.mmr.add_pruned(hash.clone())?;
+        }
+        self.resize_bitmap_tree();
+        for leaf_index in nodes_deleted.iter() {
+            self.delete(leaf_index as usize);
+        }
+        Ok(())
+    }
+
+    // This function returns the chunk of the deletion bitmap with the given index
+    fn get_chunk(&self, chunk: usize) -> Vec<u8> {
+        let mut bytes = vec![0; BITMAP_CHUNK_BITS / 8];
+        let first_leaf = chunk * BITMAP_CHUNK_BITS;
+        for bit in 0..BITMAP_CHUNK_BITS {
+            if self.is_deleted(first_leaf + bit) {
+                bytes[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        bytes
+    }
+
+    // This function rehashes the chunk of the deletion bitmap with the given index in the bitmap tree
+    fn update_chunk(&mut self, chunk: usize) {
+        let hash = hash_chunk::<D>(&self.get_chunk(chunk));
+        self.bitmap_tree.set_chunk(chunk, hash);
+    }
+
+    // This function resizes the bitmap tree to the number of leaves after leaves were added or removed. The chunks
+    // that are added are empty, and the last chunk is rehashed, as the deletions of removed leaves are discarded.
+    fn resize_bitmap_tree(&mut self) {
+        let count = chunk_count(self.mmr.get_leaf_count());
+        self.bitmap_tree.resize(count, &hash_chunk::<D>(&[0; BITMAP_CHUNK_BITS / 8]));
+        if count > 0 {
+            self.update_chunk(count - 1);
+        }
+    }
+
+    // This function returns the hash of the leaf with the given leaf index, which must exist
+    fn get_leaf_hash(&self, leaf_index: usize) -> Result<ObjectHash, MerkleMountainRangeError> {
+        self.mmr.get_hash(leaf_to_node_index(leaf_index))?.ok_or(MerkleMountainRangeError::MissingNode)
+    }
+}
+
+/// This function verifies that the proof shows that a leaf with the given hash is in a PrunedMmr with the given merkle
+/// root, and that the leaf has not been deleted from it. A proof for a leaf index that is not in the mmr, or that does
+/// not fit in the deletion bitmap, is rejected.
+pub fn verify_unspent_proof<D: Digest>(root: &[u8], leaf_hash: &[u8], proof: &UnspentProof) -> bool {
+    let leaf_index = proof.proof.leaf_index;
+    let leaf_count = leaf_count(proof.proof.mmr_size);
+    if leaf_index >= leaf_count || leaf_index > u32::max_value() as usize || proof.chunk.len() != BITMAP_CHUNK_BITS / 8
+    {
+        return false;
+    }
+    let bit = leaf_index % BITMAP_CHUNK_BITS;
+    if proof.chunk[bit / 8] & (1 << (bit % 8)) != 0 {
+        return false;
+    }
+    // the chunk is hashed up to the root of the bitmap tree, whose shape is given by the number of chunks
+    let (mut index, mut count) = (leaf_index / BITMAP_CHUNK_BITS, chunk_count(leaf_count));
+    let mut hash = hash_chunk::<D>(&proof.chunk);
+    let mut path = proof.chunk_path.iter();
+    while count > 1 {
+        if index ^ 1 < count {
+            let sibling = match path.next() {
+                Some(sibling) => sibling,
+                None => return false,
+            };
+            hash = if index % 2 == 1 { hash_node::<D>(sibling, &hash) } else { hash_node::<D>(&hash, sibling) };
+        }
+        index /= 2;
+        count = (count + 1) / 2;
+    }
+    let mmr_root = bag_peaks::<D>(&proof.proof.peaks);
+    path.next().is_none() &&
+        pruned_root::<D>(&mmr_root, leaf_count, &hash) == root &&
+        verify_proof::<D>(&mmr_root, leaf_hash, &proof.proof)
+}
+
+/// This function calculates the merkle root of a PrunedMmr from the merkle root of its underlying mmr, its number of
+/// leaves and the root of its bitmap tree. It is H(M || N || B), where M is the mmr root, N is the number of leaves as
+/// 8 little-endian bytes and B is the root of the bitmap tree. The chunks of the deletion bitmap, each hashed with the
+/// `CHUNK_PREFIX` byte in front, are the leaves of the bitmap tree, and every parent is the node hash of its two
+/// children, as in the mmr. A node without a right sibling is carried up to the next level unchanged. The root of an
+/// empty PrunedMmr is the hash of no data, like the root of an empty mmr.
+pub fn pruned_root<D: Digest>(mmr_root: &[u8], leaf_count: usize, bitmap_root: &[u8]) -> ObjectHash {
+    let mut hasher = D::new();
+    if leaf_count > 0 {
+        hasher.input(mmr_root);
+        hasher.input((leaf_count as u64).to_le_bytes());
+        hasher.input(bitmap_root);
+    }
+    hasher.result().to_vec()
+}
+
+// This function hashes a chunk of the deletion bitmap with the chunk prefix
+fn hash_chunk<D: Digest>(chunk: &[u8]) -> ObjectHash {
+    let mut hasher = D::new();
+    hasher.input([CHUNK_PREFIX]);
+    hasher.input(chunk);
+    hasher.result().to_vec()
+}
+
+// This function returns the number of chunks of the deletion bitmap of a PrunedMmr with the given number of leaves
+fn chunk_count(leaf_count: usize) -> usize {
+    (leaf_count + BITMAP_CHUNK_BITS - 1) / BITMAP_CHUNK_BITS
+}
+
+// This function removes the leaf indices from `leaf_count` onwards from the bitmap of deleted leaves
+fn remove_deletions_from(deleted: &mut Bitmap, leaf_count: usize) {
+    deleted.remove_range(leaf_count as u64..u64::from(u32::max_value()) + 1);
+}
@@ -0,0 +1,185 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    error::MerkleMountainRangeError,
+    merklemountainrange::{find_peaks, get_node_count, is_node_right, leaf_count, sibling_index, tree_hash},
+    merklenode::{hash_node, ObjectHash},
+    merkleproof::{bag_peaks, MAX_PROOF_MMR_SIZE},
+};
+use digest::Digest;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A range of consecutive nodes of a Merkle Mountain Range, along with the peaks of the mmr it was taken from, so
+/// that an mmr can be transferred in chunks. The peaks commit the segment to the merkle root. The segment also holds
+/// the hashes of the nodes just outside it that are needed to hash every node of the segment up to its peak, so that
+/// every node, including the leaves and parents at the edges of the segment, is checked against the peaks.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MmrSegment {
+    /// The number of nodes in the mmr the segment was taken from
+    pub mmr_size: usize,
+    /// The index of the first node in the segment
+    pub start: usize,
+    /// The hashes of the nodes in the segment, in index order
+    pub nodes: Vec<ObjectHash>,
+    /// The hashes of the peaks of the mmr, from left to right, as returned by `MerkleMountainRange::peaks`
+    pub peaks: Vec<ObjectHash>,
+    /// The hashes of the siblings outside the segment of the nodes hashed up to the peaks, in the order of the indices
+    /// returned by `boundary_siblings`
+    pub proof: Vec<ObjectHash>,
+}
+
+impl MmrSegment {
+    /// Returns the index after the last node in the segment, or None if it does not fit in a `usize`
+    pub fn end(&self) -> Option<usize> {
+        self.start.checked_add(self.nodes.len())
+    }
+
+    /// Returns the hash of the node with the given index, or None if the node is not in the segment
+    pub fn get_node(&self, index: usize) -> Option<&ObjectHash> {
+        index.checked_sub(self.start).and_then(|offset| self.nodes.get(offset))
+    }
+
+    /// Check that the peaks bag to the given merkle root, and that every node in the segment is in the mmr: the nodes
+    /// are hashed up to their peaks with the sibling hashes in the proof, checking every parent in the segment on the
+    /// way, and the hashes reached must be the peaks. Segments of mmrs larger than `MAX_PROOF_MMR_SIZE` are rejected.
+    pub fn verify<D: Digest>(&self, root: &[u8]) -> bool {
+        let end = match self.end() {
+            Some(end) if self.mmr_size <= MAX_PROOF_MMR_SIZE && end <= self.mmr_size => end,
+            _ => return false,
+        };
+        if get_node_count(leaf_count(self.mmr_size)) != self.mmr_size || bag_peaks::<D>(&self.peaks) != root {
+            return false;
+        }
+        let peak_indices = find_peaks(self.mmr_size);
+        let siblings = boundary_siblings(self.mmr_size, self.start, end);
+        if peak_indices.len() != self.peaks.len() || siblings.len() != self.proof.len() {
+            return false;
+        }
+        let mut nodes: BTreeMap<usize, ObjectHash> = (self.start..end).zip(self.nodes.iter().cloned()).collect();
+        nodes.extend(siblings.into_iter().zip(self.proof.iter().cloned()));
+        // A parent is hashed once its right child is reached, since every node comes after its left sibling
+        let mut next = 0;
+        while let Some((index, hash)) = nodes.range(next..).next().map(|(index, hash)| (*index, hash.clone())) {
+            next = index + 1;
+            if let Some(position) = peak_indices.iter().position(|peak| *peak == index) {
+                if tree_hash::<D>(index, &hash) != self.peaks[position] {
+                    return false;
+                }
+                continue;
+            }
+            if !is_node_right(index) {
+                continue;
+            }
+            let left = sibling_index(index);
+            let parent = match nodes.get(&left) {
+                Some(left_hash) => hash_node::<D>(&tree_hash::<D>(left, left_hash), &tree_hash::<D>(index, &hash)),
+                None => return false,
+            };
+            match nodes.get(&(index + 1)) {
+                Some(node) if *node != parent => return false,
+                Some(_) => (),
+                None => {
+                    nodes.insert(index + 1, parent);
+                },
+            }
+        }
+        true
+    }
+
+    /// Encode the segment into bytes that can be decoded again with `from_bytes`. All the hashes must have the same
+    /// size.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let hash_size = self.peaks.first().map_or(0, Vec::len);
+        let hash_count = self.nodes.len() + self.peaks.len() + self.proof.len();
+        let mut buf = Vec::with_capacity(48 + hash_count * hash_size);
+        for value in &[self.mmr_size, self.start, hash_size, self.nodes.len(), self.peaks.len(), self.proof.len()] {
+            buf.extend_from_slice(&(*value as u64).to_le_bytes());
+        }
+        for hash in self.nodes.iter().chain(self.peaks.iter()).chain(self.proof.iter()) {
+            buf.extend_from_slice(hash);
+        }
+        buf
+    }
+
+    /// Decode a segment written by `to_bytes`. The mmr size is bounded by `MAX_PROOF_MMR_SIZE` and the segment must end
+    /// within the mmr.
+    pub fn from_bytes(bytes: &[u8]) -> Result<MmrSegment, MerkleMountainRangeError> {
+        if bytes.len() < 48 {
+            return Err(MerkleMountainRangeError::CorruptData);
+        }
+        let mut values = [0usize; 6];
+        for (i, value) in values.iter_mut().enumerate() {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(&bytes[i * 8..i * 8 + 8]);
+            *value = u64::from_le_bytes(buf) as usize;
+        }
+        let [mmr_size, start, hash_size, node_count, peak_count, proof_count] = values;
+        let end = start.checked_add(node_count);
+        if mmr_size > MAX_PROOF_MMR_SIZE || end.map_or(true, |end| end > mmr_size) {
+            return Err(MerkleMountainRangeError::CorruptData);
+        }
+        let hashes = &bytes[48..];
+        let hash_count = node_count
+            .checked_add(peak_count)
+            .and_then(|count| count.checked_add(proof_count))
+            .ok_or(MerkleMountainRangeError::CorruptData)?;
+        if hash_count.checked_mul(hash_size) != Some(hashes.len()) || (hash_size == 0 && hash_count > 0) {
+            return Err(MerkleMountainRangeError::CorruptData);
+        }
+        let mut hashes = hashes.chunks(hash_size.max(1)).map(<[u8]>::to_vec);
+        let nodes = hashes.by_ref().take(node_count).collect();
+        let peaks = hashes.by_ref().take(peak_count).collect();
+        let proof = hashes.collect();
+        Ok(MmrSegment { mmr_size, start, nodes, peaks, proof })
+    }
+}
+
+/// This function returns the indices of the nodes whose hashes a segment of the nodes from `start` up to `end` needs
+/// to hash all of its nodes up to their peaks, in the order they are needed. These are the left siblings before the
+/// segment of the nodes hashed up from its first nodes, and the right siblings after the segment of the nodes hashed
+/// up from its last nodes. The mmr size must be canonical and the segment must end within the mmr.
+pub fn boundary_siblings(mmr_size: usize, start: usize, end: usize) -> Vec<usize> {
+    let peaks = find_peaks(mmr_size);
+    let mut nodes: BTreeSet<usize> = (start..end).collect();
+    let mut siblings = Vec::new();
+    let mut next = start;
+    while let Some(index) = nodes.range(next..).next().cloned() {
+        next = index + 1;
+        if peaks.contains(&index) {
+            continue;
+        }
+        let sibling = sibling_index(index);
+        if is_node_right(index) {
+            // the left sibling is in the segment unless its whole subtree comes before the segment
+            if sibling < start {
+                siblings.push(sibling);
+            }
+            nodes.insert(index + 1);
+        } else if index + 1 >= end {
+            // the subtree of the right sibling starts after the node, so none of it is in the segment
+            siblings.push(sibling);
+            nodes.insert(sibling + 1);
+        }
+    }
+    siblings
+}
@@ -0,0 +1,121 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::{
+    backend::MmrBackend,
+    error::MerkleMountainRangeError,
+    merklemountainrange::{is_node_right, leaf_count, MerkleMountainRange},
+    merklenode::{hash_leaf, hash_node, ObjectHash},
+    merkleproof::bag_peaks,
+};
+use digest::Digest;
+use tari_utilities::Hashable;
+
+/// A copy-on-write view of a Merkle Mountain Range. Leaves added to the view are kept in the view, on top of the nodes
+/// of the mmr it borrows, which is not changed. This lets the leaves of a candidate block be applied speculatively, to
+/// calculate the merkle root or test its validity, and be discarded by dropping the view, without cloning the mmr.
+pub struct MmrView<'a, T, D, B>
+where
+    T: Hashable,
+    D: Digest,
+    B: MmrBackend,
+{
+    mmr: &'a MerkleMountainRange<T, D, B>,
+    // the number of nodes in the mmr, after which the nodes of the view follow
+    base_len: usize,
+    nodes: Vec<ObjectHash>,
+    peaks: Vec<ObjectHash>,
+    root: ObjectHash,
+}
+
+impl<'a, T, D, B> MmrView<'a, T, D, B>
+where
+    T: Hashable,
+    D: Digest,
+    B: MmrBackend,
+{
+    pub(crate) fn new(
+        mmr: &'a MerkleMountainRange<T, D, B>,
+        base_len: usize,
+        peaks: Vec<ObjectHash>,
+        root: ObjectHash,
+    ) -> MmrView<'a, T, D, B>
+    {
+        MmrView { mmr, base_len, nodes: Vec::new(), peaks, root }
+    }
+
+    /// This function returns the hash of the node at the given index, or None if there is no such node, looking in the
+    /// view before the mmr
+    pub fn get_hash(&self, index: usize) -> Result<Option<ObjectHash>, MerkleMountainRangeError> {
+        match index.checked_sub(self.base_len) {
+            Some(offset) => Ok(self.nodes.get(offset).cloned()),
+            None => self.mmr.get_hash(index),
+        }
+    }
+
+    /// This function returns the number of leaf nodes in the mmr with the leaves of the view added
+    pub fn get_leaf_count(&self) -> usize {
+        leaf_count(self.len())
+    }
+
+    /// This function returns the merkle root of the mmr with the leaves of the view added
+    pub fn get_merkle_root(&self) -> ObjectHash {
+        self.root.clone()
+    }
+
+    /// This function adds the leaf node of an object to the view. The object itself is not kept.
+    pub fn add_object(&mut self, object: &T) {
+        self.push_leaf(object.hash());
+        self.root = bag_peaks::<D>(&self.peaks);
+    }
+
+    /// This function adds a leaf node to the view by its hash
+    pub fn add_pruned(&mut self, hash: ObjectHash) {
+        self.push_leaf(hash);
+        self.root = bag_peaks::<D>(&self.peaks);
+    }
+
+    /// This function discards the leaves added to the view, which then matches the mmr again
+    pub fn reset(&mut self) {
+        self.nodes.clear();
+        self.peaks = self.mmr.peaks().into_iter().map(|(_, hash)| hash).collect();
+        self.root = self.mmr.get_merkle_root();
+    }
+
+    // This function returns the number of nodes in the mmr with the nodes of the view added
+    fn len(&self) -> usize {
+        self.base_len + self.nodes.len()
+    }
+
+    // This function pushes a leaf node and the parent nodes it completes, like the mmr does, without updating the root
+    fn push_leaf(&mut self, hash: ObjectHash) {
+        self.peaks.push(hash_leaf::<D>(&hash));
+        self.nodes.push(hash);
+        while is_node_right(self.len() - 1) {
+            let right = self.peaks.pop().expect("A right node has a left sibling peak");
+            let left = self.peaks.pop().expect("A right node has a left sibling peak");
+            let parent = hash_node::<D>(&left, &right);
+            self.nodes.push(parent.clone());
+            self.peaks.push(parent);
+        }
+    }
+}
@@ -21,15 +21,30 @@
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use crate::support::{hashvalues::HashValues, testobject::TestObject};
-use blake2::Blake2b;
-use merklemountainrange::mmr::{self, *};
-use tari_utilities::hex::*;
+use blake2::{Blake2b, Digest};
+use merklemountainrange::{
+    backend::{LMDBBackend, MemoryBackend, MmapBackend, MmrBackend},
+    merklenode::{hash_leaf, ObjectHash, LEAF_PREFIX, NODE_PREFIX},
+    merkleproof::{bag_peaks, verify_proof, MerkleProof},
+    mmr::{self, *},
+    prunedmmr::PrunedMmr,
+    segment::MmrSegment,
+};
+use std::{
+    fs,
+    io::{Seek, SeekFrom, Write},
+};
+use storage::{
+    keyvalue_store::{BatchWrite, DataStore},
+    lmdb::{LMDBBatch, LMDBBuilder},
+};
+use tari_utilities::{hex::*, Hashable};
 
 fn create_mmr(leaves: u32) -> MerkleMountainRange<TestObject<Blake2b>, Blake2b> {
     let mut mmr: MerkleMountainRange<TestObject<Blake2b>, Blake2b> = MerkleMountainRange::new();
     for i in 1..leaves + 1 {
         let object: TestObject<Blake2b> = TestObject::new(i.to_string());
-        mmr.add_single(object);
+        mmr.add_single(object).unwrap();
     }
     mmr
 }
@@ -39,15 +54,15 @@ fn create_small_mmr() {
     let mmr = create_mmr(2);
     assert_eq!(1, mmr.get_peak_height());
     let hash_values = HashValues::new();
-    let hash0 = mmr.get_hash(0).unwrap();
-    let proof = mmr.get_hash_proof(&hash0);
+    let hash0 = mmr.get_hash(0).unwrap().unwrap();
+    let proof = mmr.get_hash_proof(&hash0).unwrap();
     let mut our_proof = Vec::new();
     for i in 0..3 {
-        our_proof.push(mmr.get_hash(i).unwrap());
+        our_proof.push(mmr.get_hash(i).unwrap().unwrap());
     }
     assert_eq!(hash_values.copy_slice(0, 2), to_hex_multiple(&proof));
-    assert_eq!(mmr.verify_proof(&our_proof), true);
-    assert_eq!(mmr.get_merkle_root(), mmr.get_hash(2).unwrap())
+    assert_eq!(mmr.verify_proof(&our_proof).unwrap(), true);
+    assert_eq!(mmr.get_merkle_root(), mmr.get_hash(2).unwrap().unwrap())
 }
 
 #[test]
@@ -56,34 +71,34 @@ fn create_mmr_with_2_peaks() {
     assert_eq!(4, mmr.get_peak_height());
     let hash_values = HashValues::new();
 
-    let hash0 = mmr.get_hash(0).unwrap();
-    let proof = mmr.get_hash_proof(&hash0);
+    let hash0 = mmr.get_hash(0).unwrap().unwrap();
+    let proof = mmr.get_hash_proof(&hash0).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![0, 1, 2, 5, 6, 13, 14, 29, 30, 37, 42]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
-    assert_eq!(mmr.verify_proof(&proof), true);
+    assert_eq!(mmr.verify_proof(&proof).unwrap(), true);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(1).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(1).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![0, 1, 2, 5, 6, 13, 14, 29, 30, 37, 42]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
     // test some more proofs
-    let proof = mmr.get_hash_proof(&mmr.get_hash(6).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(6).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![6, 13, 14, 29, 30, 37, 42]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(22).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(22).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![22, 23, 24, 27, 21, 28, 14, 29, 30, 37, 42]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(26).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(26).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![25, 26, 24, 27, 21, 28, 14, 29, 30, 37, 42]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(14).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(14).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![14, 29, 30, 37, 42]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(11).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(11).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![10, 11, 9, 12, 6, 13, 14, 29, 30, 37, 42]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
@@ -96,18 +111,18 @@ fn mmr_with_3_peaks() {
     assert_eq!(4, mmr.get_peak_height());
     let mut raw = Vec::new();
     for i in 0..39 {
-        raw.push(mmr.get_hash(i).unwrap());
+        raw.push(mmr.get_hash(i).unwrap().unwrap());
     }
     let hash_values = HashValues::new();
-    let proof = mmr.get_hash_proof(&mmr.get_hash(35).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(35).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![34, 35, 33, 36, 37, 38, 30, 43, 44]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(38).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(38).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![37, 38, 30, 43, 44]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(0).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(0).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![0, 1, 2, 5, 6, 13, 14, 29, 30, 43, 44]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
@@ -120,40 +135,40 @@ fn mmr_with_4_peaks() {
     assert_eq!(4, mmr.get_peak_height());
     let mut raw = Vec::new();
     for i in 0..42 {
-        raw.push(mmr.get_hash(i).unwrap());
+        raw.push(mmr.get_hash(i).unwrap().unwrap());
     }
     let hash_values = HashValues::new();
     assert_eq!(to_hex(&mmr.get_merkle_root()), hash_values.get_value(47));
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(35).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(35).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![34, 35, 33, 36, 37, 45, 30, 46, 47]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(34).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(34).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![34, 35, 33, 36, 37, 45, 30, 46, 47]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(21).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(21).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![21, 28, 14, 29, 30, 46, 47]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(41).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(41).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![40, 41, 37, 45, 30, 46, 47]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(0).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(0).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![0, 1, 2, 5, 6, 13, 14, 29, 30, 46, 47]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(1).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(1).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![0, 1, 2, 5, 6, 13, 14, 29, 30, 46, 47]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(21).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(21).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![21, 28, 14, 29, 30, 46, 47]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 
-    let proof = mmr.get_hash_proof(&mmr.get_hash(28).unwrap());
+    let proof = mmr.get_hash_proof(&mmr.get_hash(28).unwrap().unwrap()).unwrap();
     let our_proof = hash_values.copy_from_indices(vec![21, 28, 14, 29, 30, 46, 47]);
     assert_eq!(to_hex_multiple(&proof), our_proof);
 }
@@ -163,8 +178,486 @@ fn very_large_mmr() {
     // test test only tests that it doesn't crash currently, we need to create fuzz testing to test this properly
     let mmr = create_mmr(23000);
     let _merkle_root = mmr.get_merkle_root();
-    let proof = mmr.get_hash_proof(&mmr.get_hash(1).unwrap());
-    assert_eq!(mmr.verify_proof(&proof), true);
+    let proof = mmr.get_hash_proof(&mmr.get_hash(1).unwrap().unwrap()).unwrap();
+    assert_eq!(mmr.verify_proof(&proof).unwrap(), true);
+}
+
+#[test]
+fn rewind_mmr() {
+    for leaves in 1..20 {
+        let mut mmr = create_mmr(20);
+        let removed = mmr.get_hash(mmr::get_node_count(leaves)).unwrap().unwrap();
+        mmr.rewind(leaves).unwrap();
+        let expected = create_mmr(leaves as u32);
+        assert_eq!(mmr.get_leaf_count(), leaves);
+        assert_eq!(mmr.get_peak_height(), expected.get_peak_height());
+        assert_eq!(mmr.get_merkle_root(), expected.get_merkle_root());
+        assert!(mmr.get_object(&removed).is_none());
+        mmr.add_single(TestObject::new((leaves + 1).to_string())).unwrap();
+        assert_eq!(mmr.get_merkle_root(), create_mmr(leaves as u32 + 1).get_merkle_root());
+    }
+    let mut mmr = create_mmr(5);
+    mmr.rewind(0).unwrap();
+    assert_eq!(mmr.get_leaf_count(), 0);
+    mmr.add_single(TestObject::new("1".to_string())).unwrap();
+    assert_eq!(mmr.get_merkle_root(), create_mmr(1).get_merkle_root());
+}
+
+#[test]
+fn prune_mmr() {
+    let mut mmr = create_mmr(5);
+    let root = mmr.get_merkle_root();
+    let hash = mmr.get_hash(3).unwrap().unwrap();
+    assert!(mmr.prune_object(&hash));
+    assert!(!mmr.prune_object(&hash));
+    assert!(mmr.get_object(&hash).is_none());
+    assert_eq!(mmr.get_merkle_root(), root);
+    assert_eq!(mmr.get_hash(3).unwrap().unwrap(), hash);
+
+    // A copy built from the leaf hashes alone has the same root
+    let mut copy: MerkleMountainRange<TestObject<Blake2b>, Blake2b> = MerkleMountainRange::new();
+    assert_eq!(copy.get_merkle_root(), Blake2b::digest(&[]).to_vec());
+    for hash in mmr.get_leaf_hashes().unwrap() {
+        copy.add_pruned(hash).unwrap();
+    }
+    assert_eq!(copy.get_leaf_count(), 5);
+    assert_eq!(copy.get_merkle_root(), root);
+}
+
+#[test]
+fn inclusion_proofs() {
+    for leaves in 1..24 {
+        let mmr = create_mmr(leaves);
+        let root = mmr.get_merkle_root();
+        for leaf_index in 0..leaves as usize {
+            let proof = mmr.get_proof(leaf_index).unwrap().unwrap();
+            let leaf_hash = mmr.get_hash(get_node_count(leaf_index)).unwrap().unwrap();
+            assert!(verify_proof::<Blake2b>(&root, &leaf_hash, &proof));
+            assert!(!verify_proof::<Blake2b>(&root, &[0; 64], &proof));
+            let moved = MerkleProof { leaf_index: leaf_index ^ 1, ..proof.clone() };
+            assert!(leaves == 1 || !verify_proof::<Blake2b>(&root, &leaf_hash, &moved));
+        }
+        assert!(mmr.get_proof(leaves as usize).unwrap().is_none());
+    }
+    let mmr = create_mmr(5);
+    let proof = mmr.get_proof(2).unwrap().unwrap();
+    let leaf_hash = mmr.get_hash(3).unwrap().unwrap();
+    assert!(!verify_proof::<Blake2b>(&create_mmr(6).get_merkle_root(), &leaf_hash, &proof));
+}
+
+#[test]
+fn proof_encoding() {
+    for leaves in 1..12 {
+        let mmr = create_mmr(leaves);
+        for leaf_index in 0..leaves as usize {
+            let proof = mmr.get_proof(leaf_index).unwrap().unwrap();
+            assert!(proof.is_canonical());
+            let bytes = proof.to_bytes();
+            assert_eq!(MerkleProof::from_bytes(&bytes).unwrap(), proof);
+            assert_eq!(MerkleProof::from_hex(&proof.to_hex()).unwrap(), proof);
+            assert!(MerkleProof::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+            let mut extended = bytes.clone();
+            extended.extend_from_slice(&[0; 64]);
+            assert!(MerkleProof::from_bytes(&extended).is_err());
+        }
+    }
+    let mmr = create_mmr(7);
+    let root = mmr.get_merkle_root();
+    let proof = mmr.get_proof(4).unwrap().unwrap();
+    let leaf_hash = mmr.get_hash(get_node_count(4)).unwrap().unwrap();
+    let mut padded = proof.clone();
+    padded.path.push(proof.peaks[0].clone());
+    assert!(!padded.is_canonical());
+    assert!(!verify_proof::<Blake2b>(&root, &leaf_hash, &padded));
+    let mut truncated = proof.clone();
+    truncated.peaks[1].pop();
+    assert!(!truncated.is_canonical());
+    let mut bytes = proof.to_bytes();
+    bytes[0] = 8;
+    assert!(MerkleProof::from_bytes(&bytes).is_err());
+    assert!(MerkleProof::from_hex("not hex").is_err());
+}
+
+#[test]
+fn peaks_and_bagging() {
+    let mmr = create_mmr(0);
+    assert!(mmr.peaks().is_empty());
+    assert_eq!(bag_peaks::<Blake2b>(&[]), mmr.get_merkle_root());
+    let mmr = create_mmr(7);
+    let peaks = mmr.peaks();
+    assert_eq!(peaks.iter().map(|(index, _)| *index).collect::<Vec<_>>(), vec![6, 9, 10]);
+    assert_eq!(mmr.get_hash(6).unwrap().unwrap(), peaks[0].1);
+    assert_eq!(mmr.get_hash(9).unwrap().unwrap(), peaks[1].1);
+    // The last peak is a leaf, which is bagged with the leaf prefix
+    let mut hasher = Blake2b::new();
+    hasher.input([LEAF_PREFIX]);
+    hasher.input(mmr.get_hash(10).unwrap().unwrap());
+    assert_eq!(hasher.result().to_vec(), peaks[2].1);
+    let mut hasher = Blake2b::new();
+    hasher.input([NODE_PREFIX]);
+    hasher.input(&peaks[1].1);
+    hasher.input(&peaks[2].1);
+    let right = hasher.result().to_vec();
+    let mut hasher = Blake2b::new();
+    hasher.input([NODE_PREFIX]);
+    hasher.input(&peaks[0].1);
+    hasher.input(&right);
+    assert_eq!(hasher.result().to_vec(), mmr.get_merkle_root());
+    let hashes = peaks.into_iter().map(|(_, hash)| hash).collect::<Vec<_>>();
+    assert_eq!(bag_peaks::<Blake2b>(&hashes), mmr.get_merkle_root());
+}
+
+#[test]
+fn prefixed_root_by_hand() {
+    let digest = |prefix: Option<u8>, parts: &[&[u8]]| {
+        let mut hasher = Blake2b::new();
+        if let Some(prefix) = prefix {
+            hasher.input([prefix]);
+        }
+        for part in parts {
+            hasher.input(part);
+        }
+        hasher.result().to_vec()
+    };
+    let leaves: Vec<ObjectHash> = ["1", "2", "3"].iter().map(|id| digest(None, &[id.as_bytes()])).collect();
+    let leaf = |i: usize| digest(Some(LEAF_PREFIX), &[&leaves[i]]);
+    // The first two leaves are hashed into a parent, and the third leaf is a peak bagged with its leaf prefix
+    let parent = digest(Some(NODE_PREFIX), &[&leaf(0), &leaf(1)]);
+    let root = digest(Some(NODE_PREFIX), &[&parent, &leaf(2)]);
+    let mmr = create_mmr(3);
+    assert_eq!(mmr.get_hash(0).unwrap().unwrap(), leaves[0]);
+    assert_eq!(mmr.get_hash(2).unwrap().unwrap(), parent);
+    assert_eq!(mmr.get_merkle_root(), root);
+}
+
+#[test]
+fn leaf_is_not_a_node() {
+    // The root of an mmr with two leaves is their parent
+    let mmr = create_mmr(2);
+    let root = mmr.get_merkle_root();
+    let parent = mmr.get_hash(2).unwrap().unwrap();
+    assert_eq!(root, parent);
+    // A leaf with the hash of the parent is hashed with the leaf prefix, so it does not have that root
+    let mut forged = MerkleMountainRange::<TestObject<Blake2b>, Blake2b>::new();
+    forged.add_pruned(parent.clone()).unwrap();
+    assert_ne!(forged.get_merkle_root(), root);
+    assert_eq!(forged.get_merkle_root(), hash_leaf::<Blake2b>(&parent));
+    // and no proof passes the parent off as a leaf of the mmr
+    let proof = MerkleProof { mmr_size: 1, leaf_index: 0, path: vec![], peaks: vec![parent.clone()] };
+    assert!(!verify_proof::<Blake2b>(&root, &parent, &proof));
+    let proof = MerkleProof { peaks: vec![hash_leaf::<Blake2b>(&parent)], ..proof };
+    assert!(!verify_proof::<Blake2b>(&root, &parent, &proof));
+}
+
+// The root recomputed from the stored nodes, without the cached peaks and root
+fn recompute_root<B: MmrBackend>(mmr: &MerkleMountainRange<TestObject<Blake2b>, Blake2b, B>) -> ObjectHash {
+    let nodes = mmr.iter_nodes().collect::<Result<Vec<_>, _>>().unwrap();
+    let peaks: Vec<ObjectHash> =
+        find_peaks(nodes.len()).into_iter().map(|index| tree_hash::<Blake2b>(index, &nodes[index])).collect();
+    bag_peaks::<Blake2b>(&peaks)
+}
+
+#[test]
+fn cached_root_is_current() {
+    let mut mmr = create_mmr(0);
+    for i in 1..12 {
+        mmr.add_single(TestObject::new(i.to_string())).unwrap();
+        assert_eq!(mmr.get_merkle_root(), recompute_root(&mmr));
+    }
+    mmr.rewind(5).unwrap();
+    assert_eq!(mmr.get_merkle_root(), recompute_root(&mmr));
+    assert_eq!(mmr.get_merkle_root(), create_mmr(5).get_merkle_root());
+    mmr.add_vec(vec![TestObject::new("6".to_string()), TestObject::new("7".to_string())]).unwrap();
+    assert_eq!(mmr.get_merkle_root(), recompute_root(&mmr));
+    mmr.compact(6).unwrap();
+    assert_eq!(mmr.get_merkle_root(), recompute_root(&mmr));
+    assert_eq!(mmr.get_merkle_root(), create_mmr(7).get_merkle_root());
+    mmr.add_pruned(TestObject::<Blake2b>::new("8".to_string()).hash()).unwrap();
+    assert_eq!(mmr.get_merkle_root(), recompute_root(&mmr));
+
+    let mut source = PrunedMmr::<TestObject<Blake2b>, Blake2b>::new();
+    let mut synced = PrunedMmr::<TestObject<Blake2b>, Blake2b>::new();
+    for i in 1..4 {
+        source.push(TestObject::new(i.to_string())).unwrap();
+        synced.push(TestObject::new(i.to_string())).unwrap();
+    }
+    source.commit_checkpoint().unwrap();
+    for i in 4..9 {
+        source.push(TestObject::new(i.to_string())).unwrap();
+    }
+    source.delete(1);
+    source.commit_checkpoint().unwrap();
+    synced.apply_diff(&source.get_diff(1).unwrap().unwrap()).unwrap();
+    assert_eq!(synced.get_mmr().get_merkle_root(), recompute_root(synced.get_mmr()));
+    assert_eq!(synced.get_merkle_root(), source.get_merkle_root());
+}
+
+#[test]
+fn validate_mmr() {
+    for leaves in 0..12 {
+        assert_eq!(create_mmr(leaves).validate(), Ok(None));
+    }
+    let nodes = create_mmr(7).iter_nodes().collect::<Result<Vec<_>, _>>().unwrap();
+    let corrupt = |corrupt: usize| {
+        let mut backend = MemoryBackend::new();
+        for (index, hash) in nodes.iter().enumerate() {
+            backend.append(if index == corrupt { vec![0; 64] } else { hash.clone() }).unwrap();
+        }
+        let mmr = MerkleMountainRange::<TestObject<Blake2b>, Blake2b, _>::with_backend(backend).unwrap();
+        mmr.validate().unwrap()
+    };
+    // Parents are re-hashed from their children
+    assert_eq!(corrupt(2), Some(2));
+    assert_eq!(corrupt(6), Some(6));
+    assert_eq!(corrupt(9), Some(9));
+    // A leaf without data can only be checked against its parent
+    assert_eq!(corrupt(0), Some(2));
+    assert_eq!(corrupt(4), Some(5));
+    // A node whose parent is missing
+    let mut backend = MemoryBackend::new();
+    for hash in nodes.iter().take(2) {
+        backend.append(hash.clone()).unwrap();
+    }
+    let mmr = MerkleMountainRange::<TestObject<Blake2b>, Blake2b, _>::with_backend(backend).unwrap();
+    assert_eq!(mmr.validate(), Ok(Some(2)));
+}
+
+#[test]
+fn iterate_leaves_and_nodes() {
+    let mut mmr = create_mmr(11);
+    let leaf_hashes = mmr.get_leaf_hashes().unwrap();
+    assert_eq!(leaf_hashes.len(), 11);
+    mmr.prune_object(&leaf_hashes[4]);
+    let leaves: Vec<_> = mmr.iter_leaves_range(3..20).map(Result::unwrap).collect();
+    assert_eq!(leaves.len(), 8);
+    assert_eq!(leaves[0].0, leaf_hashes[3]);
+    assert!(leaves[0].1.is_some());
+    assert!(leaves[1].1.is_none());
+
+    let nodes: Vec<_> = mmr.iter_nodes().map(Result::unwrap).collect();
+    assert_eq!(nodes.len(), get_node_count(11));
+    assert_eq!(nodes[6], mmr.get_hash(6).unwrap().unwrap());
+    assert_eq!(mmr.iter_nodes_range(15..100).count(), nodes.len() - 15);
+    assert_eq!(mmr.iter_nodes_range(30..40).count(), 0);
+}
+
+#[test]
+fn add_vec_parallel() {
+    let mut mmr = create_mmr(3);
+    mmr.add_vec_parallel((4..40).map(|i| TestObject::new(i.to_string())).collect()).unwrap();
+    let expected = create_mmr(39);
+    assert_eq!(mmr.get_merkle_root(), expected.get_merkle_root());
+    assert_eq!(mmr.get_leaf_hashes().unwrap(), expected.get_leaf_hashes().unwrap());
+    assert!(mmr.get_object(&expected.get_leaf_hashes().unwrap()[20]).is_some());
+}
+
+#[test]
+fn views() {
+    let mmr = create_mmr(5);
+    let root = mmr.get_merkle_root();
+    let mut view = mmr.view();
+    for i in 6..12 {
+        view.add_object(&TestObject::new(i.to_string()));
+    }
+    let expected = create_mmr(11);
+    assert_eq!(view.get_leaf_count(), 11);
+    assert_eq!(view.get_merkle_root(), expected.get_merkle_root());
+    assert_eq!(view.get_hash(3).unwrap(), expected.get_hash(3).unwrap());
+    assert_eq!(view.get_hash(17).unwrap(), expected.get_hash(17).unwrap());
+    assert!(view.get_hash(19).unwrap().is_none());
+    view.reset();
+    assert_eq!(view.get_merkle_root(), root);
+    // The mmr is not changed by its views
+    assert_eq!(mmr.get_leaf_count(), 5);
+    assert_eq!(mmr.get_merkle_root(), root);
+}
+
+#[test]
+fn compaction() {
+    let expected = create_mmr(11);
+    let mut mmr = create_mmr(11);
+    let stats = mmr.compact(8).unwrap();
+    assert_eq!(stats, CompactionStats { nodes_removed: 4, bytes_removed: 4 * 64, objects_removed: 8 });
+    assert_eq!(mmr.compact(8).unwrap(), CompactionStats { nodes_removed: 0, bytes_removed: 0, objects_removed: 0 });
+    // The removed parents are recomputed, and proofs can still be made for every leaf
+    assert_eq!(mmr.get_merkle_root(), expected.get_merkle_root());
+    for index in 0..get_node_count(11) {
+        assert_eq!(mmr.get_hash(index).unwrap(), expected.get_hash(index).unwrap());
+    }
+    for leaf_index in 0..11 {
+        assert_eq!(mmr.get_proof(leaf_index).unwrap(), expected.get_proof(leaf_index).unwrap());
+    }
+    assert_eq!(mmr.validate(), Ok(None));
+    mmr.rewind(6).unwrap();
+    assert_eq!(mmr.get_merkle_root(), create_mmr(6).get_merkle_root());
+}
+
+#[test]
+fn segments() {
+    let mmr = create_mmr(13);
+    let root = mmr.get_merkle_root();
+    for (start, end) in &[(0, 7), (7, 10), (10, 30), (3, 12)] {
+        let segment = mmr.get_segment(*start..*end).unwrap();
+        assert!(segment.verify::<Blake2b>(&root));
+        assert_eq!(MmrSegment::from_bytes(&segment.to_bytes()).unwrap(), segment);
+    }
+    // Every node of any segment is hashed up to its peak
+    let size = get_node_count(13);
+    for start in 0..size {
+        for end in start + 1..=size {
+            assert!(mmr.get_segment(start..end).unwrap().verify::<Blake2b>(&root));
+        }
+    }
+    // A tampered leaf at the edge of a segment, whose parent is not in the segment, is found
+    let mut segment = mmr.get_segment(0..2).unwrap();
+    assert!(!segment.proof.is_empty());
+    segment.nodes[0] = TestObject::<Blake2b>::new("forged".to_string()).hash();
+    assert!(!segment.verify::<Blake2b>(&root));
+    let mut segment = mmr.get_segment(3..5).unwrap();
+    segment.nodes[1] = segment.nodes[0].clone();
+    assert!(!segment.verify::<Blake2b>(&root));
+    let mut segment = mmr.get_segment(3..5).unwrap();
+    segment.proof[0] = segment.nodes[0].clone();
+    assert!(!segment.verify::<Blake2b>(&root));
+    segment.proof.pop();
+    assert!(!segment.verify::<Blake2b>(&root));
+
+    let mut segment = mmr.get_segment(0..7).unwrap();
+    assert!(!segment.verify::<Blake2b>(&create_mmr(12).get_merkle_root()));
+    segment.nodes[1] = segment.nodes[0].clone();
+    assert!(!segment.verify::<Blake2b>(&root));
+    let bytes = segment.to_bytes();
+    assert!(MmrSegment::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    let empty = create_mmr(0).get_segment(0..10).unwrap();
+    assert_eq!(MmrSegment::from_bytes(&empty.to_bytes()).unwrap(), empty);
+    // Hostile positions and sizes are rejected rather than overflowing
+    let segment = mmr.get_segment(0..7).unwrap();
+    for (mmr_size, start) in &[(usize::max_value(), 0), (segment.mmr_size, usize::max_value())] {
+        let hostile = MmrSegment { mmr_size: *mmr_size, start: *start, ..segment.clone() };
+        assert!(!hostile.verify::<Blake2b>(&root));
+        assert!(MmrSegment::from_bytes(&hostile.to_bytes()).is_err());
+    }
+}
+
+#[test]
+fn lmdb_backend() {
+    fs::create_dir("./tests/test_mmr_lmdb").unwrap();
+    {
+        let backend = LMDBBackend::open("./tests/test_mmr_lmdb/", "mmr", 16).unwrap();
+        let mut mmr = MerkleMountainRange::<TestObject<Blake2b>, Blake2b, _>::with_backend(backend).unwrap();
+        for i in 1..16 {
+            mmr.add_single(TestObject::new(i.to_string())).unwrap();
+        }
+        mmr.rewind(13).unwrap();
+    }
+    // The node hashes survive reopening the database, the data does not
+    let expected = create_mmr(13);
+    let backend = LMDBBackend::open("./tests/test_mmr_lmdb/", "mmr", 16).unwrap();
+    let mut mmr = MerkleMountainRange::<TestObject<Blake2b>, Blake2b, _>::with_backend(backend).unwrap();
+    assert_eq!(mmr.get_leaf_count(), 13);
+    assert_eq!(mmr.get_peak_height(), expected.get_peak_height());
+    assert_eq!(mmr.get_merkle_root(), expected.get_merkle_root());
+    assert!(mmr.get_object(&expected.get_hash(0).unwrap().unwrap()).is_none());
+    mmr.add_single(TestObject::new("14".to_string())).unwrap();
+    assert_eq!(mmr.get_merkle_root(), create_mmr(14).get_merkle_root());
+    assert!(fs::remove_dir_all("./tests/test_mmr_lmdb").is_ok());
+}
+
+#[test]
+fn lmdb_backend_in_shared_store() {
+    fs::create_dir("./tests/test_mmr_lmdb_batch").unwrap();
+    let mut store = LMDBBuilder::new()
+        .set_path("./tests/test_mmr_lmdb_batch/")
+        .set_mapsize(16)
+        .add_database("blocks")
+        .add_database("mmr")
+        .build()
+        .unwrap();
+    let expected = create_mmr(13);
+    {
+        let backend = LMDBBackend::with_store(&store, "mmr").unwrap();
+        let mut mmr = MerkleMountainRange::<TestObject<Blake2b>, Blake2b, _>::with_backend(backend).unwrap();
+        for i in 1..16 {
+            mmr.add_single(TestObject::new(i.to_string())).unwrap();
+        }
+        mmr.rewind(13).unwrap();
+        // Nothing is stored until the changes are committed along with the rest of a batch
+        assert!(LMDBBackend::with_store(&store, "mmr").unwrap().is_empty());
+        let mut batch = LMDBBatch::new(&store).unwrap();
+        batch.connect("blocks").unwrap();
+        batch.put_raw(b"tip", vec![13]).unwrap();
+        mmr.get_backend_mut().write_changes(&mut batch).unwrap();
+        batch.commit().unwrap();
+        mmr.get_backend_mut().mark_written();
+        // A change that is never written is lost
+        mmr.add_single(TestObject::new("14".to_string())).unwrap();
+    }
+    store.connect("blocks").unwrap();
+    assert_eq!(store.get_raw(b"tip").unwrap(), Some(vec![13]));
+    let backend = LMDBBackend::with_store(&store, "mmr").unwrap();
+    let mut mmr = MerkleMountainRange::<TestObject<Blake2b>, Blake2b, _>::with_backend(backend).unwrap();
+    assert_eq!(mmr.get_leaf_count(), 13);
+    assert_eq!(mmr.get_merkle_root(), expected.get_merkle_root());
+    // The data of a leaf is only restored if it hashes to the stored leaf
+    let hash = expected.get_hash(0).unwrap().unwrap();
+    assert!(!mmr.restore_object(0, TestObject::new("2".to_string())).unwrap());
+    assert!(!mmr.restore_object(13, TestObject::new("14".to_string())).unwrap());
+    assert!(mmr.get_object(&hash).is_none());
+    assert!(mmr.restore_object(0, TestObject::new("1".to_string())).unwrap());
+    assert!(mmr.get_object(&hash).is_some());
+    assert!(fs::remove_dir_all("./tests/test_mmr_lmdb_batch").is_ok());
+}
+
+#[test]
+fn mmap_backend() {
+    fs::create_dir("./tests/test_mmr_mmap").unwrap();
+    let path = "./tests/test_mmr_mmap/outputs.mmr";
+    {
+        let backend = MmapBackend::open(path, 64).unwrap();
+        let mut mmr = MerkleMountainRange::<TestObject<Blake2b>, Blake2b, _>::with_backend(backend).unwrap();
+        for i in 1..16 {
+            mmr.add_single(TestObject::new(i.to_string())).unwrap();
+        }
+        mmr.rewind(13).unwrap();
+    }
+    let mut backend = MmapBackend::open(path, 64).unwrap();
+    assert_eq!(backend.len(), get_node_count(13));
+    assert!(backend.append(vec![0; 32]).is_err());
+    let mmr = MerkleMountainRange::<TestObject<Blake2b>, Blake2b, _>::with_backend(backend).unwrap();
+    assert_eq!(mmr.get_merkle_root(), create_mmr(13).get_merkle_root());
+    // The file does not hold hashes of another size
+    assert!(MmapBackend::open(path, 32).is_err());
+    // A corrupt node count whose offset overflows is rejected
+    let mut file = fs::OpenOptions::new().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(16)).unwrap();
+    file.write_all(&(1u64 << 58).to_be_bytes()).unwrap();
+    drop(file);
+    assert!(MmapBackend::open(path, 64).is_err());
+    assert!(fs::remove_dir_all("./tests/test_mmr_mmap").is_ok());
+}
+
+#[test]
+fn test_leaf_count() {
+    let counts = [0, 1, 1, 2, 3, 3, 3, 4, 5, 5, 6, 7, 7, 7, 7, 8];
+    for (size, leaves) in counts.iter().enumerate() {
+        assert_eq!(mmr::leaf_count(size), *leaves);
+    }
+    for leaves in 0..100 {
+        assert_eq!(mmr::leaf_count(mmr::get_node_count(leaves)), leaves);
+    }
+}
+
+#[test]
+fn test_index_conversion() {
+    let leaves = [0, 1, 3, 4, 7, 8, 10, 11, 15, 16];
+    for (leaf_index, index) in leaves.iter().enumerate() {
+        assert_eq!(mmr::leaf_to_node_index(leaf_index), *index);
+        assert_eq!(mmr::node_to_leaf_index(*index), Some(leaf_index));
+    }
+    for index in [2, 5, 6, 9, 12, 13, 14].iter() {
+        assert_eq!(mmr::node_to_leaf_index(*index), None);
+    }
+    assert_eq!(mmr::find_peaks(mmr::get_node_count(11)), vec![14, 17, 18]);
 }
 
 #[test]
@@ -0,0 +1,350 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use crate::support::testobject::TestObject;
+use blake2::Blake2b;
+use merklemountainrange::{
+    error::MerkleMountainRangeError,
+    mmr::*,
+    prunedmmr::{verify_unspent_proof, PrunedMmr, BITMAP_CHUNK_BITS},
+};
+use tari_utilities::Hashable;
+
+fn create_pruned_mmr(leaves: usize) -> PrunedMmr<TestObject<Blake2b>, Blake2b> {
+    let mut mmr = PrunedMmr::new();
+    for i in 1..=leaves {
+        assert_eq!(mmr.push(TestObject::new(i.to_string())).unwrap(), i - 1);
+    }
+    mmr
+}
+
+#[test]
+fn delete_and_compact() {
+    let mut mmr = create_pruned_mmr(7);
+    let mmr_root = mmr.get_mmr().get_merkle_root();
+    let root = mmr.get_merkle_root();
+    assert!(mmr.delete(2));
+    assert!(!mmr.delete(2));
+    assert!(!mmr.delete(7));
+    assert!(mmr.is_deleted(2));
+    assert_eq!(mmr.get_unspent_count(), 6);
+    // Deleting a leaf changes the root, but not the root of the underlying mmr
+    assert_eq!(mmr.get_mmr().get_merkle_root(), mmr_root);
+    let deleted_root = mmr.get_merkle_root();
+    assert_ne!(deleted_root, root);
+
+    let hash = mmr.get_mmr().get_hash(get_node_count(2)).unwrap().unwrap();
+    assert!(mmr.get_mmr().get_object(&hash).is_some());
+    assert_eq!(mmr.compact().unwrap(), 1);
+    assert!(mmr.get_mmr().get_object(&hash).is_none());
+    assert_eq!(mmr.get_merkle_root(), deleted_root);
+
+    assert!(mmr.undelete(2));
+    assert!(!mmr.undelete(2));
+    assert_eq!(mmr.get_merkle_root(), root);
+}
+
+#[test]
+fn compacting_keeps_root() {
+    // A contiguous run of deletions is stored differently once the bitmap is optimized, which must not change the root
+    let mut mmr = create_pruned_mmr(40);
+    let mut uncompacted = create_pruned_mmr(40);
+    for leaf_index in 5..30 {
+        assert!(mmr.delete(leaf_index));
+        assert!(uncompacted.delete(leaf_index));
+    }
+    let root = mmr.get_merkle_root();
+    assert_eq!(mmr.compact().unwrap(), 25);
+    assert_eq!(mmr.get_merkle_root(), root);
+    assert_eq!(uncompacted.get_merkle_root(), root);
+    let proof = mmr.get_unspent_proof(30).unwrap().unwrap();
+    let hash = mmr.get_mmr().get_hash(get_node_count(30)).unwrap().unwrap();
+    assert!(verify_unspent_proof::<Blake2b>(&root, &hash, &proof));
+}
+
+#[test]
+fn unspent_proofs() {
+    let mut mmr = create_pruned_mmr(7);
+    assert!(mmr.delete(2));
+    let root = mmr.get_merkle_root();
+    for leaf_index in 0..7 {
+        let leaf_hash = mmr.get_mmr().get_hash(get_node_count(leaf_index)).unwrap().unwrap();
+        match mmr.get_unspent_proof(leaf_index).unwrap() {
+            Some(proof) => assert!(verify_unspent_proof::<Blake2b>(&root, &leaf_hash, &proof)),
+            None => assert_eq!(leaf_index, 2),
+        }
+    }
+    assert!(mmr.get_unspent_proof(7).unwrap().is_none());
+
+    // A proof made before the leaf was deleted does not verify against the new root
+    let leaf_hash = mmr.get_mmr().get_hash(get_node_count(3)).unwrap().unwrap();
+    let proof = mmr.get_unspent_proof(3).unwrap().unwrap();
+    assert!(mmr.delete(3));
+    assert!(!verify_unspent_proof::<Blake2b>(&mmr.get_merkle_root(), &leaf_hash, &proof));
+    // and a proof cannot hide the deletion from the bitmap the root commits to
+    let mut forged = mmr.get_unspent_proof(4).unwrap().unwrap();
+    forged.proof = proof.proof;
+    assert!(!verify_unspent_proof::<Blake2b>(&mmr.get_merkle_root(), &leaf_hash, &forged));
+}
+
+#[test]
+fn unspent_proofs_across_chunks() {
+    let mut mmr = create_pruned_mmr(1300);
+    for leaf_index in (0..1300).step_by(7) {
+        assert!(mmr.delete(leaf_index));
+    }
+    let root = mmr.get_merkle_root();
+    // The bitmap tree updated on every deletion has the same root as one built from the finished bitmap
+    let mut copy: MerkleMountainRange<TestObject<Blake2b>, Blake2b> = MerkleMountainRange::new();
+    for hash in mmr.get_mmr().get_leaf_hashes().unwrap() {
+        copy.add_pruned(hash).unwrap();
+    }
+    let rebuilt = PrunedMmr::from_mmr(copy, mmr.get_deleted().clone());
+    assert_eq!(rebuilt.get_merkle_root(), root);
+    for &leaf_index in &[1, 255, 256, 1000, 1299] {
+        let leaf_hash = mmr.get_mmr().get_hash(get_node_count(leaf_index)).unwrap().unwrap();
+        let proof = mmr.get_unspent_proof(leaf_index).unwrap().unwrap();
+        // The proof holds a single chunk of the bitmap and a path of at most log2(6) hashes
+        assert_eq!(proof.chunk.len(), BITMAP_CHUNK_BITS / 8);
+        assert!(proof.chunk_path.len() <= 3);
+        assert!(verify_unspent_proof::<Blake2b>(&root, &leaf_hash, &proof));
+
+        let mut tampered = proof.clone();
+        let bit = (leaf_index + 1) % BITMAP_CHUNK_BITS;
+        tampered.chunk[bit / 8] ^= 1 << (bit % 8);
+        assert!(!verify_unspent_proof::<Blake2b>(&root, &leaf_hash, &tampered));
+        let mut tampered = proof.clone();
+        tampered.chunk_path.push(root.clone());
+        assert!(!verify_unspent_proof::<Blake2b>(&root, &leaf_hash, &tampered));
+        let mut tampered = proof.clone();
+        tampered.chunk_path.pop();
+        assert!(!verify_unspent_proof::<Blake2b>(&root, &leaf_hash, &tampered));
+    }
+
+    // A proof for a leaf index outside of the mmr or the bitmap is rejected
+    let leaf_hash = mmr.get_mmr().get_hash(get_node_count(1)).unwrap().unwrap();
+    let mut proof = mmr.get_unspent_proof(1).unwrap().unwrap();
+    proof.proof.leaf_index = 1300;
+    assert!(!verify_unspent_proof::<Blake2b>(&root, &leaf_hash, &proof));
+    proof.proof.leaf_index = u32::max_value() as usize + 2;
+    assert!(!verify_unspent_proof::<Blake2b>(&root, &leaf_hash, &proof));
+}
+
+#[test]
+fn calculated_roots() {
+    let mut mmr = create_pruned_mmr(250);
+    mmr.delete(3);
+    let objects: Vec<TestObject<Blake2b>> = (251..=270).map(|i| TestObject::new(i.to_string())).collect();
+    let root = mmr.calculate_root(&objects, &[7, 260, 300]);
+    assert_eq!(mmr.get_merkle_root(), pruned_root_with_deletions(250, &[3]));
+    for object in objects {
+        mmr.push(object).unwrap();
+    }
+    mmr.delete(7);
+    mmr.delete(260);
+    assert_eq!(mmr.get_merkle_root(), root);
+
+    // Leaves added by their hashes give the same root
+    let mut pruned = PrunedMmr::<TestObject<Blake2b>, Blake2b>::new();
+    for hash in mmr.get_mmr().get_leaf_hashes().unwrap() {
+        pruned.push_pruned(hash).unwrap();
+    }
+    for leaf_index in mmr.get_deleted().iter() {
+        assert!(pruned.delete(leaf_index as usize));
+    }
+    assert_eq!(pruned.get_merkle_root(), root);
+}
+
+fn pruned_root_with_deletions(leaves: usize, deletions: &[usize]) -> Vec<u8> {
+    let mut mmr = create_pruned_mmr(leaves);
+    for &leaf_index in deletions {
+        assert!(mmr.delete(leaf_index));
+    }
+    mmr.get_merkle_root()
+}
+
+#[test]
+fn rewind_drops_deletions() {
+    let mut mmr = create_pruned_mmr(6);
+    mmr.delete(1);
+    mmr.delete(4);
+    mmr.rewind(3).unwrap();
+    assert!(mmr.is_deleted(1));
+    assert!(!mmr.is_deleted(4));
+    mmr.push(TestObject::new("4".to_string())).unwrap();
+    let mut expected = create_pruned_mmr(4);
+    expected.delete(1);
+    assert_eq!(mmr.get_merkle_root(), expected.get_merkle_root());
+}
+
+#[test]
+fn rewind_across_chunks() {
+    let mut mmr = create_pruned_mmr(600);
+    mmr.delete(10);
+    mmr.delete(300);
+    mmr.commit_checkpoint().unwrap();
+    for i in 601..=800 {
+        mmr.push(TestObject::new(i.to_string())).unwrap();
+    }
+    mmr.delete(20);
+    mmr.delete(550);
+    mmr.delete(700);
+    mmr.commit_checkpoint().unwrap();
+    mmr.rewind_checkpoints(1).unwrap();
+    let mut expected = create_pruned_mmr(600);
+    expected.delete(10);
+    expected.delete(300);
+    assert_eq!(mmr.get_merkle_root(), expected.get_merkle_root());
+    mmr.rewind(250).unwrap();
+    let mut expected = create_pruned_mmr(250);
+    expected.delete(10);
+    assert_eq!(mmr.get_merkle_root(), expected.get_merkle_root());
+}
+
+#[test]
+fn checkpoints() {
+    let mut mmr = create_pruned_mmr(3);
+    mmr.commit_checkpoint().unwrap();
+    let first_root = mmr.get_merkle_root();
+    mmr.push(TestObject::new("4".to_string())).unwrap();
+    mmr.delete(1);
+    mmr.commit_checkpoint().unwrap();
+    mmr.push(TestObject::new("5".to_string())).unwrap();
+    mmr.push(TestObject::new("6".to_string())).unwrap();
+    mmr.delete(3);
+    mmr.delete(5);
+    mmr.commit_checkpoint().unwrap();
+    let tip_root = mmr.get_merkle_root();
+    assert_eq!(mmr.get_checkpoint_count(), 3);
+    assert_eq!(mmr.get_checkpoint(1).unwrap().leaf_count, 3);
+    assert_eq!(mmr.get_checkpoints_since(2).len(), 1);
+    assert!(mmr.get_checkpoints_since(4).is_empty());
+
+    // A node at the first checkpoint catches up with the changes since then
+    let mut synced = create_pruned_mmr(3);
+    synced.commit_checkpoint().unwrap();
+    assert_eq!(synced.get_merkle_root(), first_root);
+    for checkpoint in mmr.get_checkpoints_since(1) {
+        synced.apply_checkpoint(checkpoint).unwrap();
+    }
+    assert_eq!(synced.get_merkle_root(), tip_root);
+    assert!(synced.apply_checkpoint(mmr.get_checkpoint(2).unwrap()).is_err());
+
+    // Uncommitted changes are discarded when rolling back
+    mmr.push(TestObject::new("7".to_string())).unwrap();
+    mmr.delete(0);
+    let removed = mmr.rewind_checkpoints(1).unwrap();
+    assert_eq!(removed.len(), 2);
+    assert_eq!(mmr.get_merkle_root(), first_root);
+    assert!(!mmr.is_deleted(0) && !mmr.is_deleted(1));
+    for checkpoint in removed.iter() {
+        mmr.apply_checkpoint(checkpoint).unwrap();
+    }
+    assert_eq!(mmr.get_merkle_root(), tip_root);
+}
+
+#[test]
+fn restore_from_checkpoints() {
+    let mut mmr = create_pruned_mmr(3);
+    mmr.commit_checkpoint().unwrap();
+    mmr.push(TestObject::new("4".to_string())).unwrap();
+    mmr.delete(1);
+    mmr.commit_checkpoint().unwrap();
+    mmr.push(TestObject::new("5".to_string())).unwrap();
+    mmr.push(TestObject::new("6".to_string())).unwrap();
+    mmr.delete(3);
+    mmr.delete(5);
+    mmr.commit_checkpoint().unwrap();
+    let checkpoints = (0..3)
+        .map(|height| {
+            let checkpoint = mmr.get_checkpoint(height).unwrap();
+            (checkpoint.leaf_count, checkpoint.nodes_deleted.to_vec())
+        })
+        .collect::<Vec<_>>();
+    // An mmr that only holds the hashes of the leaves, such as one opened on a stored backend
+    let leaves = || {
+        let mut leaves = MerkleMountainRange::<TestObject<Blake2b>, Blake2b>::new();
+        for i in 1..=6 {
+            leaves.add_pruned(TestObject::<Blake2b>::new(i.to_string()).hash()).unwrap();
+        }
+        leaves
+    };
+
+    let mut restored = PrunedMmr::from_checkpoints(leaves(), &checkpoints).unwrap();
+    assert_eq!(restored.get_merkle_root(), mmr.get_merkle_root());
+    assert_eq!(restored.get_checkpoint_count(), 3);
+    assert!(restored.is_deleted(1) && restored.is_deleted(3) && restored.is_deleted(5));
+    assert!(!restored.is_deleted(4));
+    assert_eq!(restored.rewind_checkpoints(2).unwrap(), mmr.rewind_checkpoints(2).unwrap());
+    assert_eq!(restored.get_merkle_root(), mmr.get_merkle_root());
+
+    let invalid = [
+        // The leaves are not all added by a checkpoint
+        vec![],
+        vec![(1, vec![]), (3, vec![])],
+        // The leaf counts are out of order
+        vec![(0, vec![]), (4, vec![]), (3, vec![])],
+        // The leaf counts are beyond the end of the mmr
+        vec![(0, vec![]), (7, vec![])],
+        // A leaf is deleted before it was added
+        vec![(0, vec![3]), (3, vec![]), (4, vec![])],
+        // A leaf is deleted twice
+        vec![(0, vec![]), (3, vec![1]), (4, vec![1])],
+    ];
+    for checkpoints in invalid.iter() {
+        match PrunedMmr::from_checkpoints(leaves(), checkpoints) {
+            Err(MerkleMountainRangeError::InvalidCheckpoint) => {},
+            _ => panic!("Invalid checkpoints were accepted"),
+        }
+    }
+}
+
+#[test]
+fn diffs() {
+    let mut mmr = create_pruned_mmr(3);
+    mmr.commit_checkpoint().unwrap();
+    mmr.push(TestObject::new("4".to_string())).unwrap();
+    mmr.delete(1);
+    mmr.commit_checkpoint().unwrap();
+    mmr.push(TestObject::new("5".to_string())).unwrap();
+    mmr.delete(3);
+    mmr.commit_checkpoint().unwrap();
+    let tip_root = mmr.get_merkle_root();
+    // Uncommitted changes are not part of a diff
+    mmr.push(TestObject::new("6".to_string())).unwrap();
+    mmr.delete(0);
+
+    let diff = mmr.get_diff(1).unwrap().unwrap();
+    assert_eq!(diff.leaf_count, 3);
+    assert_eq!(diff.nodes_added.len(), 2);
+    assert_eq!(diff.nodes_deleted.cardinality(), 2);
+    assert!(mmr.get_diff(3).unwrap().unwrap().nodes_added.is_empty());
+    assert!(mmr.get_diff(4).unwrap().is_none());
+
+    // A node that is a few blocks behind catches up with a single diff
+    let mut synced = create_pruned_mmr(3);
+    synced.apply_diff(&diff).unwrap();
+    assert_eq!(synced.get_merkle_root(), tip_root);
+    synced.commit_checkpoint().unwrap();
+    assert_eq!(synced.get_checkpoint_count(), 1);
+    assert_eq!(synced.apply_diff(&diff), Err(MerkleMountainRangeError::InvalidDiff));
+}
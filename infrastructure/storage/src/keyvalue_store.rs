@@ -67,6 +67,9 @@ pub trait DataStore {
     /// Save a value at the given key. Existing values are overwritten
     fn put_raw(&mut self, key: &[u8], value: Vec<u8>) -> Result<(), DatastoreError>;
 
+    /// Remove the value at the given key. Removing a key that does not exist has no effect
+    fn delete(&mut self, key: &[u8]) -> Result<(), DatastoreError>;
+
     /// Serialize a value using Bincode and then save it value at the given key. Existing values are overwritten
     fn put<T: Serialize>(&mut self, key: &str, value: &T) -> Result<(), DatastoreError> {
         let key = key.as_bytes();
@@ -83,9 +86,19 @@ pub trait BatchWrite {
 
     fn new(store: &Self::Store) -> Result<Self::Batcher, DatastoreError>;
 
+    /// Write to the logical database with `name` from now on. If the Datastore does not support multiple logical
+    /// databases, this function has no effect
+    fn connect(&mut self, name: &str) -> Result<(), DatastoreError>;
+
     /// Save a value at the given key. Existing values are overwritten
     fn put_raw(&mut self, key: &[u8], value: Vec<u8>) -> Result<(), DatastoreError>;
 
+    /// Remove the value at the given key. Removing a key that does not exist has no effect
+    fn delete(&mut self, key: &[u8]) -> Result<(), DatastoreError>;
+
+    /// Remove every value in the current database
+    fn clear(&mut self) -> Result<(), DatastoreError>;
+
     /// Serialize a value and then save it value at the given key. Existing values are overwritten
     fn put<T: Serialize>(&mut self, key: &str, value: &T) -> Result<(), DatastoreError> {
         let key = key.as_bytes();
@@ -93,9 +106,9 @@ pub trait BatchWrite {
         self.put_raw(key, val)
     }
 
-    /// Commit all puts in the batch write to the database
+    /// Commit all the changes in the batch write to the database
     fn commit(self) -> Result<(), DatastoreError>;
 
-    /// Discard all puts that have been made in this batch
+    /// Discard all the changes that have been made in this batch
     fn abort(self) -> Result<(), DatastoreError>;
 }
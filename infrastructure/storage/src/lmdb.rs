@@ -84,7 +84,9 @@ impl LMDBBuilder {
 }
 
 /// A Struct for holding state for the LMDB implementation of DataStore and BatchWrite. To create an instance of
-/// LMDBStore, use [LMDBBuilder](struct.lmdbbuilder.html).
+/// LMDBStore, use [LMDBBuilder](struct.lmdbbuilder.html). Clones of a store share its database environment, but each
+/// clone is connected to its own current database.
+#[derive(Clone)]
 pub struct LMDBStore {
     pub(crate) env: Arc<lmdb::Environment>,
     pub(crate) databases: HashMap<String, Arc<lmdb::Database<'static>>>,
@@ -127,9 +129,21 @@ impl DataStore for LMDBStore {
         }
         tx.commit().map_err(|e| e.into())
     }
+
+    fn delete(&mut self, key: &[u8]) -> Result<(), DatastoreError> {
+        let tx = lmdb::WriteTransaction::new(self.env.clone())?;
+        {
+            let mut accessor = tx.access();
+            accessor.del_key(&self.curr_db, key).to_opt()?;
+        }
+        tx.commit().map_err(|e| e.into())
+    }
 }
 
-struct LMDBBatch<'a> {
+/// A single LMDB write transaction. It starts on the current database of the store it was created from, and can be
+/// connected to any of the other databases of the store, so that values in several databases are written atomically.
+pub struct LMDBBatch<'a> {
+    databases: HashMap<String, Arc<lmdb::Database<'static>>>,
     db: Arc<lmdb::Database<'static>>,
     tx: lmdb::WriteTransaction<'a>,
 }
@@ -139,7 +153,21 @@ impl<'a> BatchWrite for LMDBBatch<'a> {
     type Store = LMDBStore;
 
     fn new(store: &LMDBStore) -> Result<LMDBBatch<'a>, DatastoreError> {
-        Ok(LMDBBatch { db: store.curr_db.clone(), tx: lmdb::WriteTransaction::new(store.env.clone())? })
+        Ok(LMDBBatch {
+            databases: store.databases.clone(),
+            db: store.curr_db.clone(),
+            tx: lmdb::WriteTransaction::new(store.env.clone())?,
+        })
+    }
+
+    fn connect(&mut self, name: &str) -> Result<(), DatastoreError> {
+        match self.databases.get(name) {
+            Some(db) => {
+                self.db = db.clone();
+                Ok(())
+            },
+            None => Err(DatastoreError::UnknownDatabase),
+        }
     }
 
     fn put_raw(&mut self, key: &[u8], value: Vec<u8>) -> Result<(), DatastoreError> {
@@ -150,6 +178,22 @@ impl<'a> BatchWrite for LMDBBatch<'a> {
         Ok(())
     }
 
+    fn delete(&mut self, key: &[u8]) -> Result<(), DatastoreError> {
+        {
+            let mut accessor = self.tx.access();
+            accessor.del_key(&self.db, key).to_opt()?;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), DatastoreError> {
+        {
+            let mut accessor = self.tx.access();
+            accessor.clear_db(&self.db)?;
+        }
+        Ok(())
+    }
+
     fn commit(self) -> Result<(), DatastoreError> {
         self.tx.commit().map_err(|e| e.into())
     }
@@ -225,6 +269,40 @@ mod test {
         assert!(fs::remove_dir_all("./tests/test_tx").is_ok());
     }
 
+    #[test]
+    fn batch_writes_to_several_databases() {
+        fs::create_dir("./tests/test_tx_dbs").unwrap();
+        let mut store = LMDBBuilder::new()
+            .set_path("./tests/test_tx_dbs/")
+            .add_database("db1")
+            .add_database("db2")
+            .build()
+            .unwrap();
+        store.connect("db1").unwrap();
+        store.put_raw(b"a", b"apple".to_vec()).unwrap();
+        store.put_raw(b"b", b"banana".to_vec()).unwrap();
+        let mut batch = LMDBBatch::new(&store).unwrap();
+        batch.delete(b"a").unwrap();
+        batch.connect("db2").unwrap();
+        batch.put_raw(b"c", b"carrot".to_vec()).unwrap();
+        assert!(batch.connect("db3").is_err());
+        // Nothing is written until the batch is committed
+        assert!(store.exists(b"a").unwrap());
+        batch.commit().unwrap();
+        assert!(!store.exists(b"a").unwrap());
+        assert!(store.exists(b"b").unwrap());
+        let mut db2 = store.clone();
+        db2.connect("db2").unwrap();
+        assert_eq!(&db2.get_raw(b"c").unwrap().unwrap(), b"carrot");
+
+        let mut batch = LMDBBatch::new(&store).unwrap();
+        batch.clear().unwrap();
+        batch.commit().unwrap();
+        assert!(!store.exists(b"b").unwrap());
+        assert!(db2.exists(b"c").unwrap());
+        assert!(fs::remove_dir_all("./tests/test_tx_dbs").is_ok());
+    }
+
     #[test]
     fn writes_to_default_db() {
         fs::create_dir("./tests/test_default").unwrap();
@@ -314,6 +392,20 @@ mod test {
         assert!(fs::remove_dir_all("./tests/test_10k").is_ok());
     }
 
+    #[test]
+    fn delete_values() {
+        fs::create_dir("./tests/test_delete").unwrap();
+        let mut store = LMDBBuilder::new().set_path("./tests/test_delete/").build().unwrap();
+        store.put_raw(b"England", b"rose".to_vec()).unwrap();
+        store.put_raw(b"Scotland", b"thistle".to_vec()).unwrap();
+        store.delete(b"England").unwrap();
+        assert!(!store.exists(b"England").unwrap());
+        assert!(store.exists(b"Scotland").unwrap());
+        // Deleting a missing key is not an error
+        store.delete(b"Wales").unwrap();
+        assert!(fs::remove_dir_all("./tests/test_delete").is_ok());
+    }
+
     #[test]
     fn test_exist_on_different_databases() {
         fs::create_dir("./tests/test_exist").unwrap();
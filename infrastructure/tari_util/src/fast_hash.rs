@@ -0,0 +1,177 @@
+// Copyright 2019 The Tari Project
+//
+// Redistribution and use in source and binary forms, with or without modification, are permitted provided that the
+// following conditions are met:
+//
+// 1. Redistributions of source code must retain the above copyright notice, this list of conditions and the following
+// disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright notice, this list of conditions and the
+// following disclaimer in the documentation and/or other materials provided with the distribution.
+//
+// 3. Neither the name of the copyright holder nor the names of its contributors may be used to endorse or promote
+// products derived from this software without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES,
+// INCLUDING, BUT NOT LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF LIABILITY,
+// WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
+// USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Fast, non-cryptographic hashing for integrity checks that are *not* part of consensus, e.g. database checksums,
+//! message envelope integrity, inventory caches and LRU keys. Consensus hashing must continue to use the Blake2b
+//! based hashers in the crypto crate; nothing in this module is collision resistant against an adversary who knows
+//! the key.
+
+/// A 64-bit hash over a byte slice. Implementations are cheap to construct and do not allocate.
+pub trait FastHash {
+    fn fast_hash(&self, data: &[u8]) -> u64;
+}
+
+/// Keyed SipHash-2-4. Use a random key per process (or per database) for hash tables and caches that are fed with
+/// peer-supplied data, so that remote peers cannot precompute colliding inputs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SipHash24 {
+    k0: u64,
+    k1: u64,
+}
+
+impl SipHash24 {
+    /// Create a new SipHash-2-4 instance from a 128-bit key
+    pub fn new(key: &[u8; 16]) -> SipHash24 {
+        let mut k0 = [0u8; 8];
+        let mut k1 = [0u8; 8];
+        k0.copy_from_slice(&key[..8]);
+        k1.copy_from_slice(&key[8..]);
+        SipHash24 { k0: u64::from_le_bytes(k0), k1: u64::from_le_bytes(k1) }
+    }
+}
+
+struct SipState {
+    v0: u64,
+    v1: u64,
+    v2: u64,
+    v3: u64,
+}
+
+impl SipState {
+    fn round(&mut self) {
+        self.v0 = self.v0.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(13) ^ self.v0;
+        self.v0 = self.v0.rotate_left(32);
+        self.v2 = self.v2.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(16) ^ self.v2;
+        self.v0 = self.v0.wrapping_add(self.v3);
+        self.v3 = self.v3.rotate_left(21) ^ self.v0;
+        self.v2 = self.v2.wrapping_add(self.v1);
+        self.v1 = self.v1.rotate_left(17) ^ self.v2;
+        self.v2 = self.v2.rotate_left(32);
+    }
+
+    fn compress(&mut self, m: u64) {
+        self.v3 ^= m;
+        self.round();
+        self.round();
+        self.v0 ^= m;
+    }
+}
+
+impl FastHash for SipHash24 {
+    fn fast_hash(&self, data: &[u8]) -> u64 {
+        let mut state = SipState {
+            v0: self.k0 ^ 0x736f_6d65_7073_6575,
+            v1: self.k1 ^ 0x646f_7261_6e64_6f6d,
+            v2: self.k0 ^ 0x6c79_6765_6e65_7261,
+            v3: self.k1 ^ 0x7465_6462_7974_6573,
+        };
+        let mut chunks = data.chunks_exact(8);
+        for chunk in &mut chunks {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            state.compress(u64::from_le_bytes(word));
+        }
+        // The final word holds the remaining bytes and the message length (mod 256) in the top byte
+        let mut last = [0u8; 8];
+        let remainder = chunks.remainder();
+        last[..remainder.len()].copy_from_slice(remainder);
+        last[7] = data.len() as u8;
+        state.compress(u64::from_le_bytes(last));
+        state.v2 ^= 0xff;
+        for _ in 0..4 {
+            state.round();
+        }
+        state.v0 ^ state.v1 ^ state.v2 ^ state.v3
+    }
+}
+
+/// Unkeyed 64-bit FNV-1a. Only suitable for checksums over trusted, local data (e.g. detecting torn writes in a
+/// database file); prefer [SipHash24](struct.SipHash24.html) whenever the input can be influenced by a peer.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Fnv1a64;
+
+impl FastHash for Fnv1a64 {
+    fn fast_hash(&self, data: &[u8]) -> u64 {
+        data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3))
+    }
+}
+
+/// The non-consensus hash functions that can be selected at runtime, e.g. from a node configuration file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FastHashAlgorithm {
+    SipHash24(SipHash24),
+    Fnv1a64,
+}
+
+impl FastHash for FastHashAlgorithm {
+    fn fast_hash(&self, data: &[u8]) -> u64 {
+        match self {
+            FastHashAlgorithm::SipHash24(h) => h.fast_hash(data),
+            FastHashAlgorithm::Fnv1a64 => Fnv1a64.fast_hash(data),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reference_key() -> [u8; 16] {
+        let mut key = [0u8; 16];
+        for (i, k) in key.iter_mut().enumerate() {
+            *k = i as u8;
+        }
+        key
+    }
+
+    #[test]
+    fn siphash_reference_vectors() {
+        // Test vectors from the SipHash reference implementation (key = 00 01 .. 0f, message = 00 01 .. len-1)
+        let hasher = SipHash24::new(&reference_key());
+        let msg: Vec<u8> = (0u8..16).collect();
+        assert_eq!(hasher.fast_hash(&msg[..0]), 0x726f_db47_dd0e_0e31);
+        assert_eq!(hasher.fast_hash(&msg[..1]), 0x74f8_39c5_93dc_67fd);
+        assert_eq!(hasher.fast_hash(&msg[..8]), 0x93f5_f579_9a93_2462);
+        assert_eq!(hasher.fast_hash(&msg[..15]), 0xa129_ca61_49be_45e5);
+    }
+
+    #[test]
+    fn fnv1a_reference_vectors() {
+        assert_eq!(Fnv1a64.fast_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(Fnv1a64.fast_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(Fnv1a64.fast_hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn runtime_selection() {
+        let key = reference_key();
+        let data = b"inventory item";
+        assert_eq!(
+            FastHashAlgorithm::SipHash24(SipHash24::new(&key)).fast_hash(data),
+            SipHash24::new(&key).fast_hash(data)
+        );
+        assert_eq!(FastHashAlgorithm::Fnv1a64.fast_hash(data), Fnv1a64.fast_hash(data));
+        assert_ne!(SipHash24::new(&[0u8; 16]).fast_hash(data), SipHash24::new(&key).fast_hash(data));
+    }
+}
@@ -20,7 +20,105 @@
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+use std::{cmp::Ordering, ops::Deref};
+
 /// This trait is used to describe how an object should be hashed
 pub trait Hashable {
     fn hash(&self) -> Vec<u8>;
 }
+
+/// The canonical byte encoding of a consensus object, e.g. a Merkle mountain range leaf. For types that derive
+/// `MmrLeaf`, `Hashable::hash` is the digest of exactly these bytes, so the MMR leaf hash and the encoding can never
+/// drift apart.
+pub trait ConsensusEncoding {
+    fn consensus_encode(&self) -> Vec<u8>;
+}
+
+/// A [Hashable](trait.Hashable.html) value together with its hash, which is computed once when the value is wrapped
+/// and then used for every comparison and every call to `hash`. `#[derive(HashableOrdering)]` recomputes the hash on
+/// every comparison, so wrap values in `CachedHash` when sorting or repeatedly comparing large collections.
+///
+/// The cache is never invalidated: the wrapped value is only reachable immutably, through `Deref`, so it cannot change
+/// while it is wrapped. To modify the value, take it back with `into_inner` and wrap it again, which hashes it anew.
+#[derive(Clone, Debug)]
+pub struct CachedHash<T: Hashable> {
+    value: T,
+    hash: Vec<u8>,
+}
+
+impl<T: Hashable> CachedHash<T> {
+    /// Wrap the value, hashing it once
+    pub fn new(value: T) -> CachedHash<T> {
+        let hash = value.hash();
+        CachedHash { value, hash }
+    }
+
+    /// Unwrap the value, discarding the cached hash
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: Hashable> Hashable for CachedHash<T> {
+    fn hash(&self) -> Vec<u8> {
+        self.hash.clone()
+    }
+}
+
+impl<T: Hashable> Deref for CachedHash<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: Hashable> Ord for CachedHash<T> {
+    fn cmp(&self, other: &CachedHash<T>) -> Ordering {
+        self.hash.cmp(&other.hash)
+    }
+}
+
+impl<T: Hashable> PartialOrd for CachedHash<T> {
+    fn partial_cmp(&self, other: &CachedHash<T>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Hashable> PartialEq for CachedHash<T> {
+    fn eq(&self, other: &CachedHash<T>) -> bool {
+        self.hash == other.hash
+    }
+}
+
+impl<T: Hashable> Eq for CachedHash<T> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    struct Counted<'a> {
+        n: u8,
+        calls: &'a Cell<usize>,
+    }
+
+    impl<'a> Hashable for Counted<'a> {
+        fn hash(&self) -> Vec<u8> {
+            self.calls.set(self.calls.get() + 1);
+            vec![255 - self.n]
+        }
+    }
+
+    #[test]
+    fn sorting_hashes_each_value_once() {
+        let calls = Cell::new(0);
+        let mut values: Vec<CachedHash<Counted>> =
+            (0..50u8).map(|n| CachedHash::new(Counted { n, calls: &calls })).collect();
+        values.sort();
+        assert_eq!(calls.get(), 50);
+        assert_eq!(values[0].n, 49);
+        assert_eq!(values[49].n, 0);
+        assert_eq!(values.pop().unwrap().into_inner().n, 0);
+    }
+}
@@ -20,10 +20,11 @@
 // WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE
 // USE OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 pub mod byte_array;
+pub mod fast_hash;
 pub mod hash;
 pub mod hex;
 
 pub use self::{
     byte_array::{ByteArray, ByteArrayError},
-    hash::Hashable,
+    hash::{CachedHash, ConsensusEncoding, Hashable},
 };